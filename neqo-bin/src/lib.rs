@@ -132,7 +132,7 @@ pub struct QuicParameters {
     #[arg(long = "ss", default_value = "classic",
         value_parser = clap::builder::PossibleValuesParser::new(SlowStart::VARIANTS)
             .map(|s| s.parse::<SlowStart>().unwrap()))]
-    /// The slow start algorithm to use.
+    /// The slow start algorithm to use. BBR has its own startup phase and ignores this.
     pub slow_start: SlowStart,
 
     #[arg(long = "no-pacing")]
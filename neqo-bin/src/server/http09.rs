@@ -233,7 +233,8 @@ impl super::HttpServer for HttpServer {
                     ConnectionEvent::StateChange(_)
                     | ConnectionEvent::SendStreamCreatable { .. }
                     | ConnectionEvent::SendStreamComplete { .. }
-                    | ConnectionEvent::PathMigrated { .. } => (),
+                    | ConnectionEvent::PathMigrated { .. }
+                    | ConnectionEvent::PathFailover { .. } => (),
                     e => qwarn!("unhandled event {e:?}"),
                 }
             }
@@ -7,7 +7,7 @@
 /// Owned contiguous byte array with an optional offset.
 ///
 /// Inspired by `bytes` crate's `Bytes`.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Bytes {
     data: Vec<u8>,
     offset: usize,
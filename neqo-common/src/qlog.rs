@@ -6,11 +6,13 @@
 
 use std::{
     cell::RefCell,
+    collections::VecDeque,
     fmt::{self, Display},
     fs::OpenOptions,
-    io::BufWriter,
+    io::{self, BufWriter, Write},
     path::PathBuf,
     rc::Rc,
+    sync::{Arc, Mutex, PoisonError, mpsc},
     time::{Instant, SystemTime},
 };
 
@@ -31,8 +33,11 @@ pub struct Qlog {
 }
 
 pub struct SharedStreamer {
-    qlog_path: PathBuf,
+    /// Where the log is written, for debugging.
+    target: String,
     streamer: QlogStreamer,
+    /// The time of the last event that was logged with a time.
+    latest: Instant,
 }
 
 impl Qlog {
@@ -71,6 +76,35 @@ impl Qlog {
         Self::enabled(streamer, qlog_path)
     }
 
+    /// Create an enabled `Qlog` configuration that sends records to a [`Sink`].
+    /// Once the output reaches `max_size` bytes, if that is set, any further
+    /// records are discarded.
+    ///
+    /// # Errors
+    ///
+    /// Will return `qlog::Error` if it cannot write to the new log.
+    pub fn enabled_with_sink(
+        sink: Box<dyn Sink>,
+        max_size: Option<usize>,
+        role: Role,
+        title: Option<String>,
+        description: Option<String>,
+        now: Instant,
+    ) -> Result<Self, qlog::Error> {
+        let mut streamer = QlogStreamer::new(
+            qlog::QLOG_VERSION.to_string(),
+            title,
+            description,
+            None,
+            now,
+            new_trace(role),
+            qlog::events::EventImportance::Extra,
+            Box::new(SinkWriter::new(sink, max_size)),
+        );
+        streamer.start_log()?;
+        Ok(Self::with_streamer(streamer, "sink".to_string()))
+    }
+
     /// Create an enabled `Qlog` configuration.
     ///
     /// This needs to be called before the connection is used, because otherwise `Qlog`-logging will
@@ -81,13 +115,20 @@ impl Qlog {
     /// Will return `qlog::Error` if it cannot write to the new log.
     pub fn enabled(mut streamer: QlogStreamer, qlog_path: PathBuf) -> Result<Self, qlog::Error> {
         streamer.start_log()?;
+        Ok(Self::with_streamer(
+            streamer,
+            qlog_path.display().to_string(),
+        ))
+    }
 
-        Ok(Self {
+    fn with_streamer(streamer: QlogStreamer, target: String) -> Self {
+        Self {
             inner: Some(Rc::new(RefCell::new(Some(SharedStreamer {
-                qlog_path,
+                target,
+                latest: streamer.start_time(),
                 streamer,
             })))),
-        })
+        }
     }
 
     /// Create a disabled `Qlog` configuration.
@@ -107,19 +148,57 @@ impl Qlog {
     where
         F: FnOnce() -> Option<qlog::events::EventData>,
     {
-        self.add_event_with_stream(|s| {
+        self.add_event_with_stream_at(
+            |s| {
+                if let Some(ev_data) = f() {
+                    s.add_event_data_with_instant(ev_data, now)?;
+                }
+                Ok(())
+            },
+            now,
+        );
+    }
+
+    /// If logging enabled, closure may generate an event to be logged at the
+    /// time of the last event that was logged with a time.  Use this only for
+    /// events that are raised from functions that are not given the time, such
+    /// as those driven by the stream API.
+    pub fn add_event_at_latest<F>(&mut self, f: F)
+    where
+        F: FnOnce() -> Option<qlog::events::EventData>,
+    {
+        self.with_shared_streamer(|s| {
             if let Some(ev_data) = f() {
-                s.add_event_data_with_instant(ev_data, now)?;
+                s.streamer.add_event_data_with_instant(ev_data, s.latest)?;
             }
             Ok(())
         });
     }
 
+    /// If logging enabled, closure is given the Qlog stream to write events and
+    /// frames to, which it logs at `now`.
+    pub fn add_event_with_stream_at<F>(&mut self, f: F, now: Instant)
+    where
+        F: FnOnce(&mut QlogStreamer) -> Result<(), qlog::Error>,
+    {
+        self.with_shared_streamer(|s| {
+            s.latest = now;
+            f(&mut s.streamer)
+        });
+    }
+
     /// If logging enabled, closure is given the Qlog stream to write events and
     /// frames to.
     pub fn add_event_with_stream<F>(&mut self, f: F)
     where
         F: FnOnce(&mut QlogStreamer) -> Result<(), qlog::Error>,
+    {
+        self.with_shared_streamer(|s| f(&mut s.streamer));
+    }
+
+    fn with_shared_streamer<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut SharedStreamer) -> Result<(), qlog::Error>,
     {
         let Some(inner) = self.inner.as_mut() else {
             return;
@@ -134,7 +213,7 @@ impl Qlog {
             return;
         };
 
-        match f(&mut shared_streamer.streamer) {
+        match f(shared_streamer) {
             // `Error::Done` means "event was below the importance threshold" - not an actual error.
             Ok(()) | Err(qlog::Error::Done) => (),
             Err(e) => {
@@ -152,7 +231,7 @@ impl Qlog {
 
 impl fmt::Debug for SharedStreamer {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Qlog writing to {}", self.qlog_path.display())
+        write!(f, "Qlog writing to {}", self.target)
     }
 }
 
@@ -164,6 +243,243 @@ impl Drop for SharedStreamer {
     }
 }
 
+/// A destination for qlog records.
+///
+/// Qlog is serialized as JSON-SEQ (RFC 7464), so each record starts with a
+/// record separator (0x1e) and ends with a newline.  The first record that a
+/// sink receives is the header that describes the trace.
+pub trait Sink: Send + Sync {
+    /// Take a single record.
+    fn record(&mut self, record: &[u8]);
+}
+
+impl Sink for mpsc::Sender<Vec<u8>> {
+    fn record(&mut self, record: &[u8]) {
+        // If the receiver is gone, nobody is listening, which is fine.
+        _ = self.send(record.to_vec());
+    }
+}
+
+/// A [`Sink`] that passes each record to a function.
+pub struct Callback<F>(pub F);
+
+impl<F> Sink for Callback<F>
+where
+    F: FnMut(&[u8]) + Send + Sync,
+{
+    fn record(&mut self, record: &[u8]) {
+        (self.0)(record);
+    }
+}
+
+/// A [`Sink`] that keeps the most recent records in memory, up to a limit on
+/// their total size.  The header is always kept, so that the contents remain a
+/// valid trace.  Clones share the same buffer.
+#[derive(Debug, Clone)]
+pub struct RingBuffer {
+    inner: Arc<Mutex<Ring>>,
+}
+
+#[derive(Debug)]
+struct Ring {
+    capacity: usize,
+    header: Option<Vec<u8>>,
+    records: VecDeque<Vec<u8>>,
+    /// The total size of `records`.
+    size: usize,
+}
+
+impl RingBuffer {
+    /// Create a buffer that holds up to `capacity` bytes of records,
+    /// not counting the header.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Ring {
+                capacity,
+                header: None,
+                records: VecDeque::new(),
+                size: 0,
+            })),
+        }
+    }
+
+    /// The header and the records that are currently held, in JSON-SEQ format.
+    #[must_use]
+    pub fn contents(&self) -> Vec<u8> {
+        let ring = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut out = ring.header.clone().unwrap_or_default();
+        for r in &ring.records {
+            out.extend_from_slice(r);
+        }
+        out
+    }
+}
+
+impl Sink for RingBuffer {
+    fn record(&mut self, record: &[u8]) {
+        let mut ring = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if ring.header.is_none() {
+            ring.header = Some(record.to_vec());
+            return;
+        }
+        if record.len() > ring.capacity {
+            return;
+        }
+        while ring.size + record.len() > ring.capacity {
+            let Some(old) = ring.records.pop_front() else {
+                break;
+            };
+            ring.size -= old.len();
+        }
+        ring.size += record.len();
+        ring.records.push_back(record.to_vec());
+    }
+}
+
+/// Splits the output of a `QlogStreamer` into records for a [`Sink`].
+struct SinkWriter {
+    sink: Box<dyn Sink>,
+    /// A partial record.
+    pending: Vec<u8>,
+    /// The number of bytes output so far, including any that were discarded.
+    written: usize,
+    max_size: Option<usize>,
+}
+
+impl SinkWriter {
+    fn new(sink: Box<dyn Sink>, max_size: Option<usize>) -> Self {
+        Self {
+            sink,
+            pending: Vec::new(),
+            written: 0,
+            max_size,
+        }
+    }
+
+    fn emit(&mut self) {
+        let before = self.written;
+        self.written = self.written.saturating_add(self.pending.len());
+        match self.max_size {
+            Some(max) if self.written > max => {
+                if before <= max {
+                    log::info!("Qlog reached its limit of {max} bytes; discarding further events");
+                }
+            }
+            _ => self.sink.record(&self.pending),
+        }
+        self.pending.clear();
+    }
+}
+
+impl Write for SinkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Records can't contain a newline, except at the end.
+        let mut rest = buf;
+        while let Some(i) = rest.iter().position(|&b| b == b'\n') {
+            self.pending.extend_from_slice(&rest[..=i]);
+            self.emit();
+            rest = &rest[i + 1..];
+        }
+        self.pending.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Settings for tracing many connections, such as those of a server, with sinks.
+#[derive(Clone)]
+pub struct SinkConfig {
+    new_sink: Rc<dyn Fn(&str) -> Box<dyn Sink>>,
+    sample_percent: u8,
+    max_size: Option<usize>,
+}
+
+impl SinkConfig {
+    /// `new_sink` creates a sink for each connection that is traced.
+    /// It is passed a label that identifies the connection.
+    pub fn new<F>(new_sink: F) -> Self
+    where
+        F: Fn(&str) -> Box<dyn Sink> + 'static,
+    {
+        Self {
+            new_sink: Rc::new(new_sink),
+            sample_percent: 100,
+            max_size: None,
+        }
+    }
+
+    /// Trace only this percentage of connections.  The default is 100.
+    ///
+    /// # Panics
+    ///
+    /// If `percent` is more than 100.
+    #[must_use]
+    pub const fn sample_percent(mut self, percent: u8) -> Self {
+        assert!(percent <= 100, "sample percentage out of range");
+        self.sample_percent = percent;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_sample_percent(&self) -> u8 {
+        self.sample_percent
+    }
+
+    /// Stop tracing a connection once its trace reaches this many bytes.
+    #[must_use]
+    pub const fn max_size(mut self, max_size: Option<usize>) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_max_size(&self) -> Option<usize> {
+        self.max_size
+    }
+
+    /// Create a `Qlog` for a connection.  This is disabled unless the connection
+    /// is sampled, which `random`, a uniformly distributed random value, decides.
+    #[must_use]
+    pub fn create(
+        &self,
+        random: u16,
+        label: &str,
+        role: Role,
+        title: Option<String>,
+        description: Option<String>,
+        now: Instant,
+    ) -> Qlog {
+        if random % 100 >= u16::from(self.sample_percent) {
+            return Qlog::disabled();
+        }
+        Qlog::enabled_with_sink(
+            (self.new_sink)(label),
+            self.max_size,
+            role,
+            title,
+            description,
+            now,
+        )
+        .unwrap_or_else(|e| {
+            log::error!("Failed to create qlog for {label}: {e}");
+            Qlog::disabled()
+        })
+    }
+}
+
+impl fmt::Debug for SinkConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SinkConfig")
+            .field("sample_percent", &self.sample_percent)
+            .field("max_size", &self.max_size)
+            .finish_non_exhaustive()
+    }
+}
+
 #[must_use]
 pub fn new_trace(role: Role) -> TraceSeq {
     TraceSeq {
@@ -196,9 +512,15 @@ pub fn new_trace(role: Role) -> TraceSeq {
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod test {
-    use test_fixture::EXPECTED_LOG_HEADER;
+    use std::{
+        sync::{Arc, Mutex, mpsc},
+        time::Duration,
+    };
+
+    use test_fixture::{EXPECTED_LOG_HEADER, now};
 
-    use super::Qlog;
+    use super::{Callback, Qlog, RingBuffer, Sink, SinkConfig};
+    use crate::Role;
 
     const EV_DATA: qlog::events::EventData =
         qlog::events::EventData::SpinBitUpdated(qlog::events::connectivity::SpinBitUpdated {
@@ -217,12 +539,9 @@ mod test {
         assert_eq!(contents.to_string(), EXPECTED_LOG_HEADER);
     }
 
-    #[test]
-    fn add_event_at() {
+    /// Replace the time of the first event with zero.
+    fn zero_time(mut output: String) -> String {
         const TIME_PREFIX: &str = "\"time\":";
-        let (mut log, contents) = test_fixture::new_neqo_qlog();
-        log.add_event_at(|| Some(EV_DATA), test_fixture::now());
-        let mut output = contents.to_string();
         if let Some(range) = output.find(TIME_PREFIX).and_then(|start| {
             let time_start = start + TIME_PREFIX.len();
             output[time_start..]
@@ -231,7 +550,38 @@ mod test {
         }) {
             output.replace_range(range, "0.0");
         }
-        assert_eq!(output, format!("{EXPECTED_LOG_HEADER}{EXPECTED_LOG_EVENT}"));
+        output
+    }
+
+    #[test]
+    fn add_event_at() {
+        let (mut log, contents) = test_fixture::new_neqo_qlog();
+        log.add_event_at(|| Some(EV_DATA), test_fixture::now());
+        assert_eq!(
+            zero_time(contents.to_string()),
+            format!("{EXPECTED_LOG_HEADER}{EXPECTED_LOG_EVENT}")
+        );
+    }
+
+    /// The values of the times of all events.
+    fn event_times(output: &str) -> Vec<&str> {
+        output
+            .split("\"time\":")
+            .skip(1)
+            .filter_map(|s| s.split(',').next())
+            .collect()
+    }
+
+    #[test]
+    fn add_event_at_latest() {
+        let (mut log, contents) = test_fixture::new_neqo_qlog();
+        log.add_event_at(|| Some(EV_DATA), now() + Duration::from_secs(1));
+        log.add_event_at_latest(|| Some(EV_DATA));
+        let output = contents.to_string();
+        let [first, second] = event_times(&output)[..] else {
+            panic!("expected two events");
+        };
+        assert_eq!(first, second);
     }
 
     #[test]
@@ -264,4 +614,98 @@ mod test {
         log.add_event_with_stream(|_| Err(qlog::Error::IoError(std::io::Error::other("e"))));
         assert!(!clone.is_enabled());
     }
+
+    fn sink_qlog(sink: Box<dyn Sink>, max_size: Option<usize>) -> Qlog {
+        Qlog::enabled_with_sink(sink, max_size, Role::Client, None, None, now()).unwrap()
+    }
+
+    fn is_record(r: &[u8]) -> bool {
+        r.starts_with(b"\x1e") && r.ends_with(b"\n")
+    }
+
+    #[test]
+    fn channel_sink() {
+        let (tx, rx) = mpsc::channel();
+        let mut log = sink_qlog(Box::new(tx), None);
+        assert!(format!("{log:?}").contains("Qlog writing to sink"));
+        log.add_event_at(|| Some(EV_DATA), now());
+        let records = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| is_record(r)));
+        assert!(records[0].starts_with(b"\x1e{\"qlog_version\""));
+        assert!(records[1].ends_with(b"\"data\":{\"state\":true}}\n"));
+    }
+
+    #[test]
+    fn callback_sink_max_size() {
+        const MAX_SIZE: usize = 1000;
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let r = Arc::clone(&records);
+        let sink = Callback(move |record: &[u8]| r.lock().unwrap().push(record.to_vec()));
+        let mut log = sink_qlog(Box::new(sink), Some(MAX_SIZE));
+        for _ in 0..20 {
+            log.add_event_at(|| Some(EV_DATA), now());
+        }
+        // Logging continues, but only the records that fit are passed on.
+        assert!(log.is_enabled());
+        let records = records.lock().unwrap();
+        assert!(records.iter().all(|r| is_record(r)));
+        assert!(records.len() > 1 && records.len() < 21);
+        assert!(records.iter().map(Vec::len).sum::<usize>() <= MAX_SIZE);
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let mut ring = RingBuffer::new(4);
+        for r in [b"h\n", b"a\n", b"b\n", b"c\n"] {
+            ring.record(r);
+        }
+        // The header is kept, along with the most recent records that fit.
+        assert_eq!(ring.contents(), b"h\nb\nc\n");
+        ring.record(b"large\n");
+        assert_eq!(ring.contents(), b"h\nb\nc\n");
+    }
+
+    #[test]
+    fn ring_buffer_sink() {
+        let ring = RingBuffer::new(200);
+        let mut log = sink_qlog(Box::new(ring.clone()), None);
+        for _ in 0..10 {
+            log.add_event_at(|| Some(EV_DATA), now());
+        }
+        let contents = ring.contents();
+        let records = contents
+            .split_inclusive(|&b| b == b'\n')
+            .collect::<Vec<_>>();
+        assert!(records.iter().all(|r| is_record(r)));
+        assert!(records[0].starts_with(b"\x1e{\"qlog_version\""));
+        assert!(records.len() > 1 && records.len() < 11);
+    }
+
+    #[test]
+    fn sink_config_sampling() {
+        let config = SinkConfig::new(|_: &str| -> Box<dyn Sink> { Box::new(RingBuffer::new(100)) })
+            .sample_percent(10)
+            .max_size(Some(1000));
+        assert_eq!(config.get_sample_percent(), 10);
+        assert_eq!(config.get_max_size(), Some(1000));
+        let create = |random| {
+            config
+                .create(random, "label", Role::Server, None, None, now())
+                .is_enabled()
+        };
+        assert!(create(0));
+        assert!(create(9));
+        assert!(!create(10));
+        assert!(create(109));
+        assert!(!create(u16::MAX));
+
+        let none = SinkConfig::new(|_: &str| -> Box<dyn Sink> { Box::new(RingBuffer::new(100)) })
+            .sample_percent(0);
+        assert!(
+            !none
+                .create(0, "label", Role::Server, None, None, now())
+                .is_enabled()
+        );
+    }
 }
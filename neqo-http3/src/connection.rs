@@ -1023,6 +1023,45 @@ impl Http3Connection {
         self.handle_stream_manipulation_output(res, stream_id, conn)
     }
 
+    /// The next contiguous chunk of stream data, borrowed from the QUIC stream so that
+    /// it can be used without copying. Use `consume_data` to mark it as read.
+    ///
+    /// # Errors
+    ///
+    /// It returns an error if a stream does not exist or an error happens while reading a stream,
+    /// e.g. early close, etc.
+    pub fn read_data_chunk<'a>(&self, conn: &'a Connection, stream_id: StreamId) -> Res<&'a [u8]> {
+        self.recv_streams
+            .get(&stream_id)
+            .ok_or(Error::InvalidStreamId)?
+            .read_data_chunk(conn)
+    }
+
+    /// Mark `amount` bytes from the start of the chunk returned by `read_data_chunk` as read.
+    /// Returns true if the stream is done.
+    ///
+    /// # Errors
+    ///
+    /// It returns an error if a stream does not exist, `amount` is larger than the chunk,
+    /// or an error happens while reading a stream, e.g. early close, protocol error, etc.
+    pub fn consume_data(
+        &mut self,
+        conn: &mut Connection,
+        stream_id: StreamId,
+        amount: usize,
+        now: Instant,
+    ) -> Res<bool> {
+        qdebug!("[{self}] consume_data {amount} from stream {stream_id}");
+        let res = self
+            .recv_streams
+            .get_mut(&stream_id)
+            .ok_or(Error::InvalidStreamId)?
+            .consume_data(conn, amount, now)
+            .map(|fin| ((), fin));
+        self.handle_stream_manipulation_output(res, stream_id, conn)
+            .map(|((), fin)| fin)
+    }
+
     /// This is called when an application resets a stream.
     /// The application reset will close both sides.
     pub fn stream_reset_send(
@@ -701,6 +701,40 @@ impl Http3Client {
         res
     }
 
+    /// The zero-copy counterpart to [`Self::read_data`]. Response data are returned as a
+    /// slice borrowed from the stream's receive buffer, so they can be passed on without
+    /// being copied first. The slice is empty if no data is available. Data stays buffered
+    /// until [`Self::consume_data`] is called.
+    ///
+    /// # Errors
+    ///
+    /// It returns an error if a stream does not exist or an error happen while reading a stream,
+    /// e.g. early close, etc.
+    pub fn read_data_chunk(&self, stream_id: StreamId) -> Res<&[u8]> {
+        self.base_handler.read_data_chunk(&self.conn, stream_id)
+    }
+
+    /// Mark the first `amount` bytes of the slice returned by [`Self::read_data_chunk`] as
+    /// read, which releases flow control credit for them. Returns true when the stream is
+    /// done, like the `bool` returned by [`Self::read_data`].
+    ///
+    /// # Errors
+    ///
+    /// It returns an error if a stream does not exist, `amount` is larger than the slice,
+    /// or an error happen while reading a stream, e.g. early close, protocol error, etc.
+    pub fn consume_data(&mut self, now: Instant, stream_id: StreamId, amount: usize) -> Res<bool> {
+        qdebug!("[{self}] consume_data {amount} from stream {stream_id}");
+        let res = self
+            .base_handler
+            .consume_data(&mut self.conn, stream_id, amount, now);
+        if let Err(e) = &res
+            && e.connection_error()
+        {
+            self.close(now, e.code(), "");
+        }
+        res
+    }
+
     // API: Push streams
 
     /// Cancel a push
@@ -975,9 +1009,11 @@ impl Http3Client {
                     self.base_handler.handle_datagram(dgram);
                 }
                 ConnectionEvent::SendStreamComplete { .. }
+                | ConnectionEvent::SendStreamExpired { .. }
                 | ConnectionEvent::OutgoingDatagramOutcome { .. }
                 | ConnectionEvent::SconeUpdated(_)
-                | ConnectionEvent::PathMigrated { .. } => {}
+                | ConnectionEvent::PathMigrated { .. }
+                | ConnectionEvent::PathFailover { .. } => {}
             }
         }
         Ok(())
@@ -2614,6 +2650,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fetch_basic_zero_copy() {
+        let (mut client, mut server, request_stream_id) = connect_and_send_request(true);
+
+        // The content will be send in 2 DATA frames.
+        server_send_response_and_exchange_packet(
+            &mut client,
+            &mut server,
+            request_stream_id,
+            HTTP_RESPONSE_1,
+            true,
+        );
+
+        let mut data = Vec::new();
+        let mut fin = false;
+        while !fin {
+            // Each chunk stops at the end of a DATA frame.
+            let chunk = client.read_data_chunk(request_stream_id).unwrap();
+            assert!(!chunk.is_empty());
+            data.extend_from_slice(chunk);
+            let amount = chunk.len();
+            fin = client
+                .consume_data(now(), request_stream_id, amount)
+                .unwrap();
+        }
+        assert_eq!(data, EXPECTED_RESPONSE_DATA_1);
+
+        // The stream is gone once all of it has been consumed.
+        assert_eq!(
+            client.read_data_chunk(request_stream_id).unwrap_err(),
+            Error::InvalidStreamId
+        );
+
+        client.close(now(), 0, "");
+    }
+
     // Helper function: read response when a server sends HTTP_RESPONSE_2.
     fn read_response(
         client: &mut Http3Client,
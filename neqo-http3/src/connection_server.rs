@@ -289,10 +289,12 @@ impl Http3ServerHandler {
                 | ConnectionEvent::ZeroRttRejected
                 | ConnectionEvent::ResumptionToken(..) => return Err(Error::HttpInternal(4)),
                 ConnectionEvent::SendStreamComplete { .. }
+                | ConnectionEvent::SendStreamExpired { .. }
                 | ConnectionEvent::SendStreamCreatable { .. }
                 | ConnectionEvent::OutgoingDatagramOutcome { .. }
                 | ConnectionEvent::SconeUpdated(_)
-                | ConnectionEvent::PathMigrated { .. } => {}
+                | ConnectionEvent::PathMigrated { .. }
+                | ConnectionEvent::PathFailover { .. } => {}
             }
         }
         Ok(())
@@ -422,4 +424,42 @@ impl Http3ServerHandler {
         }
         res
     }
+
+    /// The zero-copy counterpart to [`Self::read_data`]. Request data are returned as a
+    /// slice borrowed from the stream's receive buffer. The slice is empty if no data is
+    /// available. Data stays buffered until [`Self::consume_data`] is called.
+    ///
+    /// # Errors
+    ///
+    /// It returns an error if a stream does not exist or an error happen while reading a stream,
+    /// e.g. early close, etc.
+    pub fn read_data_chunk<'a>(&self, conn: &'a Connection, stream_id: StreamId) -> Res<&'a [u8]> {
+        self.base_handler.read_data_chunk(conn, stream_id)
+    }
+
+    /// Mark the first `amount` bytes of the slice returned by [`Self::read_data_chunk`] as
+    /// read. Returns true when the stream is done, like the `bool` returned by
+    /// [`Self::read_data`].
+    ///
+    /// # Errors
+    ///
+    /// It returns an error if a stream does not exist, `amount` is larger than the slice,
+    /// or an error happen while reading a stream, e.g. early close, protocol error, etc.
+    pub fn consume_data(
+        &mut self,
+        conn: &mut Connection,
+        now: Instant,
+        stream_id: StreamId,
+        amount: usize,
+    ) -> Res<bool> {
+        qdebug!("[{self}] consume_data {amount} from stream {stream_id}");
+        let res = self.base_handler.consume_data(conn, stream_id, amount, now);
+        if let Err(e) = &res
+            && e.connection_error()
+        {
+            self.close(conn, now, e);
+        }
+        self.needs_processing = true;
+        res
+    }
 }
@@ -9,7 +9,7 @@ use neqo_transport::{ConnectionParameters, StreamType};
 use test_fixture::now;
 
 use crate::{
-    Error, Http3Parameters,
+    Error, Http3Parameters, Http3ServerEvent,
     features::extended_connect::{
         CloseReason,
         tests::webtransport::{DATAGRAM_SIZE, WtTest, wt_default_parameters},
@@ -70,6 +70,32 @@ fn wt_client_stream_bidi() {
     assert_eq!(recv_stats.bytes_read(), to_u64(BUF_SERVER.len()));
 }
 
+#[test]
+fn wt_client_stream_zero_copy_server() {
+    const BUF_CLIENT: &[u8] = &[3; 10];
+
+    let mut wt = WtTest::new();
+    wt.server.set_zero_copy_receive(true);
+    let wt_session = wt.create_wt_session();
+    let wt_stream = wt.create_wt_stream_client(wt_session.stream_id(), StreamType::UniDi);
+    wt.send_data_client(wt_stream, BUF_CLIENT);
+
+    let mut readable = None;
+    while let Some(event) = wt.server.next_event() {
+        assert!(!matches!(event, Http3ServerEvent::Data { .. }));
+        if let Http3ServerEvent::DataReadable { stream } = event {
+            readable = Some(stream);
+        }
+    }
+    let wt_server_stream = readable.unwrap();
+    assert_eq!(wt_server_stream.stream_id(), wt_stream);
+
+    let chunk = wt_server_stream.read_data_chunk(<[u8]>::to_vec).unwrap();
+    assert_eq!(chunk, BUF_CLIENT);
+    assert!(!wt_server_stream.consume_data(chunk.len(), now()).unwrap());
+    assert!(wt_server_stream.read_data_chunk(<[u8]>::is_empty).unwrap());
+}
+
 #[test]
 fn wt_server_stream_uni() {
     const BUF_SERVER: &[u8] = &[2; 30];
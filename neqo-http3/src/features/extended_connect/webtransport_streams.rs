@@ -84,6 +84,19 @@ impl RecvStream for WebTransportRecvStream {
         Ok((amount, fin))
     }
 
+    fn read_data_chunk<'a>(&self, conn: &'a Connection) -> Res<&'a [u8]> {
+        Ok(conn.stream_recv_chunk(self.stream_id)?)
+    }
+
+    fn consume_data(&mut self, conn: &mut Connection, amount: usize, _now: Instant) -> Res<bool> {
+        let fin = conn.stream_recv_consume(self.stream_id, amount)?;
+        self.fin = fin;
+        if fin {
+            self.session.borrow_mut().remove_recv_stream(self.stream_id);
+        }
+        Ok(fin)
+    }
+
     fn stats(&mut self, conn: &mut Connection) -> Res<recv_stream::Stats> {
         const TYPE_LEN_UNI: usize = Encoder::varint_len(WEBTRANSPORT_UNI_STREAM);
         const TYPE_LEN_BIDI: usize = Encoder::varint_len(WEBTRANSPORT_STREAM);
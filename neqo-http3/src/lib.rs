@@ -472,6 +472,27 @@ trait RecvStream: Stream {
         Err(Error::InvalidStreamId)
     }
 
+    /// The zero-copy counterpart to `read_data`: returns the next contiguous chunk of
+    /// data that the app can read, which is borrowed from the quic stream.
+    ///
+    /// # Errors
+    ///
+    /// An error may happen while reading a stream, e.g. early close, etc.
+    fn read_data_chunk<'a>(&self, _conn: &'a Connection) -> Res<&'a [u8]> {
+        Err(Error::InvalidStreamId)
+    }
+
+    /// Mark `amount` bytes from the start of the chunk returned by `read_data_chunk` as
+    /// read. The function returns true if the stream is completely done and can be
+    /// forgotten, i.e. removed from all records.
+    ///
+    /// # Errors
+    ///
+    /// An error may happen while reading a stream, e.g. early close, protocol error, etc.
+    fn consume_data(&mut self, _conn: &mut Connection, _amount: usize, _now: Instant) -> Res<bool> {
+        Err(Error::InvalidStreamId)
+    }
+
     fn http_stream(&mut self) -> Option<&mut dyn HttpRecvStream> {
         None
     }
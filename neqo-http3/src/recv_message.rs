@@ -454,6 +454,54 @@ impl RecvStream for RecvMessage {
         }
     }
 
+    fn read_data_chunk<'a>(&self, conn: &'a Connection) -> Res<&'a [u8]> {
+        if let RecvMessageState::ReadingData { remaining_data_len } = self.state {
+            let chunk = conn.stream_recv_chunk(self.stream_id)?;
+            Ok(&chunk[..min(remaining_data_len, chunk.len())])
+        } else {
+            Ok(&[])
+        }
+    }
+
+    fn consume_data(&mut self, conn: &mut Connection, amount: usize, now: Instant) -> Res<bool> {
+        match self.state {
+            RecvMessageState::ReadingData {
+                ref mut remaining_data_len,
+            } => {
+                if amount > *remaining_data_len {
+                    return Err(Error::InvalidInput);
+                }
+                let fin = conn.stream_recv_consume(self.stream_id, amount)?;
+                qlog::h3_data_moved_up(conn.qlog_mut(), self.stream_id, amount, now);
+                *remaining_data_len -= amount;
+
+                if fin {
+                    if *remaining_data_len > 0 {
+                        return Err(Error::HttpFrame);
+                    }
+                    self.set_closed();
+                    return Ok(true);
+                } else if *remaining_data_len == 0 {
+                    self.state = RecvMessageState::WaitingForData {
+                        frame_reader: FrameReader::new(),
+                    };
+                    self.receive_internal(conn, false, now)?;
+                    if matches!(self.state, RecvMessageState::ClosePending) {
+                        self.set_closed();
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            RecvMessageState::ClosePending => {
+                self.set_closed();
+                Ok(true)
+            }
+            _ if amount == 0 => Ok(false),
+            _ => Err(Error::InvalidInput),
+        }
+    }
+
     fn http_stream(&mut self) -> Option<&mut dyn HttpRecvStream> {
         Some(self)
     }
@@ -13,7 +13,7 @@ use std::{
     time::Instant,
 };
 
-use neqo_common::{Datagram, qtrace};
+use neqo_common::{Datagram, qlog::SinkConfig, qtrace};
 use neqo_transport::{
     ConnectionIdGenerator, Output, OutputBatch,
     server::{ConnectionRef, Server, ValidateAddress},
@@ -41,6 +41,7 @@ pub struct Http3Server {
     http3_parameters: Http3Parameters,
     http3_handlers: HashMap<ConnectionRef, HandlerRef>,
     events: Http3ServerEvents,
+    zero_copy_receive: bool,
 }
 
 impl Display for Http3Server {
@@ -77,6 +78,7 @@ impl Http3Server {
             http3_parameters,
             http3_handlers: HashMap::default(),
             events: Http3ServerEvents::default(),
+            zero_copy_receive: false,
         })
     }
 
@@ -84,6 +86,10 @@ impl Http3Server {
         self.server.set_qlog_dir(dir);
     }
 
+    pub fn set_qlog_sinks(&mut self, sinks: Option<SinkConfig>) {
+        self.server.set_qlog_sinks(sinks);
+    }
+
     pub fn set_validation(&self, v: ValidateAddress) {
         self.server.set_validation(v);
     }
@@ -92,6 +98,14 @@ impl Http3Server {
         self.server.set_ciphers(ciphers);
     }
 
+    /// When enabled, readable request and WebTransport stream data is announced with
+    /// [`Http3ServerEvent::DataReadable`] instead of being copied into
+    /// [`Http3ServerEvent::Data`] events. The application then reads it in place with the
+    /// stream's `read_data_chunk` and `consume_data`.
+    pub const fn set_zero_copy_receive(&mut self, enable: bool) {
+        self.zero_copy_receive = enable;
+    }
+
     /// Enable encrypted client hello (ECH).
     ///
     /// # Errors
@@ -209,6 +223,12 @@ impl Http3Server {
                         headers,
                         fin,
                     ),
+                    Http3ServerConnEvent::DataReadable { stream_info }
+                        if self.zero_copy_receive =>
+                    {
+                        self.events
+                            .data_readable(conn.clone(), Rc::clone(handler), stream_info);
+                    }
                     Http3ServerConnEvent::DataReadable { stream_info } => {
                         prepare_data(
                             stream_info,
@@ -1037,7 +1057,8 @@ mod tests {
                     stream.send_data(RESPONSE_BODY, now()).unwrap();
                     data_received += 1;
                 }
-                Http3ServerEvent::DataWritable { .. }
+                Http3ServerEvent::DataReadable { .. }
+                | Http3ServerEvent::DataWritable { .. }
                 | Http3ServerEvent::StreamReset { .. }
                 | Http3ServerEvent::StreamStopSending { .. }
                 | Http3ServerEvent::StateChange { .. }
@@ -1050,6 +1071,48 @@ mod tests {
         assert_eq!(data_received, 1);
     }
 
+    #[test]
+    fn server_request_with_body_zero_copy() {
+        let (mut hconn, mut peer_conn) = connect();
+        hconn.set_zero_copy_receive(true);
+
+        let stream_id = peer_conn.stream_create(StreamType::BiDi).unwrap();
+        peer_conn.stream_send(stream_id, REQUEST_WITH_BODY).unwrap();
+        peer_conn.stream_close_send(stream_id).unwrap();
+
+        let out = peer_conn.process_output(now());
+        hconn.process(out.dgram(), now());
+
+        let mut readable = None;
+        while let Some(event) = hconn.next_event() {
+            match event {
+                Http3ServerEvent::Headers { headers, fin, .. } => {
+                    check_request_header(&headers);
+                    assert!(!fin);
+                }
+                Http3ServerEvent::DataReadable { stream } => readable = Some(stream),
+                Http3ServerEvent::Data { .. } => panic!("unexpected Data event"),
+                _ => {}
+            }
+        }
+        let stream = readable.unwrap();
+        assert_eq!(stream.stream_id(), stream_id);
+
+        let body = stream.read_data_chunk(<[u8]>::to_vec).unwrap();
+        assert_eq!(body, REQUEST_BODY);
+        assert_eq!(
+            stream.consume_data(body.len() + 1, now()).unwrap_err(),
+            Error::InvalidInput
+        );
+        assert!(stream.consume_data(body.len(), now()).unwrap());
+
+        // The stream is gone once all of it has been consumed.
+        assert_eq!(
+            stream.read_data_chunk(<[u8]>::is_empty).unwrap_err(),
+            Error::InvalidStreamId
+        );
+    }
+
     #[test]
     fn server_request_with_body_send_stop_sending() {
         let (mut hconn, mut peer_conn) = connect();
@@ -1087,7 +1150,8 @@ mod tests {
                 Http3ServerEvent::Data { .. } => {
                     panic!("We should not have a Data event");
                 }
-                Http3ServerEvent::DataWritable { .. }
+                Http3ServerEvent::DataReadable { .. }
+                | Http3ServerEvent::DataWritable { .. }
                 | Http3ServerEvent::StreamReset { .. }
                 | Http3ServerEvent::StreamStopSending { .. }
                 | Http3ServerEvent::StateChange { .. }
@@ -1115,7 +1179,8 @@ mod tests {
                 Http3ServerEvent::Data { .. } => {
                     panic!("We should not have a Data event");
                 }
-                Http3ServerEvent::DataWritable { .. }
+                Http3ServerEvent::DataReadable { .. }
+                | Http3ServerEvent::DataWritable { .. }
                 | Http3ServerEvent::StreamReset { .. }
                 | Http3ServerEvent::StreamStopSending { .. }
                 | Http3ServerEvent::StateChange { .. }
@@ -1160,7 +1225,8 @@ mod tests {
                 Http3ServerEvent::Data { .. } => {
                     panic!("We should not have a Data event");
                 }
-                Http3ServerEvent::DataWritable { .. }
+                Http3ServerEvent::DataReadable { .. }
+                | Http3ServerEvent::DataWritable { .. }
                 | Http3ServerEvent::StreamReset { .. }
                 | Http3ServerEvent::StreamStopSending { .. }
                 | Http3ServerEvent::StateChange { .. }
@@ -1378,7 +1444,8 @@ mod tests {
                 Http3ServerEvent::Data { stream, .. } => {
                     assert!(requests.contains_key(&stream.stream_id()));
                 }
-                Http3ServerEvent::DataWritable { .. }
+                Http3ServerEvent::DataReadable { .. }
+                | Http3ServerEvent::DataWritable { .. }
                 | Http3ServerEvent::StreamReset { .. }
                 | Http3ServerEvent::StreamStopSending { .. }
                 | Http3ServerEvent::StateChange { .. }
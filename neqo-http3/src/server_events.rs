@@ -76,6 +76,38 @@ impl StreamHandler {
             .send_data(self.stream_id(), buf, &mut self.conn.borrow_mut(), now)
     }
 
+    /// Call `f` with the next chunk of received data, borrowed from the stream's receive
+    /// buffer. The chunk is empty if no data is available. Data stays buffered until
+    /// [`Self::consume_data`] is called.
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if a stream does not exist anymore.
+    pub fn read_data_chunk<R>(&self, f: impl FnOnce(&[u8]) -> R) -> Res<R> {
+        let conn = self.conn.borrow();
+        let chunk = self
+            .handler
+            .borrow()
+            .read_data_chunk(&conn, self.stream_id())?;
+        Ok(f(chunk))
+    }
+
+    /// Mark the first `amount` bytes of the chunk passed to [`Self::read_data_chunk`] as
+    /// read. Returns true when the stream is done.
+    ///
+    /// # Errors
+    ///
+    /// It may return `InvalidStreamId` if a stream does not exist anymore, or `InvalidInput`
+    /// if `amount` is larger than the chunk.
+    pub fn consume_data(&self, amount: usize, now: Instant) -> Res<bool> {
+        self.handler.borrow_mut().consume_data(
+            &mut self.conn.borrow_mut(),
+            now,
+            self.stream_id(),
+            amount,
+        )
+    }
+
     /// Bytes sendable on stream at the QUIC layer.
     ///
     /// Note that this does not yet account for HTTP3 frame headers.
@@ -258,6 +290,11 @@ pub enum Http3ServerEvent {
         data: Vec<u8>,
         fin: bool,
     },
+    /// Request data can be read with `read_data_chunk`. This replaces `Data` when
+    /// [`crate::Http3Server::set_zero_copy_receive`] is enabled.
+    DataReadable {
+        stream: Http3OrWebTransportStream,
+    },
     DataWritable {
         stream: Http3OrWebTransportStream,
     },
@@ -342,6 +379,18 @@ impl Http3ServerEvents {
         });
     }
 
+    /// Insert a `DataReadable` event.
+    pub(crate) fn data_readable(
+        &self,
+        conn: ConnectionRef,
+        handler: Rc<RefCell<Http3ServerHandler>>,
+        stream_info: Http3StreamInfo,
+    ) {
+        self.insert(Http3ServerEvent::DataReadable {
+            stream: Http3OrWebTransportStream::new(conn, handler, stream_info),
+        });
+    }
+
     pub(crate) fn data_writable(
         &self,
         conn: ConnectionRef,
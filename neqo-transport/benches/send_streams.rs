@@ -12,7 +12,7 @@
 use std::{cell::RefCell, hint::black_box, rc::Rc};
 
 use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
-use neqo_common::{Encoder, to_u64};
+use neqo_common::{Bytes, Encoder, to_u64};
 use neqo_transport::{
     ConnectionEvents, FrameStats, SenderFlowControl,
     packet::{self, Builder},
@@ -26,6 +26,8 @@ const MAX_STREAM_DATA: u64 = 1 << 20; // 1 MiB credit
 // Must be small enough that a STREAM frame fits inside `packet::LIMIT` with room to spare,
 // so the packet builder doesn't fill before idle streams are visited.
 const DATA: &[u8] = &[0x5a; 200];
+/// The size of a large application write, such as one chunk of a file transfer.
+const LARGE_WRITE: usize = 1 << 16; // 64 KiB
 
 fn make_streams(n_streams: usize) -> SendStreams {
     make_streams_inner(n_streams, false, false)
@@ -210,6 +212,45 @@ fn write_frames_3_groups_9_sendordered(c: &mut Criterion) {
     });
 }
 
+fn make_stream() -> SendStream {
+    let conn_fc = Rc::new(RefCell::new(SenderFlowControl::new((), u64::MAX)));
+    SendStream::new(
+        StreamId::from(0),
+        MAX_STREAM_DATA,
+        conn_fc,
+        ConnectionEvents::default(),
+    )
+}
+
+/// `SendStream::send` of a large write, which copies the data into the send buffer.
+fn send_large_copy(c: &mut Criterion) {
+    let data = vec![0x5a; LARGE_WRITE];
+    c.bench_function("SendStream::send 64KiB", |b| {
+        b.iter_batched_ref(
+            make_stream,
+            |s| black_box(s.send(&data).expect("send failed")),
+            BatchSize::SmallInput,
+        );
+    });
+}
+
+/// `SendStream::send_bytes` of a large write, which keeps the buffer without copying.
+/// Compare with `send_large_copy`.
+fn send_large_bytes(c: &mut Criterion) {
+    let data = vec![0x5a; LARGE_WRITE];
+    c.bench_function("SendStream::send_bytes 64KiB", |b| {
+        b.iter_batched(
+            || (make_stream(), Bytes::from(data.clone())),
+            |(mut s, data)| {
+                let unsent = s.send_bytes(data).expect("send failed");
+                // Return the stream so that it is dropped outside of the measurement.
+                black_box((s, unsent))
+            },
+            BatchSize::SmallInput,
+        );
+    });
+}
+
 criterion_group! {
     name = benches;
     config = { neqo_common::log::init(None); Criterion::default() };
@@ -220,6 +261,8 @@ criterion_group! {
         write_frames_20_fair_all_active,
         write_frames_3_groups_9_streams,
         write_frames_5_sendordered,
-        write_frames_3_groups_9_sendordered
+        write_frames_3_groups_9_sendordered,
+        send_large_copy,
+        send_large_bytes
 }
 criterion_main!(benches);
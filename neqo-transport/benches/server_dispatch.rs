@@ -0,0 +1,100 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![expect(
+    clippy::significant_drop_tightening,
+    reason = "Inherent in codspeed criterion_group! macro."
+)]
+
+use std::{cell::RefCell, hint::black_box, iter, rc::Rc};
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use neqo_common::Datagram;
+use neqo_transport::{Connection, ConnectionParameters, State, StreamType, server::Server};
+use nss::AllowZeroRtt;
+use test_fixture::{
+    CountingConnectionIdGenerator, DEFAULT_ALPN, DEFAULT_KEYS, anti_replay, default_client,
+    maybe_authenticate, now,
+};
+
+/// The number of connections that the server has.  The cost should be the same for all.
+const CONNECTIONS: &[usize] = &[1, 100, 1_000, 10_000];
+
+fn new_server() -> Server {
+    Server::new(
+        now(),
+        DEFAULT_KEYS,
+        DEFAULT_ALPN,
+        anti_replay(),
+        Box::new(AllowZeroRtt {}),
+        Rc::new(RefCell::new(CountingConnectionIdGenerator::default())),
+        ConnectionParameters::default(),
+    )
+    .expect("create server")
+}
+
+/// Complete a handshake with `server` and return the client.
+fn connect(server: &mut Server) -> Connection {
+    let mut client = default_client();
+    let mut dgram = None;
+    while *client.state() != State::Confirmed {
+        _ = maybe_authenticate(&mut client);
+        let out = client.process(dgram, now()).dgram();
+        dgram = server.process(out, now()).dgram();
+    }
+    client
+}
+
+/// A short header packet carrying stream data from the last of `n` connections.
+fn setup(n: usize) -> (Server, Datagram) {
+    let mut server = new_server();
+    let mut client = (0..n)
+        .map(|_| connect(&mut server))
+        .last()
+        .expect("at least one connection");
+    let stream = client
+        .stream_create(StreamType::UniDi)
+        .expect("create stream");
+    client.stream_send(stream, &[0; 100]).expect("send");
+    let dgram = client.process_output(now()).dgram().expect("datagram");
+    (server, dgram)
+}
+
+/// `Server::process_multiple_input` for a packet on an established connection,
+/// as the number of connections grows.
+fn server_dispatch(c: &mut Criterion) {
+    for &n in CONNECTIONS {
+        let (mut server, dgram) = setup(n);
+        c.bench_function(
+            &format!("Server::process_multiple_input {n} connections"),
+            |b| {
+                b.iter_batched(
+                    || dgram.clone(),
+                    |d| black_box(server.process_multiple_input(iter::once(d), now())),
+                    BatchSize::SmallInput,
+                );
+            },
+        );
+    }
+}
+
+/// `Server::process_output` when no connection has anything to do,
+/// as the number of connections grows.
+fn server_output_idle(c: &mut Criterion) {
+    for &n in CONNECTIONS {
+        let (mut server, _) = setup(n);
+        while server.process_output(now()).dgram().is_some() {}
+        c.bench_function(
+            &format!("Server::process_output {n} idle connections"),
+            |b| {
+                b.iter(|| black_box(server.process_output(now())));
+            },
+        );
+    }
+}
+
+criterion_group!(benches, server_dispatch, server_output_idle);
+criterion_main!(benches);
@@ -25,7 +25,7 @@ const FIXED_SEED: &str = "62df6933ba1f543cece01db8f27fb2025529b27f93df39e19f006e
 
 /// Creates a ready simulator for benchmarking transfer.
 #[must_use]
-fn setup(label: &str, seed: Option<&str>, pacing: bool) -> ReadySimulator {
+fn setup(label: &str, seed: Option<&str>, pacing: bool, zero_copy: bool) -> ReadySimulator {
     let nodes = boxed![
         Node::new_client(
             ConnectionParameters::default()
@@ -33,7 +33,7 @@ fn setup(label: &str, seed: Option<&str>, pacing: bool) -> ReadySimulator {
                 .pacing(pacing)
                 .mlkem(false),
             boxed![ReachState::new(State::Confirmed)],
-            boxed![SendData::new(TRANSFER_AMOUNT)]
+            boxed![SendData::new(TRANSFER_AMOUNT).zero_copy(zero_copy)]
         ),
         TailDrop::dsl_uplink(),
         Delay::new(DELAY),
@@ -68,14 +68,18 @@ pub fn bench(c: &mut Criterion, name_prefix: &str) {
     group.noise_threshold(0.03);
     group.throughput(Throughput::Bytes(to_u64(TRANSFER_AMOUNT)));
     for (label, seed) in configs {
-        for pacing in [false, true] {
-            group.bench_function(format!("{name_prefix}/pacing-{pacing}/{label}"), |b| {
-                b.iter_batched(
-                    || setup(label, seed, pacing),
-                    |sim| black_box(sim.run()),
-                    SmallInput,
-                );
-            });
+        for (pacing, zero_copy) in [(false, false), (true, false), (true, true)] {
+            let suffix = if zero_copy { "/zero-copy" } else { "" };
+            group.bench_function(
+                format!("{name_prefix}/pacing-{pacing}/{label}{suffix}"),
+                |b| {
+                    b.iter_batched(
+                        || setup(label, seed, pacing, zero_copy),
+                        |sim| black_box(sim.run()),
+                        SmallInput,
+                    );
+                },
+            );
         }
     }
     group.finish();
@@ -14,7 +14,7 @@ use crate::{
     connection::params::ConnectionParameters,
     frame::FrameType,
     packet,
-    recovery::{self},
+    recovery::{self, PACKET_THRESHOLD},
     stats::FrameStats,
     tracking::DEFAULT_REMOTE_ACK_DELAY,
 };
@@ -51,7 +51,9 @@ impl AckRate {
             seqno,
             to_u64(self.packets + 1),
             u64::try_from(self.delay.as_micros()).unwrap_or(u64::MAX),
-            0,
+            // Only ask for an immediate ACK when reordering might cause
+            // a packet to be declared lost.
+            PACKET_THRESHOLD,
         ])
     }
 
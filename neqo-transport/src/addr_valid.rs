@@ -7,20 +7,22 @@
 // This file implements functions necessary for address validation.
 
 use std::{
+    fmt::{self, Debug, Formatter},
+    mem,
     net::{IpAddr, SocketAddr},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
 use neqo_common::{Buffer, Decoder, Encoder, Role, expect_usize, qinfo, qtrace};
 use nss::{
-    constants::{TLS_AES_128_GCM_SHA256, TLS_VERSION_1_3},
-    selfencrypt::SelfEncrypt,
+    Mode, RecordProtection as Aead, RecordProtectionOps as _, TLS_AES_128_GCM_SHA256,
+    TLS_VERSION_1_3, hkdf, random,
 };
 use smallvec::SmallVec;
 use static_assertions::const_assert;
 
 use crate::{
-    Res,
+    Error, Res,
     cid::ConnectionId,
     frame::{FrameEncoder as _, FrameType},
     packet, recovery,
@@ -36,6 +38,11 @@ const TOKEN_IDENTIFIER_NEW_TOKEN: &[u8] = &[0xad, 0x9a, 0x8b, 0x8d, 0x86];
 
 const_assert!(TOKEN_IDENTIFIER_RETRY.len() == TOKEN_IDENTIFIER_NEW_TOKEN.len());
 
+/// How long a Retry token is valid for.
+const EXPIRATION_RETRY: Duration = Duration::from_secs(5);
+/// How long a `NEW_TOKEN` token is valid for.
+const EXPIRATION_NEW_TOKEN: Duration = Duration::from_secs(60 * 60 * 24);
+
 /// The maximum number of tokens we'll save from `NEW_TOKEN` frames.
 /// This should be the same as the value of `MAX_TICKETS` in `nss`.
 const MAX_NEW_TOKEN: usize = 4;
@@ -46,7 +53,7 @@ const MAX_SAVED_TOKENS: usize = 8;
 
 /// `ValidateAddress` determines what sort of address validation is performed.
 /// In short, this determines when a Retry packet is sent.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ValidateAddress {
     /// Require address validation never.
     Never,
@@ -63,21 +70,116 @@ pub enum AddressValidationResult {
     Invalid,
 }
 
+/// A key that protects address validation tokens.
+///
+/// Each token is protected with an AEAD key that is derived from this key
+/// and a random salt, which is carried in the token along with the key identifier.
+#[derive(Clone)]
+struct TokenKey {
+    id: u8,
+    key: [u8; Self::LEN],
+}
+
+impl TokenKey {
+    const LEN: usize = 32;
+    const SALT_LEN: usize = 16;
+    /// The length of the key identifier and salt at the start of a protected token.
+    const HEADER_LEN: usize = 1 + Self::SALT_LEN;
+    const LABEL_PREFIX: &'static str = "neqo token ";
+
+    fn random(id: u8) -> Self {
+        Self {
+            id,
+            key: random::<{ Self::LEN }>(),
+        }
+    }
+
+    fn aead(&self, salt: &[u8], mode: Mode) -> Res<Aead> {
+        let prk = hkdf::extract(
+            TLS_VERSION_1_3,
+            TLS_AES_128_GCM_SHA256,
+            Some(&hkdf::import_key(TLS_VERSION_1_3, salt)?),
+            &hkdf::import_key(TLS_VERSION_1_3, &self.key)?,
+        )?;
+        Ok(Aead::new(
+            TLS_VERSION_1_3,
+            TLS_AES_128_GCM_SHA256,
+            &prk,
+            Self::LABEL_PREFIX,
+            mode,
+        )?)
+    }
+
+    /// Protect `plaintext`.  The key identifier and salt are authenticated along with `aad`.
+    fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Res<Vec<u8>> {
+        let salt = random::<{ Self::SALT_LEN }>();
+        let aead = self.aead(&salt, Mode::Encrypt)?;
+        let mut sealed =
+            Encoder::with_capacity(Self::HEADER_LEN + plaintext.len() + aead.expansion());
+        sealed.encode_byte(self.id);
+        sealed.encode(&salt);
+        let aad = [aad, sealed.as_ref()].concat();
+        let mut buf = vec![0; plaintext.len() + aead.expansion()];
+        sealed.encode(aead.encrypt(0, &aad, plaintext, &mut buf)?);
+        Ok(sealed.into())
+    }
+
+    /// Remove protection from `sealed`, which starts with the identifier of this key.
+    fn open(&self, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        let (header, ciphertext) = sealed.split_at_checked(Self::HEADER_LEN)?;
+        let aead = self.aead(&header[1..], Mode::Decrypt).ok()?;
+        let aad = [aad, header].concat();
+        let mut buf = vec![0; ciphertext.len()];
+        aead.decrypt(0, &aad, ciphertext, &mut buf)
+            .ok()
+            .map(<[u8]>::to_vec)
+    }
+}
+
+/// Don't leak key material into logs.
+impl Debug for TokenKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "TokenKey {}", self.id)
+    }
+}
+
 pub struct AddressValidation {
     /// What sort of validation is performed.
     validation: ValidateAddress,
-    /// A self-encryption object used for protecting Retry tokens.
-    self_encrypt: SelfEncrypt,
+    /// The key used to protect new tokens.
+    key: TokenKey,
+    /// The key that was replaced by `key`.  Tokens protected with this are still accepted.
+    previous_key: Option<TokenKey>,
+    /// How often keys are rotated, if at all.
+    key_rotation: Option<Duration>,
+    /// When `key` was installed.
+    key_installed: Instant,
     /// When this object was created.
     start_time: Instant,
+    /// The time since the UNIX epoch when this object was created.
+    /// Token expiry is expressed in wall clock time, so that tokens can be
+    /// checked by other servers that share keys.
+    start_since_epoch: Duration,
 }
 
 impl AddressValidation {
+    /// By default, keys are rotated once every `NEW_TOKEN` token lifetime.
+    /// This ensures that tokens remain valid until they expire.
+    pub const DEFAULT_KEY_ROTATION: Duration = EXPIRATION_NEW_TOKEN;
+
+    /// # Errors
+    /// When the system clock is set to a time before the UNIX epoch.
     pub fn new(now: Instant, validation: ValidateAddress) -> Res<Self> {
         Ok(Self {
             validation,
-            self_encrypt: SelfEncrypt::new(TLS_VERSION_1_3, TLS_AES_128_GCM_SHA256)?,
+            key: TokenKey::random(0),
+            previous_key: None,
+            key_rotation: Some(Self::DEFAULT_KEY_ROTATION),
+            key_installed: now,
             start_time: now,
+            start_since_epoch: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_err(|_| Error::Internal)?,
         })
     }
 
@@ -113,10 +215,6 @@ impl AddressValidation {
         peer_address: SocketAddr,
         now: Instant,
     ) -> Res<Vec<u8>> {
-        const EXPIRATION_RETRY: Duration = Duration::from_secs(5);
-        const EXPIRATION_NEW_TOKEN: Duration = Duration::from_secs(60 * 60 * 24);
-
-        // TODO(mt) rotate keys on a fixed schedule.
         let retry = dcid.is_some();
         let mut data = Encoder::default();
         let end = now
@@ -125,15 +223,14 @@ impl AddressValidation {
             } else {
                 EXPIRATION_NEW_TOKEN
             };
-        let end_millis = u32::try_from(end.duration_since(self.start_time).as_millis())?;
-        data.encode_uint(4, end_millis);
+        data.encode_uint(8, self.unix_millis(end));
         if let Some(dcid) = dcid {
             data.encode(dcid);
         }
 
         // Include the token identifier ("Retry"/~) in the AAD, then keep it for plaintext.
         let mut buf = Self::encode_aad(peer_address, retry);
-        let encrypted = self.self_encrypt.seal(buf.as_ref(), data.as_ref())?;
+        let encrypted = self.key.seal(buf.as_ref(), data.as_ref())?;
         #[cfg(feature = "build-fuzzing-corpus")]
         let mut corpus_data = buf.as_ref()[TOKEN_IDENTIFIER_RETRY.len()..].to_vec();
         buf.truncate(TOKEN_IDENTIFIER_RETRY.len());
@@ -171,6 +268,109 @@ impl AddressValidation {
         self.validation = validation;
     }
 
+    #[must_use]
+    pub const fn validation(&self) -> ValidateAddress {
+        self.validation
+    }
+
+    /// The time `t`, as milliseconds since the UNIX epoch.
+    fn unix_millis(&self, t: Instant) -> u64 {
+        let since_epoch = self.start_since_epoch + t.saturating_duration_since(self.start_time);
+        u64::try_from(since_epoch.as_millis()).unwrap_or(u64::MAX)
+    }
+
+    /// The current key, then the previous key, if any.
+    fn keys(&self) -> impl Iterator<Item = &TokenKey> {
+        [Some(&self.key), self.previous_key.as_ref()]
+            .into_iter()
+            .flatten()
+    }
+
+    /// Set how often the keys that protect tokens are rotated, or `None` to
+    /// only rotate keys when [`Self::rotate_keys`] is called.
+    ///
+    /// Tokens protected with the previous key are still accepted, so tokens
+    /// remain usable for between one and two intervals.  An interval shorter than
+    /// the lifetime of `NEW_TOKEN` tokens, which is one day, means that some clients
+    /// will need to validate their address with a Retry.
+    pub fn set_key_rotation(&mut self, interval: Option<Duration>) {
+        qtrace!("AddressValidation {self:p}: key rotation {interval:?}");
+        self.key_rotation = interval;
+    }
+
+    /// Replace the key that protects new tokens with a new random key.
+    /// Tokens protected with the current key are still accepted,
+    /// but those protected with the previous key are not.
+    pub fn rotate_keys(&mut self, now: Instant) {
+        let next = TokenKey::random(self.key.id.wrapping_add(1));
+        qinfo!("AddressValidation {self:p}: rotate to {next:?}");
+        self.previous_key = Some(mem::replace(&mut self.key, next));
+        self.key_installed = now;
+    }
+
+    /// Rotate keys if the rotation interval has passed since the current key was installed.
+    pub fn maybe_rotate_keys(&mut self, now: Instant) {
+        if let Some(interval) = self.key_rotation
+            && now.saturating_duration_since(self.key_installed) >= interval
+        {
+            self.rotate_keys(now);
+        }
+    }
+
+    /// Export the keys that protect tokens, so that they can be passed to
+    /// [`Self::import_keys`] on other servers.  Those servers can then
+    /// accept tokens that this server creates and vice versa.
+    ///
+    /// The output contains secret key material, so it needs to be protected.
+    #[must_use]
+    pub fn export_keys(&self) -> Vec<u8> {
+        let mut enc = Encoder::default();
+        for key in self.keys() {
+            enc.encode_byte(key.id);
+            enc.encode(&key.key);
+        }
+        enc.into()
+    }
+
+    /// Replace the keys that protect tokens with keys from [`Self::export_keys`].
+    ///
+    /// Servers that share keys need to rotate them together, so this disables
+    /// automatic rotation.  Keys need to be rotated on one server, then exported
+    /// to the others.  Use [`Self::set_key_rotation`] to enable automatic rotation
+    /// again on the server that rotates keys for the others.
+    ///
+    /// # Errors
+    /// When `keys` is not valid.
+    pub fn import_keys(&mut self, keys: &[u8], now: Instant) -> Res<()> {
+        fn decode_key(dec: &mut Decoder) -> Option<TokenKey> {
+            Some(TokenKey {
+                id: dec.decode_uint()?,
+                key: dec.decode(TokenKey::LEN)?.try_into().ok()?,
+            })
+        }
+
+        let mut dec = Decoder::new(keys);
+        let key = decode_key(&mut dec).ok_or(Error::InvalidInput)?;
+        let previous_key = if dec.remaining() > 0 {
+            let previous = decode_key(&mut dec).ok_or(Error::InvalidInput)?;
+            if previous.id == key.id {
+                return Err(Error::InvalidInput);
+            }
+            Some(previous)
+        } else {
+            None
+        };
+        if dec.remaining() > 0 {
+            return Err(Error::InvalidInput);
+        }
+        qinfo!("AddressValidation {self:p}: import {key:?}, previous {previous_key:?}");
+        self.key = key;
+        self.previous_key = previous_key;
+        self.key_installed = now;
+        self.key_rotation = None;
+        Ok(())
+    }
+
     /// Decrypts `token` and returns the connection ID it contains.
     /// Returns a tuple with a boolean indicating whether this thinks
     /// that the token was a Retry token, and a connection ID, that is
@@ -183,13 +383,14 @@ impl AddressValidation {
         now: Instant,
     ) -> Option<ConnectionId> {
         let peer_addr = Self::encode_aad(peer_address, retry);
-        let data = self.self_encrypt.open(peer_addr.as_ref(), token).ok()?;
+        let id = *token.first()?;
+        let key = self.keys().find(|k| k.id == id)?;
+        let data = key.open(peer_addr.as_ref(), token)?;
         let mut dec = Decoder::new(&data);
         {
-            let d = dec.decode_uint::<u32>()?;
-            let end = self.start_time + Duration::from_millis(u64::from(d));
-            if end < now {
-                qtrace!("Expired token: {end:?} vs. {now:?}");
+            let end = dec.decode_uint::<u64>()?;
+            if end < self.unix_millis(now) {
+                qtrace!("Expired token: {end} vs. {now:?}");
                 return None;
             }
         }
@@ -217,10 +418,21 @@ impl AddressValidation {
         peer_address: SocketAddr,
         now: Instant,
     ) -> AddressValidationResult {
-        qtrace!("AddressValidation {self:p}: validate {:?}", self.validation);
+        self.validate_with(token, peer_address, now, self.validation)
+    }
+
+    /// Like [`Self::validate`], but with a different policy than the configured one.
+    pub fn validate_with(
+        &self,
+        token: &[u8],
+        peer_address: SocketAddr,
+        now: Instant,
+        validation: ValidateAddress,
+    ) -> AddressValidationResult {
+        qtrace!("AddressValidation {self:p}: validate {validation:?}");
 
         if token.is_empty() {
-            if self.validation == ValidateAddress::Never {
+            if validation == ValidateAddress::Never {
                 qinfo!("AddressValidation: no token; accepting");
                 return AddressValidationResult::Pass;
             }
@@ -249,7 +461,7 @@ impl AddressValidation {
                     }
                 } else if cid.is_empty() {
                     // An empty connection ID means NEW_TOKEN.
-                    if self.validation == ValidateAddress::Always {
+                    if validation == ValidateAddress::Always {
                         qinfo!("AddressValidation: valid NEW_TOKEN token; validating again");
                         AddressValidationResult::Validate
                     } else {
@@ -267,7 +479,7 @@ impl AddressValidation {
                     // If this looked like a Retry, treat it as being bad.
                     qinfo!("AddressValidation: invalid Retry token; rejecting");
                     AddressValidationResult::Invalid
-                } else if self.validation == ValidateAddress::Never {
+                } else if validation == ValidateAddress::Never {
                     // We don't require validation, so OK.
                     qinfo!("AddressValidation: invalid NEW_TOKEN token; accepting");
                     AddressValidationResult::Pass
@@ -461,12 +673,166 @@ impl NewTokenSender {
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
+    use std::{
+        net::{IpAddr, Ipv6Addr, SocketAddr},
+        time::Duration,
+    };
+
     use neqo_common::{Encoder, Role};
+    use test_fixture::now;
 
-    use super::{NewTokenFrameStatus, NewTokenSender, NewTokenState};
+    use super::{
+        AddressValidation, AddressValidationResult, NewTokenFrameStatus, NewTokenSender,
+        NewTokenState, TokenKey, ValidateAddress,
+    };
+    use crate::cid::ConnectionId;
 
     const ONE: &[u8] = &[1, 2, 3];
     const TWO: &[u8] = &[4, 5];
+    const PEER: SocketAddr = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 443);
+    const DCID: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8];
+
+    fn address_validation() -> AddressValidation {
+        nss::init().unwrap();
+        AddressValidation::new(now(), ValidateAddress::NoToken).unwrap()
+    }
+
+    /// Check that `av` accepts both tokens.
+    fn accepts(av: &AddressValidation, retry: &[u8], new_token: &[u8]) -> bool {
+        let retry_ok = matches!(
+            av.validate(retry, PEER, now()),
+            AddressValidationResult::ValidRetry(cid) if cid == ConnectionId::from(DCID)
+        );
+        let new_token_ok = matches!(
+            av.validate(new_token, PEER, now()),
+            AddressValidationResult::Pass
+        );
+        assert_eq!(retry_ok, new_token_ok);
+        retry_ok
+    }
+
+    fn tokens(av: &AddressValidation) -> (Vec<u8>, Vec<u8>) {
+        (
+            av.generate_retry_token(&ConnectionId::from(DCID), PEER, now())
+                .unwrap(),
+            av.generate_new_token(PEER, now()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn previous_key_accepted() {
+        let mut av = address_validation();
+        let (retry, new_token) = tokens(&av);
+        assert!(accepts(&av, &retry, &new_token));
+
+        av.rotate_keys(now());
+        assert!(accepts(&av, &retry, &new_token));
+
+        // Two rotations later, the key is gone.
+        av.rotate_keys(now());
+        assert!(!accepts(&av, &retry, &new_token));
+        assert!(matches!(
+            av.validate(&retry, PEER, now()),
+            AddressValidationResult::Invalid
+        ));
+        assert!(matches!(
+            av.validate(&new_token, PEER, now()),
+            AddressValidationResult::Validate
+        ));
+    }
+
+    #[test]
+    fn automatic_rotation() {
+        const INTERVAL: Duration = Duration::from_secs(10);
+        let mut av = address_validation();
+        av.set_key_rotation(Some(INTERVAL));
+        let keys = av.export_keys();
+
+        av.maybe_rotate_keys(now() + INTERVAL - Duration::from_millis(1));
+        assert_eq!(av.export_keys(), keys);
+        av.maybe_rotate_keys(now() + INTERVAL);
+        assert_ne!(av.export_keys(), keys);
+        assert_eq!(av.export_keys().len(), 2 * (1 + TokenKey::LEN));
+
+        av.set_key_rotation(None);
+        let keys = av.export_keys();
+        av.maybe_rotate_keys(now() + INTERVAL * 10);
+        assert_eq!(av.export_keys(), keys);
+    }
+
+    #[test]
+    fn shared_keys() {
+        let mut a = address_validation();
+        a.rotate_keys(now());
+        let (retry, new_token) = tokens(&a);
+
+        let mut b = address_validation();
+        assert!(!accepts(&b, &retry, &new_token));
+        b.import_keys(&a.export_keys(), now()).unwrap();
+        assert!(accepts(&b, &retry, &new_token));
+        let (retry, new_token) = tokens(&b);
+        assert!(accepts(&a, &retry, &new_token));
+    }
+
+    #[test]
+    fn imported_keys_not_rotated() {
+        let keys = address_validation().export_keys();
+        let mut a = address_validation();
+        let mut b = address_validation();
+        a.import_keys(&keys, now()).unwrap();
+        b.import_keys(&keys, now()).unwrap();
+
+        let later = now() + AddressValidation::DEFAULT_KEY_ROTATION;
+        a.maybe_rotate_keys(later);
+        b.maybe_rotate_keys(later);
+        assert_eq!(a.export_keys(), keys);
+        assert_eq!(b.export_keys(), keys);
+    }
+
+    #[test]
+    fn import_invalid_keys() {
+        let mut av = address_validation();
+        let keys = av.export_keys();
+        let (retry, new_token) = tokens(&av);
+        av.rotate_keys(now());
+        let two_keys = av.export_keys();
+
+        assert!(av.import_keys(&[], now()).is_err());
+        assert!(av.import_keys(&keys[..keys.len() - 1], now()).is_err());
+        assert!(
+            av.import_keys(&[two_keys.as_slice(), &[0]].concat(), now())
+                .is_err()
+        );
+        assert!(
+            av.import_keys(&[keys.as_slice(), &keys].concat(), now())
+                .is_err()
+        );
+
+        // Failed imports leave keys unchanged.
+        assert_eq!(av.export_keys(), two_keys);
+        av.import_keys(&keys, now()).unwrap();
+        assert_eq!(av.export_keys(), keys);
+        assert!(accepts(&av, &retry, &new_token));
+    }
+
+    #[test]
+    fn expired_tokens() {
+        let av = address_validation();
+        let (retry, new_token) = tokens(&av);
+        let later = now() + Duration::from_secs(6);
+        assert!(matches!(
+            av.validate(&retry, PEER, later),
+            AddressValidationResult::Invalid
+        ));
+        assert!(matches!(
+            av.validate(&new_token, PEER, later),
+            AddressValidationResult::Pass
+        ));
+        assert!(matches!(
+            av.validate(&new_token, PEER, now() + Duration::from_secs(60 * 60 * 25)),
+            AddressValidationResult::Validate
+        ));
+    }
 
     #[test]
     fn new_token_frame_status_len() {
@@ -0,0 +1,1313 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//
+//! BBR congestion control, version 3, as per
+//! <https://datatracker.ietf.org/doc/html/draft-ietf-ccwg-bbr>
+//!
+//! BBR builds a model of the path from delivery rate samples, see
+//! <https://datatracker.ietf.org/doc/html/draft-ietf-ccwg-bbr#section-4.5>,
+//! and sets both the pacing rate and the congestion window from that model.
+
+use std::{
+    cmp::{max, min},
+    fmt::{self, Display},
+    time::{Duration, Instant},
+};
+
+use neqo_common::{qdebug, qinfo, qlog::Qlog, qtrace, to_u64};
+use nss::random;
+use rustc_hash::FxHashMap as HashMap;
+
+use super::{CongestionController, classic_cc::cwnd_initial};
+use crate::{
+    Pmtud, packet, qlog,
+    recovery::sent,
+    rtt::RttEstimate,
+    stats::{CongestionControlStats, SlowStartExitReason, SlowStartExitStats},
+};
+
+/// The states of the BBR state machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::IntoStaticStr)]
+pub enum State {
+    /// Rapidly probe for bandwidth, at the start of the connection.
+    #[strum(to_string = "startup")]
+    Startup,
+    /// Drain the queue that was built in `Startup`.
+    #[strum(to_string = "drain")]
+    Drain,
+    /// Slow down to drain any queue built while probing for bandwidth.
+    #[strum(to_string = "probe_bw_down")]
+    ProbeBwDown,
+    /// Cruise at the estimated bandwidth with a small amount of headroom.
+    #[strum(to_string = "probe_bw_cruise")]
+    ProbeBwCruise,
+    /// Refill the pipe before probing for more bandwidth.
+    #[strum(to_string = "probe_bw_refill")]
+    ProbeBwRefill,
+    /// Probe for more bandwidth by sending faster than the estimate.
+    #[strum(to_string = "probe_bw_up")]
+    ProbeBwUp,
+    /// Reduce the amount in flight to measure the minimum RTT.
+    #[strum(to_string = "probe_rtt")]
+    ProbeRtt,
+}
+
+impl State {
+    const fn is_probe_bw(self) -> bool {
+        matches!(
+            self,
+            Self::ProbeBwDown | Self::ProbeBwCruise | Self::ProbeBwRefill | Self::ProbeBwUp
+        )
+    }
+
+    /// Whether the state deliberately sends faster than the bandwidth estimate.
+    const fn is_probing_bw(self) -> bool {
+        matches!(self, Self::Startup | Self::ProbeBwRefill | Self::ProbeBwUp)
+    }
+
+    /// The pacing gain and congestion window gain for this state, as integers out of
+    /// [`Bbr::SCALE`].
+    const fn gains(self) -> (usize, usize) {
+        match self {
+            Self::Startup => (Bbr::STARTUP_PACING_GAIN, Bbr::DEFAULT_CWND_GAIN),
+            Self::Drain => (Bbr::DRAIN_PACING_GAIN, Bbr::DEFAULT_CWND_GAIN),
+            Self::ProbeBwDown => (Bbr::PROBE_BW_DOWN_PACING_GAIN, Bbr::DEFAULT_CWND_GAIN),
+            Self::ProbeBwCruise | Self::ProbeBwRefill => (Bbr::SCALE, Bbr::DEFAULT_CWND_GAIN),
+            Self::ProbeBwUp => (Bbr::PROBE_BW_UP_PACING_GAIN, Bbr::PROBE_BW_UP_CWND_GAIN),
+            Self::ProbeRtt => (Bbr::SCALE, Bbr::PROBE_RTT_CWND_GAIN),
+        }
+    }
+}
+
+/// Where the ACKs that are being received are in the bandwidth probing cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AckPhase {
+    Init,
+    /// Bandwidth probing has stopped, but ACKs for probing packets are still arriving.
+    ProbeStopping,
+    Refilling,
+    /// Bandwidth probing has started, but ACKs for probing packets have not arrived yet.
+    ProbeStarting,
+    /// ACKs for probing packets are arriving.
+    ProbeFeedback,
+}
+
+/// The connection state at the time a packet was sent, used to produce
+/// a delivery rate sample when the packet is acknowledged.
+#[derive(Debug, Clone, Copy)]
+struct PacketState {
+    /// [`Bbr::delivered`] when the packet was sent.
+    delivered: u64,
+    /// [`Bbr::delivered_time`] when the packet was sent.
+    delivered_time: Instant,
+    /// [`Bbr::first_sent_time`] when the packet was sent.
+    first_sent_time: Instant,
+    /// Whether the connection was application limited when the packet was sent.
+    is_app_limited: bool,
+    /// The number of bytes in flight after the packet was sent.
+    tx_in_flight: usize,
+    /// [`Bbr::lost`] when the packet was sent.
+    lost: u64,
+}
+
+/// A delivery rate sample, taken for each ACK.
+#[derive(Debug, Default, Clone, Copy)]
+struct RateSample {
+    /// The delivery rate, in bytes per second.  Zero for an invalid sample.
+    delivery_rate: u64,
+    /// Whether the packet the sample is based on was sent while application limited.
+    is_app_limited: bool,
+    /// The number of bytes delivered over the sampling interval.
+    delivered: u64,
+    /// [`Bbr::delivered`] when the newest acknowledged packet was sent.
+    prior_delivered: u64,
+    /// The time that the newest acknowledged packet was sent.
+    sent_time: Option<Instant>,
+    /// The number of bytes newly acknowledged.
+    newly_acked: usize,
+    /// The number of bytes in flight when the newest acknowledged packet was sent.
+    tx_in_flight: usize,
+    /// The number of bytes lost since the newest acknowledged packet was sent.
+    lost: usize,
+    /// The RTT measured with the newest acknowledged packet.
+    rtt: Option<Duration>,
+}
+
+/// BBR congestion control, version 3.
+///
+/// <https://datatracker.ietf.org/doc/html/draft-ietf-ccwg-bbr>
+#[derive(Debug)]
+pub struct Bbr {
+    pmtud: Pmtud,
+    qlog: Qlog,
+    state: State,
+    /// The pacing gain, as an integer out of [`Self::SCALE`].
+    pacing_gain: usize,
+    /// The congestion window gain, as an integer out of [`Self::SCALE`].
+    cwnd_gain: usize,
+    cwnd: usize,
+    bytes_in_flight: usize,
+    /// The pacing rate in bytes per second, `None` until the first ACK.
+    pacing_rate: Option<u64>,
+    /// The cap on the congestion window from the model.
+    max_inflight: usize,
+    /// Per-packet state for delivery rate sampling, for packets in flight.
+    packets: HashMap<(packet::Number, packet::Type), PacketState>,
+
+    /// The total number of bytes delivered.
+    delivered: u64,
+    /// The time [`Self::delivered`] was last updated.
+    delivered_time: Option<Instant>,
+    /// The send time of the packet that was most recently marked as delivered.
+    first_sent_time: Option<Instant>,
+    /// The value of [`Self::delivered`] when the current application limited period ends,
+    /// or zero if the connection is not application limited.
+    app_limited: u64,
+    /// The total number of bytes lost.
+    lost: u64,
+    /// The rate sample for the current ACK.
+    rs: RateSample,
+
+    /// [`Self::delivered`] at the end of the current round trip.
+    next_round_delivered: u64,
+    /// Whether the current ACK starts a new round trip.
+    round_start: bool,
+    /// Whether the connection was limited by the congestion window in the previous round.
+    cwnd_limited: bool,
+    /// Whether the connection was limited by the congestion window in the current round.
+    cwnd_limited_in_round: bool,
+
+    /// The maximum delivery rate over the current and previous bandwidth probing cycles.
+    max_bw_filter: [u64; 2],
+    /// The windowed maximum recent bandwidth sample.
+    max_bw: u64,
+    /// The short-term lower bound on bandwidth, reduced on loss.
+    bw_shortterm: Option<u64>,
+    /// The bandwidth estimate used by the model.
+    bw: u64,
+    /// The maximum delivery rate in the current loss round.
+    bw_latest: u64,
+    /// The maximum amount delivered in the current loss round.
+    inflight_latest: usize,
+    /// The long-term upper bound on bytes in flight.
+    inflight_longterm: Option<usize>,
+    /// The short-term lower bound on bytes in flight, reduced on loss.
+    inflight_shortterm: Option<usize>,
+
+    /// The minimum RTT over the last [`Self::MIN_RTT_FILTER_LEN`].
+    min_rtt: Option<Duration>,
+    min_rtt_stamp: Option<Instant>,
+    /// The minimum RTT over the last [`Self::PROBE_RTT_INTERVAL`].
+    probe_rtt_min_delay: Option<Duration>,
+    probe_rtt_min_stamp: Option<Instant>,
+    probe_rtt_expired: bool,
+    probe_rtt_done_stamp: Option<Instant>,
+    probe_rtt_round_done: bool,
+    /// The congestion window saved on entering recovery or `ProbeRTT`.
+    prior_cwnd: usize,
+    /// Whether the connection is restarting after being idle.
+    idle_restart: bool,
+
+    /// The bandwidth that was last observed to grow substantially.
+    full_bw: u64,
+    /// The number of rounds without substantial bandwidth growth.
+    full_bw_count: usize,
+    /// Whether the most recent bandwidth probe found the pipe full.
+    full_bw_now: bool,
+    /// Whether the pipe has been found full at least once.
+    full_bw_reached: bool,
+
+    /// Whether loss or ECN CE marks were seen in the current loss round.
+    loss_in_round: bool,
+    /// Whether the current ACK starts a new loss round.
+    loss_round_start: bool,
+    /// [`Self::delivered`] at the end of the current loss round.
+    loss_round_delivered: u64,
+    /// The number of loss events in the current loss round.
+    loss_events_in_round: usize,
+    /// Whether the loss rate in the current loss round was too high during `Startup`.
+    loss_too_high_in_round: bool,
+
+    /// The start of the current bandwidth probing phase.
+    cycle_stamp: Option<Instant>,
+    ack_phase: AckPhase,
+    rounds_since_bw_probe: usize,
+    /// How long to wait before probing for bandwidth again.
+    bw_probe_wait: Duration,
+    /// Whether the rate samples come from a bandwidth probe.
+    bw_probe_samples: bool,
+    bw_probe_up_rounds: u32,
+    bw_probe_up_acks: usize,
+    /// The number of bytes to acknowledge before raising [`Self::inflight_longterm`] by one
+    /// packet.
+    probe_up_cnt: usize,
+
+    /// Windowed maximum of bytes acknowledged beyond the expected amount, over two windows.
+    extra_acked: [usize; 2],
+    extra_acked_win_idx: usize,
+    extra_acked_win_rtts: usize,
+    extra_acked_interval_start: Option<Instant>,
+    extra_acked_delivered: usize,
+
+    /// When the current loss recovery episode started.
+    recovery_start: Option<Instant>,
+    /// Whether to restrict sending to the amount delivered, in the first round of recovery.
+    packet_conservation: bool,
+}
+
+impl Display for Bbr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BBRv3 [state: {:?}, cwnd: {}, bif: {}, bw: {}, min_rtt: {:?}]",
+            self.state, self.cwnd, self.bytes_in_flight, self.bw, self.min_rtt
+        )
+    }
+}
+
+impl Bbr {
+    /// Scale factor for integer approximation of gains and other fractional values.
+    pub const SCALE: usize = 100;
+    /// Pacing gain in `Startup` (= 2.77, that is 4 ln 2).
+    const STARTUP_PACING_GAIN: usize = 277;
+    /// Pacing gain in `Drain` (= 0.35).
+    const DRAIN_PACING_GAIN: usize = 35;
+    /// Pacing gain in `ProbeBW_DOWN` (= 0.90).
+    const PROBE_BW_DOWN_PACING_GAIN: usize = 90;
+    /// Pacing gain in `ProbeBW_UP` (= 1.25).
+    const PROBE_BW_UP_PACING_GAIN: usize = 125;
+    /// Congestion window gain in most states (= 2.0).
+    const DEFAULT_CWND_GAIN: usize = 200;
+    /// Congestion window gain in `ProbeBW_UP` (= 2.25).
+    const PROBE_BW_UP_CWND_GAIN: usize = 225;
+    /// Congestion window gain in `ProbeRTT` (= 0.5).
+    const PROBE_RTT_CWND_GAIN: usize = 50;
+    /// Multiplicative decrease on loss (= 0.7).
+    pub const BETA: usize = 70;
+    /// The loss rate above which the amount in flight is considered too high (= 0.02).
+    const LOSS_THRESH: usize = 2;
+    /// Headroom left for other flows in `ProbeBW_CRUISE` (= 0.15).
+    const HEADROOM: usize = 15;
+    /// Pacing slightly below the estimated bandwidth keeps queues small (= 0.01).
+    const PACING_MARGIN: usize = 1;
+    /// The bandwidth growth per round in `Startup` that means the pipe is not yet full (= 1.25).
+    const FULL_BW_THRESH: usize = 125;
+    /// The number of rounds without bandwidth growth before the pipe is considered full.
+    const FULL_BW_COUNT: usize = 3;
+    /// The number of loss events in a round that ends `Startup`.
+    const STARTUP_FULL_LOSS_COUNT: usize = 6;
+    /// The minimum congestion window, in packets.
+    const MIN_PIPE_CWND_PKTS: usize = 4;
+    const MIN_RTT_FILTER_LEN: Duration = Duration::from_secs(10);
+    const PROBE_RTT_INTERVAL: Duration = Duration::from_secs(5);
+    const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+    /// The minimum wait between bandwidth probes; up to a second is added at random.
+    const BW_PROBE_WAIT_BASE: Duration = Duration::from_secs(2);
+    /// The maximum number of rounds between bandwidth probes, for coexistence with Reno.
+    const BW_PROBE_MAX_ROUNDS: usize = 63;
+    /// The number of rounds in each of the two windows of the ACK aggregation filter.
+    const EXTRA_ACKED_WIN_RTTS: usize = 5;
+
+    #[must_use]
+    pub fn new(pmtud: Pmtud) -> Self {
+        let cwnd = cwnd_initial(pmtud.plpmtu());
+        let (pacing_gain, cwnd_gain) = State::Startup.gains();
+        Self {
+            pmtud,
+            qlog: Qlog::disabled(),
+            state: State::Startup,
+            pacing_gain,
+            cwnd_gain,
+            cwnd,
+            bytes_in_flight: 0,
+            pacing_rate: None,
+            max_inflight: cwnd,
+            packets: HashMap::default(),
+            delivered: 0,
+            delivered_time: None,
+            first_sent_time: None,
+            app_limited: 0,
+            lost: 0,
+            rs: RateSample::default(),
+            next_round_delivered: 0,
+            round_start: false,
+            cwnd_limited: false,
+            cwnd_limited_in_round: false,
+            max_bw_filter: [0; 2],
+            max_bw: 0,
+            bw_shortterm: None,
+            bw: 0,
+            bw_latest: 0,
+            inflight_latest: 0,
+            inflight_longterm: None,
+            inflight_shortterm: None,
+            min_rtt: None,
+            min_rtt_stamp: None,
+            probe_rtt_min_delay: None,
+            probe_rtt_min_stamp: None,
+            probe_rtt_expired: false,
+            probe_rtt_done_stamp: None,
+            probe_rtt_round_done: false,
+            prior_cwnd: 0,
+            idle_restart: false,
+            full_bw: 0,
+            full_bw_count: 0,
+            full_bw_now: false,
+            full_bw_reached: false,
+            loss_in_round: false,
+            loss_round_start: false,
+            loss_round_delivered: 0,
+            loss_events_in_round: 0,
+            loss_too_high_in_round: false,
+            cycle_stamp: None,
+            ack_phase: AckPhase::Init,
+            rounds_since_bw_probe: 0,
+            bw_probe_wait: Duration::ZERO,
+            bw_probe_samples: false,
+            bw_probe_up_rounds: 0,
+            bw_probe_up_acks: 0,
+            probe_up_cnt: usize::MAX,
+            extra_acked: [0; 2],
+            extra_acked_win_idx: 0,
+            extra_acked_win_rtts: 0,
+            extra_acked_interval_start: None,
+            extra_acked_delivered: 0,
+            recovery_start: None,
+            packet_conservation: false,
+        }
+    }
+
+    #[cfg(test)]
+    pub const fn state(&self) -> State {
+        self.state
+    }
+
+    /// The bandwidth estimate, in bytes per second.
+    #[cfg(test)]
+    pub const fn bw(&self) -> u64 {
+        self.bw
+    }
+
+    #[cfg(test)]
+    pub const fn min_rtt(&self) -> Option<Duration> {
+        self.min_rtt
+    }
+
+    const fn mtu(&self) -> usize {
+        self.pmtud.plpmtu()
+    }
+
+    const fn min_pipe_cwnd(&self) -> usize {
+        Self::MIN_PIPE_CWND_PKTS * self.mtu()
+    }
+
+    /// The number of bytes delivered at `bw` bytes per second over `d`.
+    fn bytes_at_rate(bw: u64, d: Duration) -> usize {
+        usize::try_from(u128::from(bw) * d.as_nanos() / 1_000_000_000).unwrap_or(usize::MAX)
+    }
+
+    /// The rate, in bytes per second, of delivering `bytes` over `d`.
+    fn rate(bytes: u64, d: Duration) -> u64 {
+        u64::try_from(u128::from(bytes) * 1_000_000_000 / d.as_nanos().max(1)).unwrap_or(u64::MAX)
+    }
+
+    /// Whether losing `lost` bytes out of `tx_in_flight` exceeds [`Self::LOSS_THRESH`].
+    const fn is_inflight_too_high(lost: usize, tx_in_flight: usize) -> bool {
+        lost.saturating_mul(Self::SCALE) > tx_in_flight.saturating_mul(Self::LOSS_THRESH)
+    }
+
+    fn set_state(&mut self, state: State, now: Instant) {
+        if self.state == state {
+            return;
+        }
+        qdebug!("[{self}] state -> {state:?}");
+        qlog::congestion_state_updated(
+            &mut self.qlog,
+            Some(self.state.into()),
+            state.into(),
+            None,
+            now,
+        );
+        self.state = state;
+        (self.pacing_gain, self.cwnd_gain) = state.gains();
+    }
+
+    const fn mark_app_limited(&mut self) {
+        let limit = self.delivered + self.bytes_in_flight as u64;
+        self.app_limited = if limit > 0 { limit } else { 1 };
+    }
+
+    /// Produce the rate sample for an ACK, from the packets it acknowledged.
+    /// Returns `false` if no packets that count towards delivery were acknowledged.
+    fn generate_rate_sample(
+        &mut self,
+        acked_pkts: &[sent::Packet],
+        rtt_est: &RttEstimate,
+        now: Instant,
+    ) -> bool {
+        self.rs = RateSample::default();
+        let mut elapsed = Duration::ZERO;
+        for pkt in acked_pkts {
+            if !pkt.cc_outstanding() {
+                continue;
+            }
+            // Bytes in flight is reset on a path change, but we may still get ACKs for
+            // packets sent before.
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+            let Some(p) = self.packets.remove(&(pkt.pn(), pkt.packet_type())) else {
+                continue;
+            };
+            self.rs.newly_acked += pkt.len();
+            self.delivered += to_u64(pkt.len());
+            self.delivered_time = Some(now);
+
+            // The packets are ordered from largest packet number down,
+            // so the first one is the most recently sent.
+            if self.rs.sent_time.is_none() {
+                self.rs.prior_delivered = p.delivered;
+                self.rs.sent_time = Some(pkt.time_sent());
+                self.rs.is_app_limited = p.is_app_limited;
+                self.rs.tx_in_flight = p.tx_in_flight;
+                self.rs.lost = usize::try_from(self.lost - p.lost).unwrap_or(usize::MAX);
+                self.rs.rtt = Some(now.saturating_duration_since(pkt.time_sent()));
+                let send_elapsed = pkt.time_sent().saturating_duration_since(p.first_sent_time);
+                let ack_elapsed = now.saturating_duration_since(p.delivered_time);
+                elapsed = max(send_elapsed, ack_elapsed);
+                self.first_sent_time = Some(pkt.time_sent());
+            }
+        }
+        if self.rs.sent_time.is_none() {
+            return false;
+        }
+
+        if self.app_limited != 0 && self.delivered > self.app_limited {
+            self.app_limited = 0;
+        }
+        self.rs.delivered = self.delivered - self.rs.prior_delivered;
+        // An interval shorter than the minimum RTT can't produce an accurate rate.
+        if !elapsed.is_zero() && elapsed >= rtt_est.minimum() {
+            self.rs.delivery_rate = Self::rate(self.rs.delivered, elapsed);
+        }
+        qtrace!("[{self}] rate sample {:?}", self.rs);
+        true
+    }
+
+    const fn start_round(&mut self) {
+        self.next_round_delivered = self.delivered;
+    }
+
+    const fn update_round(&mut self) {
+        self.round_start = self.rs.prior_delivered >= self.next_round_delivered;
+        if self.round_start {
+            self.start_round();
+            self.rounds_since_bw_probe += 1;
+            self.cwnd_limited = self.cwnd_limited_in_round;
+            self.cwnd_limited_in_round = false;
+        }
+    }
+
+    fn update_latest_delivery_signals(&mut self) {
+        self.loss_round_start = false;
+        self.bw_latest = max(self.bw_latest, self.rs.delivery_rate);
+        self.inflight_latest = max(
+            self.inflight_latest,
+            usize::try_from(self.rs.delivered).unwrap_or(usize::MAX),
+        );
+        if self.rs.prior_delivered >= self.loss_round_delivered {
+            self.loss_round_delivered = self.delivered;
+            self.loss_round_start = true;
+        }
+    }
+
+    fn advance_latest_delivery_signals(&mut self) {
+        if self.loss_round_start {
+            self.bw_latest = self.rs.delivery_rate;
+            self.inflight_latest = usize::try_from(self.rs.delivered).unwrap_or(usize::MAX);
+        }
+    }
+
+    const fn reset_congestion_signals(&mut self) {
+        self.loss_in_round = false;
+        self.bw_latest = 0;
+        self.inflight_latest = 0;
+    }
+
+    fn update_max_bw(&mut self) {
+        self.update_round();
+        if self.rs.delivery_rate >= self.max_bw || !self.rs.is_app_limited {
+            self.max_bw_filter[1] = max(self.max_bw_filter[1], self.rs.delivery_rate);
+            self.max_bw = max(self.max_bw_filter[0], self.max_bw_filter[1]);
+        }
+    }
+
+    /// Forget the bandwidth samples from the previous probing cycle.
+    const fn advance_max_bw_filter(&mut self) {
+        if self.max_bw_filter[1] == 0 {
+            return;
+        }
+        self.max_bw_filter = [self.max_bw_filter[1], 0];
+    }
+
+    fn update_congestion_signals(&mut self, cc_stats: &mut CongestionControlStats) {
+        self.update_max_bw();
+        if !self.loss_round_start {
+            return;
+        }
+        self.check_startup_high_loss(cc_stats);
+        self.adapt_lower_bounds_from_congestion();
+        self.loss_in_round = false;
+        self.loss_events_in_round = 0;
+        self.loss_too_high_in_round = false;
+    }
+
+    fn adapt_lower_bounds_from_congestion(&mut self) {
+        if self.state.is_probing_bw() || !self.loss_in_round {
+            return;
+        }
+        let bw_shortterm = *self.bw_shortterm.get_or_insert(self.max_bw);
+        let inflight_shortterm = *self.inflight_shortterm.get_or_insert(self.cwnd);
+        self.bw_shortterm = Some(max(
+            self.bw_latest,
+            bw_shortterm * to_u64(Self::BETA) / to_u64(Self::SCALE),
+        ));
+        self.inflight_shortterm = Some(max(
+            self.inflight_latest,
+            inflight_shortterm * Self::BETA / Self::SCALE,
+        ));
+    }
+
+    const fn reset_short_term_model(&mut self) {
+        self.bw_shortterm = None;
+        self.inflight_shortterm = None;
+    }
+
+    fn bound_bw_for_model(&mut self) {
+        self.bw = self
+            .bw_shortterm
+            .map_or(self.max_bw, |bw_shortterm| min(self.max_bw, bw_shortterm));
+    }
+
+    fn update_ack_aggregation(&mut self, now: Instant) {
+        if self.round_start {
+            self.extra_acked_win_rtts += 1;
+            if self.extra_acked_win_rtts >= Self::EXTRA_ACKED_WIN_RTTS {
+                self.extra_acked_win_rtts = 0;
+                self.extra_acked_win_idx = 1 - self.extra_acked_win_idx;
+                self.extra_acked[self.extra_acked_win_idx] = 0;
+            }
+        }
+        let start = *self.extra_acked_interval_start.get_or_insert(now);
+        let mut expected = Self::bytes_at_rate(self.bw, now.saturating_duration_since(start));
+        // Reset the interval if the ACK rate is below the expected rate.
+        if self.extra_acked_delivered <= expected {
+            self.extra_acked_delivered = 0;
+            self.extra_acked_interval_start = Some(now);
+            expected = 0;
+        }
+        self.extra_acked_delivered += self.rs.newly_acked;
+        let extra = min(
+            self.extra_acked_delivered.saturating_sub(expected),
+            self.cwnd,
+        );
+        let idx = self.extra_acked_win_idx;
+        self.extra_acked[idx] = max(self.extra_acked[idx], extra);
+    }
+
+    const fn reset_full_bw(&mut self) {
+        self.full_bw = 0;
+        self.full_bw_count = 0;
+        self.full_bw_now = false;
+    }
+
+    fn check_full_bw_reached(&mut self) {
+        if self.full_bw_now
+            || !self.round_start
+            || self.rs.is_app_limited
+            || self.rs.delivery_rate == 0
+        {
+            return;
+        }
+        if u128::from(self.rs.delivery_rate) * (Self::SCALE as u128)
+            >= u128::from(self.full_bw) * (Self::FULL_BW_THRESH as u128)
+        {
+            self.reset_full_bw();
+            self.full_bw = self.rs.delivery_rate;
+            return;
+        }
+        self.full_bw_count += 1;
+        self.full_bw_now = self.full_bw_count >= Self::FULL_BW_COUNT;
+        if self.full_bw_now {
+            self.full_bw_reached = true;
+        }
+    }
+
+    fn check_startup_high_loss(&mut self, cc_stats: &mut CongestionControlStats) {
+        if self.state == State::Startup
+            && !self.full_bw_reached
+            && self.loss_too_high_in_round
+            && self.loss_events_in_round >= Self::STARTUP_FULL_LOSS_COUNT
+        {
+            qinfo!("[{self}] exiting startup on high loss");
+            self.full_bw_reached = true;
+            self.inflight_longterm = Some(max(
+                self.bdp_multiple(self.max_bw, Self::SCALE),
+                self.inflight_latest,
+            ));
+            self.record_startup_exit(SlowStartExitReason::Heuristic, cc_stats);
+        }
+    }
+
+    fn record_startup_exit(
+        &self,
+        reason: SlowStartExitReason,
+        cc_stats: &mut CongestionControlStats,
+    ) {
+        if cc_stats.slow_start_exit.is_none() {
+            cc_stats.slow_start_exit = Some(SlowStartExitStats {
+                reason,
+                detection_cwnd: self.cwnd,
+                exit_cwnd: self.cwnd,
+                bytes_in_flight: self.bytes_in_flight,
+            });
+        }
+    }
+
+    fn check_startup_done(&mut self, now: Instant, cc_stats: &mut CongestionControlStats) {
+        if self.state == State::Startup && self.full_bw_reached {
+            self.record_startup_exit(SlowStartExitReason::Heuristic, cc_stats);
+            self.set_state(State::Drain, now);
+        }
+    }
+
+    fn check_drain_done(&mut self, now: Instant) {
+        if self.state == State::Drain && self.bytes_in_flight <= self.inflight(self.bw, Self::SCALE)
+        {
+            self.start_probe_bw_down(now);
+        }
+    }
+
+    fn pick_probe_wait(&mut self) {
+        let [r1, r2, r3] = random::<3>();
+        self.rounds_since_bw_probe = usize::from(r1 & 1);
+        let jitter = u64::from(u16::from_le_bytes([r2, r3])) % 1_000;
+        self.bw_probe_wait = Self::BW_PROBE_WAIT_BASE + Duration::from_millis(jitter);
+    }
+
+    fn start_probe_bw_down(&mut self, now: Instant) {
+        self.reset_congestion_signals();
+        self.probe_up_cnt = usize::MAX;
+        self.pick_probe_wait();
+        self.cycle_stamp = Some(now);
+        self.ack_phase = AckPhase::ProbeStopping;
+        self.start_round();
+        self.set_state(State::ProbeBwDown, now);
+    }
+
+    fn start_probe_bw_cruise(&mut self, now: Instant) {
+        self.set_state(State::ProbeBwCruise, now);
+    }
+
+    fn start_probe_bw_refill(&mut self, now: Instant) {
+        self.reset_short_term_model();
+        self.bw_probe_up_rounds = 0;
+        self.bw_probe_up_acks = 0;
+        self.ack_phase = AckPhase::Refilling;
+        self.start_round();
+        self.set_state(State::ProbeBwRefill, now);
+    }
+
+    fn start_probe_bw_up(&mut self, now: Instant) {
+        self.ack_phase = AckPhase::ProbeStarting;
+        self.start_round();
+        self.reset_full_bw();
+        self.full_bw = self.rs.delivery_rate;
+        self.cycle_stamp = Some(now);
+        self.set_state(State::ProbeBwUp, now);
+        self.raise_inflight_longterm_slope();
+    }
+
+    fn has_elapsed_in_phase(&self, interval: Duration, now: Instant) -> bool {
+        self.cycle_stamp.is_some_and(|t| now > t + interval)
+    }
+
+    /// Probe for bandwidth at least as often as Reno would fill a buffer of the same size.
+    fn is_reno_coexistence_probe_time(&self) -> bool {
+        let reno_rounds = self.target_inflight() / self.mtu();
+        self.rounds_since_bw_probe >= min(reno_rounds, Self::BW_PROBE_MAX_ROUNDS)
+    }
+
+    fn is_time_to_probe_bw(&mut self, now: Instant) -> bool {
+        if self.has_elapsed_in_phase(self.bw_probe_wait, now)
+            || self.is_reno_coexistence_probe_time()
+        {
+            self.start_probe_bw_refill(now);
+            return true;
+        }
+        false
+    }
+
+    fn is_time_to_cruise(&self) -> bool {
+        self.bytes_in_flight <= self.inflight_with_headroom()
+            && self.bytes_in_flight <= self.inflight(self.max_bw, Self::SCALE)
+    }
+
+    fn is_time_to_go_down(&mut self) -> bool {
+        if self.cwnd_limited && self.inflight_longterm.is_some_and(|hi| self.cwnd >= hi) {
+            // The long-term bound limits sending, so the bandwidth samples say nothing
+            // about whether the pipe is full.  Keep probing.
+            self.reset_full_bw();
+            self.full_bw = self.rs.delivery_rate;
+            false
+        } else {
+            self.full_bw_now
+        }
+    }
+
+    fn raise_inflight_longterm_slope(&mut self) {
+        let growth_this_round = 1 << self.bw_probe_up_rounds;
+        self.bw_probe_up_rounds = min(self.bw_probe_up_rounds + 1, 30);
+        self.probe_up_cnt = max(self.cwnd / growth_this_round, self.mtu());
+    }
+
+    fn probe_inflight_longterm_upward(&mut self) {
+        let Some(hi) = self.inflight_longterm else {
+            return;
+        };
+        if !self.cwnd_limited || self.cwnd < hi {
+            return;
+        }
+        self.bw_probe_up_acks += self.rs.newly_acked;
+        if self.bw_probe_up_acks >= self.probe_up_cnt {
+            let delta = self.bw_probe_up_acks / self.probe_up_cnt;
+            self.bw_probe_up_acks -= delta * self.probe_up_cnt;
+            self.inflight_longterm = Some(hi + delta * self.mtu());
+        }
+        if self.round_start {
+            self.raise_inflight_longterm_slope();
+        }
+    }
+
+    fn handle_inflight_too_high(
+        &mut self,
+        is_app_limited: bool,
+        tx_in_flight: usize,
+        now: Instant,
+    ) {
+        self.bw_probe_samples = false;
+        if !is_app_limited {
+            self.inflight_longterm = Some(max(
+                tx_in_flight,
+                self.target_inflight() * Self::BETA / Self::SCALE,
+            ));
+        }
+        if self.state == State::ProbeBwUp {
+            self.start_probe_bw_down(now);
+        }
+    }
+
+    /// Estimate the number of bytes in flight when the loss rate crossed [`Self::LOSS_THRESH`].
+    const fn inflight_at_loss(tx_in_flight: usize, lost: usize, len: usize) -> usize {
+        let inflight_prev = tx_in_flight.saturating_sub(len);
+        let lost_prev = lost.saturating_sub(len);
+        let lost_prefix = (Self::LOSS_THRESH * inflight_prev)
+            .saturating_sub(lost_prev * Self::SCALE)
+            / (Self::SCALE - Self::LOSS_THRESH);
+        inflight_prev + lost_prefix
+    }
+
+    fn handle_lost_packet(&mut self, p: &PacketState, len: usize, now: Instant) {
+        let lost = usize::try_from(self.lost - p.lost).unwrap_or(usize::MAX);
+        if !Self::is_inflight_too_high(lost, p.tx_in_flight) {
+            return;
+        }
+        if self.state == State::Startup {
+            self.loss_too_high_in_round = true;
+        }
+        if self.bw_probe_samples {
+            let tx_in_flight = Self::inflight_at_loss(p.tx_in_flight, lost, len);
+            self.handle_inflight_too_high(p.is_app_limited, tx_in_flight, now);
+        }
+    }
+
+    fn adapt_long_term_model(&mut self, now: Instant) {
+        if self.ack_phase == AckPhase::ProbeStarting && self.round_start {
+            self.ack_phase = AckPhase::ProbeFeedback;
+        }
+        if self.ack_phase == AckPhase::ProbeStopping && self.round_start {
+            // The samples from bandwidth probing have all arrived.
+            self.bw_probe_samples = false;
+            self.ack_phase = AckPhase::Init;
+            if self.state.is_probe_bw() && !self.rs.is_app_limited {
+                self.advance_max_bw_filter();
+            }
+        }
+        if Self::is_inflight_too_high(self.rs.lost, self.rs.tx_in_flight) {
+            if self.bw_probe_samples {
+                self.handle_inflight_too_high(self.rs.is_app_limited, self.rs.tx_in_flight, now);
+            }
+            return;
+        }
+        // The loss rate is acceptable, so the upper bound can be raised.
+        let Some(hi) = self.inflight_longterm else {
+            return;
+        };
+        if self.rs.tx_in_flight > hi {
+            self.inflight_longterm = Some(self.rs.tx_in_flight);
+        }
+        if self.state == State::ProbeBwUp {
+            self.probe_inflight_longterm_upward();
+        }
+    }
+
+    fn update_probe_bw_cycle_phase(&mut self, now: Instant) {
+        if !self.full_bw_reached {
+            return;
+        }
+        self.adapt_long_term_model(now);
+        match self.state {
+            State::ProbeBwDown => {
+                if !self.is_time_to_probe_bw(now) && self.is_time_to_cruise() {
+                    self.start_probe_bw_cruise(now);
+                }
+            }
+            State::ProbeBwCruise => {
+                self.is_time_to_probe_bw(now);
+            }
+            State::ProbeBwRefill => {
+                // After one round of refilling the pipe, start probing.
+                if self.round_start {
+                    self.bw_probe_samples = true;
+                    self.start_probe_bw_up(now);
+                }
+            }
+            State::ProbeBwUp => {
+                if self.is_time_to_go_down() {
+                    self.start_probe_bw_down(now);
+                }
+            }
+            State::Startup | State::Drain | State::ProbeRtt => {}
+        }
+    }
+
+    fn update_min_rtt(&mut self, now: Instant) {
+        self.probe_rtt_expired = self
+            .probe_rtt_min_stamp
+            .is_some_and(|t| now > t + Self::PROBE_RTT_INTERVAL);
+        if let Some(rtt) = self.rs.rtt
+            && (self.probe_rtt_expired || self.probe_rtt_min_delay.is_none_or(|d| rtt < d))
+        {
+            self.probe_rtt_min_delay = Some(rtt);
+            self.probe_rtt_min_stamp = Some(now);
+        }
+        let min_rtt_expired = self
+            .min_rtt_stamp
+            .is_some_and(|t| now > t + Self::MIN_RTT_FILTER_LEN);
+        if let Some(delay) = self.probe_rtt_min_delay
+            && (min_rtt_expired || self.min_rtt.is_none_or(|m| delay < m))
+        {
+            self.min_rtt = Some(delay);
+            self.min_rtt_stamp = self.probe_rtt_min_stamp;
+        }
+    }
+
+    fn save_cwnd(&self) -> usize {
+        if self.recovery_start.is_none() && self.state != State::ProbeRtt {
+            self.cwnd
+        } else {
+            max(self.prior_cwnd, self.cwnd)
+        }
+    }
+
+    fn restore_cwnd(&mut self) {
+        self.cwnd = max(self.cwnd, self.prior_cwnd);
+    }
+
+    fn probe_rtt_cwnd(&self) -> usize {
+        max(
+            self.bdp_multiple(self.bw, Self::PROBE_RTT_CWND_GAIN),
+            self.min_pipe_cwnd(),
+        )
+    }
+
+    fn check_probe_rtt(&mut self, now: Instant) {
+        if self.state != State::ProbeRtt && self.probe_rtt_expired && !self.idle_restart {
+            self.prior_cwnd = self.save_cwnd();
+            self.set_state(State::ProbeRtt, now);
+            self.probe_rtt_done_stamp = None;
+            self.ack_phase = AckPhase::ProbeStopping;
+            self.start_round();
+        }
+        if self.state == State::ProbeRtt {
+            self.handle_probe_rtt(now);
+        }
+        if self.rs.delivered > 0 {
+            self.idle_restart = false;
+        }
+    }
+
+    fn handle_probe_rtt(&mut self, now: Instant) {
+        // Ignore low rate samples during ProbeRTT.
+        self.mark_app_limited();
+        if self.probe_rtt_done_stamp.is_none() && self.bytes_in_flight <= self.probe_rtt_cwnd() {
+            // Wait for at least `PROBE_RTT_DURATION` and one round.
+            self.probe_rtt_done_stamp = Some(now + Self::PROBE_RTT_DURATION);
+            self.probe_rtt_round_done = false;
+            self.start_round();
+        } else if self.probe_rtt_done_stamp.is_some() {
+            if self.round_start {
+                self.probe_rtt_round_done = true;
+            }
+            if self.probe_rtt_round_done {
+                self.check_probe_rtt_done(now);
+            }
+        }
+    }
+
+    fn check_probe_rtt_done(&mut self, now: Instant) {
+        if self.probe_rtt_done_stamp.is_some_and(|t| now > t) {
+            // Schedule the next ProbeRTT.
+            self.probe_rtt_min_stamp = Some(now);
+            self.restore_cwnd();
+            self.reset_short_term_model();
+            if self.full_bw_reached {
+                self.start_probe_bw_down(now);
+                self.start_probe_bw_cruise(now);
+            } else {
+                self.set_state(State::Startup, now);
+            }
+        }
+    }
+
+    fn handle_restart_from_idle(&mut self, now: Instant) {
+        self.idle_restart = true;
+        self.extra_acked_interval_start = Some(now);
+        if self.state.is_probe_bw() {
+            self.set_pacing_rate_with_gain(Self::SCALE);
+        } else if self.state == State::ProbeRtt {
+            self.check_probe_rtt_done(now);
+        }
+    }
+
+    /// `gain` times the bandwidth-delay product for `bw`, with `gain` out of [`Self::SCALE`].
+    fn bdp_multiple(&self, bw: u64, gain: usize) -> usize {
+        self.min_rtt.map_or_else(
+            || cwnd_initial(self.mtu()),
+            |min_rtt| Self::bytes_at_rate(bw, min_rtt).saturating_mul(gain) / Self::SCALE,
+        )
+    }
+
+    fn send_quantum(&self) -> usize {
+        let quantum = self.pacing_rate.map_or(0, |rate| {
+            Self::bytes_at_rate(rate, Duration::from_millis(1))
+        });
+        max(min(quantum, 64 * 1024), 2 * self.mtu())
+    }
+
+    fn quantization_budget(&self, inflight: usize) -> usize {
+        let inflight = max(max(inflight, 3 * self.send_quantum()), self.min_pipe_cwnd());
+        if self.state == State::ProbeBwUp {
+            inflight + 2 * self.mtu()
+        } else {
+            inflight
+        }
+    }
+
+    fn inflight(&self, bw: u64, gain: usize) -> usize {
+        self.quantization_budget(self.bdp_multiple(bw, gain))
+    }
+
+    fn target_inflight(&self) -> usize {
+        min(self.bdp_multiple(self.bw, Self::SCALE), self.cwnd)
+    }
+
+    fn inflight_with_headroom(&self) -> usize {
+        let Some(hi) = self.inflight_longterm else {
+            return usize::MAX;
+        };
+        let headroom = max(self.mtu(), hi * Self::HEADROOM / Self::SCALE);
+        max(hi.saturating_sub(headroom), self.min_pipe_cwnd())
+    }
+
+    /// Start pacing at the `Startup` gain times the initial window over the smoothed RTT,
+    /// rather than at the rate measured from the first few packets.
+    fn init_pacing_rate(&mut self, rtt_est: &RttEstimate) {
+        let bw = Self::rate(to_u64(cwnd_initial(self.mtu())), rtt_est.estimate());
+        self.pacing_rate = Some(bw * to_u64(Self::STARTUP_PACING_GAIN) / to_u64(Self::SCALE));
+    }
+
+    fn set_pacing_rate_with_gain(&mut self, gain: usize) {
+        if self.bw == 0 {
+            return;
+        }
+        let rate =
+            u128::from(self.bw) * (gain as u128) * ((Self::SCALE - Self::PACING_MARGIN) as u128)
+                / ((Self::SCALE * Self::SCALE) as u128);
+        let rate = u64::try_from(rate).unwrap_or(u64::MAX);
+        // Only increase the pacing rate in Startup, as samples might be app-limited.
+        if self.full_bw_reached || self.pacing_rate.is_none_or(|r| rate > r) {
+            self.pacing_rate = Some(rate);
+        }
+    }
+
+    fn set_cwnd(&mut self) {
+        let extra_acked = max(self.extra_acked[0], self.extra_acked[1]);
+        self.max_inflight = self.quantization_budget(
+            self.bdp_multiple(self.bw, self.cwnd_gain)
+                .saturating_add(extra_acked),
+        );
+
+        if self.packet_conservation {
+            self.cwnd = max(self.cwnd, self.bytes_in_flight + self.rs.newly_acked);
+        } else {
+            if self.full_bw_reached {
+                self.cwnd = min(self.cwnd + self.rs.newly_acked, self.max_inflight);
+            } else if self.cwnd < self.max_inflight
+                || self.delivered < to_u64(cwnd_initial(self.mtu()))
+            {
+                self.cwnd += self.rs.newly_acked;
+            }
+            self.cwnd = max(self.cwnd, self.min_pipe_cwnd());
+        }
+
+        if self.state == State::ProbeRtt {
+            self.cwnd = min(self.cwnd, self.probe_rtt_cwnd());
+        }
+
+        // Bound the congestion window by the model.
+        let cap = if self.state.is_probe_bw() && self.state != State::ProbeBwCruise {
+            self.inflight_longterm.unwrap_or(usize::MAX)
+        } else if matches!(self.state, State::ProbeRtt | State::ProbeBwCruise) {
+            self.inflight_with_headroom()
+        } else {
+            usize::MAX
+        };
+        let cap = max(
+            min(cap, self.inflight_shortterm.unwrap_or(usize::MAX)),
+            self.min_pipe_cwnd(),
+        );
+        self.cwnd = min(self.cwnd, cap);
+    }
+
+    /// End loss recovery once a packet sent after recovery started is acknowledged,
+    /// and packet conservation after the first round of recovery.
+    fn update_recovery(&mut self) {
+        if let (Some(start), Some(sent)) = (self.recovery_start, self.rs.sent_time)
+            && sent > start
+        {
+            qdebug!("[{self}] exiting recovery");
+            self.recovery_start = None;
+            self.packet_conservation = false;
+            self.restore_cwnd();
+        }
+        if self.round_start {
+            self.packet_conservation = false;
+        }
+    }
+
+    fn update_model_and_state(&mut self, now: Instant, cc_stats: &mut CongestionControlStats) {
+        self.update_latest_delivery_signals();
+        self.update_congestion_signals(cc_stats);
+        self.update_ack_aggregation(now);
+        self.check_full_bw_reached();
+        self.check_startup_done(now, cc_stats);
+        self.check_drain_done(now);
+        self.update_probe_bw_cycle_phase(now);
+        self.update_min_rtt(now);
+        self.check_probe_rtt(now);
+        self.advance_latest_delivery_signals();
+        self.bound_bw_for_model();
+    }
+}
+
+impl CongestionController for Bbr {
+    fn set_qlog(&mut self, qlog: Qlog) {
+        self.pmtud.set_qlog(qlog.clone());
+        self.qlog = qlog;
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    fn bytes_in_flight(&self) -> usize {
+        self.bytes_in_flight
+    }
+
+    fn cwnd_avail(&self) -> usize {
+        self.cwnd.saturating_sub(self.bytes_in_flight)
+    }
+
+    fn cwnd_min(&self) -> usize {
+        self.min_pipe_cwnd()
+    }
+
+    fn pmtud(&self) -> &Pmtud {
+        &self.pmtud
+    }
+
+    fn pmtud_mut(&mut self) -> &mut Pmtud {
+        &mut self.pmtud
+    }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        self.pacing_rate
+    }
+
+    fn on_packets_acked(
+        &mut self,
+        acked_pkts: &[sent::Packet],
+        rtt_est: &RttEstimate,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) {
+        if self.generate_rate_sample(acked_pkts, rtt_est, now) {
+            self.update_model_and_state(now, cc_stats);
+            self.update_recovery();
+            if self.pacing_rate.is_none() {
+                self.init_pacing_rate(rtt_est);
+            }
+            self.set_pacing_rate_with_gain(self.pacing_gain);
+            self.set_cwnd();
+        }
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [
+                qlog::Metric::CongestionWindow(self.cwnd),
+                qlog::Metric::BytesInFlight(self.bytes_in_flight),
+            ],
+            now,
+        );
+        qdebug!("[{self}] on_packets_acked");
+    }
+
+    fn on_packets_lost(
+        &mut self,
+        _first_rtt_sample_time: Option<Instant>,
+        _prev_largest_acked_sent: Option<Instant>,
+        _pto: Duration,
+        lost_packets: &[sent::Packet],
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        let mut lost_bytes = 0;
+        for pkt in lost_packets {
+            if !pkt.cc_in_flight() {
+                continue;
+            }
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+            let p = self.packets.remove(&(pkt.pn(), pkt.packet_type()));
+            // Lost PMTUD probes say nothing about congestion.
+            if pkt.is_pmtud_probe() {
+                continue;
+            }
+            self.lost += to_u64(pkt.len());
+            lost_bytes += pkt.len();
+            if let Some(p) = p {
+                self.handle_lost_packet(&p, pkt.len(), now);
+            }
+        }
+        if lost_bytes == 0 {
+            return false;
+        }
+
+        self.loss_in_round = true;
+        self.loss_events_in_round += 1;
+        if self.recovery_start.is_some() {
+            self.cwnd = max(self.cwnd.saturating_sub(lost_bytes), self.mtu());
+        } else {
+            qinfo!("[{self}] loss -> recovery");
+            cc_stats.congestion_events.loss += 1;
+            self.prior_cwnd = self.save_cwnd();
+            self.cwnd = self.bytes_in_flight + self.mtu();
+            self.recovery_start = Some(now);
+            self.packet_conservation = true;
+            self.start_round();
+        }
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [
+                qlog::Metric::CongestionWindow(self.cwnd),
+                qlog::Metric::BytesInFlight(self.bytes_in_flight),
+            ],
+            now,
+        );
+        true
+    }
+
+    /// ECN CE marks are treated like loss in the lower bounds of the model,
+    /// but don't cause a recovery episode.
+    fn on_ecn_ce_received(
+        &mut self,
+        _largest_acked_pkt: &sent::Packet,
+        _now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        cc_stats.congestion_events.ecn += 1;
+        self.loss_in_round = true;
+        false
+    }
+
+    fn recovery_packet(&self) -> bool {
+        false
+    }
+
+    fn discard(&mut self, pkt: &sent::Packet, now: Instant) {
+        self.packets.remove(&(pkt.pn(), pkt.packet_type()));
+        if pkt.cc_outstanding() {
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+            qlog::metrics_updated(
+                &mut self.qlog,
+                [qlog::Metric::BytesInFlight(self.bytes_in_flight)],
+                now,
+            );
+            qtrace!("[{self}] Ignore pkt with size {}", pkt.len());
+        }
+    }
+
+    fn on_packet_sent(&mut self, pkt: &sent::Packet, now: Instant) {
+        if !pkt.cc_in_flight() {
+            return;
+        }
+        if self.bytes_in_flight == 0 {
+            // Nothing was in flight, so the application didn't have anything to send.
+            self.first_sent_time = Some(pkt.time_sent());
+            self.delivered_time = Some(pkt.time_sent());
+            self.mark_app_limited();
+            self.handle_restart_from_idle(now);
+        }
+        self.bytes_in_flight += pkt.len();
+        self.packets.insert(
+            (pkt.pn(), pkt.packet_type()),
+            PacketState {
+                delivered: self.delivered,
+                delivered_time: self.delivered_time.unwrap_or(now),
+                first_sent_time: self.first_sent_time.unwrap_or(now),
+                is_app_limited: self.app_limited != 0,
+                tx_in_flight: self.bytes_in_flight,
+                lost: self.lost,
+            },
+        );
+        if self.bytes_in_flight + self.mtu() > self.cwnd {
+            self.cwnd_limited_in_round = true;
+        }
+        qtrace!("[{self}] packet_sent pn={}, ps={}", pkt.pn(), pkt.len());
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [qlog::Metric::BytesInFlight(self.bytes_in_flight)],
+            now,
+        );
+    }
+
+    fn discard_in_flight(&mut self, now: Instant) {
+        self.bytes_in_flight = 0;
+        self.packets.clear();
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [qlog::Metric::BytesInFlight(self.bytes_in_flight)],
+            now,
+        );
+    }
+}
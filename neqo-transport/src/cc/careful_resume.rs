@@ -0,0 +1,231 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Careful Resume, which reuses the congestion window of an earlier connection.
+//
+// See <https://datatracker.ietf.org/doc/draft-ietf-tsvwg-careful-resume/>.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use enum_map::Enum;
+use neqo_common::{Buffer, Decoder, Encoder, qdebug, to_u64};
+
+use crate::{packet, recovery::sent, rtt::RttEstimate};
+
+/// Congestion state that a client saves at the end of a connection, so that
+/// a later connection to the same server can start with a larger window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavedCongestionState {
+    cwnd: usize,
+    rtt: Duration,
+    remote: IpAddr,
+}
+
+impl SavedCongestionState {
+    #[must_use]
+    pub const fn new(cwnd: usize, rtt: Duration, remote: IpAddr) -> Self {
+        Self { cwnd, rtt, remote }
+    }
+
+    /// The congestion window, in bytes.
+    #[must_use]
+    pub const fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    /// The minimum RTT.
+    #[must_use]
+    pub const fn rtt(&self) -> Duration {
+        self.rtt
+    }
+
+    /// The address of the server.  The state is only reused on a path to this address.
+    #[must_use]
+    pub const fn remote(&self) -> IpAddr {
+        self.remote
+    }
+
+    pub(crate) fn encode<B: Buffer>(&self, enc: &mut Encoder<B>) {
+        enc.encode_varint(to_u64(self.cwnd));
+        enc.encode_varint(u64::try_from(self.rtt.as_micros()).unwrap_or(u64::MAX));
+        match self.remote {
+            IpAddr::V4(a) => {
+                enc.encode_byte(4);
+                enc.encode(&a.octets());
+            }
+            IpAddr::V6(a) => {
+                enc.encode_byte(6);
+                enc.encode(&a.octets());
+            }
+        }
+    }
+
+    /// Decode saved state, returning `None` if it is absent or malformed.
+    pub(crate) fn decode(dec: &mut Decoder) -> Option<Self> {
+        let cwnd = usize::try_from(dec.decode_varint()?).ok()?;
+        let rtt = Duration::from_micros(dec.decode_varint()?);
+        let remote = match dec.decode_uint::<u8>()? {
+            4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(dec.decode(4)?).ok()?)),
+            6 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(dec.decode(16)?).ok()?)),
+            _ => return None,
+        };
+        Some(Self::new(cwnd, rtt, remote))
+    }
+}
+
+/// The phases of Careful Resume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum, strum::IntoStaticStr)]
+pub enum Phase {
+    /// Normal slow start, while checking that the path still matches the saved state.
+    #[strum(to_string = "careful_resume_reconnaissance")]
+    Reconnaissance,
+    /// The congestion window has jumped to the saved value, but no packet sent with
+    /// that window has been acknowledged.
+    #[strum(to_string = "careful_resume_unvalidated")]
+    Unvalidated,
+    /// Packets sent during the unvalidated phase are being acknowledged.
+    #[strum(to_string = "careful_resume_validating")]
+    Validating,
+    /// Congestion was detected for packets sent with the jumped window.
+    #[strum(to_string = "careful_resume_safe_retreat")]
+    SafeRetreat,
+    /// Careful Resume has finished, or was abandoned.
+    #[strum(to_string = "careful_resume_normal")]
+    Normal,
+}
+
+/// The state of Careful Resume for a path.
+#[derive(Debug)]
+pub struct CarefulResume {
+    saved: SavedCongestionState,
+    phase: Phase,
+    /// The number of bytes acknowledged since the connection started, which is a
+    /// measure of the capacity that the path has been shown to have.
+    pipesize: usize,
+    /// The first and last packets sent during the unvalidated phase.
+    first_unvalidated: Option<packet::Number>,
+    last_unvalidated: Option<packet::Number>,
+}
+
+impl CarefulResume {
+    #[must_use]
+    pub const fn new(saved: SavedCongestionState) -> Self {
+        Self {
+            saved,
+            phase: Phase::Reconnaissance,
+            pipesize: 0,
+            first_unvalidated: None,
+            last_unvalidated: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    #[must_use]
+    pub const fn pipesize(&self) -> usize {
+        self.pipesize
+    }
+
+    /// The window to jump to: half of the saved congestion window.
+    #[must_use]
+    pub const fn jump_cwnd(&self) -> usize {
+        self.saved.cwnd / 2
+    }
+
+    /// Whether the congestion window must not grow in the current phase.
+    #[must_use]
+    pub const fn holds_cwnd(&self) -> bool {
+        matches!(self.phase, Phase::Unvalidated | Phase::SafeRetreat)
+    }
+
+    fn set_phase(&mut self, phase: Phase) -> Option<Phase> {
+        qdebug!("Careful Resume {:?} -> {phase:?}", self.phase);
+        self.phase = phase;
+        Some(phase)
+    }
+
+    pub fn on_packet_sent(&mut self, pn: packet::Number) {
+        if self.phase == Phase::Unvalidated {
+            self.first_unvalidated.get_or_insert(pn);
+            self.last_unvalidated = Some(pn);
+        }
+    }
+
+    /// Whether the RTT measured on this connection is close enough to the saved one
+    /// for the saved window to apply.
+    fn rtt_confirmed(&self, rtt: Duration) -> bool {
+        rtt >= self.saved.rtt / 2 && rtt < self.saved.rtt * 10
+    }
+
+    /// Whether `acked_pkts` include a packet sent at or after `pn`.
+    fn acked(acked_pkts: &[sent::Packet], pn: Option<packet::Number>) -> bool {
+        pn.is_some_and(|pn| acked_pkts.first().is_some_and(|p| p.pn() >= pn))
+    }
+
+    /// Process acknowledged packets.  `cwnd_limited` is whether the sender was using
+    /// the whole congestion window.  Returns the new phase, if it changed.
+    pub fn on_packets_acked(
+        &mut self,
+        acked_pkts: &[sent::Packet],
+        rtt_est: &RttEstimate,
+        cwnd: usize,
+        cwnd_limited: bool,
+    ) -> Option<Phase> {
+        self.pipesize += acked_pkts
+            .iter()
+            .filter(|p| p.cc_outstanding())
+            .map(sent::Packet::len)
+            .sum::<usize>();
+        match self.phase {
+            Phase::Reconnaissance => {
+                if rtt_est.is_guesstimate() || rtt_est.first_sample_time().is_none() {
+                    None
+                } else if !self.rtt_confirmed(rtt_est.latest_rtt()) || self.jump_cwnd() <= cwnd {
+                    self.set_phase(Phase::Normal)
+                } else if cwnd_limited {
+                    self.set_phase(Phase::Unvalidated)
+                } else {
+                    None
+                }
+            }
+            Phase::Unvalidated if Self::acked(acked_pkts, self.first_unvalidated) => {
+                self.set_phase(Phase::Validating)
+            }
+            Phase::Validating | Phase::SafeRetreat
+                if Self::acked(acked_pkts, self.last_unvalidated) =>
+            {
+                self.set_phase(Phase::Normal)
+            }
+            _ => None,
+        }
+    }
+
+    /// Process a congestion event.  Returns the new phase, if it changed.
+    pub fn on_congestion_event(&mut self) -> Option<Phase> {
+        match self.phase {
+            Phase::Reconnaissance => self.set_phase(Phase::Normal),
+            // Nothing was sent with the jumped window, so there is nothing to retreat from.
+            Phase::Unvalidated if self.last_unvalidated.is_none() => self.set_phase(Phase::Normal),
+            Phase::Unvalidated | Phase::Validating => self.set_phase(Phase::SafeRetreat),
+            Phase::SafeRetreat | Phase::Normal => None,
+        }
+    }
+
+    /// Abandon Careful Resume, such as after persistent congestion.
+    pub fn abandon(&mut self) -> Option<Phase> {
+        if self.phase == Phase::Normal {
+            None
+        } else {
+            self.set_phase(Phase::Normal)
+        }
+    }
+}
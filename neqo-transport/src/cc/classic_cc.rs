@@ -18,7 +18,11 @@ use rustc_hash::FxHashMap as HashMap;
 use super::CongestionController;
 use crate::{
     Pmtud,
-    cc::CongestionTrigger::{self, Ecn, Loss},
+    cc::{
+        CongestionTrigger::{self, Ecn, Loss},
+        SavedCongestionState,
+        careful_resume::{self, CarefulResume},
+    },
     packet, qlog,
     recovery::sent,
     rtt::RttEstimate,
@@ -100,6 +104,18 @@ pub trait WindowAdjustment: Display + Debug {
     ) -> (usize, usize);
     /// Cubic needs this signal to reset its epoch.
     fn on_app_limited(&mut self);
+    /// Called with the number of packets that the peer newly reported as ECN CE marked,
+    /// before the ACK frame that reported them is processed.
+    fn on_ecn_ce_marks(&mut self, _ce_marks: u64) {}
+    /// Called with all packets newly acknowledged by an ACK frame, including those that
+    /// don't count towards growing the congestion window.
+    fn on_packets_acked(
+        &mut self,
+        _acked_pkts: &[sent::Packet],
+        _now: Instant,
+        _cc_stats: &mut CongestionControlStats,
+    ) {
+    }
     /// Store the current congestion controller state, to be recovered in the case of a spurious
     /// congestion event.
     fn save_undo_state(&mut self);
@@ -216,6 +232,8 @@ pub struct ClassicCongestionController<S, T> {
     stored: Option<State>,
     /// Whether to recover from spurious congestion events by restoring prior state.
     spurious_recovery: bool,
+    /// Careful Resume state, if the connection resumed with saved congestion state.
+    careful_resume: Option<CarefulResume>,
 }
 
 impl<S: Display, T: Display> Display for ClassicCongestionController<S, T> {
@@ -290,6 +308,7 @@ where
     ) {
         let mut is_app_limited = true;
         let mut new_acked = 0;
+        let cwnd_limited = !self.app_limited();
         let largest_packet_acked = acked_pkts
             .first()
             .expect("`acked_pkts.first().is_some()` is checked in `Loss::on_ack_received`");
@@ -332,10 +351,30 @@ where
             new_acked += pkt.len();
         }
 
+        self.congestion_control
+            .on_packets_acked(acked_pkts, now, cc_stats);
+
         if self.current.phase.in_slow_start() {
             self.slow_start.record_acked_bytes(new_acked);
         }
 
+        if self.careful_resume_on_packets_acked(acked_pkts, rtt_est, cwnd_limited, now, cc_stats) {
+            qdebug!(
+                "[{self}] on_packets_acked this={self:p}, careful resume holds cwnd={}, bytes_in_flight={}",
+                self.current.congestion_window,
+                self.bytes_in_flight,
+            );
+            qlog::metrics_updated(
+                &mut self.qlog,
+                [
+                    qlog::Metric::CongestionWindow(self.current.congestion_window),
+                    qlog::Metric::BytesInFlight(self.bytes_in_flight),
+                ],
+                now,
+            );
+            return;
+        }
+
         if is_app_limited {
             self.congestion_control.on_app_limited();
             qdebug!(
@@ -523,6 +562,12 @@ where
             lost_packets_no_pmtud(),
             now,
         );
+        if persistent_congestion && let Some(cr) = &mut self.careful_resume {
+            let old = cr.phase();
+            if let Some(phase) = cr.abandon() {
+                self.set_careful_resume_phase(Some(old.into()), phase, now, cc_stats);
+            }
+        }
         qdebug!(
             "on_packets_lost this={self:p}, bytes_in_flight={}, cwnd={}, phase={:?}",
             self.bytes_in_flight,
@@ -545,6 +590,17 @@ where
         self.on_congestion_event(largest_acked_pkt, Ecn, self.bytes_in_flight, now, cc_stats)
     }
 
+    fn on_ecn_ce_marks_received(
+        &mut self,
+        largest_acked_pkt: &sent::Packet,
+        ce_marks: u64,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        self.congestion_control.on_ecn_ce_marks(ce_marks);
+        self.on_ecn_ce_received(largest_acked_pkt, now, cc_stats)
+    }
+
     fn discard(&mut self, pkt: &sent::Packet, now: Instant) {
         if pkt.cc_outstanding() {
             assert!(self.bytes_in_flight >= pkt.len());
@@ -579,6 +635,10 @@ where
             return;
         }
 
+        if let Some(cr) = &mut self.careful_resume {
+            cr.on_packet_sent(pkt.pn());
+        }
+
         // Pass next packet number to send into slow start algorithm during slow start.
         if self.current.phase.in_slow_start() {
             self.slow_start.on_packet_sent(pkt.pn(), pkt.len());
@@ -609,9 +669,22 @@ where
     fn recovery_packet(&self) -> bool {
         self.current.phase == Phase::RecoveryStart
     }
+
+    fn careful_resume(
+        &mut self,
+        saved: SavedCongestionState,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) {
+        qinfo!("[{self}] Careful Resume with {saved:?}");
+        let cr = CarefulResume::new(saved);
+        let phase = cr.phase();
+        self.careful_resume = Some(cr);
+        self.set_careful_resume_phase(Some(self.current.phase.into()), phase, now, cc_stats);
+    }
 }
 
-const fn cwnd_initial(mtu: usize) -> usize {
+pub const fn cwnd_initial(mtu: usize) -> usize {
     const_min(CWND_INITIAL_PKTS * mtu, const_max(2 * mtu, 14_720))
 }
 
@@ -638,6 +711,7 @@ where
             current: State::new(mtu),
             stored: None,
             spurious_recovery,
+            careful_resume: None,
         }
     }
 
@@ -671,6 +745,101 @@ where
         self.current.acked_bytes
     }
 
+    #[cfg(test)]
+    pub fn careful_resume_phase(&self) -> Option<careful_resume::Phase> {
+        self.careful_resume.as_ref().map(CarefulResume::phase)
+    }
+
+    /// Record a Careful Resume phase change and adjust the congestion window for the new phase.
+    fn set_careful_resume_phase(
+        &mut self,
+        old: Option<&'static str>,
+        phase: careful_resume::Phase,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) {
+        let Some(cr) = &self.careful_resume else {
+            return;
+        };
+        let (jump_cwnd, pipesize) = (cr.jump_cwnd(), cr.pipesize());
+        match phase {
+            careful_resume::Phase::Unvalidated => {
+                self.current.congestion_window = jump_cwnd;
+                cc_stats.careful_resume.jump_cwnd = Some(jump_cwnd);
+            }
+            // Don't send more unvalidated data, but keep what the path has shown it can carry.
+            careful_resume::Phase::Validating => {
+                self.current.congestion_window = max(self.bytes_in_flight, pipesize);
+            }
+            // Leaving safe retreat, slow start up to the capacity that was validated.
+            careful_resume::Phase::Normal
+                if old == Some(careful_resume::Phase::SafeRetreat.into()) =>
+            {
+                self.current.ssthresh = Some(max(pipesize, self.current.congestion_window));
+            }
+            // The window for safe retreat is set by `on_congestion_event`.
+            _ => {}
+        }
+        qinfo!(
+            "[{self}] Careful Resume -> {phase:?}; cwnd {}, pipesize {pipesize}",
+            self.current.congestion_window
+        );
+        cc_stats.careful_resume.phases[phase] += 1;
+        qlog::congestion_state_updated(&mut self.qlog, old, phase.into(), None, now);
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [qlog::Metric::CongestionWindow(
+                self.current.congestion_window,
+            )]
+            .into_iter()
+            .chain(self.current.ssthresh.map(qlog::Metric::SsThresh)),
+            now,
+        );
+    }
+
+    /// Pass acknowledged packets to Careful Resume.
+    /// Returns true if the congestion window is not to grow.
+    fn careful_resume_on_packets_acked(
+        &mut self,
+        acked_pkts: &[sent::Packet],
+        rtt_est: &RttEstimate,
+        cwnd_limited: bool,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        let Some(cr) = &mut self.careful_resume else {
+            return false;
+        };
+        let old = cr.phase();
+        if let Some(phase) = cr.on_packets_acked(
+            acked_pkts,
+            rtt_est,
+            self.current.congestion_window,
+            cwnd_limited,
+        ) {
+            self.set_careful_resume_phase(Some(old.into()), phase, now, cc_stats);
+        }
+        self.careful_resume
+            .as_ref()
+            .is_some_and(CarefulResume::holds_cwnd)
+    }
+
+    /// Pass a congestion event to Careful Resume.
+    /// Returns the congestion window to retreat to, if packets sent with the jumped
+    /// window were affected.
+    fn careful_resume_on_congestion_event(
+        &mut self,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> Option<usize> {
+        let cr = self.careful_resume.as_mut()?;
+        let old = cr.phase();
+        let phase = cr.on_congestion_event()?;
+        let pipesize = cr.pipesize();
+        self.set_careful_resume_phase(Some(old.into()), phase, now, cc_stats);
+        (phase == careful_resume::Phase::SafeRetreat).then_some(pipesize / 2)
+    }
+
     fn set_phase(
         &mut self,
         phase: Phase,
@@ -906,13 +1075,18 @@ where
         }
 
         let detection_cwnd = self.current.congestion_window;
-        let (cwnd, acked_bytes) = self.congestion_control.reduce_cwnd(
-            self.current.congestion_window,
-            self.current.acked_bytes,
-            self.max_datagram_size(),
-            congestion_trigger,
-            cc_stats,
-        );
+        let (cwnd, acked_bytes) =
+            if let Some(cwnd) = self.careful_resume_on_congestion_event(now, cc_stats) {
+                (cwnd, 0)
+            } else {
+                self.congestion_control.reduce_cwnd(
+                    self.current.congestion_window,
+                    self.current.acked_bytes,
+                    self.max_datagram_size(),
+                    congestion_trigger,
+                    cc_stats,
+                )
+            };
         self.current.congestion_window = max(cwnd, self.cwnd_min());
         self.current.acked_bytes = acked_bytes;
         self.current.ssthresh = Some(self.current.congestion_window);
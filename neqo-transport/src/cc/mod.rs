@@ -7,7 +7,8 @@
 // Congestion control
 
 use std::{
-    fmt::{Debug, Display},
+    fmt::{self, Debug, Display, Formatter},
+    rc::Rc,
     time::{Duration, Instant},
 };
 
@@ -15,13 +16,18 @@ use neqo_common::qlog::Qlog;
 
 use crate::{Pmtud, recovery::sent, rtt::RttEstimate, stats::CongestionControlStats};
 
+mod bbr;
+mod careful_resume;
 mod classic_cc;
 mod classic_slow_start;
 mod cubic;
 mod hystart;
 mod new_reno;
+mod prague;
 mod search;
 
+pub use bbr::Bbr;
+pub use careful_resume::{Phase as CarefulResumePhase, SavedCongestionState};
 pub use classic_cc::{
     CWND_INITIAL_PKTS, ClassicCongestionController, PERSISTENT_CONG_THRESH, Phase,
 };
@@ -29,6 +35,7 @@ pub use classic_slow_start::ClassicSlowStart;
 pub use cubic::Cubic;
 pub use hystart::{HyStart, HyStartCssBaseline};
 pub use new_reno::NewReno;
+pub use prague::Prague;
 #[cfg(test)]
 pub use search::Outcome;
 pub use search::Search;
@@ -42,27 +49,47 @@ pub enum CongestionTrigger {
     Ecn,
 }
 
+/// A congestion controller for a single path.
+///
+/// Applications can supply their own implementation using
+/// [`crate::ConnectionParameters::congestion_control_factory`].
 pub trait CongestionController: Display + Debug {
     fn set_qlog(&mut self, qlog: Qlog);
 
+    /// The congestion window, in bytes.
     #[must_use]
     fn cwnd(&self) -> usize;
 
+    /// The number of bytes in packets that count towards the congestion window.
     #[must_use]
     fn bytes_in_flight(&self) -> usize;
 
+    /// The number of bytes that can be sent now.
     #[must_use]
     fn cwnd_avail(&self) -> usize;
 
+    /// The smallest value the congestion window can take.
     #[must_use]
     fn cwnd_min(&self) -> usize;
 
+    /// The rate at which to pace packets, in bytes per second, for controllers that
+    /// set it directly.  `None` means that the pacing rate is derived from the
+    /// congestion window and RTT.
+    #[must_use]
+    fn pacing_rate(&self) -> Option<u64> {
+        None
+    }
+
+    /// The path MTU discovery state, which the controller owns.
     #[must_use]
     fn pmtud(&self) -> &Pmtud;
 
     #[must_use]
     fn pmtud_mut(&mut self) -> &mut Pmtud;
 
+    /// Called when packets are acknowledged, with the largest packet number first.
+    /// If the peer reports receive timestamps, packets carry the time that they
+    /// were received; see [`sent::Packet::time_received`].
     fn on_packets_acked(
         &mut self,
         acked_pkts: &[sent::Packet],
@@ -71,6 +98,7 @@ pub trait CongestionController: Display + Debug {
         cc_stats: &mut CongestionControlStats,
     );
 
+    /// Called when packets are declared lost.
     /// Returns true if the congestion window was reduced.
     fn on_packets_lost(
         &mut self,
@@ -82,6 +110,7 @@ pub trait CongestionController: Display + Debug {
         cc_stats: &mut CongestionControlStats,
     ) -> bool;
 
+    /// Called when the peer reports an increase in ECN CE marks.
     /// Returns true if the congestion window was reduced.
     fn on_ecn_ce_received(
         &mut self,
@@ -90,14 +119,46 @@ pub trait CongestionController: Display + Debug {
         cc_stats: &mut CongestionControlStats,
     ) -> bool;
 
+    /// Called when the peer reports an increase in ECN CE marks, with the number of
+    /// packets that were newly reported as CE marked.
+    /// The default ignores the number and calls [`Self::on_ecn_ce_received`].
+    /// Returns true if the congestion window was reduced.
+    fn on_ecn_ce_marks_received(
+        &mut self,
+        largest_acked_pkt: &sent::Packet,
+        _ce_marks: u64,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        self.on_ecn_ce_received(largest_acked_pkt, now, cc_stats)
+    }
+
+    /// Whether the next packet is the first sent in a recovery period,
+    /// which is allowed to exceed the congestion window.
     #[must_use]
     fn recovery_packet(&self) -> bool;
 
+    /// Stop tracking a packet that will not be acknowledged or declared lost.
     fn discard(&mut self, pkt: &sent::Packet, now: Instant);
 
+    /// Called for every packet that is sent.
     fn on_packet_sent(&mut self, pkt: &sent::Packet, now: Instant);
 
+    /// Stop tracking all packets in flight, such as after a path change.
     fn discard_in_flight(&mut self, now: Instant);
+
+    /// Start Careful Resume with congestion state saved from an earlier connection
+    /// on the same path.  This is called before any packet is sent.
+    /// Controllers that don't support Careful Resume ignore this.
+    ///
+    /// See <https://datatracker.ietf.org/doc/draft-ietf-tsvwg-careful-resume/>.
+    fn careful_resume(
+        &mut self,
+        _saved: SavedCongestionState,
+        _now: Instant,
+        _cc_stats: &mut CongestionControlStats,
+    ) {
+    }
 }
 
 #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, strum::EnumString, strum::VariantNames)]
@@ -108,6 +169,13 @@ pub enum CongestionControl {
     #[strum(serialize = "cubic")]
     #[default]
     Cubic,
+    #[strum(serialize = "bbr")]
+    Bbr,
+    /// Prague, for L4S.  Marks packets ECT(1) unless
+    /// [`crate::ConnectionParameters::ecn_codepoint`] says otherwise.
+    /// Always uses classic slow start.
+    #[strum(serialize = "prague")]
+    Prague,
 }
 
 #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, strum::EnumString, strum::VariantNames)]
@@ -122,11 +190,33 @@ pub enum SlowStart {
     Search,
 }
 
+/// Creates the congestion controller for a path, in place of the built-in
+/// controllers that [`CongestionControl`] selects from.
+/// The controller that is created takes ownership of the path's [`Pmtud`] state.
+#[derive(Clone)]
+pub struct CongestionControlFactory(Rc<dyn Fn(Pmtud) -> Box<dyn CongestionController>>);
+
+impl CongestionControlFactory {
+    pub fn new(f: Box<dyn Fn(Pmtud) -> Box<dyn CongestionController>>) -> Self {
+        Self(Rc::from(f))
+    }
+
+    pub fn create(&self, pmtud: Pmtud) -> Box<dyn CongestionController> {
+        (self.0)(pmtud)
+    }
+}
+
+impl Debug for CongestionControlFactory {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("CongestionControlFactory")
+    }
+}
+
 /// A concrete congestion controller, dispatching across all combinations of
-/// algorithm and slow-start strategy.
+/// algorithm and slow-start strategy, and any controller the application supplies.
 ///
 /// This enum avoids the heap allocation and vtable indirection of `Box<dyn CongestionController>`
-/// on the per-packet hot path.
+/// on the per-packet hot path for the built-in controllers.
 #[derive(Debug, strum::Display)]
 pub enum CongestionControlImplementation {
     #[strum(to_string = "{0}")]
@@ -141,12 +231,28 @@ pub enum CongestionControlImplementation {
     HyStartCubic(ClassicCongestionController<HyStart, Cubic>),
     #[strum(to_string = "{0}")]
     SearchCubic(ClassicCongestionController<Search, Cubic>),
+    #[strum(to_string = "{0}")]
+    Bbr(Bbr),
+    #[strum(to_string = "{0}")]
+    Prague(ClassicCongestionController<ClassicSlowStart, Prague>),
+    #[strum(to_string = "{0}")]
+    Custom(Box<dyn CongestionController>),
 }
 
 macro_rules! dispatch {
     ($self:ident . $method:ident $args:tt) => {
         neqo_common::dispatch!(
-            [ClassicNewReno, HyStartNewReno, SearchNewReno, ClassicCubic, HyStartCubic, SearchCubic]
+            [
+                ClassicNewReno,
+                HyStartNewReno,
+                SearchNewReno,
+                ClassicCubic,
+                HyStartCubic,
+                SearchCubic,
+                Bbr,
+                Prague,
+                Custom,
+            ]
             $self . $method $args
         )
     };
@@ -173,6 +279,10 @@ impl CongestionController for CongestionControlImplementation {
         dispatch!(self.cwnd_min())
     }
 
+    fn pacing_rate(&self) -> Option<u64> {
+        dispatch!(self.pacing_rate())
+    }
+
     fn pmtud(&self) -> &Pmtud {
         dispatch!(self.pmtud())
     }
@@ -219,6 +329,16 @@ impl CongestionController for CongestionControlImplementation {
         dispatch!(self.on_ecn_ce_received(largest_acked_pkt, now, cc_stats))
     }
 
+    fn on_ecn_ce_marks_received(
+        &mut self,
+        largest_acked_pkt: &sent::Packet,
+        ce_marks: u64,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        dispatch!(self.on_ecn_ce_marks_received(largest_acked_pkt, ce_marks, now, cc_stats))
+    }
+
     fn recovery_packet(&self) -> bool {
         dispatch!(self.recovery_packet())
     }
@@ -234,6 +354,15 @@ impl CongestionController for CongestionControlImplementation {
     fn discard_in_flight(&mut self, now: Instant) {
         dispatch!(self.discard_in_flight(now));
     }
+
+    fn careful_resume(
+        &mut self,
+        saved: SavedCongestionState,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) {
+        dispatch!(self.careful_resume(saved, now, cc_stats));
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,165 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Prague congestion control, for L4S.
+//
+// See <https://datatracker.ietf.org/doc/html/draft-briscoe-iccrg-prague-congestion-control>
+// and, for the estimate of the marking fraction, DCTCP in RFC 8257.
+
+use std::{
+    cmp::min,
+    fmt::{self, Display},
+    time::{Duration, Instant},
+};
+
+use neqo_common::{qdebug, to_u64};
+
+use crate::{
+    cc::{CongestionTrigger, classic_cc::WindowAdjustment},
+    recovery::sent,
+    stats::CongestionControlStats,
+};
+
+/// A Reno-style congestion controller that responds to ECN CE marks in proportion to
+/// the fraction of bytes that are marked, rather than halving the congestion window.
+///
+/// Packets are marked ECT(1) so that L4S-aware queues apply a shallow marking threshold.
+/// Loss is still handled like `NewReno`.
+#[derive(Debug)]
+pub struct Prague {
+    /// The moving average of the fraction of CE marked bytes, `alpha` in RFC 8257,
+    /// scaled by [`Self::ALPHA_SCALE`].
+    alpha: u64,
+    /// When the current round started.  A round ends when a packet sent after this is
+    /// acknowledged.
+    round_start: Option<Instant>,
+    /// The number of packets acknowledged in this round.
+    acked_pkts: u64,
+    /// The number of packets reported as CE marked in this round.
+    ce_marks: u64,
+}
+
+impl Default for Prague {
+    fn default() -> Self {
+        Self {
+            alpha: Self::ALPHA_SCALE,
+            round_start: None,
+            acked_pkts: 0,
+            ce_marks: 0,
+        }
+    }
+}
+
+impl Display for Prague {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Prague")
+    }
+}
+
+impl Prague {
+    /// The fixed-point scale of `alpha` and the marking fraction.
+    pub const ALPHA_SCALE: u64 = 1 << 10;
+    /// The gain of the moving average, as a shift: g = 1/16.
+    ///
+    /// <https://datatracker.ietf.org/doc/html/rfc8257#section-4.2>
+    const G_SHIFT: u32 = 4;
+
+    /// The moving average of the fraction of CE marked bytes, scaled by [`Self::ALPHA_SCALE`].
+    #[cfg(test)]
+    pub const fn alpha(&self) -> u64 {
+        self.alpha
+    }
+
+    /// Fold the fraction of marked bytes in the round that just ended into `alpha`.
+    ///
+    /// The peer reports CE marks as packet counts, so the fraction of marked bytes is
+    /// estimated from the fraction of marked packets.
+    fn end_round(&mut self, now: Instant, cc_stats: &mut CongestionControlStats) {
+        let fraction = if self.acked_pkts == 0 {
+            0
+        } else {
+            min(self.ce_marks, self.acked_pkts) * Self::ALPHA_SCALE / self.acked_pkts
+        };
+        // Like Linux DCTCP, let `alpha` decay to zero once the shift no longer reduces it.
+        let decay = self.alpha >> Self::G_SHIFT;
+        self.alpha -= if decay == 0 { self.alpha } else { decay };
+        self.alpha += fraction >> Self::G_SHIFT;
+        qdebug!(
+            "[{self}] round ended: {} of {} packets CE marked, alpha {}",
+            self.ce_marks,
+            self.acked_pkts,
+            self.alpha
+        );
+        cc_stats.ecn_ce_fraction = Some(
+            f64::from(u16::try_from(fraction).expect("fraction <= ALPHA_SCALE"))
+                / f64::from(u16::try_from(Self::ALPHA_SCALE).expect("fits")),
+        );
+        self.round_start = Some(now);
+        self.acked_pkts = 0;
+        self.ce_marks = 0;
+    }
+}
+
+impl WindowAdjustment for Prague {
+    fn bytes_for_cwnd_increase(
+        &mut self,
+        curr_cwnd: usize,
+        _new_acked_bytes: usize,
+        _min_rtt: Duration,
+        _max_datagram_size: usize,
+        _now: Instant,
+    ) -> usize {
+        curr_cwnd
+    }
+
+    fn reduce_cwnd(
+        &mut self,
+        curr_cwnd: usize,
+        acked_bytes: usize,
+        _max_datagram_size: usize,
+        congestion_trigger: CongestionTrigger,
+        _cc_stats: &mut CongestionControlStats,
+    ) -> (usize, usize) {
+        match congestion_trigger {
+            CongestionTrigger::Loss(_) => (curr_cwnd / 2, acked_bytes / 2),
+            // cwnd = cwnd * (1 - alpha / 2)
+            CongestionTrigger::Ecn => {
+                let alpha = usize::try_from(self.alpha).expect("alpha <= ALPHA_SCALE");
+                let divisor = 2 * usize::try_from(Self::ALPHA_SCALE).expect("fits");
+                (
+                    curr_cwnd - curr_cwnd * alpha / divisor,
+                    acked_bytes - acked_bytes * alpha / divisor,
+                )
+            }
+        }
+    }
+
+    fn on_app_limited(&mut self) {}
+
+    fn on_ecn_ce_marks(&mut self, ce_marks: u64) {
+        self.ce_marks += ce_marks;
+    }
+
+    fn on_packets_acked(
+        &mut self,
+        acked_pkts: &[sent::Packet],
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) {
+        self.acked_pkts += to_u64(acked_pkts.len());
+        let round_start = *self.round_start.get_or_insert(now);
+        if acked_pkts
+            .first()
+            .is_some_and(|p| p.time_sent() >= round_start)
+        {
+            self.end_round(now, cc_stats);
+        }
+    }
+
+    fn save_undo_state(&mut self) {}
+
+    fn restore_undo_state(&mut self, _cc_stats: &mut CongestionControlStats) {}
+}
@@ -0,0 +1,243 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! BBRv3 test suite
+
+use std::{
+    cmp::max,
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use neqo_common::to_u64;
+use test_fixture::{fixture_init, now};
+
+use super::{RTT, make_cc_bbr};
+use crate::{
+    cc::{Bbr, CongestionControl, CongestionController as _, bbr::State},
+    packet, recovery,
+    recovery::sent,
+    rtt::RttEstimate,
+    stats::CongestionControlStats,
+};
+
+/// The bottleneck rate of the simulated path, in bytes per second (10 Mbit/s).
+const RATE: u64 = 1_250_000;
+const PTO: Duration = Duration::from_millis(300);
+
+/// A sender that always has data to send, over a path with a single bottleneck
+/// with an unlimited FIFO queue.  Every packet is acknowledged individually,
+/// [`RTT`] after it leaves the bottleneck.
+struct Sim {
+    cc: Bbr,
+    rtt_est: RttEstimate,
+    stats: CongestionControlStats,
+    now: Instant,
+    next_pn: u64,
+    /// The earliest time the pacer allows the next packet to be sent.
+    next_send: Instant,
+    /// When the bottleneck finishes sending the packets it has queued.
+    busy_until: Instant,
+    /// Packets in flight, with the time they are acknowledged.
+    in_flight: VecDeque<(sent::Packet, Instant)>,
+}
+
+impl Sim {
+    fn new() -> Self {
+        let now = now();
+        Self {
+            cc: make_cc_bbr(),
+            rtt_est: RttEstimate::new(RTT),
+            stats: CongestionControlStats::default(),
+            now,
+            next_pn: 0,
+            next_send: now,
+            busy_until: now,
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        self.cc.pmtud().plpmtu()
+    }
+
+    fn send(&mut self) {
+        let mtu = self.mtu();
+        while self.cc.cwnd_avail() >= mtu && self.next_send <= self.now {
+            let pkt = sent::make_packet(self.next_pn, self.now, mtu);
+            self.next_pn += 1;
+            self.cc.on_packet_sent(&pkt, self.now);
+            self.busy_until = max(self.now, self.busy_until)
+                + Duration::from_nanos(to_u64(mtu) * 1_000_000_000 / RATE);
+            self.in_flight.push_back((pkt, self.busy_until + RTT));
+            if let Some(rate) = self.cc.pacing_rate() {
+                self.next_send =
+                    self.now + Duration::from_nanos(to_u64(mtu) * 1_000_000_000 / rate.max(1));
+            }
+        }
+    }
+
+    /// Run the simulation until `end`, calling `f` after every ACK.
+    fn run_until<F: FnMut(&Bbr)>(&mut self, end: Instant, mut f: F) {
+        while self.now < end {
+            self.send();
+            let next_send = (self.cc.cwnd_avail() >= self.mtu()).then_some(self.next_send);
+            let next_ack = self.in_flight.front().map(|&(_, t)| t);
+            self.now = [next_send, next_ack]
+                .into_iter()
+                .flatten()
+                .min()
+                .expect("either sending or waiting for an ACK");
+            while self.in_flight.front().is_some_and(|&(_, t)| t <= self.now) {
+                let (pkt, _) = self.in_flight.pop_front().unwrap();
+                self.cc
+                    .on_packets_acked(&[pkt], &self.rtt_est, self.now, &mut self.stats);
+                f(&self.cc);
+            }
+        }
+    }
+}
+
+#[test]
+fn parse() {
+    assert_eq!(
+        "bbr".parse::<CongestionControl>(),
+        Ok(CongestionControl::Bbr)
+    );
+    assert_eq!(
+        "BBR".parse::<CongestionControl>(),
+        Ok(CongestionControl::Bbr)
+    );
+}
+
+#[test]
+fn startup_estimates_path() {
+    fixture_init();
+    let mut sim = Sim::new();
+    assert_eq!(sim.cc.state(), State::Startup);
+    assert_eq!(sim.cc.pacing_rate(), None);
+
+    let end = sim.now + Duration::from_secs(3);
+    sim.run_until(end, |_| {});
+
+    assert!(sim.stats.slow_start_exit.is_some());
+    assert!(
+        matches!(
+            sim.cc.state(),
+            State::ProbeBwDown | State::ProbeBwCruise | State::ProbeBwRefill | State::ProbeBwUp
+        ),
+        "unexpected state {:?}",
+        sim.cc.state()
+    );
+    let bw = sim.cc.bw();
+    assert!(
+        bw > RATE * 9 / 10 && bw < RATE * 105 / 100,
+        "bandwidth estimate {bw} should be close to {RATE}"
+    );
+    let min_rtt = sim.cc.min_rtt().unwrap();
+    assert!(
+        min_rtt >= RTT && min_rtt < RTT + Duration::from_millis(5),
+        "min_rtt {min_rtt:?} should be close to {RTT:?}"
+    );
+    assert!(sim.cc.pacing_rate().is_some());
+}
+
+#[test]
+fn probe_rtt() {
+    fixture_init();
+    let mut sim = Sim::new();
+    let start = sim.now;
+    let mut entered = None;
+    sim.run_until(start + Duration::from_secs(7), |cc| {
+        if cc.state() == State::ProbeRtt && entered.is_none() {
+            entered = Some(cc.min_rtt().unwrap());
+        }
+    });
+    assert!(entered.is_some(), "ProbeRTT was not entered");
+    assert_ne!(sim.cc.state(), State::ProbeRtt, "ProbeRTT did not end");
+    assert_eq!(sim.cc.min_rtt(), entered);
+}
+
+#[test]
+fn loss_enters_recovery() {
+    fixture_init();
+    let mut cc = make_cc_bbr();
+    let mut stats = CongestionControlStats::default();
+    let rtt_est = RttEstimate::new(RTT);
+    let mtu = cc.pmtud().plpmtu();
+    let mut now = now();
+    let pkts: Vec<_> = (0..10).map(|pn| sent::make_packet(pn, now, mtu)).collect();
+    for pkt in &pkts {
+        cc.on_packet_sent(pkt, now);
+    }
+    let cwnd_before = cc.cwnd();
+
+    now += RTT;
+    assert!(cc.on_packets_lost(None, None, PTO, &pkts[..1], now, &mut stats));
+    assert_eq!(stats.congestion_events.loss, 1);
+    assert_eq!(cc.bytes_in_flight(), 9 * mtu);
+    assert_eq!(cc.cwnd(), cc.bytes_in_flight() + mtu);
+
+    // More losses in the same recovery episode reduce the window further,
+    // but aren't counted as another congestion event.
+    assert!(cc.on_packets_lost(None, None, PTO, &pkts[1..2], now, &mut stats));
+    assert_eq!(stats.congestion_events.loss, 1);
+    assert_eq!(cc.cwnd(), 9 * mtu);
+
+    // Acknowledging a packet that was sent before recovery started doesn't end it.
+    cc.on_packets_acked(&pkts[2..3], &rtt_est, now, &mut stats);
+    assert!(cc.on_packets_lost(None, None, PTO, &pkts[3..4], now, &mut stats));
+    assert_eq!(stats.congestion_events.loss, 1);
+
+    // Acknowledging a packet sent after recovery started does,
+    // and restores the congestion window.
+    now += Duration::from_millis(10);
+    let pkt = sent::make_packet(10, now, mtu);
+    cc.on_packet_sent(&pkt, now);
+    now += RTT;
+    cc.on_packets_acked(&[pkt], &rtt_est, now, &mut stats);
+    assert!(cc.cwnd() >= cwnd_before);
+
+    // So the next loss is a new congestion event.
+    assert!(cc.on_packets_lost(None, None, PTO, &pkts[4..5], now, &mut stats));
+    assert_eq!(stats.congestion_events.loss, 2);
+}
+
+#[test]
+fn lost_pmtud_probe_is_not_congestion() {
+    fixture_init();
+    let mut cc = make_cc_bbr();
+    let mut stats = CongestionControlStats::default();
+    let now = now();
+    let probe = sent::Packet::new(
+        packet::Type::Short,
+        0,
+        now,
+        true,
+        vec![recovery::Token::PmtudProbe],
+        cc.pmtud().plpmtu() + 1,
+    );
+    cc.on_packet_sent(&probe, now);
+    let cwnd = cc.cwnd();
+    assert!(!cc.on_packets_lost(None, None, PTO, &[probe], now + RTT, &mut stats));
+    assert_eq!(stats.congestion_events.loss, 0);
+    assert_eq!(cc.cwnd(), cwnd);
+    assert_eq!(cc.bytes_in_flight(), 0);
+}
+
+#[test]
+fn ecn_ce() {
+    fixture_init();
+    let mut cc = make_cc_bbr();
+    let mut stats = CongestionControlStats::default();
+    let now = now();
+    let pkt = sent::make_packet(0, now, cc.pmtud().plpmtu());
+    cc.on_packet_sent(&pkt, now);
+    let cwnd = cc.cwnd();
+    assert!(!cc.on_ecn_ce_received(&pkt, now + RTT, &mut stats));
+    assert_eq!(stats.congestion_events.ecn, 1);
+    assert_eq!(cc.cwnd(), cwnd);
+}
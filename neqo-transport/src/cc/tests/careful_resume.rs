@@ -0,0 +1,230 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Careful Resume test suite
+
+use std::{
+    net::{IpAddr, Ipv6Addr},
+    time::{Duration, Instant},
+};
+
+use neqo_common::{Decoder, Encoder, qlog::Qlog};
+use test_fixture::now;
+
+use super::{IP_ADDR, RTT, make_cc_newreno};
+use crate::{
+    cc::{
+        CWND_INITIAL_PKTS, CarefulResumePhase as Phase, ClassicCongestionController,
+        ClassicSlowStart, CongestionController as _, NewReno, SavedCongestionState,
+    },
+    recovery::sent,
+    rtt::{RttEstimate, RttSource},
+    stats::CongestionControlStats,
+};
+
+const PTO: Duration = Duration::from_millis(300);
+const SAVED_CWND_PKTS: usize = 100;
+
+struct Resumed {
+    cc: ClassicCongestionController<ClassicSlowStart, NewReno>,
+    stats: CongestionControlStats,
+    now: Instant,
+    next_pn: u64,
+}
+
+impl Resumed {
+    fn new(saved_cwnd_pkts: usize) -> Self {
+        let mut cc = make_cc_newreno();
+        let mut stats = CongestionControlStats::default();
+        let now = now();
+        let saved =
+            SavedCongestionState::new(saved_cwnd_pkts * cc.max_datagram_size(), RTT, IP_ADDR);
+        cc.careful_resume(saved, now, &mut stats);
+        Self {
+            cc,
+            stats,
+            now,
+            next_pn: 0,
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        self.cc.max_datagram_size()
+    }
+
+    fn phase(&self) -> Option<Phase> {
+        self.cc.careful_resume_phase()
+    }
+
+    /// Send packets until the congestion window is full.
+    fn send_cwnd(&mut self) -> Vec<sent::Packet> {
+        let mut pkts = Vec::new();
+        while self.cc.cwnd_avail() >= self.mtu() {
+            let pkt = sent::make_packet(self.next_pn, self.now, self.mtu());
+            self.next_pn += 1;
+            self.cc.on_packet_sent(&pkt, self.now);
+            pkts.push(pkt);
+        }
+        pkts
+    }
+
+    /// Acknowledge `pkts`, with an RTT sample of `rtt`.
+    fn ack(&mut self, pkts: &[sent::Packet], rtt: Duration) {
+        let mut rtt_est = RttEstimate::new(RTT);
+        rtt_est.update(
+            &mut Qlog::disabled(),
+            rtt,
+            Duration::ZERO,
+            RttSource::Ack,
+            self.now,
+        );
+        let mut pkts = pkts.to_vec();
+        pkts.reverse();
+        self.cc
+            .on_packets_acked(&pkts, &rtt_est, self.now, &mut self.stats);
+    }
+
+    /// Fill the initial window and acknowledge its first packet one RTT later, which
+    /// jumps to the saved window.  Returns the rest of the initial window.
+    fn jump(&mut self) -> Vec<sent::Packet> {
+        let mut initial = self.send_cwnd();
+        self.now += RTT;
+        self.ack(&initial[..1], RTT);
+        initial.remove(0);
+        initial
+    }
+}
+
+#[test]
+fn saved_state_roundtrip() {
+    for remote in [IP_ADDR, IpAddr::V6(Ipv6Addr::LOCALHOST)] {
+        let saved = SavedCongestionState::new(123_456, Duration::from_micros(78_901), remote);
+        let mut enc = Encoder::default();
+        saved.encode(&mut enc);
+        assert_eq!(
+            SavedCongestionState::decode(&mut Decoder::new(enc.as_ref())),
+            Some(saved)
+        );
+        assert_eq!(
+            SavedCongestionState::decode(&mut Decoder::new(&enc.as_ref()[..enc.len() - 1])),
+            None
+        );
+    }
+    assert_eq!(SavedCongestionState::decode(&mut Decoder::new(&[])), None);
+}
+
+#[test]
+fn jump_and_validate() {
+    let mut r = Resumed::new(SAVED_CWND_PKTS);
+    assert_eq!(r.phase(), Some(Phase::Reconnaissance));
+    let cwnd_initial = r.cc.cwnd_initial();
+
+    let initial = r.jump();
+    let jump_cwnd = SAVED_CWND_PKTS / 2 * r.mtu();
+    assert_eq!(r.phase(), Some(Phase::Unvalidated));
+    assert_eq!(r.cc.cwnd(), jump_cwnd);
+    assert_eq!(r.stats.careful_resume.jump_cwnd, Some(jump_cwnd));
+
+    // The window doesn't grow while the jump is unvalidated.
+    let unvalidated = r.send_cwnd();
+    r.ack(&initial, RTT);
+    assert_eq!(r.phase(), Some(Phase::Unvalidated));
+    assert_eq!(r.cc.cwnd(), jump_cwnd);
+
+    // Once the first unvalidated packet is acknowledged, the window drops to what is
+    // in flight, plus the slow start increase for the acknowledged packet.
+    r.now += RTT;
+    r.ack(&unvalidated[..1], RTT);
+    assert_eq!(r.phase(), Some(Phase::Validating));
+    assert_eq!(r.cc.cwnd(), r.cc.bytes_in_flight() + r.mtu());
+
+    // Once the last one is, the window grows normally.
+    r.ack(&unvalidated[1..], RTT);
+    assert_eq!(r.phase(), Some(Phase::Normal));
+    assert!(r.cc.cwnd() > jump_cwnd);
+    assert!(r.cc.cwnd() > cwnd_initial);
+    assert_eq!(r.stats.congestion_events.loss, 0);
+    for phase in [
+        Phase::Reconnaissance,
+        Phase::Unvalidated,
+        Phase::Validating,
+        Phase::Normal,
+    ] {
+        assert_eq!(r.stats.careful_resume.phases[phase], 1);
+    }
+    assert_eq!(r.stats.careful_resume.phases[Phase::SafeRetreat], 0);
+}
+
+#[test]
+fn safe_retreat_on_loss() {
+    let mut r = Resumed::new(SAVED_CWND_PKTS);
+    let initial = r.jump();
+    let unvalidated = r.send_cwnd();
+    r.ack(&initial, RTT);
+    let pipesize = (initial.len() + 1) * r.mtu();
+
+    // Losing an unvalidated packet retreats to half of what was acknowledged.
+    r.now += RTT;
+    let (lost, unvalidated) = unvalidated.split_first().unwrap();
+    assert!(r.cc.on_packets_lost(
+        None,
+        None,
+        PTO,
+        std::slice::from_ref(lost),
+        r.now,
+        &mut r.stats
+    ));
+    assert_eq!(r.phase(), Some(Phase::SafeRetreat));
+    assert_eq!(r.cc.cwnd(), pipesize / 2);
+    assert_eq!(r.cc.ssthresh(), Some(pipesize / 2));
+    assert_eq!(r.stats.congestion_events.loss, 1);
+
+    // When the last unvalidated packet is acknowledged, slow start can resume
+    // up to the capacity that was validated.
+    r.ack(unvalidated, RTT);
+    assert_eq!(r.phase(), Some(Phase::Normal));
+    assert_eq!(r.cc.cwnd(), pipesize / 2);
+    assert_eq!(
+        r.cc.ssthresh(),
+        Some(pipesize + unvalidated.len() * r.mtu())
+    );
+    assert_eq!(r.stats.careful_resume.phases[Phase::SafeRetreat], 1);
+    assert_eq!(r.stats.careful_resume.phases[Phase::Normal], 1);
+}
+
+#[test]
+fn rtt_mismatch() {
+    let mut r = Resumed::new(SAVED_CWND_PKTS);
+    let pkts = r.send_cwnd();
+    r.now += RTT * 10;
+    r.ack(&pkts[..1], RTT * 10);
+    assert_eq!(r.phase(), Some(Phase::Normal));
+    assert!(r.cc.cwnd() < SAVED_CWND_PKTS / 2 * r.mtu());
+    assert_eq!(r.stats.careful_resume.jump_cwnd, None);
+}
+
+/// Jumping to half of the saved window would not increase the window.
+#[test]
+fn saved_cwnd_too_small() {
+    let mut r = Resumed::new(CWND_INITIAL_PKTS);
+    let pkts = r.send_cwnd();
+    r.now += RTT;
+    r.ack(&pkts[..1], RTT);
+    assert_eq!(r.phase(), Some(Phase::Normal));
+    assert_eq!(r.stats.careful_resume.jump_cwnd, None);
+}
+
+#[test]
+fn loss_during_reconnaissance() {
+    let mut r = Resumed::new(SAVED_CWND_PKTS);
+    let cwnd = r.cc.cwnd();
+    let pkts = r.send_cwnd();
+    r.now += RTT;
+    assert!(r.cc.on_packets_lost(None, None, PTO, &pkts[..1], r.now, &mut r.stats));
+    assert_eq!(r.phase(), Some(Phase::Normal));
+    assert_eq!(r.cc.cwnd(), cwnd / 2);
+    assert_eq!(r.stats.careful_resume.phases[Phase::SafeRetreat], 0);
+}
@@ -12,14 +12,17 @@ use std::{
 use crate::{
     MIN_INITIAL_PACKET_SIZE, Pmtud,
     cc::{
-        CWND_INITIAL_PKTS, ClassicSlowStart, classic_cc::ClassicCongestionController, cubic::Cubic,
-        hystart::HyStart, new_reno::NewReno,
+        Bbr, CWND_INITIAL_PKTS, ClassicSlowStart, Prague, classic_cc::ClassicCongestionController,
+        cubic::Cubic, hystart::HyStart, new_reno::NewReno,
     },
 };
 
+mod bbr;
+mod careful_resume;
 mod cubic;
 mod hystart;
 mod new_reno;
+mod prague;
 mod search;
 
 pub const IP_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
@@ -47,6 +50,21 @@ pub fn make_cc_cubic() -> ClassicCongestionController<ClassicSlowStart, Cubic> {
     )
 }
 
+/// Helper to create `ClassicCongestionController` with Prague for tests.
+pub fn make_cc_prague() -> ClassicCongestionController<ClassicSlowStart, Prague> {
+    ClassicCongestionController::new(
+        ClassicSlowStart::default(),
+        Prague::default(),
+        Pmtud::new(IP_ADDR, MTU),
+        true,
+    )
+}
+
+/// Helper to create `Bbr` for tests.
+pub fn make_cc_bbr() -> Bbr {
+    Bbr::new(Pmtud::new(IP_ADDR, MTU))
+}
+
 /// Helper to create `ClassicCongestionController` with HyStart++ for tests.
 pub fn make_cc_hystart(paced: bool) -> ClassicCongestionController<HyStart, Cubic> {
     ClassicCongestionController::new(
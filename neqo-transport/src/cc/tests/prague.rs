@@ -0,0 +1,150 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Prague test suite
+
+use std::time::{Duration, Instant};
+
+use test_fixture::now;
+
+use super::{RTT, make_cc_prague};
+use crate::{
+    cc::{
+        ClassicCongestionController, ClassicSlowStart, CongestionControl,
+        CongestionController as _, Prague,
+    },
+    recovery::sent,
+    rtt::RttEstimate,
+    stats::CongestionControlStats,
+};
+
+const PTO: Duration = Duration::from_millis(300);
+const PACKETS_PER_ROUND: u64 = 10;
+
+struct Rounds {
+    cc: ClassicCongestionController<ClassicSlowStart, Prague>,
+    stats: CongestionControlStats,
+    now: Instant,
+    next_pn: u64,
+}
+
+impl Rounds {
+    fn new() -> Self {
+        Self {
+            cc: make_cc_prague(),
+            stats: CongestionControlStats::default(),
+            now: now(),
+            next_pn: 0,
+        }
+    }
+
+    /// Send [`PACKETS_PER_ROUND`] packets and acknowledge them all one RTT later,
+    /// with `ce_marks` of them reported as CE marked.
+    fn round(&mut self, ce_marks: u64) {
+        let mtu = self.cc.max_datagram_size();
+        let mut pkts: Vec<_> = (0..PACKETS_PER_ROUND)
+            .map(|i| sent::make_packet(self.next_pn + i, self.now, mtu))
+            .collect();
+        self.next_pn += PACKETS_PER_ROUND;
+        for pkt in &pkts {
+            self.cc.on_packet_sent(pkt, self.now);
+        }
+        self.now += RTT;
+        pkts.reverse();
+        if ce_marks > 0 {
+            self.cc
+                .on_ecn_ce_marks_received(&pkts[0], ce_marks, self.now, &mut self.stats);
+        }
+        self.cc
+            .on_packets_acked(&pkts, &RttEstimate::new(RTT), self.now, &mut self.stats);
+    }
+
+    fn alpha(&self) -> u64 {
+        self.cc.congestion_control().alpha()
+    }
+}
+
+#[test]
+fn parse() {
+    assert_eq!(
+        "prague".parse::<CongestionControl>(),
+        Ok(CongestionControl::Prague)
+    );
+}
+
+#[test]
+fn alpha_decays_without_marks() {
+    let mut r = Rounds::new();
+    assert_eq!(r.alpha(), Prague::ALPHA_SCALE);
+
+    // The first ACK starts the first round, the next one ends it.
+    r.round(0);
+    assert_eq!(r.alpha(), Prague::ALPHA_SCALE);
+    assert_eq!(r.stats.ecn_ce_fraction, None);
+    r.round(0);
+    assert_eq!(r.alpha(), Prague::ALPHA_SCALE - (Prague::ALPHA_SCALE >> 4));
+    assert_eq!(r.stats.ecn_ce_fraction, Some(0.0));
+
+    for _ in 0..100 {
+        r.round(0);
+    }
+    assert!(r.alpha() < Prague::ALPHA_SCALE / 16);
+}
+
+#[test]
+fn alpha_tracks_marked_fraction() {
+    let mut r = Rounds::new();
+    for _ in 0..200 {
+        r.round(PACKETS_PER_ROUND / 2);
+    }
+    let half = Prague::ALPHA_SCALE / 2;
+    assert!(
+        (half..half + 16).contains(&r.alpha()),
+        "alpha {} should be close to {half}",
+        r.alpha()
+    );
+    assert_eq!(r.stats.ecn_ce_fraction, Some(0.5));
+    assert_eq!(r.stats.congestion_events.loss, 0);
+    assert!(r.stats.congestion_events.ecn > 0);
+}
+
+#[test]
+fn ce_reduction_proportional_to_alpha() {
+    // Initially alpha is 1, so the first CE mark halves the window, like classic ECN.
+    let mut r = Rounds::new();
+    let cwnd = r.cc.cwnd();
+    r.round(1);
+    assert_eq!(r.cc.cwnd(), cwnd / 2);
+    assert_eq!(r.stats.congestion_events.ecn, 1);
+
+    // Once alpha has decayed, a CE mark reduces the window by only alpha / 2.
+    let mut r = Rounds::new();
+    for _ in 0..50 {
+        r.round(0);
+    }
+    let alpha = usize::try_from(r.alpha()).unwrap();
+    let scale = usize::try_from(Prague::ALPHA_SCALE).unwrap();
+    let cwnd = r.cc.cwnd();
+    r.round(1);
+    assert_eq!(r.cc.cwnd(), cwnd - cwnd * alpha / (2 * scale));
+    assert!(r.cc.cwnd() > cwnd * 9 / 10);
+    assert_eq!(r.stats.congestion_events.ecn, 1);
+}
+
+#[test]
+fn loss_halves_cwnd() {
+    let mut r = Rounds::new();
+    for _ in 0..50 {
+        r.round(0);
+    }
+    let cwnd = r.cc.cwnd();
+    let mtu = r.cc.max_datagram_size();
+    let pkt = sent::make_packet(r.next_pn, r.now, mtu);
+    r.cc.on_packet_sent(&pkt, r.now);
+    assert!(r.cc.on_packets_lost(None, None, PTO, &[pkt], r.now + RTT, &mut r.stats));
+    assert_eq!(r.cc.cwnd(), cwnd / 2);
+    assert_eq!(r.stats.congestion_events.loss, 1);
+}
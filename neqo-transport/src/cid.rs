@@ -11,6 +11,7 @@ use std::{
     cell::{Ref, RefCell},
     cmp::{max, min},
     fmt::{self, Debug, Display, Formatter},
+    mem,
     ops::Deref,
     rc::Rc,
 };
@@ -18,7 +19,9 @@ use std::{
 use neqo_common::{
     Buffer, Decoder, Encoder, expect_usize,
     hex::{Hex, HexWithLen},
-    qdebug, qinfo, to_u64,
+    qdebug, qinfo,
+    qlog::Qlog,
+    qwarn, to_u64,
 };
 use nss::{random, randomize};
 use smallvec::{SmallVec, smallvec};
@@ -26,8 +29,10 @@ use smallvec::{SmallVec, smallvec};
 use crate::{
     Error, Res,
     frame::{FrameEncoder as _, FrameType},
-    packet, recovery,
-    stateless_reset::Token as Srt,
+    packet,
+    qlog::{self, ConnectionIdOwner},
+    recovery,
+    stateless_reset::{Key as SrtKey, Token as Srt},
     stats::FrameStats,
 };
 
@@ -401,12 +406,12 @@ impl ConnectionIdStore<Srt> {
         }
     }
 
-    // Retire connection IDs and return the sequence numbers of those that were retired.
-    pub fn retire_prior_to(&mut self, retire_prior: u64) -> Vec<u64> {
+    // Retire connection IDs and return those that were retired.
+    pub fn retire_prior_to(&mut self, retire_prior: u64) -> Vec<ConnectionIdEntry<Srt>> {
         let mut retired = Vec::new();
         self.cids.retain(|e| {
             if !e.is_empty() && e.seqno < retire_prior {
-                retired.push(e.seqno);
+                retired.push(e.clone());
                 false
             } else {
                 true
@@ -434,6 +439,13 @@ impl<'a: 'b, 'b> ConnectionIdDecoderRef<'a> {
     }
 }
 
+/// A change to the set of connection IDs that are valid for a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocalConnectionIdChange {
+    Added(ConnectionId),
+    Removed(ConnectionId),
+}
+
 /// A connection ID manager looks after the generation of connection IDs,
 /// the set of connection IDs that are valid for the connection, and the
 /// generation of `NEW_CONNECTION_ID` frames.
@@ -452,6 +464,13 @@ pub struct ConnectionIdManager {
     next_seqno: u64,
     /// Outstanding, but lost `NEW_CONNECTION_ID` frames will be stored here.
     lost_new_connection_id: Vec<ConnectionIdEntry<Srt>>,
+    /// The key used to derive stateless reset tokens.  If this is not set,
+    /// tokens are random.
+    reset_key: Option<SrtKey>,
+    /// Changes to `connection_ids` that have not been taken yet.
+    /// This is `None` unless changes are being tracked.
+    changes: Option<Vec<LocalConnectionIdChange>>,
+    qlog: Qlog,
 }
 
 impl ConnectionIdManager {
@@ -486,9 +505,82 @@ impl ConnectionIdManager {
             limit: 2,
             next_seqno: 1,
             lost_new_connection_id: Vec::new(),
+            reset_key: None,
+            changes: None,
+            qlog: Qlog::disabled(),
         }
     }
 
+    pub fn set_qlog(&mut self, qlog: Qlog) {
+        self.qlog = qlog;
+    }
+
+    /// Start tracking changes to the connection IDs that are valid for the connection.
+    /// All of the current connection IDs are reported as added.
+    pub fn track_changes(&mut self) {
+        self.changes = Some(
+            self.connection_ids
+                .cids
+                .iter()
+                .map(|e| LocalConnectionIdChange::Added(e.cid.clone()))
+                .collect(),
+        );
+    }
+
+    /// Take the changes that were made since this was last called.
+    pub fn take_changes(&mut self) -> Vec<LocalConnectionIdChange> {
+        self.changes.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    /// The connection IDs that are valid for the connection.
+    pub fn local_cids(&self) -> impl Iterator<Item = &ConnectionId> {
+        self.connection_ids.cids.iter().map(|e| &e.cid)
+    }
+
+    fn add_local(&mut self, entry: ConnectionIdEntry<()>) {
+        qlog::connection_id_updated(
+            &mut self.qlog,
+            ConnectionIdOwner::Local,
+            None,
+            Some(&entry.cid),
+        );
+        if let Some(changes) = &mut self.changes {
+            changes.push(LocalConnectionIdChange::Added(entry.cid.clone()));
+        }
+        self.connection_ids.add_local(entry);
+    }
+
+    fn retire_local(&mut self, seqno: u64) {
+        for e in self.connection_ids.cids.iter().filter(|e| e.seqno == seqno) {
+            qlog::connection_id_updated(
+                &mut self.qlog,
+                ConnectionIdOwner::Local,
+                Some(&e.cid),
+                None,
+            );
+            if let Some(changes) = &mut self.changes {
+                changes.push(LocalConnectionIdChange::Removed(e.cid.clone()));
+            }
+        }
+        self.connection_ids.retire(seqno);
+    }
+
+    /// Set the key that is used to derive stateless reset tokens for new connection IDs.
+    pub fn set_reset_key(&mut self, key: SrtKey) {
+        self.reset_key = Some(key);
+    }
+
+    /// Produce the stateless reset token for `cid`.  This is derived from the
+    /// connection ID if a key is available, or random otherwise.
+    pub fn reset_token(&self, cid: &ConnectionId) -> Srt {
+        self.reset_key.as_ref().map_or_else(Srt::random, |key| {
+            key.token(cid).unwrap_or_else(|e| {
+                qwarn!("Unable to derive stateless reset token: {e}");
+                Srt::random()
+            })
+        })
+    }
+
     pub fn generator(&self) -> Rc<RefCell<dyn ConnectionIdGenerator>> {
         Rc::clone(&self.generator)
     }
@@ -508,13 +600,10 @@ impl ConnectionIdManager {
             Some(cid) => {
                 assert_ne!(cid.len(), 0);
                 debug_assert_eq!(self.next_seqno, Self::SEQNO_PREFERRED);
-                self.connection_ids.add_local(ConnectionIdEntry::new(
-                    self.next_seqno,
-                    cid.clone(),
-                    (),
-                ));
+                self.add_local(ConnectionIdEntry::new(self.next_seqno, cid.clone(), ()));
                 self.next_seqno += 1;
-                Ok((cid, Srt::random()))
+                let srt = self.reset_token(&cid);
+                Ok((cid, srt))
             }
             None => Err(Error::ConnectionIdsExhausted),
         }
@@ -543,7 +632,7 @@ impl ConnectionIdManager {
         if empty_cid {
             qdebug!("Connection ID {seqno} is zero-length, not retiring");
         } else {
-            self.connection_ids.retire(seqno);
+            self.retire_local(seqno);
             self.lost_new_connection_id.retain(|cid| cid.seqno != seqno);
         }
         Ok(())
@@ -555,12 +644,12 @@ impl ConnectionIdManager {
     /// successfully processed.
     pub fn add_odcid(&mut self, cid: ConnectionId) {
         let entry = ConnectionIdEntry::new(Self::SEQNO_ODCID, cid, ());
-        self.connection_ids.add_local(entry);
+        self.add_local(entry);
     }
 
     /// Stop treating the original destination connection ID as valid.
     pub fn remove_odcid(&mut self) {
-        self.connection_ids.retire(Self::SEQNO_ODCID);
+        self.retire_local(Self::SEQNO_ODCID);
     }
 
     pub fn set_limit(&mut self, limit: u64) {
@@ -606,11 +695,10 @@ impl ConnectionIdManager {
                 assert_ne!(cid.len(), 0);
                 let seqno = self.next_seqno;
                 self.next_seqno += 1;
-                self.connection_ids
-                    .add_local(ConnectionIdEntry::new(seqno, cid.clone(), ()));
+                self.add_local(ConnectionIdEntry::new(seqno, cid.clone(), ()));
 
-                // TODO: generate the stateless reset tokens from the connection ID and a key.
-                let entry = ConnectionIdEntry::new(seqno, cid, Srt::random());
+                let srt = self.reset_token(&cid);
+                let entry = ConnectionIdEntry::new(seqno, cid, srt);
                 entry.write(builder, stats);
                 tokens.push(recovery::Token::NewConnectionId(entry));
             }
@@ -0,0 +1,351 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Automatic failover to standby paths, built on connection migration.
+
+use std::{
+    cmp::max,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use neqo_common::qdebug;
+
+/// When to abandon the primary path for a standby path.
+///
+/// Standby paths are registered with [`crate::Connection::add_standby_path`].
+/// They are validated ahead of time and revalidated every `probe_interval`, so
+/// that the connection can move to one immediately when the primary path fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FailoverPolicy {
+    pto_count: usize,
+    ack_timeout: Option<Duration>,
+    hold_down: Duration,
+    probe_interval: Duration,
+}
+
+impl Default for FailoverPolicy {
+    fn default() -> Self {
+        Self {
+            pto_count: 2,
+            ack_timeout: None,
+            hold_down: Duration::from_secs(10),
+            probe_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+impl FailoverPolicy {
+    /// Fail over after this many consecutive PTOs on the primary path.
+    /// This should be less than [`crate::ConnectionParameters::max_pto`],
+    /// or the connection will be closed before it can fail over.
+    ///
+    /// # Panics
+    ///
+    /// If `count` is zero.
+    #[must_use]
+    pub const fn pto_count(mut self, count: usize) -> Self {
+        assert!(count > 0, "PTO count must be positive");
+        self.pto_count = count;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_pto_count(&self) -> usize {
+        self.pto_count
+    }
+
+    /// Fail over if ack-eliciting packets on the primary path go unacknowledged
+    /// for this long.  `None`, the default, leaves only the PTO count.
+    #[must_use]
+    pub const fn ack_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.ack_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_ack_timeout(&self) -> Option<Duration> {
+        self.ack_timeout
+    }
+
+    /// The minimum time between two failovers.  This stops the connection from
+    /// flapping between paths that are both in poor condition.
+    #[must_use]
+    pub const fn hold_down(mut self, hold_down: Duration) -> Self {
+        self.hold_down = hold_down;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_hold_down(&self) -> Duration {
+        self.hold_down
+    }
+
+    /// How often standby paths are revalidated with `PATH_CHALLENGE`.
+    /// This is also how long to wait before trying again to set up a standby path
+    /// that could not be created or that failed validation.
+    ///
+    /// # Panics
+    ///
+    /// If `interval` is zero.
+    #[must_use]
+    pub const fn probe_interval(mut self, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "probe interval must be positive");
+        self.probe_interval = interval;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_probe_interval(&self) -> Duration {
+        self.probe_interval
+    }
+}
+
+/// The reason that the connection moved to a standby path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FailoverReason {
+    /// The primary path had this many consecutive PTOs.
+    Pto(usize),
+    /// Nothing sent on the primary path was acknowledged for at least this long.
+    AckTimeout(Duration),
+}
+
+/// A local address that can be used for a standby path.
+#[derive(Debug)]
+struct Candidate {
+    local: SocketAddr,
+    /// When to next check on the path, or `None` if that is due now.
+    next: Option<Instant>,
+}
+
+/// Tracks the health of the primary path and the standby paths that the
+/// connection can fail over to.
+#[derive(Debug)]
+pub struct Manager {
+    policy: FailoverPolicy,
+    /// Local addresses for standby paths, in order of preference.
+    candidates: Vec<Candidate>,
+    /// When the connection last failed over.
+    last_failover: Option<Instant>,
+    /// When the first ack-eliciting packet was sent on the primary path after
+    /// the last acknowledgment was received.
+    unacked_since: Option<Instant>,
+}
+
+impl Manager {
+    pub const fn new(policy: FailoverPolicy) -> Self {
+        Self {
+            policy,
+            candidates: Vec::new(),
+            last_failover: None,
+            unacked_since: None,
+        }
+    }
+
+    pub const fn policy(&self) -> &FailoverPolicy {
+        &self.policy
+    }
+
+    /// Add a local address for a standby path.  Returns `false` if it was already present.
+    pub fn add(&mut self, local: SocketAddr) -> bool {
+        if self.contains(local) {
+            return false;
+        }
+        self.candidates.push(Candidate { local, next: None });
+        true
+    }
+
+    /// Remove a local address.  Returns `false` if it wasn't present.
+    pub fn remove(&mut self, local: SocketAddr) -> bool {
+        let before = self.candidates.len();
+        self.candidates.retain(|c| c.local != local);
+        self.candidates.len() != before
+    }
+
+    pub fn contains(&self, local: SocketAddr) -> bool {
+        self.candidates.iter().any(|c| c.local == local)
+    }
+
+    /// The local addresses of standby paths, in order of preference.
+    pub fn candidates(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.candidates.iter().map(|c| c.local)
+    }
+
+    /// The local addresses of standby paths that are due to be checked.
+    /// Each of these is not checked again until `probe_interval` has passed.
+    pub fn due(&mut self, now: Instant) -> Vec<SocketAddr> {
+        let interval = self.policy.probe_interval;
+        self.candidates
+            .iter_mut()
+            .filter(|c| c.next.is_none_or(|t| t <= now))
+            .map(|c| {
+                c.next = Some(now + interval);
+                c.local
+            })
+            .collect()
+    }
+
+    pub const fn on_packet_sent(&mut self, now: Instant) {
+        if self.unacked_since.is_none() {
+            self.unacked_since = Some(now);
+        }
+    }
+
+    pub const fn on_ack_received(&mut self) {
+        self.unacked_since = None;
+    }
+
+    /// The earliest time that another failover is allowed.
+    fn hold_down_end(&self) -> Option<Instant> {
+        self.last_failover.map(|t| t + self.policy.hold_down)
+    }
+
+    /// When acknowledgments are overdue, if they are being waited for.
+    fn ack_deadline(&self) -> Option<Instant> {
+        Some(self.unacked_since? + self.policy.ack_timeout?)
+    }
+
+    /// Determine whether the primary path has failed.
+    /// This returns `None` while failover is held down.
+    pub fn check(&self, pto_count: usize, now: Instant) -> Option<FailoverReason> {
+        if self.candidates.is_empty() || self.hold_down_end().is_some_and(|t| t > now) {
+            return None;
+        }
+        if pto_count >= self.policy.pto_count {
+            Some(FailoverReason::Pto(pto_count))
+        } else if let Some(since) = self.unacked_since
+            && self.ack_deadline().is_some_and(|t| t <= now)
+        {
+            Some(FailoverReason::AckTimeout(now - since))
+        } else {
+            None
+        }
+    }
+
+    /// Record an attempt to fail over, whether it succeeded or not.
+    /// Either way, acknowledgments are awaited afresh from this point.
+    pub fn failed_over(&mut self, success: bool, now: Instant) {
+        qdebug!("Failover attempt, success={success}");
+        if success {
+            self.last_failover = Some(now);
+        }
+        self.unacked_since = None;
+    }
+
+    /// When the manager next needs to run.
+    pub fn next_timeout(&self) -> Option<Instant> {
+        let ack = self
+            .ack_deadline()
+            .filter(|_| !self.candidates.is_empty())
+            .map(|t| self.hold_down_end().map_or(t, |h| max(t, h)));
+        self.candidates
+            .iter()
+            .filter_map(|c| c.next)
+            .chain(ack)
+            .min()
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use test_fixture::now;
+
+    use super::{FailoverPolicy, FailoverReason, Manager};
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([192, 0, 2, 1], port))
+    }
+
+    fn manager() -> Manager {
+        let mut m = Manager::new(
+            FailoverPolicy::default()
+                .pto_count(2)
+                .ack_timeout(Some(SECOND))
+                .hold_down(SECOND * 5)
+                .probe_interval(SECOND * 10),
+        );
+        assert!(m.add(addr(1)));
+        assert!(!m.add(addr(1)));
+        m
+    }
+
+    #[test]
+    fn candidates() {
+        let mut m = manager();
+        assert!(m.add(addr(2)));
+        assert_eq!(m.candidates().collect::<Vec<_>>(), [addr(1), addr(2)]);
+        assert!(m.remove(addr(1)));
+        assert!(!m.remove(addr(1)));
+        assert_eq!(m.candidates().collect::<Vec<_>>(), [addr(2)]);
+    }
+
+    #[test]
+    fn due() {
+        let mut m = manager();
+        let now = now();
+        assert_eq!(m.due(now), [addr(1)]);
+        assert!(m.due(now + SECOND).is_empty());
+        assert_eq!(m.next_timeout(), Some(now + SECOND * 10));
+        assert_eq!(m.due(now + SECOND * 10), [addr(1)]);
+    }
+
+    #[test]
+    fn pto() {
+        let mut m = manager();
+        let now = now();
+        _ = m.due(now);
+        assert_eq!(m.check(1, now), None);
+        assert_eq!(m.check(2, now), Some(FailoverReason::Pto(2)));
+
+        // Without any standby paths, there is nothing to do.
+        m.remove(addr(1));
+        assert_eq!(m.check(2, now), None);
+    }
+
+    #[test]
+    fn ack_timeout() {
+        let mut m = manager();
+        let now = now();
+        _ = m.due(now);
+        m.on_packet_sent(now);
+        m.on_packet_sent(now + SECOND / 2);
+        assert_eq!(m.next_timeout(), Some(now + SECOND));
+        assert_eq!(m.check(0, now + SECOND / 2), None);
+        assert_eq!(
+            m.check(0, now + SECOND * 2),
+            Some(FailoverReason::AckTimeout(SECOND * 2))
+        );
+
+        m.on_ack_received();
+        assert_eq!(m.check(0, now + SECOND * 2), None);
+    }
+
+    #[test]
+    fn hold_down() {
+        let mut m = manager();
+        let now = now();
+        _ = m.due(now);
+        m.failed_over(true, now);
+        assert_eq!(m.check(5, now + SECOND), None);
+        m.on_packet_sent(now + SECOND);
+        // The ACK timer waits for the hold down to end.
+        assert_eq!(m.next_timeout(), Some(now + SECOND * 5));
+        assert_eq!(
+            m.check(0, now + SECOND * 5),
+            Some(FailoverReason::AckTimeout(SECOND * 4))
+        );
+
+        // A failed attempt doesn't start a hold down, but restarts the ACK timer.
+        m.failed_over(false, now + SECOND * 5);
+        assert_eq!(m.check(0, now + SECOND * 5), None);
+        assert_eq!(m.check(2, now + SECOND * 5), Some(FailoverReason::Pto(2)));
+    }
+}
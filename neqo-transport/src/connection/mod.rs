@@ -18,7 +18,7 @@ use std::{
 };
 
 use neqo_common::{
-    Buffer, Datagram, Decoder, Ecn, Encoder, Role, Tos, datagram,
+    Buffer, Bytes, Datagram, Decoder, Ecn, Encoder, Role, Tos, datagram,
     event::Provider as EventProvider,
     expect_usize,
     hex::{Hex, HexSnipMiddle, HexWithLen},
@@ -37,19 +37,21 @@ use strum::IntoEnumIterator as _;
 use crate::{
     AppError, CloseReason, Error, Res, StreamId,
     addr_valid::{AddressValidation, NewTokenState},
-    cc::Phase,
+    cc::{Phase, SavedCongestionState},
     cid::{
         ConnectionId, ConnectionIdEntry, ConnectionIdGenerator, ConnectionIdManager,
-        ConnectionIdRef, ConnectionIdStore,
+        ConnectionIdRef, ConnectionIdStore, LocalConnectionIdChange,
     },
-    crypto::{Crypto, CryptoDxState, Epoch},
+    crypto::{Crypto, CryptoDxState, Epoch, RESUMPTION_TOKEN_CC_TAG},
     ecn,
     events::{ConnectionEvent, ConnectionEvents, OutgoingDatagramOutcome},
     frame::{CloseError, Frame, FrameEncoder as _, FrameType},
     packet::{self},
     path::{Path, PathRef, Paths},
-    qlog,
-    quic_datagrams::{DATAGRAM_FRAME_TYPE_VARINT_LEN, DatagramTracking, QuicDatagrams},
+    qlog::{self, ConnectionIdOwner},
+    quic_datagrams::{
+        DATAGRAM_FRAME_TYPE_VARINT_LEN, DatagramOptions, DatagramTracking, QuicDatagrams,
+    },
     recovery::{self, SendProfile, sent},
     recv_stream,
     rtt::{GRANULARITY, RttEstimate},
@@ -63,9 +65,10 @@ use crate::{
         self,
         TransportParameterId::{
             self, AckDelayExponent, ActiveConnectionIdLimit, DisableMigration, GreaseQuicBit,
-            InitialSourceConnectionId, MaxAckDelay, MaxDatagramFrameSize, MaxUdpPayloadSize,
-            MinAckDelay, OriginalDestinationConnectionId, ResetStreamAt, RetrySourceConnectionId,
-            StatelessResetToken,
+            InitialSourceConnectionId, MaxAckDelay, MaxDatagramFrameSize,
+            MaxReceiveTimestampsPerAck, MaxUdpPayloadSize, MinAckDelay,
+            OriginalDestinationConnectionId, ReceiveTimestampsExponent, ResetStreamAt,
+            RetrySourceConnectionId, StatelessResetToken,
         },
         TransportParameters, TransportParametersHandler,
     },
@@ -73,6 +76,7 @@ use crate::{
     version::{self, Version},
 };
 
+mod failover;
 mod idle;
 pub mod params;
 mod state;
@@ -80,13 +84,14 @@ mod state;
 #[cfg_attr(coverage_nightly, coverage(off))]
 pub mod test_internal;
 
+pub use failover::{FailoverPolicy, FailoverReason};
 use idle::IdleTimeout;
 pub use params::ConnectionParameters;
 use params::PreferredAddressConfig;
 use state::StateSignaling;
 pub use state::{ClosingFrame, State};
 
-pub use crate::send_stream::{RetransmissionPriority, TransmissionPriority};
+pub use crate::send_stream::{RetransmissionPriority, StreamScheduler, TransmissionPriority};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ZeroRttState {
@@ -320,6 +325,8 @@ pub struct Connection {
     streams: Streams,
     state_signaling: StateSignaling,
     loss_recovery: recovery::Loss,
+    /// Manages failover to standby paths, if that is enabled.
+    failover: Option<failover::Manager>,
     events: ConnectionEvents,
     new_token: NewTokenState,
     stats: StatsCell,
@@ -430,6 +437,16 @@ impl Connection {
         let mut tps = conn_params.create_transport_parameter(role, &mut cid_manager)?;
         tps.local_mut()
             .set_bytes(InitialSourceConnectionId, local_initial_source_cid.to_vec());
+        // A server that derives stateless reset tokens also needs to provide one for
+        // the connection ID used during the handshake.
+        if role == Role::Server
+            && conn_params.get_stateless_reset_key().is_some()
+            && !local_initial_source_cid.is_empty()
+        {
+            let srt = cid_manager.reset_token(&local_initial_source_cid);
+            tps.local_mut()
+                .set_bytes(StatelessResetToken, srt.as_ref().to_vec());
+        }
 
         let tphandler = Rc::new(RefCell::new(tps));
         let crypto = Crypto::new(
@@ -445,6 +462,7 @@ impl Connection {
         let quic_datagrams = QuicDatagrams::new(
             conn_params.get_datagram_size(),
             conn_params.get_outgoing_datagram_queue(),
+            conn_params.get_datagram_share(),
             events.clone(),
         );
 
@@ -465,10 +483,16 @@ impl Connection {
             crypto,
             acks: AckTracker::default(),
             idle_timeout: IdleTimeout::new(conn_params.get_idle_timeout()),
-            streams: Streams::new(tphandler, role, events.clone()),
+            streams: Streams::new(
+                tphandler,
+                role,
+                events.clone(),
+                conn_params.get_stream_scheduler(),
+            ),
             cids: ConnectionIdStore::default(),
             state_signaling: StateSignaling::Idle,
             loss_recovery: recovery::Loss::new(stats.clone(), conn_params.get_fast_pto()),
+            failover: conn_params.get_path_failover().map(failover::Manager::new),
             events,
             new_token: NewTokenState::new(role),
             stats,
@@ -530,6 +554,9 @@ impl Connection {
     pub fn set_qlog(&mut self, qlog: Qlog) {
         self.loss_recovery.set_qlog(qlog.clone());
         self.paths.set_qlog(qlog.clone());
+        self.streams.set_qlog(qlog.clone());
+        self.crypto.states_mut().set_qlog(qlog.clone());
+        self.cid_manager.set_qlog(qlog.clone());
         self.qlog = qlog;
     }
 
@@ -570,6 +597,35 @@ impl Connection {
         }
     }
 
+    /// Set a transport parameter that this crate doesn't define, so that
+    /// the application can negotiate an extension with the peer.
+    /// This can only be called before the connection starts.
+    ///
+    /// # Errors
+    ///
+    /// `ConnectionState` if the connection has started,
+    /// and `InvalidInput` if `id` is a transport parameter that this crate defines.
+    pub fn set_local_extension_tparam(&self, id: u64, value: Vec<u8>) -> Res<()> {
+        if *self.state() != State::Init {
+            qerror!("[{self}] Cannot set extension tparam 0x{id:x} after starting");
+            return Err(Error::ConnectionState);
+        }
+        self.tps.borrow_mut().local_mut().set_extension(id, value)
+    }
+
+    /// Get the value of a transport parameter that this crate doesn't define
+    /// from the peer.  This is available once the peer's transport parameters
+    /// are received, or during 0-RTT from the parameters that were remembered
+    /// in the resumption token.
+    #[must_use]
+    pub fn peer_extension_tparam(&self, id: u64) -> Option<Vec<u8>> {
+        let tps = self.tps.borrow();
+        tps.remote_handshake()
+            .or_else(|| tps.remote_0rtt())
+            .and_then(|tp| tp.get_extension(id))
+            .map(<[u8]>::to_vec)
+    }
+
     /// `odcid` is their original choice for our CID, which we get from the Retry token.
     /// `remote_cid` is the value from the Source Connection ID field of an incoming packet: what
     /// the peer wants us to use now. `retry_cid` is what we asked them to use when we sent the
@@ -673,6 +729,14 @@ impl Connection {
             },
         );
 
+        let saved_cc = if self.conn_params.careful_resume_enabled() {
+            self.paths
+                .primary()
+                .and_then(|p| p.borrow().saved_congestion_state())
+        } else {
+            None
+        };
+
         self.crypto
             .create_resumption_token(
                 self.new_token.take_token(),
@@ -683,6 +747,7 @@ impl Connection {
                     .expect("should have transport parameters"),
                 self.version,
                 u64::try_from(rtt.as_millis()).unwrap_or(0),
+                saved_cc.as_ref(),
             )
             .expect("caller checked if a resumption token existed")
     }
@@ -789,10 +854,16 @@ impl Connection {
         );
         let mut dec = Decoder::from(token.as_ref());
 
-        let version = Version::try_from(
-            dec.decode_uint::<version::Wire>()
-                .ok_or(Error::InvalidResumptionToken)?,
-        )?;
+        let mut wire = dec
+            .decode_uint::<version::Wire>()
+            .ok_or(Error::InvalidResumptionToken)?;
+        let has_saved_cc = wire == RESUMPTION_TOKEN_CC_TAG;
+        if has_saved_cc {
+            wire = dec
+                .decode_uint::<version::Wire>()
+                .ok_or(Error::InvalidResumptionToken)?;
+        }
+        let version = Version::try_from(wire)?;
         qtrace!("[{self}]   version {version:?}");
         if !self.conn_params.get_versions().all().contains(&version) {
             return Err(Error::DisabledVersion);
@@ -801,6 +872,14 @@ impl Connection {
         let rtt = Duration::from_millis(dec.decode_varint().ok_or(Error::InvalidResumptionToken)?);
         qtrace!("[{self}]   RTT {rtt:?}");
 
+        let saved_cc = if has_saved_cc {
+            let saved_cc = dec.decode_vvec().ok_or(Error::InvalidResumptionToken)?;
+            SavedCongestionState::decode(&mut Decoder::from(saved_cc))
+        } else {
+            None
+        };
+        qtrace!("[{self}]   congestion state {saved_cc:?}");
+
         let tp_slice = dec.decode_vvec().ok_or(Error::InvalidResumptionToken)?;
         qtrace!("[{self}]   transport parameters {}", Hex::new(tp_slice));
         let mut dec_tp = Decoder::from(tp_slice);
@@ -831,12 +910,14 @@ impl Connection {
         if !init_token.is_empty() {
             self.address_validation = AddressValidationInfo::NewToken(init_token.to_vec());
         }
-        self.paths
-            .primary()
-            .ok_or(Error::Internal)?
-            .borrow_mut()
-            .rtt_mut()
-            .set_initial(rtt);
+        let path = self.paths.primary().ok_or(Error::Internal)?;
+        path.borrow_mut().rtt_mut().set_initial(rtt);
+        if let Some(saved_cc) = saved_cc
+            && self.conn_params.careful_resume_enabled()
+        {
+            path.borrow_mut()
+                .careful_resume(saved_cc, now, &mut self.stats.borrow_mut());
+        }
         self.set_initial_limits();
         // Start up TLS, which has the effect of setting up all the necessary
         // state for 0-RTT.  This only stages the CRYPTO frames.
@@ -1080,7 +1161,10 @@ impl Connection {
             return;
         }
 
+        self.streams.expire(now);
         self.streams.cleanup_closed_streams();
+        self.quic_datagrams
+            .expire(now, &mut self.stats.borrow_mut());
 
         let res = self.crypto.states_mut().check_key_update(now);
         self.absorb_error(now, res);
@@ -1093,6 +1177,9 @@ impl Connection {
             qlog::packets_lost(&mut self.qlog, &lost, now);
         }
 
+        self.maybe_fail_over(now);
+        self.setup_standby_paths(now);
+
         // Declare the connection broken if too many consecutive PTOs have gone
         // unacknowledged, i.e. the path is a black hole. This closes the connection
         // sooner than, and with a distinct reason from, the idle timeout.
@@ -1133,6 +1220,23 @@ impl Connection {
         self.cid_manager.is_valid(cid)
     }
 
+    /// Start tracking changes to the local connection IDs,
+    /// so that a server can route packets to this connection.
+    pub(crate) fn track_local_cids(&mut self) {
+        self.cid_manager.track_changes();
+    }
+
+    /// Take changes to the local connection IDs since the last call.
+    /// This is empty unless [`Self::track_local_cids`] was called.
+    pub(crate) fn take_local_cid_changes(&mut self) -> Vec<LocalConnectionIdChange> {
+        self.cid_manager.take_changes()
+    }
+
+    /// The local connection IDs that are valid for this connection.
+    pub(crate) fn local_cids(&self) -> impl Iterator<Item = &ConnectionId> {
+        self.cid_manager.local_cids()
+    }
+
     /// Process a new input datagram on the connection.
     pub fn process_input<A: AsRef<[u8]> + AsMut<[u8]>>(&mut self, d: Datagram<A>, now: Instant) {
         self.process_multiple_input(iter::once(d), now);
@@ -1177,7 +1281,7 @@ impl Connection {
             return Some(timeout.duration_since(now));
         }
 
-        let mut delays = SmallVec::<[_; 7]>::new();
+        let mut delays = SmallVec::<[_; 8]>::new();
         if let Some(ack_time) = self.acks.ack_time(now) {
             qtrace!("[{self}] Delayed ACK timer {ack_time:?}");
             delays.push(ack_time);
@@ -1215,11 +1319,31 @@ impl Connection {
             }
         }
 
+        if let Some(expiry) = self.streams.next_expiry() {
+            qtrace!("[{self}] Stream expiry timer {expiry:?}");
+            delays.push(expiry);
+        }
+
+        if let Some(expiry) = self.quic_datagrams.next_expiry() {
+            qtrace!("[{self}] Datagram expiry timer {expiry:?}");
+            delays.push(expiry);
+        }
+
         if let Some(key_update_time) = self.crypto.states().update_time() {
             qtrace!("[{self}] Key update timer {key_update_time:?}");
             delays.push(key_update_time);
         }
 
+        if self.state == State::Confirmed
+            && let Some(failover_time) = self
+                .failover
+                .as_ref()
+                .and_then(failover::Manager::next_timeout)
+        {
+            qtrace!("[{self}] Failover timer {failover_time:?}");
+            delays.push(failover_time);
+        }
+
         // `release_resumption_token_timer` is not considered here, because
         // it is not important enough to force the application to set a
         // timeout for it  It is expected that other activities will
@@ -1412,7 +1536,7 @@ impl Connection {
     }
 
     fn discard_keys(&mut self, space: PacketNumberSpace, now: Instant) {
-        if self.crypto.discard(space) {
+        if self.crypto.discard(space, now) {
             qdebug!("[{self}] Drop packet number space {space}");
             if let Some(path) = self.paths.primary() {
                 self.loss_recovery.discard(&path, space, now);
@@ -2139,6 +2263,30 @@ impl Connection {
         force: bool,
         now: Instant,
     ) -> Res<()> {
+        let path = self.migration_path(local, remote, now)?;
+        qinfo!(
+            "[{self}] Migrate to {} probe {}",
+            path.borrow(),
+            if force { "now" } else { "after" }
+        );
+        if self
+            .paths
+            .migrate(&path, force, now, &mut self.stats.borrow_mut())
+        {
+            self.loss_recovery.migrate();
+            self.path_migrated(&path);
+        }
+        Ok(())
+    }
+
+    /// Find or create a permanent path that can be migrated to, filling in
+    /// any missing address from the current primary path.
+    fn migration_path(
+        &mut self,
+        local: Option<SocketAddr>,
+        remote: Option<SocketAddr>,
+        now: Instant,
+    ) -> Res<PathRef> {
         if self.role != Role::Client {
             return Err(Error::InvalidMigration);
         }
@@ -2181,19 +2329,7 @@ impl Connection {
             &mut self.stats.borrow_mut(),
         );
         self.ensure_permanent(&path, now)?;
-        qinfo!(
-            "[{self}] Migrate to {} probe {}",
-            path.borrow(),
-            if force { "now" } else { "after" }
-        );
-        if self
-            .paths
-            .migrate(&path, force, now, &mut self.stats.borrow_mut())
-        {
-            self.loss_recovery.migrate();
-            self.path_migrated(&path);
-        }
-        Ok(())
+        Ok(path)
     }
 
     fn path_migrated(&self, path: &PathRef) {
@@ -2202,6 +2338,113 @@ impl Connection {
             .path_migrated(p.local_address(), p.remote_address());
     }
 
+    /// Register a local address for a standby path, which the connection will
+    /// move to if the primary path fails.  Standby paths use the remote address
+    /// of the primary path.  They are validated once the handshake is confirmed
+    /// and revalidated periodically after that, as set by the [`FailoverPolicy`]
+    /// in [`ConnectionParameters::path_failover`].  Addresses that are added
+    /// earlier are preferred.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `InvalidMigration` if this is not a client or failover is not
+    /// enabled, and `InvalidInput` if the address was already added.
+    pub fn add_standby_path(&mut self, local: SocketAddr, now: Instant) -> Res<()> {
+        if self.role != Role::Client {
+            return Err(Error::InvalidMigration);
+        }
+        let failover = self.failover.as_mut().ok_or(Error::InvalidMigration)?;
+        if !failover.add(local) {
+            return Err(Error::InvalidInput);
+        }
+        self.setup_standby_paths(now);
+        Ok(())
+    }
+
+    /// Stop using a local address for a standby path.
+    /// This does not affect the primary path, even if it uses the address.
+    pub fn remove_standby_path(&mut self, local: SocketAddr) {
+        if let Some(failover) = &mut self.failover
+            && failover.remove(local)
+        {
+            self.paths.remove_standby(local);
+        }
+    }
+
+    /// Create and validate any standby paths that are missing.
+    fn setup_standby_paths(&mut self, now: Instant) {
+        if self.state != State::Confirmed {
+            return;
+        }
+        let Some(failover) = &mut self.failover else {
+            return;
+        };
+        let interval = failover.policy().get_probe_interval();
+        for local in failover.due(now) {
+            if self.paths.standby(local).is_some() {
+                continue;
+            }
+            match self.migration_path(Some(local), None, now) {
+                Ok(path) => self.paths.add_standby(&path, interval),
+                Err(e) => qdebug!("[{self}] Unable to create standby path from {local}: {e:?}"),
+            }
+        }
+    }
+
+    /// Move to a standby path if the primary path has failed.
+    fn maybe_fail_over(&mut self, now: Instant) {
+        if self.state != State::Confirmed {
+            return;
+        }
+        let Some(failover) = &self.failover else {
+            return;
+        };
+        let Some(reason) = failover.check(self.loss_recovery.pto_count(), now) else {
+            return;
+        };
+        let target = failover.candidates().find_map(|local| {
+            self.paths
+                .standby(local)
+                .filter(|p| !p.borrow().is_primary() && p.borrow().is_valid())
+        });
+        let success = if let (Some(path), Some(old)) = (target, self.paths.primary()) {
+            let from = old.borrow().local_address();
+            qinfo!(
+                "[{self}] Failing over to {} after {reason:?}",
+                path.borrow()
+            );
+            self.paths
+                .fail_over(&path, now, &mut self.stats.borrow_mut());
+            self.loss_recovery.migrate();
+            self.events
+                .path_failover(from, path.borrow().local_address(), reason);
+            self.path_migrated(&path);
+            true
+        } else {
+            qinfo!("[{self}] Primary path failed after {reason:?}, but no standby path is ready");
+            false
+        };
+        if let Some(failover) = &mut self.failover {
+            failover.failed_over(success, now);
+        }
+    }
+
+    /// Save a connection ID that the peer issued.
+    fn add_remote_cid(&mut self, entry: ConnectionIdEntry<Srt>) -> Res<()> {
+        let new = (!self.cids.contains(entry.connection_id().as_cid_ref()))
+            .then(|| entry.connection_id().clone());
+        self.cids.add_remote(entry)?;
+        if let Some(cid) = new {
+            qlog::connection_id_updated(
+                &mut self.qlog,
+                ConnectionIdOwner::Remote,
+                None,
+                Some(&cid),
+            );
+        }
+        Ok(())
+    }
+
     fn migrate_to_preferred_address(&mut self, now: Instant) -> Res<()> {
         let spa: Option<(tparams::PreferredAddress, ConnectionIdEntry<Srt>)> = if matches!(
             self.conn_params.get_preferred_address(),
@@ -2214,7 +2457,7 @@ impl Connection {
         };
         if let Some((addr, cid)) = spa {
             // The connection ID isn't special, so just save it.
-            self.cids.add_remote(cid)?;
+            self.add_remote_cid(cid)?;
 
             // The preferred address doesn't dictate what the local address is, so this
             // has to use the existing address.  So only pay attention to a preferred
@@ -2442,16 +2685,27 @@ impl Connection {
             return;
         }
 
+        // Datagrams can take a share of the space ahead of stream data.
+        self.quic_datagrams
+            .write_frames(builder, tokens, stats, now, true);
+        if builder.is_full() {
+            return;
+        }
+
         for prio in [TransmissionPriority::High, TransmissionPriority::Normal] {
+            let before = builder.len();
             self.streams
                 .write_frames(prio, builder, tokens, &mut stats.frame_tx);
+            self.quic_datagrams.on_stream_data(builder.len() - before);
             if builder.is_full() {
                 return;
             }
         }
 
-        // Datagrams are best-effort and unreliable.  Let streams starve them for now.
-        self.quic_datagrams.write_frames(builder, tokens, stats);
+        // Datagrams are best-effort and unreliable.  Beyond their share,
+        // let streams starve them.
+        self.quic_datagrams
+            .write_frames(builder, tokens, stats, now, false);
         if builder.is_full() {
             return;
         }
@@ -2528,6 +2782,25 @@ impl Connection {
         probe
     }
 
+    /// Whether the peer supports draft-ietf-quic-ack-frequency, which means that
+    /// it accepts `ACK_FREQUENCY` and `IMMEDIATE_ACK` frames.
+    fn peer_supports_ack_frequency(&self) -> bool {
+        let tps = self.tps.borrow();
+        tps.remote_handshake()
+            .or_else(|| tps.remote_0rtt())
+            .is_some_and(|tp| tp.has_value(MinAckDelay))
+    }
+
+    /// Write an `IMMEDIATE_ACK` frame so that the peer acknowledges a probe
+    /// without delay, if the peer supports that and there is space.
+    fn write_immediate_ack(&self, builder: &mut packet::Builder<&mut Vec<u8>>) {
+        if builder.is_full() || builder.remaining() == 0 || !self.peer_supports_ack_frequency() {
+            return;
+        }
+        builder.encode_frame(FrameType::ImmediateAck, |_| {});
+        self.stats.borrow_mut().frame_tx.immediate_ack += 1;
+    }
+
     /// Write frames to the provided builder.  Returns a list of tokens used for
     /// tracking loss or acknowledgment, whether any frame was ACK eliciting, and
     /// whether the packet was padded.
@@ -2590,6 +2863,7 @@ impl Connection {
                         &mut tokens,
                         &mut self.stats.borrow_mut(),
                     );
+                    self.write_immediate_ack(builder);
                     ack_eliciting = true;
                 }
                 self.write_appdata_frames(builder, &mut tokens, now);
@@ -2614,6 +2888,10 @@ impl Connection {
         // Maybe send a probe now, either to probe for losses or to keep the connection live.
         let force_probe = profile.should_probe(space);
         ack_eliciting |= self.maybe_probe(path, force_probe, builder, ack_end, &mut tokens, now);
+        // Ask for PTO probes to be acknowledged without delay.
+        if force_probe && primary && space == PacketNumberSpace::ApplicationData {
+            self.write_immediate_ack(builder);
+        }
         // If this is not the primary path, this should be ack-eliciting.
         debug_assert!(primary || ack_eliciting);
 
@@ -2854,7 +3132,7 @@ impl Connection {
             }
 
             if packet_tos.is_ecn_marked() {
-                tokens.push(recovery::Token::EcnEct0);
+                tokens.push(recovery::Token::EcnMarked);
             }
 
             self.log_packet(
@@ -2886,6 +3164,11 @@ impl Connection {
 
             if ack_eliciting {
                 self.idle_timeout.on_packet_sent(now);
+                if path.borrow().is_primary()
+                    && let Some(failover) = &mut self.failover
+                {
+                    failover.on_packet_sent(now);
+                }
             }
             let sent = sent::Packet::new(
                 pt,
@@ -3134,6 +3417,11 @@ impl Connection {
 
             let max_active_cids = remote.get_integer(ActiveConnectionIdLimit);
             self.cid_manager.set_limit(max_active_cids);
+
+            let max_timestamps = remote.get_integer(MaxReceiveTimestampsPerAck);
+            if max_timestamps > 0 {
+                self.acks.receive_timestamps(max_timestamps, now);
+            }
         }
         self.set_initial_limits();
         qlog::connection_tparams_set(&mut self.qlog, &self.tps.borrow(), now);
@@ -3400,7 +3688,47 @@ impl Connection {
 
                 let ranges =
                     Frame::decode_ack_frame(largest_acknowledged, first_ack_range, &ack_ranges)?;
-                self.handle_ack(space, ranges, ecn_count.as_ref(), ack_delay, now)?;
+                self.handle_ack(space, ranges, ecn_count.as_ref(), ack_delay, &[], now)?;
+            }
+            Frame::AckReceiveTimestamps {
+                largest_acknowledged,
+                ack_delay,
+                first_ack_range,
+                ack_ranges,
+                ecn_count,
+                timestamp_ranges,
+            } => {
+                if self.conn_params.get_receive_timestamps() == 0 {
+                    qinfo!("[{self}] Receive timestamps that were not asked for");
+                    return Err(Error::ProtocolViolation);
+                }
+                if largest_acknowledged >= next_pn {
+                    qwarn!("Largest ACKed {largest_acknowledged} was never sent");
+                    return Err(Error::AckedUnsentPacket);
+                }
+
+                let ranges =
+                    Frame::decode_ack_frame(largest_acknowledged, first_ack_range, &ack_ranges)?;
+                let exponent = u32::try_from(
+                    self.tps
+                        .borrow()
+                        .remote()
+                        .get_integer(ReceiveTimestampsExponent),
+                )?;
+                let receive_times = Frame::decode_receive_timestamps(
+                    largest_acknowledged,
+                    &timestamp_ranges,
+                    exponent,
+                )?;
+                self.stats.borrow_mut().frame_rx.receive_timestamps += receive_times.len();
+                self.handle_ack(
+                    space,
+                    ranges,
+                    ecn_count.as_ref(),
+                    ack_delay,
+                    &receive_times,
+                    now,
+                )?;
             }
             Frame::Crypto { offset, data } => {
                 qtrace!(
@@ -3458,7 +3786,7 @@ impl Connection {
                 retire_prior,
             } => {
                 self.stats.borrow_mut().frame_rx.new_connection_id += 1;
-                self.cids.add_remote(ConnectionIdEntry::new(
+                self.add_remote_cid(ConnectionIdEntry::new(
                     sequence_number,
                     ConnectionId::from(connection_id),
                     stateless_reset_token,
@@ -3553,7 +3881,7 @@ impl Connection {
                 seqno,
                 tolerance,
                 delay,
-                ignore_order,
+                reordering_threshold,
             } => {
                 self.stats.borrow_mut().frame_rx.ack_frequency += 1;
                 let delay = Duration::from_micros(delay);
@@ -3561,7 +3889,11 @@ impl Connection {
                     return Err(Error::ProtocolViolation);
                 }
                 self.acks
-                    .ack_freq(seqno, tolerance - 1, delay, ignore_order);
+                    .ack_freq(seqno, tolerance - 1, delay, reordering_threshold);
+            }
+            Frame::ImmediateAck => {
+                self.stats.borrow_mut().frame_rx.immediate_ack += 1;
+                self.acks.immediate_ack(space, now);
             }
             Frame::Datagram { data, .. } => {
                 self.stats.borrow_mut().frame_rx.datagram += 1;
@@ -3602,7 +3934,7 @@ impl Connection {
                             .datagram_outcome(dgram_tracker, OutgoingDatagramOutcome::Lost);
                         self.stats.borrow_mut().datagram_tx.lost += 1;
                     }
-                    recovery::Token::EcnEct0 => self.paths.lost_ecn(&mut self.stats.borrow_mut()),
+                    recovery::Token::EcnMarked => self.paths.lost_ecn(&mut self.stats.borrow_mut()),
                     // PMTUD probe loss is handled by the PMTUD state machine.
                     recovery::Token::PmtudProbe => (),
                 }
@@ -3635,6 +3967,7 @@ impl Connection {
         ack_ranges: R,
         ack_ecn: Option<&ecn::Count>,
         ack_delay: u64,
+        receive_times: &[(packet::Number, Duration)],
         now: Instant,
     ) -> Res<()>
     where
@@ -3652,9 +3985,15 @@ impl Connection {
             ack_ranges,
             ack_ecn,
             self.decode_ack_delay(ack_delay)?,
+            receive_times,
             now,
         );
         let largest_acknowledged = acked_packets.first().map(sent::Packet::pn);
+        if acked_packets.iter().any(sent::Packet::on_primary_path)
+            && let Some(failover) = &mut self.failover
+        {
+            failover.on_ack_received();
+        }
         qlog::packets_acked(&mut self.qlog, space, &acked_packets, now);
         let mut bytes_acked = 0;
         for acked in acked_packets {
@@ -3674,7 +4013,7 @@ impl Connection {
                     recovery::Token::Datagram(dgram_tracker) => self
                         .events
                         .datagram_outcome(dgram_tracker, OutgoingDatagramOutcome::Acked),
-                    recovery::Token::EcnEct0 => self.paths.acked_ecn(),
+                    recovery::Token::EcnMarked => self.paths.acked_ecn(),
                     // We don't care about these being ACK'ed
                     recovery::Token::HandshakeDone | recovery::Token::PmtudProbe => (),
                 }
@@ -3708,7 +4047,7 @@ impl Connection {
         qdebug!("[{self}] 0-RTT rejected");
         self.resend_0rtt(now);
         self.streams.zero_rtt_rejected();
-        self.crypto.states_mut().discard_0rtt_keys();
+        self.crypto.states_mut().discard_0rtt_keys(now);
         self.events.client_0rtt_rejected();
     }
 
@@ -3878,6 +4217,61 @@ impl Connection {
         self.streams.set_sendgroup(stream_id, group_id)
     }
 
+    /// Set the weight of a stream, which determines its share of the send capacity
+    /// with [`StreamScheduler::WeightedFair`].  The default weight is
+    /// [`DEFAULT_STREAM_WEIGHT`](crate::send_stream::DEFAULT_STREAM_WEIGHT).
+    ///
+    /// # Errors
+    /// When the stream does not exist, or `weight` is zero.
+    pub fn stream_weight(&mut self, stream_id: StreamId, weight: u16) -> Res<()> {
+        self.streams.set_weight(stream_id, weight)
+    }
+
+    /// Set the time by which a stream would like its data sent.  With
+    /// [`StreamScheduler::EarliestDeadline`], streams with the earliest deadline
+    /// are served first.  This does not cause data to be discarded.
+    ///
+    /// # Errors
+    /// When the stream does not exist.
+    pub fn stream_send_deadline(
+        &mut self,
+        stream_id: StreamId,
+        deadline: Option<Instant>,
+    ) -> Res<()> {
+        self.streams.set_deadline(stream_id, deadline)
+    }
+
+    /// Give up on a stream if the peer has not acknowledged all of its data by `deadline`.
+    /// The stream then stops retransmitting data, is reset with `RESET_STREAM_AT`
+    /// so that the first `reliable_size` bytes are still delivered,
+    /// and a [`ConnectionEvent::SendStreamExpired`] event is raised.
+    /// A `reliable_size` of 0 results in a plain `RESET_STREAM`.
+    /// The error code is taken from [`ConnectionParameters::deadline_error`].
+    ///
+    /// Setting a new deadline replaces any earlier one.
+    /// Unlike [`Self::stream_send_deadline`], this discards data.
+    ///
+    /// # Errors
+    /// When the stream does not exist, or `reliable_size` is non-zero and the peer
+    /// did not enable reliable reset ([`Error::NotAvailable`]).
+    pub fn stream_set_deadline(
+        &mut self,
+        stream_id: StreamId,
+        deadline: Instant,
+        reliable_size: u64,
+    ) -> Res<()> {
+        self.streams.get_send_stream(stream_id)?;
+        if reliable_size > 0 && !self.tps.borrow().remote().get_empty(ResetStreamAt) {
+            return Err(Error::NotAvailable);
+        }
+        self.streams.set_expiry(
+            stream_id,
+            deadline,
+            self.conn_params.get_deadline_error(),
+            reliable_size,
+        )
+    }
+
     /// # Errors
     /// When the stream does not exist.
     pub fn send_stream_stats(&self, stream_id: StreamId) -> Res<send_stream::Stats> {
@@ -3931,6 +4325,24 @@ impl Connection {
         val.map(|v| v == data.len())
     }
 
+    /// Send data on a stream without copying it.
+    /// The stream holds on to `data` until the peer acknowledges it.
+    /// If flow control or the send buffer do not allow all of `data` to be sent,
+    /// as much as possible is copied and sent, and the rest of `data` is returned
+    /// so that it can be sent later.  Returns `None` if all of `data` was sent.
+    /// May cause `DATA_BLOCKED` or `STREAM_DATA_BLOCKED` frames to be sent.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` the stream does not exist,
+    /// `InvalidInput` if `data` is empty,
+    /// `FinalSizeError` if the stream has already been closed.
+    pub fn stream_send_bytes(&mut self, stream_id: StreamId, data: Bytes) -> Res<Option<Bytes>> {
+        self.streams
+            .get_send_stream_mut(stream_id)?
+            .send_bytes(data)
+    }
+
     /// Bytes that `stream_send()` is guaranteed to accept for sending.
     /// i.e. that will not be blocked by flow credits or send buffer max
     /// capacity.
@@ -4015,6 +4427,31 @@ impl Connection {
         self.streams.recv(stream_id, data)
     }
 
+    /// Get the next contiguous chunk of data received on a stream, without copying it.
+    /// The chunk is empty if no data is available.  Data stays buffered, and flow
+    /// control credit is not released, until it is marked as read using
+    /// [`Self::stream_recv_consume`].
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` if the stream does not exist.
+    /// `NoMoreData` if data and fin bit were previously read by the application.
+    pub fn stream_recv_chunk(&self, stream_id: StreamId) -> Res<&[u8]> {
+        self.streams.recv_chunk(stream_id)
+    }
+
+    /// Mark the first `amount` bytes of the chunk from [`Self::stream_recv_chunk`] as read.
+    /// bool says whether this reached the end of the stream, as for [`Self::stream_recv`].
+    ///
+    /// # Errors
+    ///
+    /// `InvalidStreamId` if the stream does not exist.
+    /// `NoMoreData` if data and fin bit were previously read by the application.
+    /// `InvalidInput` if `amount` is more than the length of the chunk.
+    pub fn stream_recv_consume(&mut self, stream_id: StreamId, amount: usize) -> Res<bool> {
+        self.streams.recv_consume(stream_id, amount)
+    }
+
     /// Application is no longer interested in this stream.
     /// # Errors
     /// When the stream ID is invalid.
@@ -4121,8 +4558,24 @@ impl Connection {
     /// `max_datagram_size` is just a current estimate and will change over
     /// time depending on the encoded size of the packet number, ack frames, etc.
     pub fn send_datagram<I: Into<DatagramTracking>>(&mut self, buf: Vec<u8>, id: I) -> Res<()> {
+        self.send_datagram_with_options(buf, id, DatagramOptions::default())
+    }
+
+    /// Queue a datagram for sending with a deadline and a priority.
+    /// A datagram that is not sent before its deadline is dropped and
+    /// reported with [`OutgoingDatagramOutcome::Expired`].
+    ///
+    /// # Errors
+    ///
+    /// As for [`Self::send_datagram`].
+    pub fn send_datagram_with_options<I: Into<DatagramTracking>>(
+        &mut self,
+        buf: Vec<u8>,
+        id: I,
+        options: DatagramOptions,
+    ) -> Res<()> {
         self.quic_datagrams
-            .add_datagram(buf, id.into(), &mut self.stats.borrow_mut())
+            .add_datagram(buf, id.into(), options, &mut self.stats.borrow_mut())
     }
 
     /// Return the PLMTU of the primary path.
@@ -4,14 +4,20 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::{cmp::max, num::NonZeroUsize, time::Duration};
+use std::{
+    cmp::{max, min},
+    num::NonZeroUsize,
+    time::Duration,
+};
 
-use neqo_common::to_u64;
+use neqo_common::{Ecn, to_u64};
 
 pub use crate::recovery::FAST_PTO_SCALE;
 use crate::{
-    CongestionControl, DEFAULT_INITIAL_RTT, HyStartCssBaseline, Res, SlowStart,
-    connection::{ConnectionIdManager, Role},
+    AppError, CongestionControl, CongestionController, DEFAULT_INITIAL_RTT, HyStartCssBaseline,
+    Pmtud, Res, SlowStart, StatelessResetKey, StreamScheduler,
+    cc::CongestionControlFactory,
+    connection::{ConnectionIdManager, FailoverPolicy, Role},
     rtt::GRANULARITY,
     stream_id::StreamType,
     tparams::{
@@ -20,7 +26,8 @@ use crate::{
             ActiveConnectionIdLimit, DisableMigration, GreaseQuicBit, IdleTimeout, InitialMaxData,
             InitialMaxStreamDataBidiLocal, InitialMaxStreamDataBidiRemote, InitialMaxStreamDataUni,
             InitialMaxStreamsBidi, InitialMaxStreamsUni, MaxAckDelay, MaxDatagramFrameSize,
-            MinAckDelay, PreferredAddress as PreferredAddressTp, ResetStreamAt, Scone,
+            MaxReceiveTimestampsPerAck, MinAckDelay, PreferredAddress as PreferredAddressTp,
+            ResetStreamAt, Scone,
         },
         TransportParametersHandler,
     },
@@ -109,8 +116,14 @@ pub enum PreferredAddressConfig {
 pub struct ConnectionParameters {
     versions: version::Config,
     congestion_control: CongestionControl,
+    /// Creates an application-supplied congestion controller.
+    /// This overrides `congestion_control` and `slow_start`.
+    congestion_control_factory: Option<CongestionControlFactory>,
     slow_start: SlowStart,
     hystart_css_baseline: HyStartCssBaseline,
+    /// The ECT codepoint to mark outgoing packets with.  If this is `None`,
+    /// ECT(1) is used with [`CongestionControl::Prague`] and ECT(0) otherwise.
+    ecn_codepoint: Option<Ecn>,
     /// Initial connection-level flow control limit.
     max_data: u64,
     /// Initial flow control limit for receiving data on bidirectional streams that the peer
@@ -140,9 +153,15 @@ pub struct ConnectionParameters {
     /// locally. `None` (the default) disables the check, leaving the idle timeout
     /// as the only backstop.
     max_pto: Option<NonZeroUsize>,
+    /// When to fail over to a standby path.  `None` (the default) disables failover.
+    path_failover: Option<FailoverPolicy>,
     preferred_address: PreferredAddressConfig,
     datagram_size: u64,
     outgoing_datagram_queue: usize,
+    datagram_share: u8,
+    /// The maximum number of packet receive timestamps to ask the peer to include in
+    /// each ACK frame.  0 disables receive timestamps.
+    receive_timestamps: u64,
     initial_rtt: Duration,
     fast_pto: u8,
     grease: bool,
@@ -163,9 +182,20 @@ pub struct ConnectionParameters {
     /// Whether to advertise support for `RESET_STREAM_AT` (reliable stream reset) via the
     /// `reset_stream_at` transport parameter.
     reliable_stream_reset: bool,
+    /// The application error code used to reset a stream when its deadline
+    /// passes; see [`crate::Connection::stream_set_deadline`].
+    deadline_error: AppError,
     /// Whether to recover from spurious congestion events by restoring prior Congestion Controller
     /// state. Detection and metrics are always active regardless of this setting.
     spurious_recovery: bool,
+    /// Whether a client saves congestion state in resumption tokens and reuses it
+    /// with Careful Resume when resuming.
+    careful_resume: bool,
+    /// How send capacity is divided between streams.
+    stream_scheduler: StreamScheduler,
+    /// The key used to derive stateless reset tokens from connection IDs.
+    /// If this is `None`, stateless reset tokens are random.
+    stateless_reset_key: Option<StatelessResetKey>,
 }
 
 impl Default for ConnectionParameters {
@@ -173,8 +203,10 @@ impl Default for ConnectionParameters {
         Self {
             versions: version::Config::default(),
             congestion_control: CongestionControl::Cubic,
+            congestion_control_factory: None,
             slow_start: SlowStart::Classic,
             hystart_css_baseline: HyStartCssBaseline::CurrentRoundMinRtt,
+            ecn_codepoint: None,
             max_data: INITIAL_LOCAL_MAX_DATA,
             max_stream_data_bidi_remote: to_u64(INITIAL_LOCAL_MAX_STREAM_DATA),
             max_stream_data_bidi_local: to_u64(INITIAL_LOCAL_MAX_STREAM_DATA),
@@ -184,9 +216,12 @@ impl Default for ConnectionParameters {
             ack_ratio: Self::DEFAULT_ACK_RATIO,
             idle_timeout: Self::DEFAULT_IDLE_TIMEOUT,
             max_pto: None,
+            path_failover: None,
             preferred_address: PreferredAddressConfig::Default,
             datagram_size: MAX_DATAGRAM_FRAME_SIZE,
             outgoing_datagram_queue: MAX_QUEUED_DATAGRAMS_DEFAULT,
+            datagram_share: 0,
+            receive_timestamps: 0,
             initial_rtt: DEFAULT_INITIAL_RTT,
             fast_pto: FAST_PTO_SCALE,
             grease: true,
@@ -199,7 +234,11 @@ impl Default for ConnectionParameters {
             randomize_first_pn: true,
             scone: false,
             reliable_stream_reset: true,
+            deadline_error: 0,
             spurious_recovery: true,
+            careful_resume: false,
+            stream_scheduler: StreamScheduler::SendOrder,
+            stateless_reset_key: None,
         }
     }
 }
@@ -243,6 +282,22 @@ impl ConnectionParameters {
         self
     }
 
+    pub(crate) const fn get_congestion_control_factory(&self) -> Option<&CongestionControlFactory> {
+        self.congestion_control_factory.as_ref()
+    }
+
+    /// Use congestion controllers that `factory` creates, rather than one of the
+    /// built-in algorithms.  `factory` is called for each path, with the path MTU
+    /// discovery state that the controller takes ownership of.
+    #[must_use]
+    pub fn congestion_control_factory(
+        mut self,
+        factory: Box<dyn Fn(Pmtud) -> Box<dyn CongestionController>>,
+    ) -> Self {
+        self.congestion_control_factory = Some(CongestionControlFactory::new(factory));
+        self
+    }
+
     #[must_use]
     pub const fn get_slow_start(&self) -> SlowStart {
         self.slow_start
@@ -265,6 +320,28 @@ impl ConnectionParameters {
         self
     }
 
+    #[must_use]
+    pub const fn get_ecn_codepoint(&self) -> Ecn {
+        match (self.ecn_codepoint, self.congestion_control) {
+            (Some(v), _) => v,
+            (None, CongestionControl::Prague) => Ecn::Ect1,
+            (None, _) => Ecn::Ect0,
+        }
+    }
+
+    /// Set the ECT codepoint that outgoing packets are marked with while the path
+    /// is ECN capable.  ECT(1) identifies L4S traffic, see RFC 9331.
+    ///
+    /// # Panics
+    ///
+    /// If `v` is not [`Ecn::Ect0`] or [`Ecn::Ect1`].
+    #[must_use]
+    pub const fn ecn_codepoint(mut self, v: Ecn) -> Self {
+        assert!(v.is_ect(), "ECN codepoint must be ECT(0) or ECT(1)");
+        self.ecn_codepoint = Some(v);
+        self
+    }
+
     #[must_use]
     pub const fn get_max_data(&self) -> u64 {
         self.max_data
@@ -391,6 +468,19 @@ impl ConnectionParameters {
         self.max_pto
     }
 
+    /// Enable automatic failover to standby paths, which a client registers with
+    /// [`crate::Connection::add_standby_path`].  `None` disables failover.
+    #[must_use]
+    pub const fn path_failover(mut self, policy: Option<FailoverPolicy>) -> Self {
+        self.path_failover = policy;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_path_failover(&self) -> Option<FailoverPolicy> {
+        self.path_failover
+    }
+
     #[must_use]
     pub const fn get_initial_rtt(&self) -> Duration {
         self.initial_rtt
@@ -425,6 +515,37 @@ impl ConnectionParameters {
         self
     }
 
+    #[must_use]
+    pub const fn get_datagram_share(&self) -> u8 {
+        self.datagram_share
+    }
+
+    /// The share of the bytes sent, as a percentage, that queued datagrams can take
+    /// ahead of stream data.  With the default of 0, datagrams are only sent
+    /// when there is no stream data to send at normal or higher priority.
+    /// Values over 100 are treated as 100.
+    #[must_use]
+    pub fn datagram_share(mut self, v: u8) -> Self {
+        self.datagram_share = min(v, 100);
+        self
+    }
+
+    #[must_use]
+    pub const fn get_receive_timestamps(&self) -> u64 {
+        self.receive_timestamps
+    }
+
+    /// Ask the peer to report when it received packets, using the ACK receive
+    /// timestamps extension, with at most this many timestamps in each ACK.
+    /// The times that are reported are passed to the congestion controller
+    /// with acknowledged packets; see [`crate::SentPacket::time_received`].
+    /// The default of 0 disables this.
+    #[must_use]
+    pub const fn receive_timestamps(mut self, v: u64) -> Self {
+        self.receive_timestamps = v;
+        self
+    }
+
     #[must_use]
     pub const fn get_fast_pto(&self) -> u8 {
         self.fast_pto
@@ -557,6 +678,19 @@ impl ConnectionParameters {
         self
     }
 
+    #[must_use]
+    pub const fn get_deadline_error(&self) -> AppError {
+        self.deadline_error
+    }
+
+    /// Set the application error code that is sent when a stream is reset because
+    /// its deadline passed.  The default is 0.
+    #[must_use]
+    pub const fn deadline_error(mut self, deadline_error: AppError) -> Self {
+        self.deadline_error = deadline_error;
+        self
+    }
+
     #[must_use]
     pub const fn spurious_recovery_enabled(&self) -> bool {
         self.spurious_recovery
@@ -568,6 +702,56 @@ impl ConnectionParameters {
         self
     }
 
+    #[must_use]
+    pub const fn careful_resume_enabled(&self) -> bool {
+        self.careful_resume
+    }
+
+    /// Enable Careful Resume at a client.  Resumption tokens then carry the congestion
+    /// window, minimum RTT and server address of the primary path at the time the token
+    /// is made, and a connection that resumes with such a token to the same server
+    /// address starts with up to half that congestion window once the RTT is confirmed.
+    /// Tokens taken later in a connection carry a more representative window.
+    ///
+    /// See <https://datatracker.ietf.org/doc/draft-ietf-tsvwg-careful-resume/>.
+    #[must_use]
+    pub const fn careful_resume(mut self, careful_resume: bool) -> Self {
+        self.careful_resume = careful_resume;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_stream_scheduler(&self) -> StreamScheduler {
+        self.stream_scheduler
+    }
+
+    /// Select how send capacity is divided between streams with data at the same
+    /// [`crate::send_stream::TransmissionPriority`].  Stream weights and deadlines
+    /// are set with [`crate::Connection::stream_weight`] and
+    /// [`crate::Connection::stream_send_deadline`].
+    #[must_use]
+    pub const fn stream_scheduler(mut self, v: StreamScheduler) -> Self {
+        self.stream_scheduler = v;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_stateless_reset_key(&self) -> Option<&StatelessResetKey> {
+        self.stateless_reset_key.as_ref()
+    }
+
+    /// Derive stateless reset tokens from connection IDs using `key`, rather than
+    /// picking them at random.  This applies to the token in transport parameters,
+    /// the token for a preferred address, and tokens in `NEW_CONNECTION_ID` frames.
+    ///
+    /// Endpoints that share a key can recompute the token for any connection ID
+    /// they issued, which allows them to send a stateless reset after losing state.
+    #[must_use]
+    pub fn stateless_reset_key(mut self, key: StatelessResetKey) -> Self {
+        self.stateless_reset_key = Some(key);
+        self
+    }
+
     /// # Errors
     /// When a connection ID cannot be obtained.
     /// # Panics
@@ -578,6 +762,9 @@ impl ConnectionParameters {
         cid_manager: &mut ConnectionIdManager,
     ) -> Res<TransportParametersHandler> {
         let mut tps = TransportParametersHandler::new(role, self.versions.clone());
+        if let Some(key) = &self.stateless_reset_key {
+            cid_manager.set_reset_key(key.clone());
+        }
         // default parameters
         tps.local_mut().set_integer(
             ActiveConnectionIdLimit,
@@ -638,6 +825,10 @@ impl ConnectionParameters {
         }
         tps.local_mut()
             .set_integer(MaxDatagramFrameSize, self.datagram_size);
+        // Receive timestamps are sent in microseconds, which is the default exponent,
+        // so only the number of timestamps is advertised.
+        tps.local_mut()
+            .set_integer(MaxReceiveTimestampsPerAck, self.receive_timestamps);
         Ok(tps)
     }
 }
@@ -692,6 +883,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn stateless_reset_key() {
+        assert!(
+            ConnectionParameters::default()
+                .get_stateless_reset_key()
+                .is_none()
+        );
+        let params = ConnectionParameters::default()
+            .stateless_reset_key(StatelessResetKey::new([1; StatelessResetKey::LEN]));
+        assert!(params.get_stateless_reset_key().is_some());
+    }
+
+    #[test]
+    fn ecn_codepoint() {
+        let params = ConnectionParameters::default();
+        assert_eq!(params.get_ecn_codepoint(), Ecn::Ect0);
+        let params = params.congestion_control(CongestionControl::Prague);
+        assert_eq!(params.get_ecn_codepoint(), Ecn::Ect1);
+        let params = params.ecn_codepoint(Ecn::Ect0);
+        assert_eq!(params.get_ecn_codepoint(), Ecn::Ect0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ECN codepoint must be ECT(0) or ECT(1)")]
+    fn ecn_codepoint_ce() {
+        _ = ConnectionParameters::default().ecn_codepoint(Ecn::Ce);
+    }
+
     #[test]
     fn spurious_recovery_enabled() {
         // Default is true; verify builder can toggle it.
@@ -702,4 +921,68 @@ mod tests {
                 .spurious_recovery_enabled()
         );
     }
+
+    #[test]
+    fn careful_resume_enabled() {
+        assert!(!ConnectionParameters::default().careful_resume_enabled());
+        assert!(
+            ConnectionParameters::default()
+                .careful_resume(true)
+                .careful_resume_enabled()
+        );
+    }
+
+    #[test]
+    fn stream_scheduler() {
+        assert_eq!(
+            ConnectionParameters::default().get_stream_scheduler(),
+            StreamScheduler::SendOrder
+        );
+        assert_eq!(
+            ConnectionParameters::default()
+                .stream_scheduler(StreamScheduler::WeightedFair)
+                .get_stream_scheduler(),
+            StreamScheduler::WeightedFair
+        );
+    }
+
+    #[test]
+    fn datagram_share() {
+        assert_eq!(ConnectionParameters::default().get_datagram_share(), 0);
+        assert_eq!(
+            ConnectionParameters::default()
+                .datagram_share(25)
+                .get_datagram_share(),
+            25
+        );
+        assert_eq!(
+            ConnectionParameters::default()
+                .datagram_share(200)
+                .get_datagram_share(),
+            100
+        );
+    }
+
+    #[test]
+    fn receive_timestamps() {
+        assert_eq!(ConnectionParameters::default().get_receive_timestamps(), 0);
+        assert_eq!(
+            ConnectionParameters::default()
+                .receive_timestamps(16)
+                .get_receive_timestamps(),
+            16
+        );
+    }
+
+    #[test]
+    fn path_failover() {
+        assert_eq!(ConnectionParameters::default().get_path_failover(), None);
+        let policy = FailoverPolicy::default().pto_count(3);
+        assert_eq!(
+            ConnectionParameters::default()
+                .path_failover(Some(policy))
+                .get_path_failover(),
+            Some(policy)
+        );
+    }
 }
@@ -9,9 +9,9 @@ use std::time::Duration;
 use test_fixture::{DEFAULT_ADDR_V4, assertions};
 
 use super::{
-    super::ConnectionParameters, DEFAULT_RTT, ack_bytes, connect_rtt_idle, default_client,
-    default_server, fill_cwnd, increase_cwnd, induce_persistent_congestion, new_client, new_server,
-    send_something,
+    super::ConnectionParameters, AT_LEAST_PTO, DEFAULT_RTT, ack_bytes, connect_rtt_idle,
+    default_client, default_server, fill_cwnd, increase_cwnd, induce_persistent_congestion,
+    new_client, new_server, send_something,
 };
 use crate::{connection::tests::assert_path_challenge_min_len, stream_id::StreamType};
 
@@ -201,3 +201,59 @@ fn migrate_ack_delay() {
     assert!(af.is_some());
     assert_eq!(client.stats().frame_tx.ack_frequency, ad_before + 1);
 }
+
+/// A PTO probe carries an `IMMEDIATE_ACK` frame, so the peer acknowledges it straight away.
+#[test]
+fn immediate_ack_on_pto() {
+    let mut client = default_client();
+    let mut server = default_server();
+    let now = connect_rtt_idle(&mut client, &mut server, DEFAULT_RTT);
+
+    // Lose a packet, then wait for the PTO.
+    drop(send_something(&mut client, now));
+    let now = now + AT_LEAST_PTO;
+    let before = client.stats().frame_tx.immediate_ack;
+    let probe = client.process_output(now).dgram().unwrap();
+    assert_eq!(client.stats().frame_tx.immediate_ack, before + 1);
+
+    server.process_input(probe, now);
+    assert_eq!(server.stats().frame_rx.immediate_ack, 1);
+    let ack = server.process_output(now).dgram();
+    assert!(ack.is_some());
+}
+
+/// A client that asks for receive timestamps gets them in the server's ACK frames.
+#[test]
+fn receive_timestamps() {
+    const MAX_TIMESTAMPS: u64 = 8;
+    let mut client = new_client(ConnectionParameters::default().receive_timestamps(MAX_TIMESTAMPS));
+    let mut server = default_server();
+    let now = connect_rtt_idle(&mut client, &mut server, DEFAULT_RTT);
+
+    let stream = client.stream_create(StreamType::UniDi).unwrap();
+    let (pkts, now) = fill_cwnd(&mut client, stream, now);
+    let ack = ack_bytes(&mut server, stream, pkts, now);
+    let sent = server.stats().frame_tx.receive_timestamps;
+    assert!(sent > 0);
+    assert!(sent <= usize::try_from(MAX_TIMESTAMPS).unwrap());
+
+    client.process_input(ack, now);
+    assert_eq!(client.stats().frame_rx.receive_timestamps, sent);
+    // The server didn't ask for any.
+    assert_eq!(client.stats().frame_tx.receive_timestamps, 0);
+}
+
+/// Without being asked, ACK frames don't include receive timestamps.
+#[test]
+fn receive_timestamps_default() {
+    let mut client = default_client();
+    let mut server = default_server();
+    let now = connect_rtt_idle(&mut client, &mut server, DEFAULT_RTT);
+
+    let stream = client.stream_create(StreamType::UniDi).unwrap();
+    let (pkts, now) = fill_cwnd(&mut client, stream, now);
+    let ack = ack_bytes(&mut server, stream, pkts, now);
+    client.process_input(ack, now);
+    assert_eq!(server.stats().frame_tx.receive_timestamps, 0);
+    assert_eq!(client.stats().frame_rx.receive_timestamps, 0);
+}
@@ -4,13 +4,14 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, rc::Rc, time::Duration};
 
 use neqo_common::{event::Provider as _, to_u64};
 use static_assertions::const_assert;
 
 use super::{
-    AT_LEAST_PTO, assert_error, connect_force_idle, default_server, new_client, new_server, now,
+    AT_LEAST_PTO, assert_error, connect_force_idle, default_server, fill_cwnd, new_client,
+    new_server, now,
 };
 use crate::{
     CloseReason, Connection, ConnectionParameters, Error, MIN_INITIAL_PACKET_SIZE, Pmtud,
@@ -19,7 +20,7 @@ use crate::{
     events::{ConnectionEvent, OutgoingDatagramOutcome},
     frame::FrameType,
     packet,
-    quic_datagrams::QuicDatagram,
+    quic_datagrams::{DatagramOptions, QuicDatagram},
     send_stream::{RetransmissionPriority, TransmissionPriority},
 };
 
@@ -489,6 +490,175 @@ fn outgoing_datagram_queue_full() {
     ));
 }
 
+/// When the queue is full, the oldest datagram with the lowest priority is dropped.
+#[test]
+fn outgoing_datagram_queue_full_priority() {
+    let (mut client, _server) = connect_datagram();
+
+    let high = DatagramOptions::default().priority(1);
+    assert_eq!(
+        client.send_datagram_with_options(DATA_SMALLER_THAN_MTU.to_vec(), Some(1), high),
+        Ok(())
+    );
+    assert_eq!(
+        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(2)),
+        Ok(())
+    );
+    assert_eq!(
+        client.send_datagram_with_options(DATA_SMALLER_THAN_MTU.to_vec(), Some(3), high),
+        Ok(())
+    );
+    assert!(matches!(
+        client.next_event().unwrap(),
+        ConnectionEvent::OutgoingDatagramOutcome { id, outcome } if id == 2 && outcome == OutgoingDatagramOutcome::DroppedQueueFull
+    ));
+
+    // A datagram with a lower priority than all of those queued is dropped itself.
+    assert_eq!(
+        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(4)),
+        Ok(())
+    );
+    assert!(matches!(
+        client.next_event().unwrap(),
+        ConnectionEvent::OutgoingDatagramOutcome { id, outcome } if id == 4 && outcome == OutgoingDatagramOutcome::DroppedQueueFull
+    ));
+    assert_eq!(client.stats().datagram_tx.dropped_queue_full, 2);
+}
+
+/// A datagram with a higher priority is sent before those queued ahead of it.
+#[test]
+fn datagram_priority() {
+    let (mut client, mut server) = connect_datagram();
+
+    assert_eq!(
+        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(1)),
+        Ok(())
+    );
+    assert_eq!(
+        client.send_datagram_with_options(
+            DATA_MTU.to_vec(),
+            Some(2),
+            DatagramOptions::default().priority(1)
+        ),
+        Ok(())
+    );
+
+    if let ConnectionEvent::Datagram(data) =
+        &send_packet_and_get_server_event(&mut client, &mut server)
+    {
+        assert_eq!(data, DATA_MTU);
+    } else {
+        panic!();
+    }
+    if let ConnectionEvent::Datagram(data) =
+        &send_packet_and_get_server_event(&mut client, &mut server)
+    {
+        assert_eq!(data, DATA_SMALLER_THAN_MTU);
+    } else {
+        panic!();
+    }
+}
+
+#[test]
+fn datagram_expired() {
+    let (mut client, mut server) = connect_datagram();
+
+    let dgram_sent = client.stats().frame_tx.datagram;
+    assert_eq!(
+        client.send_datagram_with_options(
+            DATA_MTU.to_vec(),
+            Some(1),
+            DatagramOptions::default().deadline(now() + AT_LEAST_PTO)
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        client.send_datagram(DATA_SMALLER_THAN_MTU.to_vec(), Some(2)),
+        Ok(())
+    );
+
+    // The first datagram is dropped once its deadline passes, without being sent.
+    let later = now() + AT_LEAST_PTO;
+    let out = client.process_output(later).dgram();
+    assert!(matches!(
+        client.next_event().unwrap(),
+        ConnectionEvent::OutgoingDatagramOutcome { id, outcome } if id == 1 && outcome == OutgoingDatagramOutcome::Expired
+    ));
+    assert_eq!(client.stats().datagram_tx.expired, 1);
+    assert_eq!(client.stats().frame_tx.datagram, dgram_sent + 1);
+
+    server.process_input(out.unwrap(), later);
+    assert!(
+        server
+            .events()
+            .any(|e| matches!(e, ConnectionEvent::Datagram(data) if data == DATA_SMALLER_THAN_MTU))
+    );
+}
+
+/// A datagram that can't be sent is dropped at its deadline,
+/// even if nothing else needs the connection to wake up.
+#[test]
+fn datagram_expiry_timer() {
+    const DEADLINE: Duration = Duration::from_millis(5);
+    let (mut client, _server) = connect_datagram();
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    let (_, now) = fill_cwnd(&mut client, stream_id, now());
+
+    client
+        .send_datagram_with_options(
+            DATA_SMALLER_THAN_MTU.to_vec(),
+            Some(1),
+            DatagramOptions::default().deadline(now + DEADLINE),
+        )
+        .unwrap();
+    let deadline = now + DEADLINE;
+    let mut t = now;
+    while t < deadline {
+        t += client.process_output(t).callback();
+    }
+    assert_eq!(t, deadline);
+
+    _ = client.process_output(deadline);
+    assert!(client.events().any(|e| matches!(
+        e,
+        ConnectionEvent::OutgoingDatagramOutcome { id, outcome }
+            if id == 1 && outcome == OutgoingDatagramOutcome::Expired
+    )));
+    assert_eq!(client.stats().datagram_tx.expired, 1);
+}
+
+/// With a share of the bytes sent, a datagram is sent ahead of normal priority
+/// stream data, but then has to wait for stream data to earn more credit.
+#[test]
+fn datagram_share_before_stream_data() {
+    let mut client = new_client(
+        ConnectionParameters::default()
+            .datagram_size(QuicDatagram::MAX_SIZE)
+            .outgoing_datagram_queue(OUTGOING_QUEUE)
+            .datagram_share(50),
+    );
+    let mut server =
+        new_server(ConnectionParameters::default().datagram_size(QuicDatagram::MAX_SIZE));
+    connect_force_idle(&mut client, &mut server);
+
+    let stream_id = client.stream_create(StreamType::BiDi).unwrap();
+    client
+        .stream_send(stream_id, &[6; 4 * DATAGRAM_LEN_MTU])
+        .unwrap();
+    assert_eq!(client.send_datagram(DATA_MTU.to_vec(), Some(1)), Ok(()));
+    assert_eq!(client.send_datagram(DATA_MTU.to_vec(), Some(2)), Ok(()));
+
+    let dgram_sent = client.stats().frame_tx.datagram;
+    assert!(matches!(
+        send_packet_and_get_server_event(&mut client, &mut server),
+        ConnectionEvent::Datagram(data) if data == DATA_MTU
+    ));
+    assert!(
+        matches!(send_packet_and_get_server_event(&mut client, &mut server), ConnectionEvent::RecvStreamReadable { stream_id: s } if s == stream_id)
+    );
+    assert_eq!(client.stats().frame_tx.datagram, dgram_sent + 1);
+}
+
 fn send_datagram(sender: &mut Connection, receiver: &mut Connection, data: Vec<u8>) {
     let dgram_sent = sender.stats().frame_tx.datagram;
     assert_eq!(sender.send_datagram(data, Some(1)), Ok(()));
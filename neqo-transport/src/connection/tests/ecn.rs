@@ -290,6 +290,62 @@ fn disables_on_remark() {
     assert_ecn_disabled(client_pkt.tos());
 }
 
+fn remark_ect0(d: Datagram) -> Datagram {
+    if d.tos().is_ecn_marked() {
+        set_tos(d, Ecn::Ect0)
+    } else {
+        d
+    }
+}
+
+#[test]
+fn ect1_codepoint() {
+    let now = now();
+    let mut client = new_client(ConnectionParameters::default().ecn_codepoint(Ecn::Ect1));
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+
+    for _ in 0..ecn::TEST_COUNT {
+        let ack = send_and_receive(&mut client, &mut server, now);
+        client.process_input(ack.unwrap(), now);
+    }
+
+    // The path validated with ECT(1), and the client keeps marking.
+    let client_pkt = send_something(&mut client, now);
+    assert_eq!(Ecn::from(client_pkt.tos()), Ecn::Ect1);
+    let stats = client.stats();
+    assert_eq!(
+        stats.ecn_path_validation[ecn::ValidationOutcome::Capable],
+        1
+    );
+    assert_eq!(stats.ecn_tx[packet::Type::Short][Ecn::Ect0], 0);
+    assert!(server.stats().ecn_rx[packet::Type::Short][Ecn::Ect1] > 0);
+}
+
+#[test]
+fn ect1_disables_on_remark() {
+    let now = now();
+    let mut client = new_client(ConnectionParameters::default().ecn_codepoint(Ecn::Ect1));
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+
+    for _ in 0..ecn::TEST_COUNT {
+        if let Some(ack) =
+            send_with_modifier_and_receive(&mut client, &mut server, now, remark_ect0)
+        {
+            client.process_input(ack, now);
+        }
+    }
+
+    let client_pkt = send_something(&mut client, now);
+    assert_ecn_disabled(client_pkt.tos());
+    assert_eq!(
+        client.stats().ecn_path_validation
+            [ecn::ValidationOutcome::NotCapable(ecn::ValidationError::ReceivedUnsentECT0)],
+        1
+    );
+}
+
 /// This function performs a handshake over a path that modifies packets via `orig_path_modifier`.
 /// It then sends `burst` packets on that path, and then migrates to a new path that
 /// modifies packets via `new_path_modifier`.  It sends `burst` packets on the new path.
@@ -7,7 +7,7 @@
 use neqo_common::{Datagram, qdebug};
 use test_fixture::{
     assertions::{is_handshake, is_initial},
-    now, split_datagram,
+    new_neqo_qlog, now, split_datagram,
 };
 
 use super::{
@@ -16,7 +16,7 @@ use super::{
         Connection, ConnectionParameters, Error, Output, State, StreamType,
     },
     AT_LEAST_PTO, connect, connect_force_idle, default_client, default_server, maybe_authenticate,
-    send_and_receive, send_something,
+    new_client_with_qlog, send_and_receive, send_something,
 };
 use crate::{
     MIN_INITIAL_PACKET_SIZE,
@@ -467,3 +467,26 @@ fn initial_handshake_pto_padding() {
     }
     assert!(found_coalesced);
 }
+
+#[test]
+fn qlog_key_update() {
+    let (mut client, client_log) = new_client_with_qlog(ConnectionParameters::default());
+    let mut server = default_server();
+    let (log, server_log) = new_neqo_qlog();
+    server.set_qlog(log);
+    connect_force_idle(&mut client, &mut server);
+
+    client.initiate_key_update().unwrap();
+    assert!(send_and_receive(&mut client, &mut server, now()).is_some());
+    drop((client, server));
+
+    let client_log = client_log.to_string();
+    // Handshake keys are discarded once the handshake is confirmed.
+    assert!(client_log.contains(r#""trigger":"tls""#));
+    assert!(client_log.contains(r#""generation":1"#));
+    assert!(client_log.contains(r#""trigger":"local_update""#));
+
+    let server_log = server_log.to_string();
+    assert!(server_log.contains(r#""generation":1"#));
+    assert!(server_log.contains(r#""trigger":"remote_update""#));
+}
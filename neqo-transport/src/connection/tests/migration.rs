@@ -12,12 +12,13 @@
 
 use std::{
     cell::RefCell,
+    iter,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     rc::Rc,
     time::{Duration, Instant},
 };
 
-use neqo_common::{Datagram, Decoder, event::Provider as _, qdebug, to_u64};
+use neqo_common::{Datagram, Decoder, event::Provider as _, hex::Hex, qdebug, to_u64};
 use test_fixture::{
     DEFAULT_ADDR, DEFAULT_ADDR_V4,
     assertions::{assert_v4_path, assert_v6_path},
@@ -27,13 +28,13 @@ use test_fixture::{
 use super::{
     super::{Connection, Output, State, StreamType},
     CountingConnectionIdGenerator, connect_fail, connect_force_idle, connect_rtt_idle,
-    default_client, default_server, maybe_authenticate, new_client, new_server, send_something,
-    zero_len_cid_client,
+    default_client, default_server, maybe_authenticate, new_client, new_client_with_qlog,
+    new_server, send_something, zero_len_cid_client,
 };
 use crate::{
     CloseReason, ConnectionEvent, ConnectionId, ConnectionIdDecoder as _, ConnectionIdGenerator,
-    ConnectionIdRef, ConnectionParameters, EmptyConnectionIdGenerator, Error,
-    MIN_INITIAL_PACKET_SIZE,
+    ConnectionIdRef, ConnectionParameters, EmptyConnectionIdGenerator, Error, FailoverPolicy,
+    FailoverReason, MIN_INITIAL_PACKET_SIZE,
     cid::ConnectionIdManager,
     connection::tests::{
         assert_path_challenge_min_len, connect, send_something_paced, send_with_extra,
@@ -627,6 +628,28 @@ fn migrate_same_fail() {
     ));
 }
 
+#[test]
+fn migrate_qlog() {
+    let (mut client, contents) = new_client_with_qlog(ConnectionParameters::default());
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    let now = now();
+
+    client
+        .migrate(Some(DEFAULT_ADDR_V4), Some(DEFAULT_ADDR_V4), false, now)
+        .unwrap();
+    let probe = client.process_output(now).dgram().unwrap();
+    let resp = server.process(Some(probe), now).dgram().unwrap();
+    client.process_input(resp, now);
+    drop(client);
+
+    let log = contents.to_string();
+    let path = format!("{DEFAULT_ADDR_V4}->{DEFAULT_ADDR_V4}");
+    assert!(log.contains(&format!("path migration started: {path}")));
+    assert!(log.contains(&format!("path validated: {path}")));
+    assert!(log.contains(&format!("path migrated: {path}")));
+}
+
 /// This gets the connection ID from a datagram using the default
 /// connection ID generator/decoder.
 pub fn get_cid(d: &Datagram) -> ConnectionIdRef<'_> {
@@ -1145,6 +1168,38 @@ fn retire_all() {
     assert_ne!(get_cid(&retire), original_cid);
 }
 
+/// Connection IDs from the peer are logged when they are received and retired.
+#[test]
+fn retire_all_qlog() {
+    let (mut client, contents) = new_client_with_qlog(ConnectionParameters::default());
+    let cid_gen: Rc<RefCell<dyn ConnectionIdGenerator>> =
+        Rc::new(RefCell::new(CountingConnectionIdGenerator::default()));
+    let mut server = Connection::new_server(
+        test_fixture::DEFAULT_KEYS,
+        test_fixture::DEFAULT_ALPN,
+        Rc::clone(&cid_gen),
+        ConnectionParameters::default(),
+    )
+    .unwrap();
+    connect_force_idle(&mut client, &mut server);
+
+    let original_cid = ConnectionId::from(get_cid(&send_something(&mut client, now())));
+    let ncid = send_with_extra(&mut server, NewConnectionIds::retire_all(cid_gen), now());
+    client.process_input(ncid, now());
+    let new_cid = ConnectionId::from(get_cid(&send_something(&mut client, now())));
+    drop(client);
+
+    let log = contents.to_string();
+    assert!(log.contains(&format!(
+        r#""owner":"remote","new":"{}""#,
+        Hex::new(&new_cid)
+    )));
+    assert!(log.contains(&format!(
+        r#""owner":"remote","old":"{}""#,
+        Hex::new(&original_cid)
+    )));
+}
+
 /// RFC 9000, Section 5.1.2: an endpoint SHOULD bound the number of connection IDs it
 /// has retired but not yet had acknowledged, and MAY treat exceeding that bound as a
 /// `CONNECTION_ID_LIMIT_ERROR`.  A peer that streams `NEW_CONNECTION_ID` frames with an
@@ -1372,3 +1427,182 @@ fn error_on_new_path_with_no_connection_id() {
         State::Closed(CloseReason::Transport(Error::UnknownFrameType))
     ));
 }
+
+/// A second local address for the client, on the same network as `DEFAULT_ADDR`.
+fn standby_addr() -> SocketAddr {
+    SocketAddr::new(DEFAULT_ADDR.ip(), DEFAULT_ADDR.port() + 1)
+}
+
+fn failover_client() -> Connection {
+    new_client(ConnectionParameters::default().path_failover(Some(FailoverPolicy::default())))
+}
+
+/// Register a standby path and have the server validate it.
+fn validate_standby(client: &mut Connection, server: &mut Connection, now: Instant) {
+    client.add_standby_path(standby_addr(), now).unwrap();
+
+    let probe = client.process_output(now).dgram().unwrap();
+    assert_eq!(probe.source(), standby_addr());
+    assert_path_challenge_min_len(client, &probe, now);
+
+    // The server responds on the new path, but stays on the old one.
+    let resp = server.process(Some(probe), now).dgram().unwrap();
+    assert_eq!(resp.destination(), standby_addr());
+    client.process_input(resp, now);
+
+    // The client might need to answer a challenge from the server.
+    if let Some(d) = client.process_output(now).dgram() {
+        server.process_input(d, now);
+    }
+
+    let standby = client.paths.standby(standby_addr()).unwrap();
+    assert!(standby.borrow().is_valid());
+    assert!(!standby.borrow().is_primary());
+    assert_eq!(
+        client.paths.primary().unwrap().borrow().local_address(),
+        DEFAULT_ADDR
+    );
+}
+
+#[test]
+fn failover_invalid() {
+    let mut client = default_client();
+    let mut server =
+        new_server(ConnectionParameters::default().path_failover(Some(FailoverPolicy::default())));
+    connect_force_idle(&mut client, &mut server);
+
+    // Failover needs to be enabled, and only works for clients.
+    assert_eq!(
+        client.add_standby_path(standby_addr(), now()).unwrap_err(),
+        Error::InvalidMigration
+    );
+    assert_eq!(
+        server.add_standby_path(standby_addr(), now()).unwrap_err(),
+        Error::InvalidMigration
+    );
+
+    let mut client = failover_client();
+    client.add_standby_path(standby_addr(), now()).unwrap();
+    assert_eq!(
+        client.add_standby_path(standby_addr(), now()).unwrap_err(),
+        Error::InvalidInput
+    );
+}
+
+#[test]
+fn standby_path_validated() {
+    // A standby path isn't set up until the handshake is confirmed.
+    let mut client = failover_client();
+    client.add_standby_path(standby_addr(), now()).unwrap();
+    assert!(client.paths.standby(standby_addr()).is_none());
+
+    let mut client = failover_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    let mut now = now();
+    validate_standby(&mut client, &mut server, now);
+
+    // The standby path is revalidated periodically.
+    now += FailoverPolicy::default().get_probe_interval();
+    let probe = iter::from_fn(|| client.process_output(now).dgram())
+        .find(|d| d.source() == standby_addr())
+        .unwrap();
+    assert_path_challenge_min_len(&client, &probe, now);
+    assert!(
+        !client
+            .paths
+            .standby(standby_addr())
+            .unwrap()
+            .borrow()
+            .is_valid()
+    );
+
+    // Removing the address stops the path from being kept.
+    client.remove_standby_path(standby_addr());
+    assert!(client.paths.standby(standby_addr()).is_none());
+}
+
+#[test]
+fn failover_after_pto() {
+    let mut client = failover_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    let mut now = now();
+    validate_standby(&mut client, &mut server, now);
+
+    // Everything sent on the primary path is lost.
+    drop(send_something(&mut client, now));
+    let events = loop {
+        match client.process_output(now) {
+            Output::Datagram(_) => (),
+            Output::Callback(t) => now += t,
+            Output::None => panic!("connection closed"),
+        }
+        let events = client.events().collect::<Vec<_>>();
+        if events
+            .iter()
+            .any(|e| matches!(e, ConnectionEvent::PathFailover { .. }))
+        {
+            break events;
+        }
+    };
+    // `PathFailover` is followed by `PathMigrated` for the new path.
+    let failover = events
+        .iter()
+        .position(|e| matches!(e, ConnectionEvent::PathFailover { .. }))
+        .unwrap();
+    assert_eq!(
+        events[failover..failover + 2],
+        [
+            ConnectionEvent::PathFailover {
+                from: DEFAULT_ADDR,
+                to: standby_addr(),
+                reason: FailoverReason::Pto(2),
+            },
+            ConnectionEvent::PathMigrated {
+                local: standby_addr(),
+                remote: DEFAULT_ADDR,
+            },
+        ]
+    );
+    assert_eq!(
+        client.paths.primary().unwrap().borrow().local_address(),
+        standby_addr()
+    );
+
+    // The client now sends on the standby path, which the server migrates to.
+    let dgram = send_something(&mut client, now);
+    assert_eq!(dgram.source(), standby_addr());
+    server.process_input(dgram, now);
+    assert!(server.events().any(
+        |e| matches!(e, ConnectionEvent::PathMigrated { remote, .. } if remote == standby_addr())
+    ));
+}
+
+#[test]
+fn failover_without_standby() {
+    let mut client = failover_client();
+    let mut server = default_server();
+    connect_force_idle(&mut client, &mut server);
+    let mut now = now();
+    client.add_standby_path(standby_addr(), now).unwrap();
+
+    // The standby path is never validated, so there is nothing to fail over to.
+    drop(send_something(&mut client, now));
+    while client.loss_recovery.pto_count() < 2 {
+        match client.process_output(now) {
+            Output::Datagram(_) => (),
+            Output::Callback(t) => now += t,
+            Output::None => panic!("connection closed"),
+        }
+    }
+    assert!(
+        !client
+            .events()
+            .any(|e| matches!(e, ConnectionEvent::PathFailover { .. }))
+    );
+    assert_eq!(
+        client.paths.primary().unwrap().borrow().local_address(),
+        DEFAULT_ADDR
+    );
+}
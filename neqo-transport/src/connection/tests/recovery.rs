@@ -29,6 +29,7 @@ use crate::{
     packet,
     recovery::{
         FAST_PTO_SCALE, MAX_OUTSTANDING_UNACK, MAX_PTO_PACKET_COUNT, MIN_OUTSTANDING_UNACK,
+        PACKET_THRESHOLD,
     },
     rtt::GRANULARITY,
     tparams::{TransportParameter, TransportParameterId::*},
@@ -234,6 +235,12 @@ fn spurious_loss_is_taken_back() {
         pkt0_len,
         "late-acked bytes should be counted exactly once"
     );
+    // pkt0 was reordered by three packets, so the reordering threshold now allows that.
+    assert_eq!(
+        client.stats().loss_packet_threshold,
+        PACKET_THRESHOLD + 1,
+        "reordering threshold should grow after a spurious loss"
+    );
 }
 
 #[test]
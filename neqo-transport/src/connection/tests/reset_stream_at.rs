@@ -6,14 +6,16 @@
 
 // Tests for RESET_STREAM_AT (draft-ietf-quic-reliable-stream-reset).
 
-use neqo_common::event::Provider as _;
+use std::time::Duration;
+
+use neqo_common::{event::Provider as _, to_u64};
 use test_fixture::now;
 
 use super::{
     connect, default_client, default_server, exchange, new_client, new_server, send_with_extra,
 };
 use crate::{
-    Connection, ConnectionParameters, Error, StreamId, StreamType,
+    AppError, Connection, ConnectionParameters, Error, StreamId, StreamType,
     connection::test_internal::FrameWriter, events::ConnectionEvent, frame::FrameType, packet,
 };
 
@@ -177,3 +179,113 @@ fn unadvertised_reset_stream_at_is_rejected() {
     client.process_input(dgram, now());
     assert!(client.state().closed());
 }
+
+/// How long a stream with a deadline has to deliver its data.
+const DEADLINE: Duration = Duration::from_millis(200);
+/// The error code that a stream is reset with when its deadline passes.
+const DEADLINE_ERROR: AppError = 0x77;
+
+/// Create a stream with a deadline, buffer `DATA`, and send it without it being acknowledged.
+fn send_with_deadline(c: &mut Connection, reliable_size: usize) -> StreamId {
+    let stream_id = c.stream_create(StreamType::UniDi).unwrap();
+    c.stream_send(stream_id, DATA).unwrap();
+    c.stream_set_deadline(stream_id, now() + DEADLINE, to_u64(reliable_size))
+        .unwrap();
+    while c.process_output(now()).dgram().is_some() {}
+    stream_id
+}
+
+/// Once the deadline passes with data unacknowledged, the stream is reset with
+/// `RESET_STREAM_AT` and the application is told.
+#[test]
+fn deadline_sends_reset_stream_at() {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect(&mut client, &mut server);
+
+    let stream_id = send_with_deadline(&mut client, RELIABLE);
+    assert!(
+        !client
+            .events()
+            .any(|e| matches!(e, ConnectionEvent::SendStreamExpired { .. }))
+    );
+
+    let later = now() + DEADLINE;
+    while client.process_output(later).dgram().is_some() {}
+    assert_eq!(client.stats().frame_tx.reset_stream_at, 1);
+    assert_eq!(client.stats().frame_tx.reset_stream, 0);
+    assert!(client.events().any(
+        |e| matches!(e, ConnectionEvent::SendStreamExpired { stream_id: id } if id == stream_id)
+    ));
+    assert_eq!(
+        client.stream_send(stream_id, DATA).unwrap_err(),
+        Error::FinalSize
+    );
+}
+
+/// A reliable size of zero results in a plain `RESET_STREAM`, with the chosen error code.
+#[test]
+fn deadline_sends_reset_stream() {
+    let mut client = new_client(ConnectionParameters::default().deadline_error(DEADLINE_ERROR));
+    let mut server = default_server();
+    connect(&mut client, &mut server);
+
+    let stream_id = send_with_deadline(&mut client, 0);
+    while let Some(dgram) = client.process_output(now() + DEADLINE).dgram() {
+        server.process_input(dgram, now() + DEADLINE);
+    }
+    assert_eq!(client.stats().frame_tx.reset_stream, 1);
+    assert_eq!(client.stats().frame_tx.reset_stream_at, 0);
+    assert!(server.events().any(|e| matches!(
+        e,
+        ConnectionEvent::RecvStreamReset { stream_id: id, app_error, .. }
+            if id == stream_id && app_error == DEADLINE_ERROR
+    )));
+}
+
+/// Nothing happens at the deadline if all data was acknowledged before then.
+#[test]
+fn deadline_after_complete() {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect(&mut client, &mut server);
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream_id, DATA).unwrap();
+    client.stream_close_send(stream_id).unwrap();
+    client
+        .stream_set_deadline(stream_id, now() + DEADLINE, to_u64(RELIABLE))
+        .unwrap();
+    let dgram = client.process_output(now()).dgram().unwrap();
+    server.process_input(dgram, now());
+    let ack = server.process_output(now() + DEADLINE / 2).dgram().unwrap();
+    client.process_input(ack, now() + DEADLINE / 2);
+
+    while client.process_output(now() + DEADLINE).dgram().is_some() {}
+    assert_eq!(client.stats().frame_tx.reset_stream_at, 0);
+    assert_eq!(client.stats().frame_tx.reset_stream, 0);
+    assert!(
+        !client
+            .events()
+            .any(|e| matches!(e, ConnectionEvent::SendStreamExpired { .. }))
+    );
+}
+
+/// A reliable size can't be used with a deadline unless the peer supports reliable resets.
+#[test]
+fn deadline_unavailable_without_peer_support() {
+    let mut client = default_client();
+    let mut server = new_server(ConnectionParameters::default().reliable_stream_reset(false));
+    connect(&mut client, &mut server);
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    assert_eq!(
+        client
+            .stream_set_deadline(stream_id, now() + DEADLINE, to_u64(RELIABLE))
+            .unwrap_err(),
+        Error::NotAvailable
+    );
+    client
+        .stream_set_deadline(stream_id, now() + DEADLINE, 0)
+        .unwrap();
+}
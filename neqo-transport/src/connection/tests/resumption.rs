@@ -19,9 +19,11 @@ use super::{
     get_tokens, new_client, resumed_server, send_something,
 };
 use crate::{
-    ConnectionParameters, DEFAULT_INITIAL_RTT, Error, MIN_INITIAL_PACKET_SIZE, State, Version,
+    CarefulResumePhase, ConnectionParameters, DEFAULT_INITIAL_RTT, Error, MIN_INITIAL_PACKET_SIZE,
+    State, Version,
     addr_valid::{AddressValidation, ValidateAddress},
     frame::FrameType,
+    stats::CarefulResumeStats,
 };
 
 #[test]
@@ -82,6 +84,75 @@ fn remember_smoothed_rtt() {
     );
 }
 
+#[test]
+fn careful_resume() {
+    const RTT: Duration = Duration::from_millis(80);
+    let params = || ConnectionParameters::default().careful_resume(true);
+
+    let mut client = new_client(params());
+    let mut server = default_server();
+    let mut now = connect_with_rtt(&mut client, &mut server, now(), RTT);
+
+    let validation = AddressValidation::new(now, ValidateAddress::NoToken).unwrap();
+    let validation = Rc::new(RefCell::new(validation));
+    server.set_validation(&validation);
+    server.send_ticket(now, &[]).expect("send ticket1");
+    server.send_ticket(now, &[]).expect("send ticket2");
+    let pkt = send_something(&mut server, now);
+    now += RTT / 2;
+    client.process_input(pkt, now);
+    let mut tokens = get_tokens(&mut client);
+    assert_eq!(tokens.len(), 2);
+
+    // A client that resumes with Careful Resume enabled starts in the reconnaissance phase.
+    let mut client = new_client(params());
+    client
+        .enable_resumption(now, tokens.pop().unwrap())
+        .unwrap();
+    assert_eq!(
+        client.stats().cc.careful_resume.phases[CarefulResumePhase::Reconnaissance],
+        1
+    );
+    let mut server = resumed_server(&client);
+    connect_with_rtt(&mut client, &mut server, now, RTT);
+    assert!(client.tls_info().unwrap().resumed());
+
+    // Without it, the saved state is ignored.
+    let mut client = default_client();
+    client
+        .enable_resumption(now, tokens.pop().unwrap())
+        .unwrap();
+    assert_eq!(
+        client.stats().cc.careful_resume,
+        CarefulResumeStats::default()
+    );
+}
+
+#[test]
+fn careful_resume_old_token() {
+    // Without saved congestion state, a token keeps the format of earlier versions,
+    // which starts with the version.
+    let mut client = default_client();
+    let mut server = default_server();
+    connect(&mut client, &mut server);
+    let token = exchange_ticket(&mut client, &mut server, now());
+    assert_eq!(
+        Decoder::from(token.as_ref()).decode_uint::<u32>(),
+        Some(Version::default().wire_version())
+    );
+
+    // A client with Careful Resume enabled accepts such a token, but has no state to use.
+    let mut client = new_client(ConnectionParameters::default().careful_resume(true));
+    client.enable_resumption(now(), token).unwrap();
+    assert_eq!(
+        client.stats().cc.careful_resume,
+        CarefulResumeStats::default()
+    );
+    let mut server = resumed_server(&client);
+    connect(&mut client, &mut server);
+    assert!(client.tls_info().unwrap().resumed());
+}
+
 fn ticket_rtt(rtt: Duration) -> Duration {
     // A simple ACK frame for a single packet with packet number 0.
     const ACK_FRAME_1: &[u8] = &[0x02, 0x00, 0x00, 0x00, 0x00];
@@ -6,12 +6,13 @@
 
 use std::{cmp::max, collections::HashMap, fmt::Debug};
 
-use neqo_common::{Role, event::Provider as _, qdebug, to_u64};
-use test_fixture::now;
+use neqo_common::{Bytes, Role, event::Provider as _, qdebug, to_u64};
+use test_fixture::{new_neqo_qlog, now};
 
 use super::{
     super::State, DEFAULT_STREAM_DATA, assert_error, connect, connect_force_idle, default_client,
-    default_server, maybe_authenticate, new_client, new_server, send_something, send_with_extra,
+    default_server, maybe_authenticate, new_client, new_client_with_qlog, new_server,
+    send_something, send_with_extra,
 };
 use crate::{
     CloseReason, Connection, ConnectionParameters, Error, StreamId, StreamType,
@@ -1409,3 +1410,113 @@ fn uni_stream_cleaned_up_after_stop_sending_acked_with_final_size_known() {
 
     client.stream_create(StreamType::UniDi).unwrap();
 }
+
+#[test]
+fn qlog_stream_events() {
+    let (mut client, client_log) = new_client_with_qlog(ConnectionParameters::default());
+    let mut server = default_server();
+    let (log, server_log) = new_neqo_qlog();
+    server.set_qlog(log);
+    connect(&mut client, &mut server);
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream_id, b"hello").unwrap();
+    client.stream_close_send(stream_id).unwrap();
+    exchange_data(&mut client, &mut server);
+
+    let mut buf = [0; 16];
+    let (len, fin) = server.stream_recv(stream_id, &mut buf).unwrap();
+    assert_eq!((len, fin), (5, true));
+    drop((client, server));
+
+    let client_log = client_log.to_string();
+    assert!(client_log.contains(r#""stream_side":"sending""#));
+    assert!(client_log.contains(r#""new":"data_sent""#));
+    assert!(client_log.contains(r#""length":5,"from":"application","to":"transport""#));
+
+    let server_log = server_log.to_string();
+    assert!(server_log.contains(r#""stream_side":"receiving""#));
+    assert!(server_log.contains(r#""new":"data_read""#));
+    assert!(server_log.contains(r#""length":5,"from":"transport","to":"application""#));
+}
+
+#[test]
+fn qlog_flow_control_blocked() {
+    let (mut client, client_log) = new_client_with_qlog(ConnectionParameters::default());
+    let mut server =
+        new_server(ConnectionParameters::default().max_stream_data(StreamType::UniDi, true, 10));
+    connect(&mut client, &mut server);
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    assert_eq!(client.stream_send(stream_id, &[0; 20]).unwrap(), 10);
+    // Sending the data exhausts the stream flow control credit.
+    drop(client.process_output(now()));
+    drop(client);
+
+    assert!(
+        client_log
+            .to_string()
+            .contains(&format!("stream {stream_id} blocked by flow control at 10"))
+    );
+}
+
+/// A buffer that is larger than the peer's flow control window is sent in part,
+/// and the rest is sent once the peer provides more credit.
+#[test]
+fn stream_send_bytes() {
+    const WINDOW: usize = 1000;
+    let mut client = default_client();
+    let mut server = new_server(ConnectionParameters::default().max_stream_data(
+        StreamType::UniDi,
+        true,
+        to_u64(WINDOW),
+    ));
+    connect(&mut client, &mut server);
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    assert_eq!(client.stream_avail_send_space(stream_id).unwrap(), WINDOW);
+    let data = Bytes::from(vec![7; WINDOW + WINDOW / 2]);
+    let rest = client.stream_send_bytes(stream_id, data).unwrap().unwrap();
+    assert_eq!(rest.len(), WINDOW / 2);
+    assert_eq!(client.stream_avail_send_space(stream_id).unwrap(), 0);
+    // Without more credit, nothing more is sent.
+    let rest = client.stream_send_bytes(stream_id, rest).unwrap().unwrap();
+    assert_eq!(rest.len(), WINDOW / 2);
+
+    exchange_data(&mut client, &mut server);
+    let mut buf = vec![0; WINDOW * 2];
+    let (len, fin) = server.stream_recv(stream_id, &mut buf).unwrap();
+    assert_eq!((len, fin), (WINDOW, false));
+    exchange_data(&mut client, &mut server);
+
+    // Reading the data gave the client more credit, so the rest can be sent.
+    assert!(client.stream_send_bytes(stream_id, rest).unwrap().is_none());
+    client.stream_close_send(stream_id).unwrap();
+    exchange_data(&mut client, &mut server);
+
+    let (len, fin) = server.stream_recv(stream_id, &mut buf).unwrap();
+    assert_eq!((len, fin), (WINDOW / 2, true));
+    assert!(buf[..len].iter().all(|b| *b == 7));
+}
+
+#[test]
+fn stream_recv_chunk() {
+    let mut client = default_client();
+    let mut server = default_server();
+    connect(&mut client, &mut server);
+
+    let stream_id = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream_id, b"hello").unwrap();
+    client.stream_close_send(stream_id).unwrap();
+    exchange_data(&mut client, &mut server);
+
+    assert_eq!(server.stream_recv_chunk(stream_id).unwrap(), b"hello");
+    assert!(!server.stream_recv_consume(stream_id, 2).unwrap());
+    assert_eq!(server.stream_recv_chunk(stream_id).unwrap(), b"llo");
+    assert_eq!(
+        server.stream_recv_consume(stream_id, 4),
+        Err(Error::InvalidInput)
+    );
+    assert!(server.stream_recv_consume(stream_id, 3).unwrap());
+    assert_eq!(server.stream_recv_chunk(stream_id), Err(Error::NoMoreData));
+}
@@ -126,6 +126,44 @@ fn zero_rtt_send_coalesce() {
     assert_eq!(client_stream_id, server_stream_id.as_u64());
 }
 
+/// Extension transport parameters are remembered for 0-RTT,
+/// and a server that changes them can't accept 0-RTT.
+#[test]
+fn zero_rtt_extension_tparam() {
+    const EXTENSION: u64 = 0x7a3b;
+
+    let mut client = default_client();
+    let mut server = default_server();
+    client
+        .set_local_extension_tparam(EXTENSION, vec![1])
+        .unwrap();
+    server
+        .set_local_extension_tparam(EXTENSION, vec![2])
+        .unwrap();
+    connect(&mut client, &mut server);
+    assert_eq!(client.peer_extension_tparam(EXTENSION), Some(vec![2]));
+    assert_eq!(server.peer_extension_tparam(EXTENSION), Some(vec![1]));
+    assert_eq!(
+        client.set_local_extension_tparam(EXTENSION, vec![3]),
+        Err(Error::ConnectionState)
+    );
+
+    let token = exchange_ticket(&mut client, &mut server, now());
+    let mut client = default_client();
+    client
+        .enable_resumption(now(), token)
+        .expect("should set token");
+    assert_eq!(client.peer_extension_tparam(EXTENSION), Some(vec![2]));
+
+    let mut server = resumed_server(&client);
+    server
+        .set_local_extension_tparam(EXTENSION, vec![3])
+        .unwrap();
+    connect(&mut client, &mut server);
+    assert!(!client.tls_info().unwrap().early_data_accepted());
+    assert_eq!(client.peer_extension_tparam(EXTENSION), Some(vec![3]));
+}
+
 #[test]
 fn zero_rtt_before_resumption_token() {
     let mut client = default_client();
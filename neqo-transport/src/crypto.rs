@@ -28,7 +28,9 @@ use enum_map::EnumMap;
 use neqo_common::{
     Buffer, Encoder, Role,
     hex::{Hex, HexSnipMiddle},
-    qdebug, qinfo, qtrace, to_u64,
+    qdebug, qinfo,
+    qlog::Qlog,
+    qtrace, to_u64,
 };
 pub use nss::Epoch;
 use nss::{
@@ -42,9 +44,11 @@ use nss::{
 
 use crate::{
     ConnectionParameters, Error, Res,
+    cc::SavedCongestionState,
     cid::ConnectionIdRef,
     frame::{FrameEncoder as _, FrameType},
     packet::{self},
+    qlog::{self, KeyUpdateTrigger},
     recovery,
     recv_stream::RxStreamOrderer,
     send_stream::TxBuffer,
@@ -52,9 +56,14 @@ use crate::{
     stats::FrameStats,
     tparams::{TpZeroRttChecker, TransportParameters, TransportParametersHandler},
     tracking::PacketNumberSpace,
-    version::Version,
+    version::{self, Version},
 };
 
+/// Resumption tokens that carry saved congestion state start with this value
+/// in place of the version.  Version 0 is reserved for Version Negotiation, so it never
+/// appears in tokens without congestion state, which keep their original format.
+pub const RESUMPTION_TOKEN_CC_TAG: version::Wire = 0;
+
 /// The number of invocations remaining on a write cipher before we try
 /// to update keys.  This has to be much smaller than the number returned
 /// by `CryptoDxState::limit` or updates will happen too often.  As we don't
@@ -127,12 +136,17 @@ impl Crypto {
             Version::Draft29 => 0xffa5,
         };
         agent.extension_handler(extension, tphandler)?;
+        let role = if matches!(agent, Agent::Client(_)) {
+            Role::Client
+        } else {
+            Role::Server
+        };
         Ok(Self {
             version,
             protocols,
             tls: agent,
             streams: CryptoStreams::default(),
-            states: CryptoStates::default(),
+            states: CryptoStates::new(role),
         })
     }
 
@@ -377,9 +391,9 @@ impl Crypto {
 
     /// Discard state for a packet number space and return true
     /// if something was discarded.
-    pub fn discard(&mut self, space: PacketNumberSpace) -> bool {
+    pub fn discard(&mut self, space: PacketNumberSpace, now: Instant) -> bool {
         self.streams.discard(space);
-        self.states.discard(space)
+        self.states.discard(space, now)
     }
 
     pub fn create_resumption_token(
@@ -388,13 +402,20 @@ impl Crypto {
         tps: &TransportParameters,
         version: Version,
         rtt: u64,
+        saved_cc: Option<&SavedCongestionState>,
     ) -> Option<ResumptionToken> {
         if let Agent::Client(ref mut c) = self.tls {
             c.resumption_token().as_ref().map(|t| {
                 qtrace!("TLS token {}", Hex::new(t.as_ref()));
                 let mut enc = Encoder::default();
+                if saved_cc.is_some() {
+                    enc.encode_uint(4, RESUMPTION_TOKEN_CC_TAG);
+                }
                 enc.encode_uint(4, version.wire_version());
                 enc.encode_varint(rtt);
+                if let Some(saved_cc) = saved_cc {
+                    enc.encode_vvec_with(|enc_inner| saved_cc.encode(enc_inner));
+                }
                 enc.encode_vvec_with(|enc_inner| {
                     tps.encode(enc_inner);
                 });
@@ -496,6 +517,10 @@ pub struct CryptoDxState {
 
 const INITIAL_LARGEST_PACKET_LEN: usize = 1 << 11; // 2048
 
+/// The labels for deriving Initial secrets.
+const CLIENT_INITIAL_LABEL: &str = "client in";
+pub const SERVER_INITIAL_LABEL: &str = "server in";
+
 impl CryptoDxState {
     pub fn new(
         version: Version,
@@ -861,8 +886,9 @@ impl CryptoDxAppData {
 /// Note that the methods on this struct take a version but those are only ever
 /// used for Initial keys; a version has been selected at the time we need to
 /// get other keys, so those have fixed versions.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct CryptoStates {
+    role: Role,
     initials: EnumMap<Version, Option<CryptoState>>,
     handshake: Option<CryptoState>,
     zero_rtt: Option<CryptoDxState>, // One direction only!
@@ -878,9 +904,30 @@ pub struct CryptoStates {
     // keys and would otherwise be detected as that update again; comparing
     // against this stops us from responding to the same update twice.
     read_update_epoch: Option<usize>,
+    qlog: Qlog,
 }
 
 impl CryptoStates {
+    pub fn new(role: Role) -> Self {
+        Self {
+            role,
+            initials: EnumMap::default(),
+            handshake: None,
+            zero_rtt: None,
+            cipher: Cipher::default(),
+            app_write: None,
+            app_read: None,
+            app_read_next: None,
+            read_update_time: None,
+            read_update_epoch: None,
+            qlog: Qlog::disabled(),
+        }
+    }
+
+    pub fn set_qlog(&mut self, qlog: Qlog) {
+        self.qlog = qlog;
+    }
+
     fn initials_is_empty(&self) -> bool {
         self.initials.values().flatten().count() == 0
     }
@@ -1069,9 +1116,6 @@ impl CryptoStates {
     where
         V: IntoIterator<Item = &'v Version>,
     {
-        const CLIENT_INITIAL_LABEL: &str = "client in";
-        const SERVER_INITIAL_LABEL: &str = "server in";
-
         let (write, read) = match role {
             Role::Client => (CLIENT_INITIAL_LABEL, SERVER_INITIAL_LABEL),
             Role::Server => (SERVER_INITIAL_LABEL, CLIENT_INITIAL_LABEL),
@@ -1170,25 +1214,31 @@ impl CryptoStates {
     }
 
     /// Discard keys and return true if that happened.
-    pub fn discard(&mut self, space: PacketNumberSpace) -> bool {
-        match space {
+    pub fn discard(&mut self, space: PacketNumberSpace, now: Instant) -> bool {
+        let (epoch, discarded) = match space {
             PacketNumberSpace::Initial => {
                 let empty = self.initials_is_empty();
                 self.initials.clear();
-                !empty
+                (Epoch::Initial, !empty)
             }
-            PacketNumberSpace::Handshake => self.handshake.take().is_some(),
+            PacketNumberSpace::Handshake => (Epoch::Handshake, self.handshake.take().is_some()),
             PacketNumberSpace::ApplicationData => panic!("Can't drop application data keys"),
+        };
+        if discarded {
+            qlog::keys_discarded(&mut self.qlog, epoch, now);
         }
+        discarded
     }
 
-    pub fn discard_0rtt_keys(&mut self) {
+    pub fn discard_0rtt_keys(&mut self, now: Instant) {
         qtrace!("[{self}] discard 0-RTT keys");
         assert!(
             self.app_read.is_none(),
             "Can't discard 0-RTT after setting application keys"
         );
-        self.zero_rtt = None;
+        if self.zero_rtt.take().is_some() {
+            qlog::keys_discarded(&mut self.qlog, Epoch::ZeroRtt, now);
+        }
     }
 
     pub fn set_handshake_keys(
@@ -1264,7 +1314,7 @@ impl CryptoStates {
         if write.can_update(largest_acknowledged) && self.read_update_time.is_none() {
             // This call additionally checks that we don't advance to the next
             // epoch while a key update is in progress.
-            if self.maybe_update_write()? {
+            if self.maybe_update_write(KeyUpdateTrigger::Local)? {
                 Ok(())
             } else {
                 qdebug!("[{self}] Write keys already updated");
@@ -1277,7 +1327,7 @@ impl CryptoStates {
     }
 
     /// Try to update, and return true if it happened.
-    fn maybe_update_write(&mut self) -> Res<bool> {
+    fn maybe_update_write(&mut self, trigger: KeyUpdateTrigger) -> Res<bool> {
         // Update write keys.  But only do so if the write keys are not already
         // ahead of the read keys.  If we initiated the key update, the write keys
         // will already be ahead.
@@ -1286,7 +1336,15 @@ impl CryptoStates {
         let read = &self.app_read.as_ref().ok_or(Error::Internal)?;
         if write.epoch() == read.epoch() {
             qdebug!("[{self}] Update write keys to epoch={}", write.epoch() + 1);
-            self.app_write = Some(write.next()?);
+            let next = write.next()?;
+            qlog::key_updated(
+                &mut self.qlog,
+                self.role,
+                CryptoDxDirection::Write,
+                next.epoch(),
+                trigger,
+            );
+            self.app_write = Some(next);
             Ok(true)
         } else {
             Ok(false)
@@ -1301,7 +1359,7 @@ impl CryptoStates {
             && app_write.dx.should_update()
         {
             qinfo!("[{self}] Initiating automatic key update");
-            if !self.maybe_update_write()? {
+            if !self.maybe_update_write(KeyUpdateTrigger::Local)? {
                 return Err(Error::KeysExhausted);
             }
         }
@@ -1329,12 +1387,19 @@ impl CryptoStates {
         self.read_update_epoch = Some(epoch);
 
         qtrace!("[{self}] Key update received");
+        qlog::key_updated(
+            &mut self.qlog,
+            self.role,
+            CryptoDxDirection::Read,
+            epoch,
+            KeyUpdateTrigger::Remote,
+        );
         // If we received a key update, then we assume that the peer has
         // acknowledged a packet we sent in this epoch. It's OK to do that
         // because they aren't allowed to update without first having received
         // something from us. If the ACK isn't in the packet that triggered this
         // key update, it must be in some other packet they have sent.
-        _ = self.maybe_update_write()?;
+        _ = self.maybe_update_write(KeyUpdateTrigger::Remote)?;
 
         // We shouldn't have 0-RTT keys at this point, but if we do, dump them.
         debug_assert_eq!(self.read_update_time.is_some(), self.has_0rtt_read());
@@ -1360,6 +1425,7 @@ impl CryptoStates {
                 if self.has_0rtt_read() {
                     qtrace!("[{self}] Discarding 0-RTT keys");
                     self.zero_rtt = None;
+                    qlog::keys_discarded(&mut self.qlog, Epoch::ZeroRtt, now);
                 } else {
                     qtrace!("[{self}] Rotating read keys");
                     mem::swap(&mut self.app_read, &mut self.app_read_next);
@@ -1413,6 +1479,7 @@ impl CryptoStates {
             })
         });
         Self {
+            role: Role::Client,
             initials,
             handshake: None,
             zero_rtt: None,
@@ -1423,6 +1490,7 @@ impl CryptoStates {
             app_read_next: Some(app_read(4)),
             read_update_time: None,
             read_update_epoch: None,
+            qlog: Qlog::disabled(),
         }
     }
 
@@ -1464,6 +1532,7 @@ impl CryptoStates {
             next_secret: secret.clone(),
         };
         Self {
+            role: Role::Client,
             initials: EnumMap::default(),
             handshake: None,
             zero_rtt: None,
@@ -1473,6 +1542,7 @@ impl CryptoStates {
             app_read_next: Some(app_read(4)),
             read_update_time: None,
             read_update_epoch: None,
+            qlog: Qlog::disabled(),
         }
     }
 }
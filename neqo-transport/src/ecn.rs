@@ -148,6 +148,7 @@ impl DerefMut for ValidationCount {
 pub enum ValidationError {
     BlackHole,
     Bleaching,
+    ReceivedUnsentECT0,
     ReceivedUnsentECT1,
 }
 
@@ -157,11 +158,14 @@ pub enum ValidationOutcome {
     NotCapable(ValidationError),
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub(crate) struct Info {
     /// The current state of ECN validation on this path.
     state: ValidationState,
 
+    /// The ECT codepoint that outgoing packets are marked with.
+    codepoint: Ecn,
+
     /// The largest ACK seen so far.
     largest_acked: packet::Number,
 
@@ -170,6 +174,18 @@ pub(crate) struct Info {
 }
 
 impl Info {
+    /// Create ECN state for a path, marking packets with `codepoint`,
+    /// which is either [`Ecn::Ect0`] or [`Ecn::Ect1`].
+    pub(crate) const fn new(codepoint: Ecn) -> Self {
+        debug_assert!(codepoint.is_ect());
+        Self {
+            state: ValidationState::NotStarted,
+            codepoint,
+            largest_acked: 0,
+            baseline: Count::new(0, 0, 0, 0),
+        }
+    }
+
     pub(crate) fn start(&mut self, stats: &mut Stats) {
         if !matches!(self.state, ValidationState::NotStarted) {
             return;
@@ -217,22 +233,25 @@ impl Info {
 
     /// Process ECN counts from an ACK frame.
     ///
-    /// Returns whether ECN counts contain new valid ECN CE marks.
+    /// Returns the number of new valid ECN CE marks in the ECN counts.
     pub(crate) fn on_packets_acked(
         &mut self,
         acked_packets: &[sent::Packet],
         ack_ecn: Option<&Count>,
         stats: &mut Stats,
-    ) -> bool {
+    ) -> u64 {
         let prev_baseline = self.baseline;
 
         self.validate_ack_ecn_and_update(acked_packets, ack_ecn, stats);
 
-        matches!(self.state, ValidationState::Capable)
-            && (self.baseline - prev_baseline)[Ecn::Ce] > 0
+        if matches!(self.state, ValidationState::Capable) {
+            (self.baseline - prev_baseline)[Ecn::Ce]
+        } else {
+            0
+        }
     }
 
-    /// An [`Ecn::Ect0`] marked packet has been acked.
+    /// An ECN marked packet has been acked.
     pub(crate) const fn acked_ecn(&mut self) {
         if let ValidationState::Testing {
             initial_probes_acked: probes_acked,
@@ -243,7 +262,7 @@ impl Info {
         }
     }
 
-    /// An [`Ecn::Ect0`] marked packet has been declared lost.
+    /// An ECN marked packet has been declared lost.
     pub(crate) fn lost_ecn(&mut self, stats: &mut Stats) {
         if let ValidationState::Testing {
             initial_probes_acked: probes_acked,
@@ -312,22 +331,30 @@ impl Info {
         // > ECN validation also fails if the sum of the increase in ECT(0) and ECN-CE counts is
         // > less than the number of newly acknowledged packets that were originally sent with an
         // > ECT(0) marking.
-        let newly_acked_sent_with_ect0: u64 = acked_packets
+        //
+        // The same applies to ECT(1) when that is the codepoint we mark packets with.
+        let newly_acked_sent_marked: u64 = acked_packets
             .iter()
-            .filter(|p| p.ecn_marked_ect0())
+            .filter(|p| p.ecn_marked())
             .count()
             .try_into()
             .expect("usize fits into u64");
         let ecn_diff = ack_ecn - self.baseline;
-        let sum_inc = ecn_diff[Ecn::Ect0] + ecn_diff[Ecn::Ce];
-        if sum_inc < newly_acked_sent_with_ect0 {
+        let sum_inc = ecn_diff[self.codepoint] + ecn_diff[Ecn::Ce];
+        let (unsent, unsent_error) = if self.codepoint == Ecn::Ect1 {
+            (Ecn::Ect0, ValidationError::ReceivedUnsentECT0)
+        } else {
+            (Ecn::Ect1, ValidationError::ReceivedUnsentECT1)
+        };
+        if sum_inc < newly_acked_sent_marked {
             qinfo!(
-                "ECN validation failed, ACK counted {sum_inc} new marks, but {newly_acked_sent_with_ect0} of newly acked packets were sent with ECT(0)"
+                "ECN validation failed, ACK counted {sum_inc} new marks, but {newly_acked_sent_marked} of newly acked packets were sent with {:?}",
+                self.codepoint
             );
             self.disable_ecn(stats, ValidationError::Bleaching);
-        } else if ecn_diff[Ecn::Ect1] > 0 {
-            qinfo!("ECN validation failed, ACK counted ECT(1) marks that were never sent");
-            self.disable_ecn(stats, ValidationError::ReceivedUnsentECT1);
+        } else if ecn_diff[unsent] > 0 {
+            qinfo!("ECN validation failed, ACK counted {unsent:?} marks that were never sent");
+            self.disable_ecn(stats, unsent_error);
         } else if self.state != ValidationState::Capable {
             qinfo!("ECN validation succeeded, path is capable");
             self.state.set(ValidationState::Capable, stats);
@@ -348,7 +375,7 @@ impl Info {
     /// The ECN mark to use for an outgoing UDP datagram.
     pub(crate) const fn ecn_mark(&self) -> Ecn {
         if self.is_marking() {
-            Ecn::Ect0
+            self.codepoint
         } else {
             Ecn::NotEct
         }
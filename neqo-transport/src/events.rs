@@ -13,7 +13,7 @@ use nss::ResumptionToken;
 
 use crate::{
     AppError,
-    connection::State,
+    connection::{FailoverReason, State},
     quic_datagrams::DatagramTracking,
     scone::Bitrate,
     stream_id::{StreamId, StreamType},
@@ -23,6 +23,8 @@ use crate::{
 pub enum OutgoingDatagramOutcome {
     DroppedTooBig,
     DroppedQueueFull,
+    /// The deadline for the datagram passed before it could be sent.
+    Expired,
     Lost,
     Acked,
 }
@@ -62,6 +64,11 @@ pub enum ConnectionEvent {
     SendStreamComplete {
         stream_id: StreamId,
     },
+    /// The deadline set with [`crate::Connection::stream_set_deadline`] passed
+    /// before the peer acknowledged all of the data on the stream, so the stream was reset.
+    SendStreamExpired {
+        stream_id: StreamId,
+    },
     /// Peer increased `MAX_STREAMS`
     SendStreamCreatable {
         stream_type: StreamType,
@@ -86,6 +93,14 @@ pub enum ConnectionEvent {
         local: SocketAddr,
         remote: SocketAddr,
     },
+    /// The primary path failed and the connection moved to a standby path.
+    /// `from` and `to` are the local addresses of the old and new paths.
+    /// This is followed by `PathMigrated` for the new path.
+    PathFailover {
+        from: SocketAddr,
+        to: SocketAddr,
+        reason: FailoverReason,
+    },
 }
 
 #[derive(Debug, Default, Clone)]
@@ -145,6 +160,15 @@ impl ConnectionEvents {
         self.insert(ConnectionEvent::SendStreamComplete { stream_id });
     }
 
+    pub fn send_stream_expired(&self, stream_id: StreamId) {
+        self.remove(|evt| {
+            matches!(evt,
+                ConnectionEvent::SendStreamWritable { stream_id: x } if *x == stream_id)
+        });
+
+        self.insert(ConnectionEvent::SendStreamExpired { stream_id });
+    }
+
     pub fn send_stream_creatable(&self, stream_type: StreamType) {
         self.insert(ConnectionEvent::SendStreamCreatable { stream_type });
     }
@@ -201,6 +225,10 @@ impl ConnectionEvents {
         self.insert(ConnectionEvent::PathMigrated { local, remote });
     }
 
+    pub fn path_failover(&self, from: SocketAddr, to: SocketAddr, reason: FailoverReason) {
+        self.insert(ConnectionEvent::PathFailover { from, to, reason });
+    }
+
     fn insert(&self, event: ConnectionEvent) {
         let mut q = self.events.borrow_mut();
 
@@ -124,16 +124,23 @@ where
         self.used
     }
 
+    /// The current limit.
+    pub const fn limit(&self) -> u64 {
+        self.limit
+    }
+
     /// Mark flow control as blocked.
     /// This only does something if the current limit exceeds the last reported blocking limit.
-    pub const fn blocked(&mut self) {
+    /// Returns `true` if this is the first time that blocking at the current limit was reported.
+    pub const fn blocked(&mut self) -> bool {
         if let Some(block) = self.blocked_at
             && self.limit <= block
         {
-            return;
+            return false;
         }
         self.blocked_at = Some(self.limit);
         self.blocked_frame = true;
+        true
     }
 
     /// Return whether a blocking frame needs to be sent.
@@ -14,9 +14,9 @@
     )
 )]
 
-use std::ops::RangeInclusive;
+use std::{ops::RangeInclusive, time::Duration};
 
-use neqo_common::{Buffer, Decoder, Encoder, MAX_VARINT, qtrace};
+use neqo_common::{Buffer, Decoder, Encoder, MAX_VARINT, qtrace, to_u64};
 use strum::FromRepr;
 
 use crate::{
@@ -61,8 +61,12 @@ pub enum FrameType {
     HandshakeDone = 0x1e,
     // draft-ietf-quic-reliable-stream-reset
     ResetStreamAt = 0x24,
-    // draft-ietf-quic-ack-delay
+    // draft-ietf-quic-ack-frequency
+    ImmediateAck = 0x1f,
     AckFrequency = 0xaf,
+    // draft-smith-quic-receive-ts
+    AckReceiveTimestamps = 0xffa0,
+    AckEcnReceiveTimestamps = 0xffa1,
     // draft-ietf-quic-datagram
     Datagram = 0x30,
     DatagramWithLen = 0x31,
@@ -158,6 +162,14 @@ pub struct AckRange {
     range: u64,
 }
 
+/// A run of packets with consecutive packet numbers in an `ACK_RECEIVE_TIMESTAMPS` frame,
+/// with the time that each was received, largest packet number first.
+#[derive(PartialEq, Eq, Debug, Default, Clone)]
+pub struct TimestampRange {
+    gap: u64,
+    deltas: Vec<u64>,
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum Frame<'a> {
     Padding(u16),
@@ -252,8 +264,20 @@ pub enum Frame<'a> {
         /// The time to delay after receiving the first packet that is
         /// not immediately acknowledged.
         delay: u64,
-        /// Ignore reordering when deciding to immediately acknowledge.
-        ignore_order: bool,
+        /// How far out of order packets need to arrive before they are
+        /// acknowledged immediately.  0 means that reordering is ignored, and
+        /// 1 means that any packet that arrives out of order is acknowledged
+        /// immediately.
+        reordering_threshold: u64,
+    },
+    ImmediateAck,
+    AckReceiveTimestamps {
+        largest_acknowledged: u64,
+        ack_delay: u64,
+        first_ack_range: u64,
+        ack_ranges: Vec<AckRange>,
+        ecn_count: Option<ecn::Count>,
+        timestamp_ranges: Vec<TimestampRange>,
     },
     Datagram {
         data: &'a [u8],
@@ -298,6 +322,11 @@ impl<'a> Frame<'a> {
             },
             Self::HandshakeDone => FrameType::HandshakeDone,
             Self::AckFrequency { .. } => FrameType::AckFrequency,
+            Self::ImmediateAck => FrameType::ImmediateAck,
+            Self::AckReceiveTimestamps { ecn_count, .. } => match ecn_count {
+                None => FrameType::AckReceiveTimestamps,
+                Some(_) => FrameType::AckEcnReceiveTimestamps,
+            },
             Self::Datagram { fill, .. } => match fill {
                 false => FrameType::Datagram,
                 true => FrameType::DatagramWithLen,
@@ -342,7 +371,10 @@ impl<'a> Frame<'a> {
     pub const fn ack_eliciting(&self) -> bool {
         !matches!(
             self,
-            Self::Ack { .. } | Self::Padding { .. } | Self::ConnectionClose { .. }
+            Self::Ack { .. }
+                | Self::AckReceiveTimestamps { .. }
+                | Self::Padding { .. }
+                | Self::ConnectionClose { .. }
         )
     }
 
@@ -406,6 +438,51 @@ impl<'a> Frame<'a> {
         Ok(acked_ranges)
     }
 
+    /// Converts the timestamp ranges of an `ACK_RECEIVE_TIMESTAMPS` frame into the
+    /// packet numbers and receive times that they describe, largest packet number
+    /// first.  Receive times are measured from the timestamp basis that the peer
+    /// chose, with deltas scaled by `2^exponent` microseconds.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ranges are invalid.
+    pub fn decode_receive_timestamps(
+        largest_acked: u64,
+        timestamp_ranges: &[TimestampRange],
+        exponent: u32,
+    ) -> Res<Vec<(u64, Duration)>> {
+        let scale = |delta: u64| {
+            delta
+                .checked_shl(exponent)
+                .filter(|v| v >> exponent == delta)
+                .ok_or(Error::FrameEncoding)
+        };
+        let mut samples = Vec::new();
+        // The smallest packet number in the previous range, which starts out
+        // so that the first gap is measured from the largest acknowledged.
+        let mut next = largest_acked + 2;
+        let mut time: Option<u64> = None;
+        for r in timestamp_ranges {
+            let largest = next.checked_sub(r.gap + 2).ok_or(Error::FrameEncoding)?;
+            let count = to_u64(r.deltas.len());
+            if largest + 1 < count {
+                return Err(Error::FrameEncoding);
+            }
+            for (pn, &delta) in (0..=largest).rev().zip(&r.deltas) {
+                let t = match time {
+                    None => scale(delta)?,
+                    Some(prev) => prev
+                        .checked_sub(scale(delta)?)
+                        .ok_or(Error::FrameEncoding)?,
+                };
+                samples.push((pn, Duration::from_micros(t)));
+                time = Some(t);
+            }
+            next = largest + 1 - count;
+        }
+        Ok(samples)
+    }
+
     #[must_use]
     pub fn dump(&self) -> String {
         match self {
@@ -443,7 +520,8 @@ impl<'a> Frame<'a> {
             Self::NewToken { .. }
             | Self::ConnectionClose { .. }
             | Self::PathResponse { .. }
-            | Self::HandshakeDone => pt == packet::Type::Short,
+            | Self::HandshakeDone
+            | Self::AckReceiveTimestamps { .. } => pt == packet::Type::Short,
             _ => pt == packet::Type::ZeroRtt || pt == packet::Type::Short,
         }
     }
@@ -474,7 +552,29 @@ impl<'a> Frame<'a> {
             d(dec.decode_varint())
         }
 
-        fn decode_ack<'a>(dec: &mut Decoder<'a>, ecn: bool) -> Res<Frame<'a>> {
+        fn decode_timestamp_ranges(dec: &mut Decoder) -> Res<Vec<TimestampRange>> {
+            let nr = dv(dec)?;
+            let mut ranges = Vec::new();
+            // Each delta takes at least a byte, so apply the same limit to them
+            // as to ACK ranges.
+            let mut total = 0;
+            for _ in 0..nr {
+                let gap = dv(dec)?;
+                let count = dv(dec)?;
+                if count == 0 {
+                    return Err(Error::FrameEncoding);
+                }
+                total += count;
+                if total >= MAX_ACK_RANGE_COUNT {
+                    return Err(Error::TooMuchData);
+                }
+                let deltas = (0..count).map(|_| dv(dec)).collect::<Res<_>>()?;
+                ranges.push(TimestampRange { gap, deltas });
+            }
+            Ok(ranges)
+        }
+
+        fn decode_ack<'a>(dec: &mut Decoder<'a>, ecn: bool, timestamps: bool) -> Res<Frame<'a>> {
             let la = dv(dec)?;
             let ad = dv(dec)?;
             let nr = dv(dec).and_then(|nr| {
@@ -501,6 +601,16 @@ impl<'a> Frame<'a> {
                 })
                 .transpose()?;
 
+            if timestamps {
+                return Ok(Frame::AckReceiveTimestamps {
+                    largest_acknowledged: la,
+                    ack_delay: ad,
+                    first_ack_range: fa,
+                    ack_ranges: arr,
+                    ecn_count,
+                    timestamp_ranges: decode_timestamp_ranges(dec)?,
+                });
+            }
             Ok(Frame::Ack {
                 largest_acknowledged: la,
                 ack_delay: ad,
@@ -553,8 +663,10 @@ impl<'a> Frame<'a> {
                     reliable_size,
                 })
             }
-            FrameType::Ack => decode_ack(dec, false),
-            FrameType::AckEcn => decode_ack(dec, true),
+            FrameType::Ack => decode_ack(dec, false, false),
+            FrameType::AckEcn => decode_ack(dec, true, false),
+            FrameType::AckReceiveTimestamps => decode_ack(dec, false, true),
+            FrameType::AckEcnReceiveTimestamps => decode_ack(dec, true, true),
             FrameType::StopSending => Ok(Self::StopSending {
                 stream_id: StreamId::from(dv(dec)?),
                 application_error_code: dv(dec)?,
@@ -697,18 +809,15 @@ impl<'a> Frame<'a> {
                     return Err(Error::FrameEncoding);
                 }
                 let delay = dv(dec)?;
-                let ignore_order = match d(dec.decode_uint::<u8>())? {
-                    0 => false,
-                    1 => true,
-                    _ => return Err(Error::FrameEncoding),
-                };
+                let reordering_threshold = dv(dec)?;
                 Ok(Self::AckFrequency {
                     seqno,
                     tolerance,
                     delay,
-                    ignore_order,
+                    reordering_threshold,
                 })
             }
+            FrameType::ImmediateAck => Ok(Self::ImmediateAck),
             FrameType::Datagram | FrameType::DatagramWithLen => {
                 let fill = t == FrameType::Datagram;
                 let data = if fill {
@@ -764,12 +873,14 @@ impl<B: Buffer> FrameEncoder for Encoder<B> {
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
+    use std::time::Duration;
+
     use neqo_common::{Decoder, Encoder, MAX_VARINT};
 
     use crate::{
         CloseError, ConnectionId, Error, StreamId, StreamType, Token as Srt,
         ecn::Count,
-        frame::{AckRange, Frame, FrameType},
+        frame::{AckRange, Frame, FrameType, TimestampRange},
         packet,
     };
 
@@ -1174,18 +1285,106 @@ mod tests {
             seqno: 10,
             tolerance: 5,
             delay: 2000,
-            ignore_order: true,
+            reordering_threshold: 1,
         };
         just_dec(&f, "40af0a0547d001");
     }
 
     #[test]
-    fn ack_frequency_ignore_error_error() {
-        let enc = Encoder::from_hex("40af0a0547d003"); // ignore_order of 3
+    fn ack_frequency_reordering_threshold() {
+        let f = Frame::AckFrequency {
+            seqno: 10,
+            tolerance: 5,
+            delay: 2000,
+            reordering_threshold: 300,
+        };
+        just_dec(&f, "40af0a0547d0412c");
+    }
+
+    #[test]
+    fn immediate_ack() {
+        let f = Frame::ImmediateAck;
+        just_dec(&f, "1f");
+        assert!(f.ack_eliciting());
+        assert!(f.is_allowed(packet::Type::Short));
+        assert!(f.is_allowed(packet::Type::ZeroRtt));
+        assert!(!f.is_allowed(packet::Type::Initial));
+    }
+
+    #[test]
+    fn ack_receive_timestamps() {
+        let f = Frame::AckReceiveTimestamps {
+            largest_acknowledged: 10,
+            ack_delay: 0,
+            first_ack_range: 10,
+            ack_ranges: vec![],
+            ecn_count: None,
+            timestamp_ranges: vec![
+                TimestampRange {
+                    gap: 0,
+                    deltas: vec![1000, 5],
+                },
+                TimestampRange {
+                    gap: 1,
+                    deltas: vec![20],
+                },
+            ],
+        };
+        just_dec(&f, "8000ffa00a00000a02000243e805010114");
+        assert!(!f.ack_eliciting());
+        assert!(f.is_allowed(packet::Type::Short));
+        assert!(!f.is_allowed(packet::Type::Handshake));
+
+        let Frame::AckReceiveTimestamps {
+            timestamp_ranges, ..
+        } = f
+        else {
+            unreachable!();
+        };
+        let us = Duration::from_micros;
+        assert_eq!(
+            Frame::decode_receive_timestamps(10, &timestamp_ranges, 0).unwrap(),
+            [(10, us(1000)), (9, us(995)), (6, us(975))]
+        );
+        assert_eq!(
+            Frame::decode_receive_timestamps(10, &timestamp_ranges, 3).unwrap(),
+            [(10, us(8000)), (9, us(7960)), (6, us(7800))]
+        );
+
+        let f = Frame::AckReceiveTimestamps {
+            largest_acknowledged: 10,
+            ack_delay: 0,
+            first_ack_range: 10,
+            ack_ranges: vec![],
+            ecn_count: Some(Count::new(0, 1, 2, 3)),
+            timestamp_ranges: vec![],
+        };
+        just_dec(&f, "8000ffa10a00000a01020300");
+        assert_eq!(f.get_type(), FrameType::AckEcnReceiveTimestamps);
+    }
+
+    #[test]
+    fn ack_receive_timestamps_invalid() {
+        // A range without any timestamps.
+        let enc = Encoder::from_hex("8000ffa00a00000a010000");
         assert_eq!(
             Frame::decode(&mut enc.as_decoder()).unwrap_err(),
             Error::FrameEncoding
         );
+
+        let range = |gap, deltas: &[u64]| TimestampRange {
+            gap,
+            deltas: deltas.to_vec(),
+        };
+        // More timestamps than packet numbers.
+        assert!(Frame::decode_receive_timestamps(3, &[range(0, &[1; 5])], 0).is_err());
+        // A gap that goes below packet number zero.
+        assert!(Frame::decode_receive_timestamps(3, &[range(4, &[1])], 0).is_err());
+        assert!(Frame::decode_receive_timestamps(5, &[range(0, &[1]), range(4, &[1])], 0).is_err());
+        // A receive time before the timestamp basis.
+        assert!(Frame::decode_receive_timestamps(3, &[range(0, &[1, 2])], 0).is_err());
+        // A receive time that overflows when scaled.
+        assert!(Frame::decode_receive_timestamps(3, &[range(0, &[u64::MAX >> 2])], 3).is_err());
     }
 
     /// Hopefully this test is eventually redundant.
@@ -1409,11 +1608,12 @@ mod tests {
                 seqno: 1,
                 tolerance: 2,
                 delay: 3,
-                ignore_order: false
+                reordering_threshold: 0
             }
             .dump(),
-            "AckFrequency { seqno: 1, tolerance: 2, delay: 3, ignore_order: false }"
+            "AckFrequency { seqno: 1, tolerance: 2, delay: 3, reordering_threshold: 0 }"
         );
+        assert_eq!(Frame::ImmediateAck.dump(), "ImmediateAck");
     }
 
     #[test]
@@ -35,6 +35,7 @@ mod path;
 mod pmtud;
 mod qlog;
 mod quic_datagrams;
+pub mod quic_lb;
 #[cfg(feature = "bench")]
 pub mod recovery;
 #[cfg(not(feature = "bench"))]
@@ -46,6 +47,7 @@ mod scone;
 pub mod send_stream;
 mod sender;
 pub mod server;
+pub mod sharded;
 mod sni;
 mod stateless_reset;
 mod stats;
@@ -56,13 +58,16 @@ mod tracking;
 pub mod version;
 
 pub use self::{
-    cc::{CongestionControl, CongestionTrigger, HyStartCssBaseline, SlowStart},
+    cc::{
+        CarefulResumePhase, CongestionControl, CongestionController, CongestionTrigger,
+        HyStartCssBaseline, SavedCongestionState, SlowStart,
+    },
     cid::{
         ConnectionId, ConnectionIdDecoder, ConnectionIdGenerator, ConnectionIdRef,
         EmptyConnectionIdGenerator, RandomConnectionIdGenerator,
     },
     connection::{
-        Connection, Output, OutputBatch, State, ZeroRttState,
+        Connection, FailoverPolicy, FailoverReason, Output, OutputBatch, State, ZeroRttState,
         params::{
             ConnectionParameters, INITIAL_LOCAL_MAX_DATA, INITIAL_LOCAL_MAX_STREAM_DATA,
             MAX_DATAGRAM_FRAME_SIZE, MAX_LOCAL_MAX_STREAM_DATA,
@@ -70,13 +75,15 @@ pub use self::{
     },
     events::{ConnectionEvent, ConnectionEvents},
     frame::CloseError,
-    packet::MIN_INITIAL_PACKET_SIZE,
+    packet::{MIN_INITIAL_PACKET_SIZE, Type as PacketType},
     pmtud::Pmtud,
-    quic_datagrams::DatagramTracking,
-    rtt::DEFAULT_INITIAL_RTT,
+    quic_datagrams::{DatagramOptions, DatagramTracking},
+    recovery::sent::Packet as SentPacket,
+    rtt::{DEFAULT_INITIAL_RTT, RttEstimate},
+    send_stream::StreamScheduler,
     sni::find_sni,
-    stateless_reset::Token,
-    stats::{SlowStartExitReason, Stats},
+    stateless_reset::{Key as StatelessResetKey, Token},
+    stats::{CongestionControlStats, ServerStats, SlowStartExitReason, Stats},
     stream_id::{StreamId, StreamType},
     version::Version,
 };
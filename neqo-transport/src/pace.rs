@@ -145,6 +145,13 @@ impl Pacer {
         Self::bytes_for(cwnd, rtt, Duration::from_secs(1))
     }
 
+    /// The inverse of [`Pacer::rate`]: the congestion window that results in
+    /// pacing at `rate` bytes per second.  This is at least one byte.
+    pub(crate) fn cwnd_for_rate(rate: u64, rtt: Duration) -> usize {
+        let cwnd = u128::from(rate) * rtt.as_nanos() / u128::from(Self::SPEEDUP) / 1_000_000_000;
+        usize::try_from(cwnd).unwrap_or(usize::MAX).max(1)
+    }
+
     /// Spend credit. This cannot fail, but instead may carry debt into the
     /// future (see [`Pacer::c`]). Users of this API are expected to call
     /// [`Pacer::next`] to determine when to spend.
@@ -278,6 +285,17 @@ mod tests {
         assert_eq!(Pacer::rate(10_000, Duration::ZERO), None);
     }
 
+    #[test]
+    fn cwnd_for_rate() {
+        let rtt = Duration::from_millis(100);
+        assert_eq!(Pacer::cwnd_for_rate(200_000, rtt), 10_000);
+        assert_eq!(
+            Pacer::rate(Pacer::cwnd_for_rate(200_000, rtt), rtt),
+            Some(200_000)
+        );
+        assert_eq!(Pacer::cwnd_for_rate(200_000, Duration::ZERO), 1);
+    }
+
     /// When the computed wait equals GRANULARITY exactly, pacing should NOT
     /// send immediately; only strictly sub-granularity waits are suppressed.
     #[test]
@@ -21,11 +21,13 @@ use nss::random;
 use crate::{
     ConnectionParameters, Stats,
     ackrate::{AckRate, PeerAckDelay},
+    cc::SavedCongestionState,
     cid::{ConnectionId, ConnectionIdRef, ConnectionIdStore, RemoteConnectionIdEntry},
     ecn,
     frame::{FrameEncoder as _, FrameType},
     packet,
     pmtud::Pmtud,
+    qlog::{self, ConnectionIdOwner, PathEvent},
     recovery::{self, sent},
     rtt::{RttEstimate, RttSource},
     scone::{Bitrate, Scone},
@@ -118,17 +120,27 @@ impl Paths {
         path.borrow().is_temporary() || !self.paths.iter().any(|p| Rc::ptr_eq(p, path))
     }
 
-    fn retire(to_retire: &mut Vec<u64>, retired: &PathRef) {
+    fn retire(to_retire: &mut Vec<u64>, qlog: &mut Qlog, retired: &PathRef) {
         if let Some(cid) = &retired.borrow().remote_cid {
             let seqno = cid.sequence_number();
             if cid.connection_id().is_empty() {
                 qdebug!("Connection ID {seqno} is zero-length, not retiring");
             } else {
-                to_retire.push(seqno);
+                Self::retire_remote_cid(to_retire, qlog, cid);
             }
         }
     }
 
+    fn retire_remote_cid(to_retire: &mut Vec<u64>, qlog: &mut Qlog, cid: &RemoteConnectionIdEntry) {
+        qlog::connection_id_updated(
+            qlog,
+            ConnectionIdOwner::Remote,
+            Some(cid.connection_id()),
+            None,
+        );
+        to_retire.push(cid.sequence_number());
+    }
+
     /// Adopt a temporary path as permanent.
     /// The first path that is made permanent is made primary.
     pub fn make_permanent(
@@ -145,7 +157,7 @@ impl Paths {
         if self.paths.len() >= MAX_PATHS {
             debug_assert_eq!(self.paths.len(), MAX_PATHS);
             let removed = self.paths.remove(1);
-            Self::retire(&mut self.to_retire, &removed);
+            Self::retire(&mut self.to_retire, &mut self.qlog, &removed);
             if self
                 .migration_target
                 .as_ref()
@@ -177,6 +189,9 @@ impl Paths {
         let old_path = self.primary.replace(Rc::clone(path)).inspect(|old| {
             old.borrow_mut().set_primary(false, now);
         });
+        if old_path.is_some() {
+            Self::path_updated(&mut self.qlog, path, PathEvent::Migrated, now);
+        }
 
         // Swap the primary path into slot 0, so that it is protected from eviction.
         let idx = self
@@ -203,10 +218,9 @@ impl Paths {
         stats: &mut Stats,
     ) -> bool {
         debug_assert!(!self.is_temporary(path));
-        let baseline = self.primary().map_or_else(
-            || ecn::Info::default().baseline(),
-            |p| p.borrow().ecn_info.baseline(),
-        );
+        let baseline = self
+            .primary()
+            .map_or_else(ecn::Count::default, |p| p.borrow().ecn_info.baseline());
         path.borrow_mut().set_ecn_baseline(baseline);
         path.borrow_mut().start_ecn(stats);
         if force || path.borrow().is_valid() {
@@ -214,12 +228,64 @@ impl Paths {
             drop(self.select_primary(path, now));
             self.migration_target = None;
         } else {
+            Self::path_updated(&mut self.qlog, path, PathEvent::MigrationStarted, now);
             self.migration_target = Some(Rc::clone(path));
         }
         path.borrow_mut().probe(stats);
         self.migration_target.is_none()
     }
 
+    /// Move to the identified path because the primary path has failed.
+    /// The old primary path is probed, so that it is not used again unless
+    /// it proves to be working.
+    pub fn fail_over(&mut self, path: &PathRef, now: Instant, stats: &mut Stats) {
+        let old = self.primary();
+        self.migrate(path, true, now, stats);
+        if let Some(old) = old {
+            old.borrow_mut().probe(stats);
+        }
+    }
+
+    fn path_updated(qlog: &mut Qlog, path: &PathRef, event: PathEvent, now: Instant) {
+        let path = path.borrow();
+        qlog::path_updated(
+            qlog,
+            path.local_address(),
+            path.remote_address(),
+            event,
+            now,
+        );
+    }
+
+    /// Keep the identified path ready for failover, revalidating it every `interval`.
+    /// A path that is not yet valid is probed at the next opportunity.
+    pub fn add_standby(&self, path: &PathRef, interval: Duration) {
+        debug_assert!(!self.is_temporary(path));
+        qdebug!("[{}] Standby path", path.borrow());
+        path.borrow_mut().set_standby(Some(interval));
+    }
+
+    /// Find the standby path that uses the given local address, if there is one.
+    pub fn standby(&self, local: SocketAddr) -> Option<PathRef> {
+        self.paths
+            .iter()
+            .find(|p| {
+                let p = p.borrow();
+                p.is_standby() && p.local_address() == local
+            })
+            .cloned()
+    }
+
+    /// Stop keeping paths with the given local address ready for failover.
+    /// Those paths are retired in the usual way once they are no longer used.
+    pub fn remove_standby(&self, local: SocketAddr) {
+        for p in &self.paths {
+            if p.borrow().local_address() == local {
+                p.borrow_mut().set_standby(None);
+            }
+        }
+    }
+
     /// Process elapsed time for active paths.
     /// Returns an true if there are viable paths remaining after tidying up.
     ///
@@ -227,6 +293,7 @@ impl Paths {
     /// for themselves.
     pub fn process_timeout(&mut self, now: Instant, pto: Duration, stats: &mut Stats) -> bool {
         let to_retire = &mut self.to_retire;
+        let qlog = &mut self.qlog;
         let mut primary_failed = false;
         self.paths.retain(|p| {
             if p.borrow_mut().process_timeout(now, pto, stats) {
@@ -236,7 +303,7 @@ impl Paths {
                 if p.borrow().is_primary() {
                     primary_failed = true;
                 }
-                Self::retire(to_retire, p);
+                Self::retire(to_retire, qlog, p);
                 false
             }
         });
@@ -257,6 +324,7 @@ impl Paths {
                 // Need a clone as `fallback` is borrowed from `self`.
                 let path = Rc::clone(fallback);
                 qinfo!("[{}] Failing over after primary path failed", path.borrow());
+                Self::path_updated(&mut self.qlog, &path, PathEvent::Migrated, now);
                 drop(self.select_primary(&path, now));
                 true
             } else {
@@ -361,11 +429,13 @@ impl Paths {
     /// and the new path cannot obtain a new connection ID, the migration attempt will fail.
     pub fn retire_cids(&mut self, retire_prior: u64, store: &mut ConnectionIdStore<Srt>) {
         let to_retire = &mut self.to_retire;
+        let qlog = &mut self.qlog;
         let migration_target = &mut self.migration_target;
 
         // First, tell the store to release any connection IDs that are too old.
-        let mut retired = store.retire_prior_to(retire_prior);
-        to_retire.append(&mut retired);
+        for cid in store.retire_prior_to(retire_prior) {
+            Self::retire_remote_cid(to_retire, qlog, &cid);
+        }
 
         self.paths.retain(|p| {
             let mut path = p.borrow_mut();
@@ -373,7 +443,7 @@ impl Paths {
                 return true;
             };
             if current.sequence_number() < retire_prior && !current.connection_id().is_empty() {
-                to_retire.push(current.sequence_number());
+                Self::retire_remote_cid(to_retire, qlog, current);
                 let new_cid = store.next();
                 let has_replacement = new_cid.is_some();
                 // There must be a connection ID available for the primary path as we
@@ -539,6 +609,8 @@ pub struct Path {
 
     /// Whether this is the primary path.
     primary: bool,
+    /// If this path is kept ready for failover, how often it is revalidated.
+    standby: Option<Duration>,
     /// Whether the current path is considered valid.
     state: ProbeState,
     /// For a path that is not validated, this is `None`.  For a validated
@@ -612,6 +684,7 @@ impl Path {
             local_cid: None,
             remote_cid: None,
             primary: false,
+            standby: None,
             state: ProbeState::ProbeNeeded { probe_count: 0 },
             validated: None,
             challenge: None,
@@ -619,7 +692,7 @@ impl Path {
             sender,
             received_bytes: 0,
             sent_bytes: 0,
-            ecn_info: ecn::Info::default(),
+            ecn_info: ecn::Info::new(conn_params.get_ecn_codepoint()),
             scone: None,
             qlog,
         }
@@ -639,6 +712,17 @@ impl Path {
         self.primary
     }
 
+    /// Whether this path is kept ready for failover.
+    pub const fn is_standby(&self) -> bool {
+        self.standby.is_some()
+    }
+
+    /// Keep this path ready for failover, revalidating it every `interval`,
+    /// or stop doing that if `interval` is `None`.
+    pub(crate) const fn set_standby(&mut self, interval: Option<Duration>) {
+        self.standby = interval;
+    }
+
     /// Whether this path is a temporary one.
     pub const fn is_temporary(&self) -> bool {
         self.remote_cid.is_none()
@@ -801,6 +885,13 @@ impl Path {
             if response == *data {
                 let need_full_probe = !*mtu;
                 self.set_valid(now);
+                qlog::path_updated(
+                    &mut self.qlog,
+                    self.local,
+                    self.remote,
+                    PathEvent::Validated,
+                    now,
+                );
                 if need_full_probe {
                     qdebug!("[{self}] Sub-MTU probe successful, reset probe count");
                     self.probe(stats);
@@ -941,6 +1032,15 @@ impl Path {
             && now >= *sent + pto
         {
             self.probe(stats);
+            if matches!(self.state, ProbeState::Failed) {
+                qlog::path_updated(
+                    &mut self.qlog,
+                    self.local,
+                    self.remote,
+                    PathEvent::ValidationFailed,
+                    now,
+                );
+            }
         }
         if matches!(self.state, ProbeState::Failed) {
             // Retire failed paths immediately.
@@ -948,6 +1048,16 @@ impl Path {
         } else if self.primary {
             // Keep valid primary paths otherwise.
             true
+        } else if let Some(interval) = self.standby {
+            // Keep standby paths, but revalidate them periodically so that they
+            // are ready if the primary path fails.
+            if matches!(self.state, ProbeState::Valid)
+                && self.validated.is_some_and(|v| v + interval <= now)
+            {
+                qdebug!("[{self}] Revalidating standby path");
+                self.probe(stats);
+            }
+            true
         } else if matches!(self.state, ProbeState::Valid) {
             // Retire validated, non-primary paths.
             // Allow more than `2 * Self::MAX_PROBES` times the PTO so that an old
@@ -966,10 +1076,10 @@ impl Path {
     /// If there is no other activity, then there is no real need to schedule a
     /// timer to cleanup old paths.
     pub fn next_timeout(&self, pto: Duration) -> Option<Instant> {
-        if let ProbeState::Probing { sent, .. } = &self.state {
-            Some(*sent + pto)
-        } else {
-            None
+        match &self.state {
+            ProbeState::Probing { sent, .. } => Some(*sent + pto),
+            ProbeState::Valid if !self.primary => Some(self.validated? + self.standby?),
+            _ => None,
         }
     }
 
@@ -993,6 +1103,31 @@ impl Path {
         &self.sender
     }
 
+    /// The congestion state to save for Careful Resume, or `None` if there is no
+    /// RTT sample yet.
+    pub fn saved_congestion_state(&self) -> Option<SavedCongestionState> {
+        (self.rtt.first_sample_time().is_some() && !self.rtt.is_guesstimate()).then(|| {
+            SavedCongestionState::new(
+                self.sender.cwnd(),
+                self.rtt.minimum(),
+                self.remote_address().ip(),
+            )
+        })
+    }
+
+    /// Start Careful Resume with congestion state saved from an earlier connection,
+    /// if that state was saved for the same server address.
+    pub fn careful_resume(&mut self, saved: SavedCongestionState, now: Instant, stats: &mut Stats) {
+        if saved.remote() == self.remote_address().ip() {
+            self.sender.careful_resume(saved, now, &mut stats.cc);
+        } else {
+            qdebug!(
+                "[{self}] Not resuming congestion state saved for {}",
+                saved.remote()
+            );
+        }
+    }
+
     /// Take a snapshot of this path's RTT and congestion-control stats into `stats`.
     pub fn update_stats(&self, stats: &mut Stats) {
         stats.rtt = self.rtt.estimate();
@@ -1084,10 +1219,11 @@ impl Path {
     ) {
         debug_assert!(self.is_primary());
 
-        let ecn_ce_received = self.ecn_info.on_packets_acked(acked_pkts, ack_ecn, stats);
-        if ecn_ce_received {
+        let ce_marks = self.ecn_info.on_packets_acked(acked_pkts, ack_ecn, stats);
+        if ce_marks > 0 {
             let cwnd_reduced = self.sender.on_ecn_ce_received(
                 acked_pkts.first().expect("must be there"),
+                ce_marks,
                 now,
                 &mut stats.cc,
             );
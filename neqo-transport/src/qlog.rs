@@ -7,37 +7,44 @@
 // Functions that handle capturing QLOG traces.
 
 use std::{
+    net::SocketAddr,
     ops::{Deref as _, RangeInclusive},
     time::{Duration, Instant},
 };
 
-use neqo_common::{Decoder, Ecn, hex::Hex, qinfo, qlog::Qlog, to_u64};
+use neqo_common::{Decoder, Ecn, Role, hex::Hex, qinfo, qlog::Qlog, to_u64};
 use qlog::events::{
-    ApplicationErrorCode, ConnectionErrorCode, EventData, RawInfo,
+    ApplicationErrorCode, ConnectionErrorCode, DataRecipient, EventData, ExData, RawInfo,
     connectivity::{
-        ConnectionClosed, ConnectionClosedTrigger, ConnectionStarted, ConnectionState,
-        ConnectionStateUpdated, MtuUpdated, TransportOwner,
+        ConnectionClosed, ConnectionClosedTrigger, ConnectionIdUpdated, ConnectionStarted,
+        ConnectionState, ConnectionStateUpdated, MtuUpdated, TransportOwner,
     },
     quic::{
-        AckedRanges, CongestionStateUpdated, CongestionStateUpdatedTrigger, ErrorSpace,
+        AckedRanges, CongestionStateUpdated, CongestionStateUpdatedTrigger, DataMoved, ErrorSpace,
         LossTimerEventType, LossTimerUpdated, MetricsUpdated, PacketDropped, PacketDroppedTrigger,
         PacketHeader, PacketLost, PacketLostTrigger, PacketNumberSpace as QlogPacketNumberSpace,
-        PacketReceived, PacketSent, PacketsAcked, QuicFrame, RecoveryParametersSet, StreamType,
-        TimerType, VersionInformation,
+        PacketReceived, PacketSent, PacketsAcked, QuicFrame, RecoveryParametersSet, StreamSide,
+        StreamState, StreamStateUpdated, StreamType, TimerType, VersionInformation,
     },
+    security::{KeyDiscarded, KeyType, KeyUpdateOrRetiredTrigger, KeyUpdated},
 };
+use serde_json::{Value, json};
 use smallvec::SmallVec;
 
 use crate::{
     CloseReason,
-    cc::{CWND_INITIAL_PKTS, CongestionControl, Cubic, PERSISTENT_CONG_THRESH},
+    cc::{Bbr, CWND_INITIAL_PKTS, CongestionControl, Cubic, PERSISTENT_CONG_THRESH},
+    cid::ConnectionId,
     connection::State,
+    crypto::{CryptoDxDirection, Epoch},
     frame::{CloseError, Frame},
     packet::{self, metadata::Direction},
     path::PathRef,
     recovery::sent,
+    recv_stream::RecvStreamState,
     rtt::{DEFAULT_INITIAL_RTT, GRANULARITY},
-    stream_id::StreamType as NeqoStreamType,
+    send_stream,
+    stream_id::{StreamId, StreamType as NeqoStreamType},
     tparams::{
         TransportParameterId::{
             self, AckDelayExponent, ActiveConnectionIdLimit, DisableMigration, InitialMaxData,
@@ -226,8 +233,8 @@ pub fn server_version_information_failed(
 }
 
 pub fn packet_io(qlog: &mut Qlog, meta: packet::MetaData, now: Instant) {
-    qlog.add_event_at(
-        || {
+    qlog.add_event_with_stream_at(
+        |stream| {
             let mut d = Decoder::from(meta.payload());
             let raw = RawInfo {
                 length: Some(to_u64(meta.length())),
@@ -236,8 +243,10 @@ pub fn packet_io(qlog: &mut Qlog, meta: packet::MetaData, now: Instant) {
             };
 
             let mut frames = SmallVec::new();
+            let mut ack_frequency_frames = Vec::new();
             while d.remaining() > 0 {
                 if let Ok(f) = Frame::decode(&mut d) {
+                    ack_frequency_frames.extend(ack_frequency_frame(&f));
                     frames.push(QuicFrame::from(f));
                 } else {
                     qinfo!("qlog: invalid frame");
@@ -245,24 +254,55 @@ pub fn packet_io(qlog: &mut Qlog, meta: packet::MetaData, now: Instant) {
                 }
             }
 
-            match meta.direction() {
-                Direction::Tx => Some(EventData::PacketSent(PacketSent {
+            let ev_data = match meta.direction() {
+                Direction::Tx => EventData::PacketSent(PacketSent {
                     header: meta.into(),
                     frames: Some(frames),
                     raw: Some(raw),
                     ..Default::default()
-                })),
-                Direction::Rx => Some(EventData::PacketReceived(PacketReceived {
+                }),
+                Direction::Rx => EventData::PacketReceived(PacketReceived {
                     header: meta.into(),
                     frames: Some(frames.to_vec()),
                     raw: Some(raw),
                     ..Default::default()
-                })),
+                }),
+            };
+            let mut ex_data = ExData::new();
+            if !ack_frequency_frames.is_empty() {
+                ex_data.insert(
+                    "ack_frequency_frames".to_string(),
+                    Value::Array(ack_frequency_frames),
+                );
             }
+            stream.add_event_data_ex_with_instant(ev_data, ex_data, now)
         },
         now,
     );
 }
+
+/// The qlog crate has no frame types for draft-ietf-quic-ack-frequency, so
+/// `QuicFrame` records those frames as unknown.  Their fields are logged
+/// separately, using the names from the draft.
+fn ack_frequency_frame(frame: &Frame) -> Option<Value> {
+    match frame {
+        Frame::AckFrequency {
+            seqno,
+            tolerance,
+            delay,
+            reordering_threshold,
+        } => Some(json!({
+            "frame_type": "ack_frequency",
+            "sequence_number": seqno,
+            "ack_eliciting_threshold": tolerance,
+            "request_max_ack_delay": Duration::from_micros(*delay).as_secs_f64() * 1000.0,
+            "reordering_threshold": reordering_threshold,
+        })),
+        Frame::ImmediateAck => Some(json!({ "frame_type": "immediate_ack" })),
+        _ => None,
+    }
+}
+
 pub fn packet_dropped(qlog: &mut Qlog, decrypt_err: &packet::DecryptionError, now: Instant) {
     qlog.add_event_at(
         || {
@@ -288,26 +328,34 @@ pub fn packet_dropped(qlog: &mut Qlog, decrypt_err: &packet::DecryptionError, no
 }
 
 pub fn packets_lost(qlog: &mut Qlog, pkts: &[sent::Packet], now: Instant) {
-    qlog.add_event_with_stream(|stream| {
-        for pkt in pkts {
-            let header =
-                PacketHeader::with_type(pkt.packet_type().into(), Some(pkt.pn()), None, None, None);
+    qlog.add_event_with_stream_at(
+        |stream| {
+            for pkt in pkts {
+                let header = PacketHeader::with_type(
+                    pkt.packet_type().into(),
+                    Some(pkt.pn()),
+                    None,
+                    None,
+                    None,
+                );
 
-            let trigger = pkt
-                .loss_info()
-                .map(|info| PacketLostTrigger::from(info.trigger))
-                .or_else(|| pkt.pto_fired().then_some(PacketLostTrigger::PtoExpired));
+                let trigger = pkt
+                    .loss_info()
+                    .map(|info| PacketLostTrigger::from(info.trigger))
+                    .or_else(|| pkt.pto_fired().then_some(PacketLostTrigger::PtoExpired));
 
-            let ev_data = EventData::PacketLost(PacketLost {
-                header: Some(header),
-                trigger,
-                ..Default::default()
-            });
+                let ev_data = EventData::PacketLost(PacketLost {
+                    header: Some(header),
+                    trigger,
+                    ..Default::default()
+                });
 
-            stream.add_event_data_with_instant(ev_data, now)?;
-        }
-        Ok(())
-    });
+                stream.add_event_data_with_instant(ev_data, now)?;
+            }
+            Ok(())
+        },
+        now,
+    );
 }
 
 pub fn recovery_parameters_set(
@@ -319,11 +367,15 @@ pub fn recovery_parameters_set(
     qlog.add_event_at(
         || {
             let loss_reduction_factor = match cc {
-                CongestionControl::NewReno => 0.5,
+                CongestionControl::NewReno | CongestionControl::Prague => 0.5,
                 CongestionControl::Cubic => {
                     f32::from(u8::try_from(Cubic::BETA_USIZE_DIVIDEND).expect("fits"))
                         / f32::from(u8::try_from(Cubic::BETA_USIZE_DIVISOR).expect("fits"))
                 }
+                CongestionControl::Bbr => {
+                    f32::from(u8::try_from(Bbr::BETA).expect("fits"))
+                        / f32::from(u8::try_from(Bbr::SCALE).expect("fits"))
+                }
             };
             Some(EventData::RecoveryParametersSet(RecoveryParametersSet {
                 reordering_threshold: Some(
@@ -347,6 +399,31 @@ pub fn recovery_parameters_set(
     );
 }
 
+/// Log the loss detection thresholds after they adapt to reordering.
+/// This repeats the parameters from [`recovery_parameters_set`] that changed.
+#[expect(
+    clippy::cast_possible_truncation,
+    reason = "The time threshold is a small multiple of the RTT."
+)]
+pub fn loss_thresholds_updated(qlog: &mut Qlog, packet: u64, time: f64, now: Instant) {
+    qlog.add_event_at(
+        || {
+            Some(EventData::RecoveryParametersSet(RecoveryParametersSet {
+                reordering_threshold: Some(u16::try_from(packet).expect("fits")),
+                time_threshold: Some(time as f32),
+                timer_granularity: None,
+                initial_rtt: None,
+                max_datagram_size: None,
+                initial_congestion_window: None,
+                minimum_congestion_window: None,
+                loss_reduction_factor: None,
+                persistent_congestion_threshold: None,
+            }))
+        },
+        now,
+    );
+}
+
 pub fn connection_closed(qlog: &mut Qlog, close_reason: &CloseReason, now: Instant) {
     qlog.add_event_at(
         || Some(EventData::ConnectionClosed(close_reason.into())),
@@ -389,8 +466,269 @@ pub fn mtu_updated(qlog: &mut Qlog, old_mtu: usize, new_mtu: usize, done: bool,
     );
 }
 
+pub fn send_stream_state_updated(
+    qlog: &mut Qlog,
+    stream_id: StreamId,
+    old: &send_stream::State,
+    new: &send_stream::State,
+) {
+    stream_state_updated(qlog, stream_id, StreamSide::Sending, old.into(), new.into());
+}
+
+pub fn recv_stream_state_updated(
+    qlog: &mut Qlog,
+    stream_id: StreamId,
+    old: &RecvStreamState,
+    new: &RecvStreamState,
+) {
+    stream_state_updated(
+        qlog,
+        stream_id,
+        StreamSide::Receiving,
+        old.into(),
+        new.into(),
+    );
+}
+
+/// Stream state changes are mostly driven by the stream API, which isn't
+/// given the time, so these events are logged at the time of the last event.
+fn stream_state_updated(
+    qlog: &mut Qlog,
+    stream_id: StreamId,
+    side: StreamSide,
+    old: StreamState,
+    new: StreamState,
+) {
+    // Several of our states map to the same qlog state.
+    if old == new {
+        return;
+    }
+    qlog.add_event_at_latest(|| {
+        Some(EventData::StreamStateUpdated(StreamStateUpdated {
+            stream_id: stream_id.as_u64(),
+            stream_type: Some(stream_id.stream_type().into()),
+            old: Some(old),
+            new,
+            stream_side: Some(side),
+        }))
+    });
+}
+
+/// The application wrote `len` bytes at `offset` to a send stream.
+pub fn data_moved_down(qlog: &mut Qlog, stream_id: StreamId, offset: u64, len: usize) {
+    data_moved(
+        qlog,
+        stream_id,
+        offset,
+        len,
+        DataRecipient::Application,
+        DataRecipient::Transport,
+    );
+}
+
+/// The application read `len` bytes at `offset` from a receive stream.
+pub fn data_moved_up(qlog: &mut Qlog, stream_id: StreamId, offset: u64, len: usize) {
+    data_moved(
+        qlog,
+        stream_id,
+        offset,
+        len,
+        DataRecipient::Transport,
+        DataRecipient::Application,
+    );
+}
+
+fn data_moved(
+    qlog: &mut Qlog,
+    stream_id: StreamId,
+    offset: u64,
+    len: usize,
+    from: DataRecipient,
+    to: DataRecipient,
+) {
+    if len == 0 {
+        return;
+    }
+    qlog.add_event_at_latest(|| {
+        Some(EventData::DataMoved(DataMoved {
+            stream_id: Some(stream_id.as_u64()),
+            offset: Some(offset),
+            length: Some(to_u64(len)),
+            from: Some(from),
+            to: Some(to),
+            raw: None,
+        }))
+    });
+}
+
+/// Sending became blocked by flow control at `limit`, either on the identified
+/// stream or, if `stream_id` is `None`, on the connection.
+/// qlog has no event for this, so it is logged as a message.
+pub fn flow_control_blocked(qlog: &mut Qlog, stream_id: Option<StreamId>, limit: u64) {
+    qlog.add_event_at_latest(|| {
+        let message = stream_id.map_or_else(
+            || format!("connection blocked by flow control at {limit}"),
+            |id| format!("stream {id} blocked by flow control at {limit}"),
+        );
+        Some(EventData::Message { message })
+    });
+}
+
+/// What caused a key update.
+#[derive(Clone, Copy)]
+pub enum KeyUpdateTrigger {
+    /// The key update was initiated locally.
+    Local,
+    /// The key update was initiated by the peer.
+    Remote,
+}
+
+impl From<KeyUpdateTrigger> for KeyUpdateOrRetiredTrigger {
+    fn from(value: KeyUpdateTrigger) -> Self {
+        match value {
+            KeyUpdateTrigger::Local => Self::LocalUpdate,
+            KeyUpdateTrigger::Remote => Self::RemoteUpdate,
+        }
+    }
+}
+
+/// The 1-RTT keys in the given direction were updated to `epoch`.
+///
+/// A key update can be initiated from the API, which isn't given the time,
+/// so this is logged at the time of the last event.  Key material is never
+/// logged.
+pub fn key_updated(
+    qlog: &mut Qlog,
+    role: Role,
+    direction: CryptoDxDirection,
+    epoch: usize,
+    trigger: KeyUpdateTrigger,
+) {
+    qlog.add_event_at_latest(|| {
+        let owner = match direction {
+            CryptoDxDirection::Write => role,
+            CryptoDxDirection::Read => role.remote(),
+        };
+        Some(EventData::KeyUpdated(KeyUpdated {
+            key_type: key_type(owner, Epoch::ApplicationData),
+            old: None,
+            new: String::new(),
+            generation: u32::try_from(epoch - usize::from(Epoch::ApplicationData)).ok(),
+            trigger: Some(trigger.into()),
+        }))
+    });
+}
+
+/// The keys for `epoch` were discarded.
+pub fn keys_discarded(qlog: &mut Qlog, epoch: Epoch, now: Instant) {
+    // Only the client sends 0-RTT.
+    let owners: &[Role] = match epoch {
+        Epoch::ZeroRtt => &[Role::Client],
+        _ => &[Role::Client, Role::Server],
+    };
+    for &owner in owners {
+        qlog.add_event_at(
+            || {
+                Some(EventData::KeyDiscarded(KeyDiscarded {
+                    key_type: key_type(owner, epoch),
+                    key: None,
+                    generation: None,
+                    trigger: Some(KeyUpdateOrRetiredTrigger::Tls),
+                }))
+            },
+            now,
+        );
+    }
+}
+
+const fn key_type(owner: Role, epoch: Epoch) -> KeyType {
+    match (owner, epoch) {
+        (Role::Client, Epoch::Initial) => KeyType::ClientInitialSecret,
+        (Role::Server, Epoch::Initial) => KeyType::ServerInitialSecret,
+        (Role::Client, Epoch::ZeroRtt) => KeyType::Client0RttSecret,
+        (Role::Server, Epoch::ZeroRtt) => KeyType::Server0RttSecret,
+        (Role::Client, Epoch::Handshake) => KeyType::ClientHandshakeSecret,
+        (Role::Server, Epoch::Handshake) => KeyType::ServerHandshakeSecret,
+        (Role::Client, Epoch::ApplicationData) => KeyType::Client1RttSecret,
+        (Role::Server, Epoch::ApplicationData) => KeyType::Server1RttSecret,
+    }
+}
+
+/// Which endpoint issued a connection ID.
+#[derive(Clone, Copy)]
+pub enum ConnectionIdOwner {
+    Local,
+    Remote,
+}
+
+impl From<ConnectionIdOwner> for TransportOwner {
+    fn from(value: ConnectionIdOwner) -> Self {
+        match value {
+            ConnectionIdOwner::Local => Self::Local,
+            ConnectionIdOwner::Remote => Self::Remote,
+        }
+    }
+}
+
+/// A connection ID was issued or retired.
+///
+/// Local connection IDs are issued while building packets and for transport
+/// parameters, neither of which is given the time, so this is logged at the
+/// time of the last event.
+pub fn connection_id_updated(
+    qlog: &mut Qlog,
+    owner: ConnectionIdOwner,
+    old: Option<&ConnectionId>,
+    new: Option<&ConnectionId>,
+) {
+    qlog.add_event_at_latest(|| {
+        Some(EventData::ConnectionIdUpdated(ConnectionIdUpdated {
+            owner: Some(owner.into()),
+            old: old.map(to_hex),
+            new: new.map(to_hex),
+        }))
+    });
+}
+
+/// A change in the validation or migration state of a path.
+#[derive(Clone, Copy)]
+pub enum PathEvent {
+    /// The path was validated.
+    Validated,
+    /// Validation of the path failed.
+    ValidationFailed,
+    /// Migration to the path will happen once it is validated.
+    MigrationStarted,
+    /// The path became the primary path.
+    Migrated,
+}
+
+/// qlog has no events for path validation or migration, so these are logged as
+/// messages.
+pub fn path_updated(
+    qlog: &mut Qlog,
+    local: SocketAddr,
+    remote: SocketAddr,
+    event: PathEvent,
+    now: Instant,
+) {
+    qlog.add_event_at(
+        || {
+            let what = match event {
+                PathEvent::Validated => "validated",
+                PathEvent::ValidationFailed => "validation failed",
+                PathEvent::MigrationStarted => "migration started",
+                PathEvent::Migrated => "migrated",
+            };
+            Some(EventData::Message {
+                message: format!("path {what}: {local}->{remote}"),
+            })
+        },
+        now,
+    );
+}
+
 #[derive(Clone, Copy)]
-#[expect(dead_code, reason = "TODO: Construct all variants.")]
 pub enum Metric {
     MinRtt(Duration),
     SmoothedRtt(Duration),
@@ -594,12 +932,21 @@ impl From<Frame<'_>> for QuicFrame {
                 length: None,
                 payload_length: None,
             },
+            // qlog doesn't have receive timestamps, so log those as ACK frames.
             Frame::Ack {
                 largest_acknowledged,
                 ack_delay,
                 first_ack_range,
                 ack_ranges,
                 ecn_count,
+            }
+            | Frame::AckReceiveTimestamps {
+                largest_acknowledged,
+                ack_delay,
+                first_ack_range,
+                ack_ranges,
+                ecn_count,
+                ..
             } => {
                 let ranges =
                     Frame::decode_ack_frame(largest_acknowledged, first_ack_range, &ack_ranges)
@@ -748,7 +1095,8 @@ impl From<Frame<'_>> for QuicFrame {
                 length: to_u64(data.len()),
                 raw: None,
             },
-            Frame::AckFrequency { .. } => Self::Unknown {
+            // These are logged in full by `packet_io`, see `ack_frequency_frame`.
+            Frame::AckFrequency { .. } | Frame::ImmediateAck => Self::Unknown {
                 frame_type_value: None,
                 raw_frame_type: frame.get_type().into(),
                 raw: None,
@@ -790,6 +1138,45 @@ impl From<NeqoStreamType> for StreamType {
     }
 }
 
+impl From<&send_stream::State> for StreamState {
+    fn from(state: &send_stream::State) -> Self {
+        match state {
+            send_stream::State::Ready { .. } => Self::Ready,
+            send_stream::State::Send { .. } => Self::Send,
+            send_stream::State::DataSent { .. } => Self::DataSent,
+            send_stream::State::DataRecvd { .. } => Self::DataReceived,
+            send_stream::State::ResetSent { .. } | send_stream::State::ResetSentReliable { .. } => {
+                Self::ResetSent
+            }
+            send_stream::State::ResetRecvd { .. } => Self::ResetReceived,
+        }
+    }
+}
+
+impl From<&RecvStreamState> for StreamState {
+    fn from(state: &RecvStreamState) -> Self {
+        // Aborting reading is local to neqo; the stream stays in the receiving
+        // state from -transport 3.2 until the peer resets it.
+        match state {
+            RecvStreamState::Recv { .. }
+            | RecvStreamState::WaitForReset { .. }
+            | RecvStreamState::AbortReading {
+                final_size_reached: false,
+                ..
+            } => Self::Receive,
+            RecvStreamState::SizeKnown { .. }
+            | RecvStreamState::SizeKnownAt { .. }
+            | RecvStreamState::AbortReading {
+                final_size_reached: true,
+                ..
+            } => Self::SizeKnown,
+            RecvStreamState::DataRecvd { .. } => Self::DataReceived,
+            RecvStreamState::DataRead { .. } => Self::DataRead,
+            RecvStreamState::ResetRecvd { .. } => Self::ResetReceived,
+        }
+    }
+}
+
 impl From<&CloseError> for ErrorSpace {
     fn from(error: &CloseError) -> Self {
         match error {
@@ -876,9 +1263,11 @@ impl From<packet::Type> for qlog::events::quic::PacketType {
 
 #[cfg(test)]
 mod tests {
+    use serde_json::json;
     use test_fixture::new_neqo_qlog;
 
-    use super::{Metric, metrics_updated};
+    use super::{Metric, ack_frequency_frame, metrics_updated};
+    use crate::frame::Frame;
 
     /// Verify that `metrics_updated` records all metric variants, including
     /// `SsThresh`, when qlog is enabled.
@@ -899,4 +1288,29 @@ mod tests {
         );
         assert!(output.contains("ssthresh"), "missing ssthresh");
     }
+
+    #[test]
+    fn ack_frequency_frames() {
+        let frame = Frame::AckFrequency {
+            seqno: 10,
+            tolerance: 5,
+            delay: 2000,
+            reordering_threshold: 3,
+        };
+        assert_eq!(
+            ack_frequency_frame(&frame),
+            Some(json!({
+                "frame_type": "ack_frequency",
+                "sequence_number": 10,
+                "ack_eliciting_threshold": 5,
+                "request_max_ack_delay": 2.0,
+                "reordering_threshold": 3,
+            }))
+        );
+        assert_eq!(
+            ack_frequency_frame(&Frame::ImmediateAck),
+            Some(json!({ "frame_type": "immediate_ack" }))
+        );
+        assert_eq!(ack_frequency_frame(&Frame::Ping), None);
+    }
 }
@@ -6,7 +6,7 @@
 
 // https://datatracker.ietf.org/doc/html/draft-ietf-quic-datagram
 
-use std::{cmp::min, collections::VecDeque};
+use std::{cmp::min, collections::VecDeque, time::Instant};
 
 use neqo_common::{Buffer, Encoder, qdebug, to_u64};
 
@@ -41,9 +41,37 @@ impl From<Option<u64>> for DatagramTracking {
     }
 }
 
+/// Options for sending a datagram.
+/// See [`crate::Connection::send_datagram_with_options`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DatagramOptions {
+    deadline: Option<Instant>,
+    priority: u8,
+}
+
+impl DatagramOptions {
+    /// Discard the datagram if it has not been sent by `deadline`.
+    #[must_use]
+    pub const fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Datagrams with a higher priority are sent before those with a lower priority,
+    /// and are the last to be dropped when the queue is full.  Datagrams with the
+    /// same priority are sent in the order that they were queued.  The default is 0.
+    #[must_use]
+    pub const fn priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
 pub struct QuicDatagram {
     data: Vec<u8>,
     tracking: DatagramTracking,
+    deadline: Option<Instant>,
+    priority: u8,
 }
 
 impl QuicDatagram {
@@ -52,6 +80,10 @@ impl QuicDatagram {
     const fn tracking(&self) -> &DatagramTracking {
         &self.tracking
     }
+
+    fn expired(&self, now: Instant) -> bool {
+        self.deadline.is_some_and(|d| d <= now)
+    }
 }
 
 impl AsRef<[u8]> for QuicDatagram {
@@ -66,8 +98,15 @@ pub struct QuicDatagrams {
     /// The max size of a datagram that would be acceptable by the peer.
     remote_datagram_size: u64,
     max_queued_outgoing_datagrams: usize,
-    /// Datagram queued for sending.
+    /// Datagram queued for sending, highest priority first.
     datagrams: VecDeque<QuicDatagram>,
+    /// The share of the bytes sent, in percent, that datagrams can use ahead of
+    /// stream data.
+    share: u8,
+    /// Credit for sending datagrams ahead of stream data, in hundredths of a byte.
+    /// Every byte of stream data or datagrams that is sent earns `share`, and
+    /// every byte of a datagram that is sent ahead of stream data costs 100.
+    credit: i64,
     conn_events: ConnectionEvents,
 }
 
@@ -75,6 +114,7 @@ impl QuicDatagrams {
     pub fn new(
         local_datagram_size: u64,
         max_queued_outgoing_datagrams: usize,
+        share: u8,
         conn_events: ConnectionEvents,
     ) -> Self {
         Self {
@@ -82,6 +122,8 @@ impl QuicDatagrams {
             remote_datagram_size: 0,
             max_queued_outgoing_datagrams,
             datagrams: VecDeque::with_capacity(max_queued_outgoing_datagrams),
+            share,
+            credit: 0,
             conn_events,
         }
     }
@@ -97,13 +139,27 @@ impl QuicDatagrams {
     /// This function tries to write a datagram frame into a packet. If the
     /// frame does not fit into the packet, the datagram will be dropped and a
     /// [`OutgoingDatagramOutcome::DroppedTooBig`] event will be posted.
+    /// Datagrams that have expired are dropped and an
+    /// [`OutgoingDatagramOutcome::Expired`] event is posted.
+    ///
+    /// If `ahead_of_streams` is set, this only writes datagrams while they are within
+    /// their share of the bytes sent.
     pub fn write_frames<B: Buffer>(
         &mut self,
         builder: &mut packet::Builder<B>,
         tokens: &mut recovery::Tokens,
         stats: &mut Stats,
+        now: Instant,
+        ahead_of_streams: bool,
     ) {
-        while let Some(dgram) = self.datagrams.pop_front() {
+        self.expire(now, stats);
+        loop {
+            if ahead_of_streams && (self.share == 0 || self.credit < 0) {
+                return;
+            }
+            let Some(dgram) = self.datagrams.pop_front() else {
+                break;
+            };
             let len = dgram.as_ref().len();
             if len + DATAGRAM_FRAME_TYPE_VARINT_LEN <= builder.remaining() {
                 // The datagram fits into the packet.
@@ -127,6 +183,8 @@ impl QuicDatagrams {
                 debug_assert!(builder.len() <= builder.limit());
                 stats.frame_tx.datagram += 1;
                 tokens.push(recovery::Token::Datagram(*dgram.tracking()));
+                let cost = if ahead_of_streams { 100 } else { 0 };
+                self.credit += (i64::from(self.share) - cost) * Self::bytes(len);
             } else if tokens.is_empty() {
                 // If the packet is empty, except packet headers, and the
                 // datagram cannot fit, drop it.
@@ -141,6 +199,40 @@ impl QuicDatagrams {
                 return;
             }
         }
+        // Don't save up credit while there is nothing to send.
+        self.credit = min(self.credit, 0);
+    }
+
+    /// Note that `len` bytes of stream data were sent, which earns credit
+    /// for sending datagrams ahead of stream data.
+    pub fn on_stream_data(&mut self, len: usize) {
+        if !self.datagrams.is_empty() {
+            self.credit += i64::from(self.share) * Self::bytes(len);
+        }
+    }
+
+    fn bytes(len: usize) -> i64 {
+        i64::try_from(len).unwrap_or(i64::MAX)
+    }
+
+    /// The earliest deadline of any queued datagram.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.datagrams.iter().filter_map(|d| d.deadline).min()
+    }
+
+    /// Drop datagrams that have passed their deadline.
+    pub fn expire(&mut self, now: Instant, stats: &mut Stats) {
+        self.datagrams.retain(|dgram| {
+            if dgram.expired(now) {
+                qdebug!("QUIC datagram expired before it could be sent.");
+                self.conn_events
+                    .datagram_outcome(dgram.tracking(), OutgoingDatagramOutcome::Expired);
+                stats.datagram_tx.expired += 1;
+                false
+            } else {
+                true
+            }
+        });
     }
 
     /// Add a datagram to the send queue.
@@ -156,6 +248,7 @@ impl QuicDatagrams {
         &mut self,
         data: Vec<u8>,
         tracking: DatagramTracking,
+        options: DatagramOptions,
         stats: &mut Stats,
     ) -> Res<()> {
         if to_u64(data.len()) > self.remote_datagram_size {
@@ -166,21 +259,44 @@ impl QuicDatagrams {
             );
             return Err(Error::TooMuchData);
         }
+        let dgram = QuicDatagram {
+            data,
+            tracking,
+            deadline: options.deadline,
+            priority: options.priority,
+        };
         if self.datagrams.len() == self.max_queued_outgoing_datagrams {
-            qdebug!("QUIC datagram queue full, dropping first datagram in queue (head-drop).");
+            // Drop the oldest of the datagrams with the lowest priority (head-drop),
+            // which might be the new one.
+            let lowest = self.datagrams.back().ok_or(Error::Internal)?.priority;
+            let dropped = if dgram.priority < lowest {
+                dgram
+            } else {
+                let pos = self.datagrams.partition_point(|d| d.priority > lowest);
+                let dropped = self.datagrams.remove(pos).ok_or(Error::Internal)?;
+                self.insert(dgram);
+                dropped
+            };
+            qdebug!("QUIC datagram queue full, dropping a datagram.");
             self.conn_events.datagram_outcome(
-                self.datagrams
-                    .pop_front()
-                    .ok_or(Error::Internal)?
-                    .tracking(),
+                dropped.tracking(),
                 OutgoingDatagramOutcome::DroppedQueueFull,
             );
             stats.datagram_tx.dropped_queue_full += 1;
+        } else {
+            self.insert(dgram);
         }
-        self.datagrams.push_back(QuicDatagram { data, tracking });
         Ok(())
     }
 
+    /// Queue a datagram after any others with the same or a higher priority.
+    fn insert(&mut self, dgram: QuicDatagram) {
+        let pos = self
+            .datagrams
+            .partition_point(|d| d.priority >= dgram.priority);
+        self.datagrams.insert(pos, dgram);
+    }
+
     pub fn handle_datagram(&self, data: &[u8]) -> Res<()> {
         // A `local_datagram_size` of 0 means we advertised a
         // max_datagram_frame_size of 0, i.e. no DATAGRAM frame support
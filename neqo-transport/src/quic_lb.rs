@@ -0,0 +1,599 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Connection IDs that a load balancer can route, following
+//! <https://datatracker.ietf.org/doc/html/draft-ietf-quic-load-balancers>.
+//!
+//! A connection ID consists of a first octet, which carries a config rotation
+//! codepoint and the length of the connection ID, followed by a server ID and
+//! a nonce.  The server ID and nonce are either in plaintext or encrypted.
+//!
+//! The configured key is used directly as an AES-128-ECB key, so connection IDs
+//! can be decoded by any load balancer that implements the draft.
+//! The key keeps the server ID confidential, so AES comes from the `aes` crate,
+//! which does not leak the key through cache timing.
+
+use std::fmt::{self, Debug, Formatter};
+
+use aes::{
+    Aes128,
+    cipher::{BlockDecrypt as _, BlockEncrypt as _, KeyInit as _},
+};
+use neqo_common::{Decoder as CodecDecoder, qdebug};
+use nss::{random, randomize};
+use smallvec::SmallVec;
+
+use crate::{
+    Error, Res,
+    cid::{ConnectionId, ConnectionIdDecoder, ConnectionIdGenerator, ConnectionIdRef},
+};
+
+/// The number of config rotation codepoints.  The last codepoint (0b111) is
+/// reserved for unroutable connection IDs.
+const CONFIG_IDS: usize = 7;
+const MIN_SERVER_ID_LEN: usize = 1;
+const MAX_SERVER_ID_LEN: usize = 15;
+const MIN_NONCE_LEN: usize = 4;
+const MAX_NONCE_LEN: usize = 18;
+/// The server ID and nonce, plus the first octet, have to fit in a connection ID.
+const MAX_PLAINTEXT_LEN: usize = ConnectionId::MAX_LEN - 1;
+/// The AES block size, which is also the plaintext length for single-pass encryption.
+const BLOCK_LEN: usize = 16;
+/// The configuration shared between a load balancer and the servers behind it.
+#[derive(Clone)]
+pub struct Config {
+    /// The config rotation codepoint.
+    id: u8,
+    server_id_len: usize,
+    nonce_len: usize,
+    /// The key for encrypted connection IDs, or `None` for plaintext.
+    key: Option<[u8; Self::KEY_LEN]>,
+}
+
+impl Config {
+    pub const KEY_LEN: usize = 16;
+
+    /// Create a configuration.
+    /// * `id` is the config rotation codepoint, from 0 to 6.
+    /// * `server_id_len` is the length of server IDs, from 1 to 15 bytes.
+    /// * `nonce_len` is the length of the nonce, from 4 to 18 bytes.
+    /// * `key` is the key for encrypting connection IDs.  If this is `None`, connection IDs are
+    ///   not encrypted.
+    ///
+    /// # Errors
+    /// When any of the values is out of range, or the server ID and nonce together
+    /// are longer than 19 bytes.
+    pub fn new(
+        id: u8,
+        server_id_len: usize,
+        nonce_len: usize,
+        key: Option<[u8; Self::KEY_LEN]>,
+    ) -> Res<Self> {
+        if usize::from(id) >= CONFIG_IDS
+            || !(MIN_SERVER_ID_LEN..=MAX_SERVER_ID_LEN).contains(&server_id_len)
+            || !(MIN_NONCE_LEN..=MAX_NONCE_LEN).contains(&nonce_len)
+            || server_id_len + nonce_len > MAX_PLAINTEXT_LEN
+        {
+            return Err(Error::InvalidInput);
+        }
+        Ok(Self {
+            id,
+            server_id_len,
+            nonce_len,
+            key,
+        })
+    }
+
+    /// The config rotation codepoint.
+    #[must_use]
+    pub const fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// The length of connection IDs that use this configuration.
+    #[must_use]
+    pub const fn cid_len(&self) -> usize {
+        1 + self.plaintext_len()
+    }
+
+    const fn plaintext_len(&self) -> usize {
+        self.server_id_len + self.nonce_len
+    }
+
+    fn cipher(&self) -> Option<Cipher> {
+        self.key
+            .as_ref()
+            .map(|key| Cipher::new(key, self.plaintext_len()))
+    }
+}
+
+/// Don't leak key material into logs.
+impl Debug for Config {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("id", &self.id)
+            .field("server_id_len", &self.server_id_len)
+            .field("nonce_len", &self.nonce_len)
+            .field("encrypted", &self.key.is_some())
+            .finish()
+    }
+}
+
+/// AES-128 in ECB mode, as used for encrypted connection IDs.
+/// Connection IDs with exactly one block of plaintext use single-pass encryption,
+/// all others use four passes.
+struct Cipher {
+    aes: Aes128,
+    plaintext_len: usize,
+}
+
+impl Cipher {
+    fn new(key: &[u8; Config::KEY_LEN], plaintext_len: usize) -> Self {
+        Self {
+            aes: Aes128::new(key.into()),
+            plaintext_len,
+        }
+    }
+
+    /// The length of each half of the plaintext for four-pass encryption.
+    /// For an odd length, the halves share the middle byte, four bits each.
+    const fn half_len(&self) -> usize {
+        self.plaintext_len.div_ceil(2)
+    }
+
+    const fn odd(&self) -> bool {
+        self.plaintext_len % 2 == 1
+    }
+
+    /// Build the input to AES for one pass: the half, zero padding,
+    /// the plaintext length, and the pass index.
+    fn expand(&self, half: &[u8], pass: u8) -> [u8; BLOCK_LEN] {
+        let mut block = [0; BLOCK_LEN];
+        block[..half.len()].copy_from_slice(half);
+        block[BLOCK_LEN - 2] = u8::try_from(self.plaintext_len).expect("plaintext fits in a block");
+        block[BLOCK_LEN - 1] = pass;
+        block
+    }
+
+    /// Take the left half of `input`, clearing the low four bits of the middle byte if
+    /// the length is odd.
+    fn left(&self, input: &[u8]) -> SmallVec<[u8; BLOCK_LEN]> {
+        let mut half = SmallVec::from_slice(&input[..self.half_len()]);
+        if self.odd() {
+            *half.last_mut().expect("half is not empty") &= 0xf0;
+        }
+        half
+    }
+
+    /// Take the right half of `input`, clearing the high four bits of the middle byte if
+    /// the length is odd.
+    fn right(&self, input: &[u8]) -> SmallVec<[u8; BLOCK_LEN]> {
+        let mut half = SmallVec::from_slice(&input[input.len() - self.half_len()..]);
+        if self.odd() {
+            half[0] &= 0x0f;
+        }
+        half
+    }
+
+    /// Perform one round: XOR `target` with a truncated encryption of the expanded `source`.
+    fn round(&self, target: &mut [u8], source: &[u8], pass: u8, left: bool) {
+        let mut mask = self.expand(source, pass);
+        self.aes.encrypt_block((&mut mask).into());
+        let mask = if left {
+            self.left(&mask)
+        } else {
+            self.right(&mask)
+        };
+        for (t, m) in target.iter_mut().zip(mask) {
+            *t ^= m;
+        }
+    }
+
+    /// Put the two halves back together into `output`.
+    fn merge(&self, left: &[u8], right: &[u8], output: &mut [u8]) {
+        let half = self.half_len();
+        output.fill(0);
+        output[..half].copy_from_slice(left);
+        let start = self.plaintext_len - half;
+        for (o, r) in output[start..].iter_mut().zip(right) {
+            *o |= r;
+        }
+    }
+
+    fn encrypt(&self, data: &mut [u8]) {
+        debug_assert_eq!(data.len(), self.plaintext_len);
+        if let Ok(block) = <&mut [u8; BLOCK_LEN]>::try_from(&mut *data) {
+            self.aes.encrypt_block(block.into());
+            return;
+        }
+
+        let mut left = self.left(data);
+        let mut right = self.right(data);
+        self.round(&mut left, &right, 1, true);
+        self.round(&mut right, &left, 2, false);
+        self.round(&mut left, &right, 3, true);
+        self.round(&mut right, &left, 4, false);
+        self.merge(&left, &right, data);
+    }
+
+    fn decrypt(&self, data: &mut [u8]) {
+        debug_assert_eq!(data.len(), self.plaintext_len);
+        if let Ok(block) = <&mut [u8; BLOCK_LEN]>::try_from(&mut *data) {
+            self.aes.decrypt_block(block.into());
+            return;
+        }
+
+        let mut left = self.left(data);
+        let mut right = self.right(data);
+        self.round(&mut right, &left, 4, false);
+        self.round(&mut left, &right, 3, true);
+        self.round(&mut right, &left, 2, false);
+        self.round(&mut left, &right, 1, true);
+        self.merge(&left, &right, data);
+    }
+}
+
+/// A connection ID generator for a server behind a QUIC-LB load balancer.
+pub struct Generator {
+    config: Config,
+    server_id: Vec<u8>,
+    cipher: Option<Cipher>,
+    /// The nonce for encrypted connection IDs is a counter, so that connection IDs
+    /// don't repeat.  This is the next value.
+    counter: u64,
+    /// For nonces that are longer than the counter, a random prefix.
+    nonce_prefix: Vec<u8>,
+}
+
+impl Generator {
+    /// Create a generator for a server with the given server ID.
+    ///
+    /// # Errors
+    /// When `server_id` doesn't match the length in `config`.
+    pub fn new(config: Config, server_id: &[u8]) -> Res<Self> {
+        if server_id.len() != config.server_id_len {
+            return Err(Error::InvalidInput);
+        }
+        let cipher = config.cipher();
+        let prefix_len = config.nonce_len.saturating_sub(size_of::<u64>());
+        let mut nonce_prefix = vec![0; prefix_len];
+        randomize(&mut nonce_prefix);
+        Ok(Self {
+            config,
+            server_id: server_id.to_vec(),
+            cipher,
+            counter: 0,
+            nonce_prefix,
+        })
+    }
+
+    /// Fill in the nonce.  Returns `None` if a counter-based nonce is exhausted.
+    fn nonce(&mut self, nonce: &mut [u8]) -> Option<()> {
+        if self.cipher.is_none() {
+            // There is nothing to protect in plaintext, so use a random nonce.
+            randomize(nonce);
+            return Some(());
+        }
+
+        let counter_len = nonce.len() - self.nonce_prefix.len();
+        if counter_len < size_of::<u64>() && self.counter >> (8 * counter_len) != 0 {
+            qdebug!("QUIC-LB nonce exhausted");
+            return None;
+        }
+        let (prefix, counter) = nonce.split_at_mut(self.nonce_prefix.len());
+        prefix.copy_from_slice(&self.nonce_prefix);
+        counter.copy_from_slice(&self.counter.to_be_bytes()[size_of::<u64>() - counter_len..]);
+        self.counter = self.counter.checked_add(1)?;
+        Some(())
+    }
+}
+
+impl ConnectionIdDecoder for Generator {
+    fn decode_cid<'a>(&self, dec: &mut CodecDecoder<'a>) -> Option<ConnectionIdRef<'a>> {
+        dec.decode(self.config.cid_len()).map(ConnectionIdRef::from)
+    }
+}
+
+impl ConnectionIdGenerator for Generator {
+    fn generate_cid(&mut self) -> Option<ConnectionId> {
+        let mut cid: SmallVec<[u8; ConnectionId::MAX_LEN]> =
+            SmallVec::from_elem(0, self.config.cid_len());
+        let plaintext_len = u8::try_from(self.config.plaintext_len()).ok()?;
+        // The first octet self-encodes the length, which is one less than the length
+        // of the connection ID.
+        cid[0] = (self.config.id << 5) | plaintext_len;
+
+        let (server_id, nonce) = cid[1..].split_at_mut(self.config.server_id_len);
+        server_id.copy_from_slice(&self.server_id);
+        self.nonce(nonce)?;
+        if let Some(cipher) = &self.cipher {
+            cipher.encrypt(&mut cid[1..]);
+        }
+        Some(ConnectionId::from(cid))
+    }
+
+    fn as_decoder(&self) -> &dyn ConnectionIdDecoder {
+        self
+    }
+}
+
+/// Finds the server ID in connection IDs that were produced by [`Generator`].
+/// This is what a load balancer uses to route packets.
+#[derive(Default)]
+pub struct Decoder {
+    configs: [Option<(Config, Option<Cipher>)>; CONFIG_IDS],
+}
+
+impl Decoder {
+    /// Add a configuration, replacing any existing configuration with the same codepoint.
+    pub fn add_config(&mut self, config: Config) {
+        let cipher = config.cipher();
+        let id = usize::from(config.id);
+        self.configs[id] = Some((config, cipher));
+    }
+
+    /// Remove the configuration for a codepoint.
+    pub fn remove_config(&mut self, id: u8) {
+        if let Some(c) = self.configs.get_mut(usize::from(id)) {
+            *c = None;
+        }
+    }
+
+    fn config_for(&self, first: u8) -> Option<&(Config, Option<Cipher>)> {
+        self.configs.get(usize::from(first >> 5))?.as_ref()
+    }
+
+    /// Extract the server ID from a connection ID.
+    /// Returns `None` if the connection ID doesn't match any configuration.
+    #[must_use]
+    pub fn server_id(&self, cid: &[u8]) -> Option<Vec<u8>> {
+        let (config, cipher) = self.config_for(*cid.first()?)?;
+        if cid.len() < config.cid_len() {
+            return None;
+        }
+        let mut plaintext = cid[1..config.cid_len()].to_vec();
+        if let Some(cipher) = cipher {
+            cipher.decrypt(&mut plaintext);
+        }
+        plaintext.truncate(config.server_id_len);
+        Some(plaintext)
+    }
+
+    /// Extract the server ID from the destination connection ID of a QUIC packet.
+    #[must_use]
+    pub fn server_id_from_packet(&self, packet: &[u8]) -> Option<Vec<u8>> {
+        let mut dec = CodecDecoder::from(packet);
+        let first = dec.decode_uint::<u8>()?;
+        let dcid = if first & 0x80 == 0 {
+            // Short header: the length comes from the configuration.
+            self.decode_cid(&mut dec)?
+        } else {
+            dec.decode(4)?; // Version
+            ConnectionIdRef::from(dec.decode_vec(1)?)
+        };
+        self.server_id(&dcid)
+    }
+}
+
+impl ConnectionIdDecoder for Decoder {
+    fn decode_cid<'a>(&self, dec: &mut CodecDecoder<'a>) -> Option<ConnectionIdRef<'a>> {
+        let (config, _) = self.config_for(dec.peek_byte()?)?;
+        dec.decode(config.cid_len()).map(ConnectionIdRef::from)
+    }
+}
+
+/// Produce a random connection ID that a load balancer can't route, using the reserved
+/// config rotation codepoint.  Clients, and servers that can't reach a load balancer,
+/// can use this.
+#[must_use]
+pub fn unroutable_cid(len: usize) -> ConnectionId {
+    let mut cid = ConnectionId::generate(len);
+    if len > 0 {
+        let mut bytes: SmallVec<[u8; ConnectionId::MAX_LEN]> = SmallVec::from_slice(&cid);
+        bytes[0] = 0xe0 | (random::<1>()[0] & 0x1f);
+        cid = ConnectionId::from(bytes);
+    }
+    cid
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use neqo_common::Encoder;
+    use test_fixture::fixture_init;
+
+    use super::{Config, Decoder, Generator, unroutable_cid};
+    use crate::cid::ConnectionIdGenerator as _;
+
+    const SERVER_ID: &[u8] = &[0x31, 0x44, 0x1a, 0x9c, 0x69, 0xc2, 0x75];
+    const KEY: [u8; Config::KEY_LEN] = [
+        0xfd, 0xf7, 0x26, 0xa9, 0x89, 0x3e, 0xc0, 0x5c, 0x06, 0x32, 0xd3, 0x95, 0x66, 0x80, 0xba,
+        0xf0,
+    ];
+
+    fn round_trip(config: &Config) {
+        fixture_init();
+        let server_id = &SERVER_ID[..config.server_id_len];
+        let mut generator = Generator::new(config.clone(), server_id).unwrap();
+        let mut decoder = Decoder::default();
+        decoder.add_config(config.clone());
+
+        let cid1 = generator.generate_cid().unwrap();
+        let cid2 = generator.generate_cid().unwrap();
+        assert_eq!(cid1.len(), config.cid_len());
+        assert_ne!(cid1, cid2);
+        assert_eq!(cid1[0] >> 5, config.id());
+        assert_eq!(usize::from(cid1[0] & 0x1f), config.cid_len() - 1);
+        assert_eq!(decoder.server_id(&cid1).unwrap(), server_id);
+        assert_eq!(decoder.server_id(&cid2).unwrap(), server_id);
+    }
+
+    #[test]
+    fn plaintext() {
+        let config = Config::new(0, 3, 4, None).unwrap();
+        round_trip(&config);
+
+        let mut generator = Generator::new(config, &SERVER_ID[..3]).unwrap();
+        let cid = generator.generate_cid().unwrap();
+        assert_eq!(&cid[1..4], &SERVER_ID[..3]);
+    }
+
+    #[test]
+    fn four_pass_even() {
+        let config = Config::new(1, 4, 6, Some(KEY)).unwrap();
+        round_trip(&config);
+
+        let mut generator = Generator::new(config, &SERVER_ID[..4]).unwrap();
+        let cid = generator.generate_cid().unwrap();
+        assert_ne!(&cid[1..5], &SERVER_ID[..4]);
+    }
+
+    #[test]
+    fn four_pass_odd() {
+        round_trip(&Config::new(2, 3, 4, Some(KEY)).unwrap());
+        round_trip(&Config::new(3, 7, 12, Some(KEY)).unwrap());
+    }
+
+    #[test]
+    fn single_pass() {
+        let config = Config::new(4, 7, 9, Some(KEY)).unwrap();
+        round_trip(&config);
+
+        let mut generator = Generator::new(config, SERVER_ID).unwrap();
+        let cid = generator.generate_cid().unwrap();
+        assert_eq!(cid.len(), 17);
+        assert_ne!(&cid[1..8], SERVER_ID);
+    }
+
+    /// Generate a connection ID with a chosen nonce.
+    fn generate_with_nonce(config: &Config, server_id: &[u8], nonce: &[u8]) -> Vec<u8> {
+        let mut generator = Generator::new(config.clone(), server_id).unwrap();
+        let (prefix, counter) = nonce.split_at(generator.nonce_prefix.len());
+        generator.nonce_prefix = prefix.to_vec();
+        let mut counter_bytes = [0; size_of::<u64>()];
+        counter_bytes[size_of::<u64>() - counter.len()..].copy_from_slice(counter);
+        generator.counter = u64::from_be_bytes(counter_bytes);
+        generator.generate_cid().unwrap().to_vec()
+    }
+
+    /// Known answers for each mode.  These were checked against a separate
+    /// implementation of the draft that uses the AES implementation from OpenSSL.
+    #[test]
+    fn known_answers() {
+        fixture_init();
+        for (config, server_id, nonce, cid) in [
+            (
+                Config::new(0, 3, 4, None).unwrap(),
+                "31441a",
+                "9c69c275",
+                "0731441a9c69c275",
+            ),
+            (
+                Config::new(4, 7, 9, Some(KEY)).unwrap(),
+                "31441a9c69c275",
+                "0e1f2a3b4c5d6e7f80",
+                "9061855eb35fab861874356b336296d719",
+            ),
+            (
+                Config::new(1, 4, 6, Some(KEY)).unwrap(),
+                "31441a9c",
+                "69c27501a2b3",
+                "2a4f7e9204bb66701924fe",
+            ),
+            (
+                Config::new(2, 3, 4, Some(KEY)).unwrap(),
+                "31441a",
+                "9c69c275",
+                "47419a8565ec9ca3",
+            ),
+            (
+                Config::new(3, 7, 12, Some(KEY)).unwrap(),
+                "31441a9c69c275",
+                "0102030405060708090a0b0c",
+                "7393815ca0f5ec2bb54cae159f4d36d69d5c4991",
+            ),
+        ] {
+            let server_id = Encoder::from_hex(server_id);
+            let cid = Encoder::from_hex(cid);
+            let mut decoder = Decoder::default();
+            decoder.add_config(config.clone());
+            assert_eq!(decoder.server_id(cid.as_ref()).unwrap(), server_id.as_ref());
+            if config.key.is_some() {
+                let nonce = Encoder::from_hex(nonce);
+                assert_eq!(
+                    generate_with_nonce(&config, server_id.as_ref(), nonce.as_ref()),
+                    cid.as_ref()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn nonce_exhausted() {
+        fixture_init();
+        let config = Config::new(0, 1, 4, Some(KEY)).unwrap();
+        let mut generator = Generator::new(config, &[1]).unwrap();
+        generator.counter = u64::from(u32::MAX);
+        assert!(generator.generate_cid().is_some());
+        assert!(generator.generate_cid().is_none());
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(Config::new(7, 3, 4, None).is_err());
+        assert!(Config::new(0, 0, 4, None).is_err());
+        assert!(Config::new(0, 16, 4, None).is_err());
+        assert!(Config::new(0, 3, 3, None).is_err());
+        assert!(Config::new(0, 3, 19, None).is_err());
+        assert!(Config::new(0, 15, 5, None).is_err());
+        assert!(Generator::new(Config::new(0, 3, 4, None).unwrap(), &[1, 2]).is_err());
+    }
+
+    #[test]
+    fn unknown_config() {
+        fixture_init();
+        let mut generator =
+            Generator::new(Config::new(0, 3, 4, None).unwrap(), &[1, 2, 3]).unwrap();
+        let cid = generator.generate_cid().unwrap();
+
+        let mut decoder = Decoder::default();
+        decoder.add_config(Config::new(1, 3, 4, None).unwrap());
+        assert!(decoder.server_id(&cid).is_none());
+        assert!(decoder.server_id(&unroutable_cid(8)).is_none());
+
+        decoder.add_config(Config::new(0, 3, 4, None).unwrap());
+        assert_eq!(decoder.server_id(&cid).unwrap(), &[1, 2, 3]);
+        decoder.remove_config(0);
+        assert!(decoder.server_id(&cid).is_none());
+    }
+
+    #[test]
+    fn from_packet() {
+        fixture_init();
+        let config = Config::new(5, 2, 8, Some(KEY)).unwrap();
+        let mut generator = Generator::new(config.clone(), &[0xab, 0xcd]).unwrap();
+        let mut decoder = Decoder::default();
+        decoder.add_config(config);
+        let cid = generator.generate_cid().unwrap();
+
+        let mut short = Encoder::default();
+        short.encode_byte(0x41).encode(&cid).encode(&[0; 20]);
+        assert_eq!(
+            decoder.server_id_from_packet(short.as_ref()).unwrap(),
+            &[0xab, 0xcd]
+        );
+
+        let mut long = Encoder::default();
+        long.encode_byte(0xc0)
+            .encode_uint(4, 1u32)
+            .encode_vec(1, &cid)
+            .encode_vec(1, &[]);
+        assert_eq!(
+            decoder.server_id_from_packet(long.as_ref()).unwrap(),
+            &[0xab, 0xcd]
+        );
+    }
+}
@@ -6,6 +6,7 @@
 
 // Tracking of sent packets and detecting their loss.
 
+mod reorder;
 pub mod sent;
 mod token;
 
@@ -18,7 +19,7 @@ use std::{
 
 use enum_map::EnumMap;
 use enumset::enum_set;
-use neqo_common::{qdebug, qinfo, qlog::Qlog, qtrace, qwarn};
+use neqo_common::{qdebug, qinfo, qlog::Qlog, qtrace, qwarn, to_u64};
 use strum::IntoEnumIterator as _;
 pub use token::{StreamRecoveryToken, Token, Tokens};
 
@@ -31,6 +32,7 @@ use crate::{
     tracking::{PacketNumberSpace, PacketNumberSpaceSet},
 };
 
+/// The initial packet reordering threshold for loss detection, from RFC 9002.
 pub const PACKET_THRESHOLD: u64 = 3;
 /// `ACK_ONLY_SIZE_LIMIT` is the minimum size of the congestion window.
 /// If the congestion window is this small, we will only send ACK frames.
@@ -308,11 +310,14 @@ impl LossRecoverySpace {
 
     /// Detect lost packets.
     /// `loss_delay` is the time we will wait before declaring something lost.
+    /// `packet_threshold` is how many packets can be acknowledged after
+    /// a packet before it is declared lost.
     /// `cleanup_delay` is the time we will wait before cleaning up a lost packet.
     pub fn detect_lost_packets(
         &mut self,
         now: Instant,
         loss_delay: Duration,
+        packet_threshold: u64,
         cleanup_delay: Duration,
         lost_packets: &mut Vec<sent::Packet>,
     ) {
@@ -340,9 +345,9 @@ impl LossRecoverySpace {
                     packet.time_sent()
                 );
                 sent::LossTrigger::TimeThreshold
-            } else if largest_acked >= Some(packet.pn() + PACKET_THRESHOLD) {
+            } else if largest_acked >= Some(packet.pn() + packet_threshold) {
                 qtrace!(
-                    "lost={}, is >= {PACKET_THRESHOLD} from largest acked {largest_acked:?}",
+                    "lost={}, is >= {packet_threshold} from largest acked {largest_acked:?}",
                     packet.pn()
                 );
                 sent::LossTrigger::ReorderingThreshold
@@ -490,11 +495,19 @@ pub struct Loss {
     fast_pto: u8,
     /// Snapshotted before input processing; see [`Self::note_timeout_type`].
     pending_timer_type: Option<qlog::LossTimerType>,
+    /// The packet and time thresholds for declaring packets lost.
+    thresholds: reorder::Thresholds,
 }
 
 impl Loss {
     #[must_use]
     pub fn new(stats: StatsCell, fast_pto: u8) -> Self {
+        let thresholds = reorder::Thresholds::default();
+        {
+            let mut s = stats.borrow_mut();
+            s.loss_packet_threshold = thresholds.packet();
+            s.loss_time_threshold = thresholds.time();
+        }
         Self {
             confirmed_time: None,
             pto_state: None,
@@ -503,6 +516,43 @@ impl Loss {
             stats,
             fast_pto,
             pending_timer_type: None,
+            thresholds,
+        }
+    }
+
+    /// The current packet reordering threshold.
+    #[must_use]
+    pub const fn packet_threshold(&self) -> u64 {
+        self.thresholds.packet()
+    }
+
+    /// Adapt the loss detection thresholds to reordering.  If any of `acked` was
+    /// previously declared lost, that loss was spurious, so raise the thresholds
+    /// to tolerate at least that much reordering; otherwise, let them decay.
+    fn adapt_thresholds(
+        &mut self,
+        acked: &[sent::Packet],
+        largest_acked: packet::Number,
+        rtt: &RttEstimate,
+        now: Instant,
+    ) {
+        let spurious = acked
+            .iter()
+            .filter(|p| p.lost())
+            .map(sent::Packet::pn)
+            .min();
+        let changed = if let Some(pn) = spurious {
+            self.thresholds
+                .on_spurious_loss(largest_acked - pn, rtt.estimate(), now)
+        } else {
+            self.thresholds.maybe_decay(rtt.estimate(), now)
+        };
+        if changed {
+            let (packet, time) = (self.thresholds.packet(), self.thresholds.time());
+            let mut stats = self.stats.borrow_mut();
+            stats.loss_packet_threshold = packet;
+            stats.loss_time_threshold = time;
+            qlog::loss_thresholds_updated(&mut self.qlog, packet, time, now);
         }
     }
 
@@ -535,6 +585,7 @@ impl Loss {
         for p in &mut dropped {
             path.discard_packet(p, now, &mut self.stats.borrow_mut());
         }
+        self.packets_in_flight_updated(now);
         dropped
     }
 
@@ -547,6 +598,7 @@ impl Loss {
         if let Some(space) = self.spaces.get_mut(pn_space) {
             path.borrow_mut().packet_sent(&mut sent_packet, now);
             space.on_packet_sent(sent_packet);
+            self.packets_in_flight_updated(now);
         } else {
             qinfo!(
                 "[{self}] ignoring packet {} from dropped space {pn_space}",
@@ -632,6 +684,7 @@ impl Loss {
         acked_ranges: R,
         ack_ecn: Option<&ecn::Count>,
         ack_delay: Duration,
+        receive_times: &[(packet::Number, Duration)],
         now: Instant,
     ) -> (Vec<sent::Packet>, Vec<sent::Packet>)
     where
@@ -642,8 +695,14 @@ impl Loss {
             return (Vec::new(), Vec::new());
         };
 
-        let (acked_packets, any_ack_eliciting) =
+        let (mut acked_packets, any_ack_eliciting) =
             space.remove_acked(acked_ranges, &mut self.stats.borrow_mut());
+        // Acknowledged packets are in descending order of packet number.
+        for &(pn, t) in receive_times {
+            if let Ok(i) = acked_packets.binary_search_by(|p| pn.cmp(&p.pn())) {
+                acked_packets[i].set_time_received(t);
+            }
+        }
         let Some(largest_acked_pkt) = acked_packets.first() else {
             // No new information.
             return (Vec::new(), Vec::new());
@@ -651,6 +710,7 @@ impl Loss {
 
         // Track largest PN acked per space
         let prev_largest_acked = space.largest_acked_sent_time;
+        let largest_acked = max(space.largest_acked.unwrap_or(0), largest_acked_pkt.pn());
         if Some(largest_acked_pkt.pn()) > space.largest_acked {
             space.largest_acked = Some(largest_acked_pkt.pn());
 
@@ -671,6 +731,12 @@ impl Loss {
             "[{self}] ACK for {pn_space:?} - largest_acked={}",
             largest_acked_pkt.pn()
         );
+        self.adapt_thresholds(
+            &acked_packets,
+            largest_acked,
+            primary_path.borrow().rtt(),
+            now,
+        );
 
         // Perform loss detection.
         // PTO is used to remove lost packets from in-flight accounting.
@@ -681,9 +747,15 @@ impl Loss {
         let Some(sp) = self.spaces.get_mut(pn_space) else {
             return (Vec::new(), Vec::new());
         };
-        let loss_delay = primary_path.borrow().rtt().loss_delay();
+        let loss_delay = self.thresholds.loss_delay(primary_path.borrow().rtt());
         let mut lost = Vec::new();
-        sp.detect_lost_packets(now, loss_delay, cleanup_delay, &mut lost);
+        sp.detect_lost_packets(
+            now,
+            loss_delay,
+            self.thresholds.packet(),
+            cleanup_delay,
+            &mut lost,
+        );
         self.count_lost(&lost);
 
         // Tell the congestion controller about any lost packets.
@@ -711,10 +783,25 @@ impl Loss {
             qlog::loss_timer_cancelled(&mut self.qlog, now);
         }
         self.pto_state = None;
+        self.packets_in_flight_updated(now);
 
         (acked_packets, lost)
     }
 
+    /// Log the number of packets in flight, across all packet number spaces.
+    fn packets_in_flight_updated(&mut self, now: Instant) {
+        let packets = self
+            .spaces
+            .iter()
+            .map(|sp| to_u64(sp.in_flight_outstanding))
+            .sum();
+        qlog::metrics_updated(
+            &mut self.qlog,
+            [qlog::Metric::PacketsInFlight(packets)],
+            now,
+        );
+    }
+
     /// When receiving a retry, get all the sent packets so that they can be flushed.
     /// We also need to pretend that they never happened for the purposes of congestion control.
     pub fn retry(&mut self, primary_path: &PathRef, now: Instant) -> Vec<sent::Packet> {
@@ -731,6 +818,7 @@ impl Loss {
         for p in &mut dropped {
             path.discard_packet(p, now, &mut self.stats.borrow_mut());
         }
+        self.packets_in_flight_updated(now);
         dropped
     }
 
@@ -764,6 +852,7 @@ impl Loss {
         for p in self.spaces.drop_space(space) {
             path.discard_packet(&p, now, &mut self.stats.borrow_mut());
         }
+        self.packets_in_flight_updated(now);
 
         // We just made progress, so discard PTO count.
         // The spec says that clients should not do this until confirming that
@@ -823,7 +912,7 @@ impl Loss {
             .iter()
             .filter_map(LossRecoverySpace::loss_recovery_timer_start)
             .min()
-            .map(|val| val + rtt.loss_delay())
+            .map(|val| val + self.thresholds.loss_delay(rtt))
     }
 
     /// Simple wrapper for the PTO calculation that avoids borrow check rules.
@@ -983,7 +1072,8 @@ impl Loss {
             qlog::loss_timer_expired(&mut self.qlog, timer_type, now);
         }
 
-        let loss_delay = primary_path.borrow().rtt().loss_delay();
+        let loss_delay = self.thresholds.loss_delay(primary_path.borrow().rtt());
+        let packet_threshold = self.thresholds.packet();
         let confirmed = self.confirmed();
 
         let mut lost_packets = Vec::new();
@@ -995,7 +1085,7 @@ impl Loss {
                 confirmed,
                 self.fast_pto,
             );
-            space.detect_lost_packets(now, loss_delay, pto, &mut lost_packets);
+            space.detect_lost_packets(now, loss_delay, packet_threshold, pto, &mut lost_packets);
 
             primary_path.borrow_mut().on_packets_lost(
                 space.largest_acked_sent_time,
@@ -1008,6 +1098,7 @@ impl Loss {
         self.count_lost(&lost_packets);
 
         self.maybe_fire_pto(primary_path, now, &mut lost_packets, has_handshake_keys);
+        self.packets_in_flight_updated(now);
         lost_packets
     }
 
@@ -1074,7 +1165,7 @@ mod tests {
 
     use super::{
         ACK_ONLY_SIZE_LIMIT, FAST_PTO_SCALE, LossRecoverySpace, MIN_OUTSTANDING_UNACK,
-        PacketNumberSpace, PtoState, SendProfile,
+        PACKET_THRESHOLD, PacketNumberSpace, PtoState, SendProfile,
     };
     use crate::{
         ConnectionParameters, Token as Srt,
@@ -1112,8 +1203,15 @@ mod tests {
             ack_delay: Duration,
             now: Instant,
         ) -> (Vec<sent::Packet>, Vec<sent::Packet>) {
-            self.lr
-                .on_ack_received(&self.path, pn_space, acked_ranges, ack_ecn, ack_delay, now)
+            self.lr.on_ack_received(
+                &self.path,
+                pn_space,
+                acked_ranges,
+                ack_ecn,
+                ack_delay,
+                &[],
+                now,
+            )
         }
 
         pub fn on_packet_sent(&mut self, sent_packet: sent::Packet, now: Instant) {
@@ -1394,6 +1492,28 @@ mod tests {
         check(&lr);
     }
 
+    // Receive times are attached to the packets that they are for.
+    #[test]
+    fn receive_times() {
+        let mut lr = Fixture::default();
+        pace(&mut lr, 4);
+        let (acked, _) = lr.lr.on_ack_received(
+            &lr.path,
+            PacketNumberSpace::ApplicationData,
+            vec![0..=3],
+            None,
+            ACK_DELAY,
+            &[(3, ms(40)), (2, ms(33)), (0, ms(19)), (7, ms(99))],
+            pn_time(3) + TEST_RTT,
+        );
+        match_acked(&acked, &[3, 2, 1, 0]);
+        let times = acked
+            .iter()
+            .map(sent::Packet::time_received)
+            .collect::<Vec<_>>();
+        assert_eq!(times, [Some(ms(40)), Some(ms(33)), None, Some(ms(19))]);
+    }
+
     // Test time loss detection as part of handling a regular ACK.
     #[test]
     fn time_loss_detection_gap() {
@@ -1487,6 +1607,58 @@ mod tests {
         assert_eq!(lost.len(), 1);
     }
 
+    /// A packet that is acknowledged after being declared lost raises the loss
+    /// detection thresholds, which then decay after a period without spurious loss.
+    #[test]
+    fn spurious_loss_raises_thresholds() {
+        let (log, contents) = test_fixture::new_neqo_qlog();
+        let mut lr = setup_lr(8); // This sends packets 0-7 and acknowledges pn 0.
+        lr.lr.set_qlog(log);
+
+        // Acknowledge 6-7, which will cause pn 1-4 to be marked as lost.
+        let (_, lost) = lr.on_ack_received(
+            PacketNumberSpace::ApplicationData,
+            vec![6..=7],
+            None,
+            ACK_DELAY,
+            pn_time(7),
+        );
+        assert_eq!(lost.len(), 4);
+        assert_eq!(lr.packet_threshold(), PACKET_THRESHOLD);
+
+        // pn 1 was only reordered, by 6 packets.
+        lr.on_ack_received(
+            PacketNumberSpace::ApplicationData,
+            vec![1..=1],
+            None,
+            ACK_DELAY,
+            pn_time(8),
+        );
+        assert_eq!(lr.packet_threshold(), 7);
+        {
+            let stats = lr.stats.borrow();
+            assert_eq!(stats.late_ack, 1);
+            assert_eq!(stats.loss_packet_threshold, 7);
+            assert!(stats.loss_time_threshold > 9.0 / 8.0);
+        }
+        let log = contents.to_string();
+        assert!(
+            log.contains(r#""reordering_threshold":7"#),
+            "Expected recovery_parameters_set event in qlog: {log}"
+        );
+
+        // Without more spurious losses, the thresholds decay.
+        lr.on_ack_received(
+            PacketNumberSpace::ApplicationData,
+            vec![5..=5],
+            None,
+            ACK_DELAY,
+            pn_time(8) + TEST_RTT * 16,
+        );
+        assert_eq!(lr.packet_threshold(), 5);
+        assert_eq!(lr.stats.borrow().loss_packet_threshold, 5);
+    }
+
     #[test]
     #[should_panic(expected = "discarding application space")]
     fn drop_app() {
@@ -1573,6 +1745,7 @@ mod tests {
             lr.spaces.get_mut(pn_space).unwrap().detect_lost_packets(
                 pn_time(3),
                 TEST_RTT,
+                PACKET_THRESHOLD,
                 TEST_RTT * 3, // unused
                 &mut lost,
             );
@@ -2192,4 +2365,25 @@ mod tests {
             "Expected timer_type ack from snapshot, got: {log}"
         );
     }
+
+    #[test]
+    fn qlog_packets_in_flight() {
+        let (log, contents) = test_fixture::new_neqo_qlog();
+        let mut lr = Fixture::default();
+        lr.lr.set_qlog(log);
+
+        pace(&mut lr, 3);
+        ack(&mut lr, 0, TEST_RTT);
+        drop(lr);
+
+        let log = contents.to_string();
+        assert!(
+            log.contains(r#""packets_in_flight":3"#),
+            "Expected 3 packets in flight in qlog: {log}"
+        );
+        assert!(
+            log.contains(r#""packets_in_flight":2"#),
+            "Expected 2 packets in flight after the ACK in qlog: {log}"
+        );
+    }
 }
@@ -0,0 +1,213 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Adaptive loss detection thresholds, in the style of RACK (RFC 8985).
+
+use std::{
+    cmp::{max, min},
+    time::{Duration, Instant},
+};
+
+use neqo_common::qdebug;
+
+use super::PACKET_THRESHOLD;
+use crate::rtt::{GRANULARITY, RttEstimate};
+
+/// The largest packet reordering threshold that we will use.
+pub const MAX_PACKET_THRESHOLD: u64 = 32;
+/// The time threshold is kept in units of `1 / TIME_THRESHOLD_SCALE` round trips.
+const TIME_THRESHOLD_SCALE: u32 = 8;
+/// The initial time threshold of 9/8 round trips (RFC 9002, Section 6.1.2).
+const TIME_THRESHOLD: u32 = 9;
+/// The largest time threshold, two round trips.
+/// RACK limits its reordering window to one smoothed RTT on top of the RTT.
+const MAX_TIME_THRESHOLD: u32 = 2 * TIME_THRESHOLD_SCALE;
+/// How much the time threshold grows for each spurious loss, a quarter of a round trip.
+const TIME_THRESHOLD_STEP: u32 = TIME_THRESHOLD_SCALE / 4;
+/// How many round trips without a spurious loss before the thresholds decay.
+const DECAY_PERIOD_RTTS: u32 = 16;
+
+/// The packet and time thresholds that are used to declare packets lost.
+///
+/// These start at the values in RFC 9002.  Each time that a packet is acknowledged
+/// after being declared lost, the thresholds are raised so that the same amount of
+/// reordering does not cause another spurious loss.  After a period without spurious
+/// losses, the thresholds decay back toward their initial values.
+#[derive(Debug)]
+pub struct Thresholds {
+    /// The number of packets by which a packet can be reordered.
+    packet: u64,
+    /// The time, in units of `1 / TIME_THRESHOLD_SCALE` round trips, by which
+    /// a packet can be reordered.
+    time: u32,
+    /// When the time threshold was last raised, if it has been.
+    raised: Option<Instant>,
+    /// When the thresholds last changed.
+    changed: Option<Instant>,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            packet: PACKET_THRESHOLD,
+            time: TIME_THRESHOLD,
+            raised: None,
+            changed: None,
+        }
+    }
+}
+
+impl Thresholds {
+    /// The current packet reordering threshold.
+    #[must_use]
+    pub const fn packet(&self) -> u64 {
+        self.packet
+    }
+
+    /// The current time threshold, as a multiple of the round-trip time.
+    #[must_use]
+    pub fn time(&self) -> f64 {
+        f64::from(self.time) / f64::from(TIME_THRESHOLD_SCALE)
+    }
+
+    /// Calculate the loss delay based on the current estimate and the last
+    /// RTT measurement received.
+    #[must_use]
+    pub fn loss_delay(&self, rtt: &RttEstimate) -> Duration {
+        // loss_delay = time_threshold * max(latest_rtt, smoothed_rtt)
+        // loss_delay = max(loss_delay, kGranularity)
+        let rtt = max(rtt.latest_rtt(), rtt.estimate());
+        max(rtt * self.time / TIME_THRESHOLD_SCALE, GRANULARITY)
+    }
+
+    /// Note that a packet was acknowledged after being declared lost.
+    /// `distance` is the number of packets between that packet and the
+    /// largest acknowledged packet.
+    /// The time threshold is raised at most once per round trip, so that a single
+    /// reordering event that affects many packets is only counted once.
+    /// Returns `true` if either threshold changed.
+    pub fn on_spurious_loss(&mut self, distance: u64, rtt: Duration, now: Instant) -> bool {
+        let packet = min(max(self.packet, distance + 1), MAX_PACKET_THRESHOLD);
+        let time = if self.raised.is_none_or(|t| t + rtt <= now) {
+            min(self.time + TIME_THRESHOLD_STEP, MAX_TIME_THRESHOLD)
+        } else {
+            self.time
+        };
+        if time != self.time {
+            self.raised = Some(now);
+        }
+        self.update(packet, time, now)
+    }
+
+    /// Let the thresholds decay after `DECAY_PERIOD_RTTS` round trips without
+    /// a spurious loss.  Each period halves the distance to the initial values.
+    /// Returns `true` if either threshold changed.
+    pub fn maybe_decay(&mut self, rtt: Duration, now: Instant) -> bool {
+        let Some(changed) = self.changed else {
+            return false;
+        };
+        if changed + rtt * DECAY_PERIOD_RTTS > now {
+            return false;
+        }
+        let packet = PACKET_THRESHOLD + (self.packet - PACKET_THRESHOLD) / 2;
+        let time = TIME_THRESHOLD + (self.time - TIME_THRESHOLD) / 2;
+        self.update(packet, time, now)
+    }
+
+    fn update(&mut self, packet: u64, time: u32, now: Instant) -> bool {
+        if packet == self.packet && time == self.time {
+            return false;
+        }
+        qdebug!(
+            "Loss thresholds: packet {}->{packet}, time {}->{time} (/{TIME_THRESHOLD_SCALE} RTT)",
+            self.packet,
+            self.time,
+        );
+        self.packet = packet;
+        self.time = time;
+        let initial = packet == PACKET_THRESHOLD && time == TIME_THRESHOLD;
+        self.changed = (!initial).then_some(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use test_fixture::now;
+
+    use super::{MAX_PACKET_THRESHOLD, Thresholds};
+    use crate::{recovery::PACKET_THRESHOLD, rtt::RttEstimate};
+
+    const RTT: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn initial() {
+        let t = Thresholds::default();
+        assert_eq!(t.packet(), PACKET_THRESHOLD);
+        assert!((t.time() - 9.0 / 8.0).abs() < f64::EPSILON);
+        assert_eq!(t.loss_delay(&RttEstimate::new(RTT)), RTT * 9 / 8);
+    }
+
+    #[test]
+    fn raise() {
+        let mut t = Thresholds::default();
+        let now = now();
+        assert!(t.on_spurious_loss(6, RTT, now));
+        assert_eq!(t.packet(), 7);
+        assert!((t.time() - 11.0 / 8.0).abs() < f64::EPSILON);
+
+        // A closer packet doesn't lower the packet threshold, and the time
+        // threshold is only raised once per round trip.
+        assert!(!t.on_spurious_loss(2, RTT, now + RTT / 2));
+        assert_eq!(t.packet(), 7);
+        assert!((t.time() - 11.0 / 8.0).abs() < f64::EPSILON);
+
+        assert!(t.on_spurious_loss(2, RTT, now + RTT));
+        assert!((t.time() - 13.0 / 8.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn limits() {
+        let mut t = Thresholds::default();
+        let mut now = now();
+        for _ in 0..10 {
+            t.on_spurious_loss(1000, RTT, now);
+            now += RTT;
+        }
+        assert_eq!(t.packet(), MAX_PACKET_THRESHOLD);
+        assert!((t.time() - 2.0).abs() < f64::EPSILON);
+        assert!(!t.on_spurious_loss(1000, RTT, now));
+    }
+
+    #[test]
+    fn decay() {
+        let mut t = Thresholds::default();
+        let mut now = now();
+        t.on_spurious_loss(10, RTT, now);
+        t.on_spurious_loss(10, RTT, now + RTT);
+        now += RTT;
+        assert_eq!(t.packet(), 11);
+        assert!((t.time() - 13.0 / 8.0).abs() < f64::EPSILON);
+
+        assert!(!t.maybe_decay(RTT, now + RTT * 15));
+        now += RTT * 16;
+        assert!(t.maybe_decay(RTT, now));
+        assert_eq!(t.packet(), 7);
+        assert!((t.time() - 11.0 / 8.0).abs() < f64::EPSILON);
+
+        // The next decay waits for another period.
+        assert!(!t.maybe_decay(RTT, now + RTT));
+        for _ in 0..3 {
+            now += RTT * 16;
+            assert!(t.maybe_decay(RTT, now));
+        }
+        assert_eq!(t.packet(), PACKET_THRESHOLD);
+        assert!((t.time() - 9.0 / 8.0).abs() < f64::EPSILON);
+        assert!(!t.maybe_decay(RTT, now + RTT * 1000));
+    }
+}
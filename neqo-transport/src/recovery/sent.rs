@@ -28,12 +28,17 @@ pub struct LossInfo {
     pub trigger: LossTrigger,
 }
 
+/// A packet that has been sent, as presented to a congestion controller.
+///
+/// The accessors that describe the packet (its number, type, size, send and receive
+/// times, and whether it counts towards bytes in flight) are stable.
 #[derive(Debug, Clone)]
 pub struct Packet {
     pt: packet::Type,
     pn: packet::Number,
     ack_eliciting: bool,
     time_sent: Instant,
+    time_received: Option<Duration>,
     primary_path: bool,
     tokens: recovery::Tokens,
 
@@ -58,6 +63,7 @@ impl Packet {
             pt,
             pn,
             time_sent,
+            time_received: None,
             ack_eliciting,
             primary_path: true,
             tokens,
@@ -79,12 +85,19 @@ impl Packet {
         self.pn
     }
 
-    /// The ECN mark of the packet.
+    /// Whether the packet was sent with an ECT mark.
     #[must_use]
-    pub fn ecn_marked_ect0(&self) -> bool {
+    pub fn ecn_marked(&self) -> bool {
         self.tokens
             .iter()
-            .any(|t| matches!(t, recovery::Token::EcnEct0))
+            .any(|t| matches!(t, recovery::Token::EcnMarked))
+    }
+
+    /// Whether the packet was sent with an ECT mark.
+    #[must_use]
+    #[deprecated(note = "packets can also be marked ECT(1), use `ecn_marked`")]
+    pub fn ecn_marked_ect0(&self) -> bool {
+        self.ecn_marked()
     }
 
     /// Returns `true` if this packet is a PMTUD probe.
@@ -101,6 +114,19 @@ impl Packet {
         self.time_sent
     }
 
+    /// The time that the peer received this packet, if it reported that with
+    /// ACK receive timestamps.  This is measured from a time that the peer chooses,
+    /// so it can't be compared with `time_sent` directly, but changes in the
+    /// difference between the two show changes in one-way delay.
+    #[must_use]
+    pub const fn time_received(&self) -> Option<Duration> {
+        self.time_received
+    }
+
+    pub(crate) const fn set_time_received(&mut self, t: Duration) {
+        self.time_received = Some(t);
+    }
+
     /// Returns `true` if the packet will elicit an ACK.
     #[must_use]
     pub const fn ack_eliciting(&self) -> bool {
@@ -126,18 +152,18 @@ impl Packet {
 
     /// Access the recovery tokens that this holds.
     #[must_use]
-    pub const fn tokens(&self) -> &recovery::Tokens {
+    pub(crate) const fn tokens(&self) -> &recovery::Tokens {
         &self.tokens
     }
 
     /// Clears the flag that had this packet on the primary path.
     /// Used when migrating to clear out state.
-    pub const fn clear_primary_path(&mut self) {
+    pub(crate) const fn clear_primary_path(&mut self) {
         self.primary_path = false;
     }
 
     /// For Initial packets, it is possible that the packet builder needs to amend the length.
-    pub fn track_padding(&mut self, padding: usize) {
+    pub(crate) fn track_padding(&mut self, padding: usize) {
         debug_assert_eq!(self.pt, packet::Type::Initial);
         self.len += padding;
     }
@@ -179,27 +205,27 @@ impl Packet {
     /// Ask whether this tracked packet has been declared lost for long enough
     /// that it can be expired and no longer tracked.
     #[must_use]
-    pub fn expired(&self, now: Instant, expiration_period: Duration) -> bool {
+    pub(crate) fn expired(&self, now: Instant, expiration_period: Duration) -> bool {
         self.loss_info
             .is_some_and(|info| (info.time + expiration_period) <= now)
     }
 
     /// Whether the packet contents were cleared out after a PTO.
     #[must_use]
-    pub const fn pto_fired(&self) -> bool {
+    pub(crate) const fn pto_fired(&self) -> bool {
         self.pto
     }
 
     /// Loss information recorded when this packet was declared lost.
     #[must_use]
-    pub const fn loss_info(&self) -> Option<LossInfo> {
+    pub(crate) const fn loss_info(&self) -> Option<LossInfo> {
         self.loss_info
     }
 
     /// On PTO, we need to get the recovery tokens so that we can ensure that
     /// the frames we sent can be sent again in the PTO packet(s).  Do that just once.
     #[must_use]
-    pub const fn pto(&mut self) -> bool {
+    pub(crate) const fn pto(&mut self) -> bool {
         if self.pto || self.lost() {
             false
         } else {
@@ -65,8 +65,8 @@ pub enum Token {
     RetireConnectionId(u64),
     AckFrequency(AckRate),
     Datagram(DatagramTracking),
-    /// A packet marked with [`neqo_common::Ecn::Ect0`].
-    EcnEct0,
+    /// A packet marked with [`neqo_common::Ecn::Ect0`] or [`neqo_common::Ecn::Ect1`].
+    EcnMarked,
     /// A PMTUD probe packet.
     PmtudProbe,
 }
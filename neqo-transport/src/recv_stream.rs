@@ -17,7 +17,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use neqo_common::{Buffer, Role, expect_usize, qtrace, qwarn, to_u64};
+use neqo_common::{Buffer, Role, expect_usize, qlog::Qlog, qtrace, qwarn, to_u64};
 use smallvec::SmallVec;
 use strum::Display;
 
@@ -26,7 +26,7 @@ use crate::{
     events::ConnectionEvents,
     fc::ReceiverFlowControl,
     frame::FrameType,
-    packet,
+    packet, qlog,
     recovery::{self, StreamRecoveryToken},
     send_stream::SendStreams,
     stats::FrameStats,
@@ -71,6 +71,15 @@ impl RecvStreams {
         self.streams.get_mut(&id).ok_or(Error::InvalidStreamId)
     }
 
+    #[allow(
+        clippy::allow_attributes,
+        clippy::missing_errors_doc,
+        reason = "OK here."
+    )]
+    pub fn get(&self, id: StreamId) -> Res<&RecvStream> {
+        self.streams.get(&id).ok_or(Error::InvalidStreamId)
+    }
+
     #[allow(
         clippy::allow_attributes,
         clippy::missing_errors_doc,
@@ -117,6 +126,19 @@ impl RecvStreams {
         Ok((n, fin))
     }
 
+    /// Consume data from the chunk returned by [`RecvStream::read_chunk`], noting when the
+    /// stream ends.
+    ///
+    /// # Errors
+    /// When the stream does not exist, has no more data, or `amount` is too large.
+    pub fn consume(&mut self, stream_id: StreamId, amount: usize) -> Res<bool> {
+        let s = self.get_mut(stream_id)?;
+        let fin = s.consume(amount)?;
+        let ended = s.is_ended();
+        self.set_ended(ended);
+        Ok(fin)
+    }
+
     /// Stop sending on a stream, noting when it ends.
     ///
     /// # Errors
@@ -553,6 +575,33 @@ impl RxStreamOrderer {
         copied
     }
 
+    /// The next contiguous chunk of data that the application can read, if any.
+    #[must_use]
+    pub fn peek(&self) -> Option<&[u8]> {
+        let (&start, data) = self.data_ranges.first_key_value()?;
+        // By construction, no entry is entirely before `self.retired`.
+        (start <= self.retired).then(|| &data[expect_usize(self.retired - start)..])
+    }
+
+    /// Mark the first `amount` bytes of the chunk returned by [`Self::peek`] as read.
+    ///
+    /// # Errors
+    /// `InvalidInput` if `amount` is more than the length of that chunk.
+    pub fn consume(&mut self, amount: usize) -> Res<()> {
+        let available = self.peek().map_or(0, <[u8]>::len);
+        if amount > available {
+            return Err(Error::InvalidInput);
+        }
+        self.retired += to_u64(amount);
+        if amount > 0 && amount == available {
+            self.data_ranges.pop_first();
+            if self.data_ranges.is_empty() {
+                self.end = self.retired; // All entries were consumed.
+            }
+        }
+        Ok(())
+    }
+
     /// Extend the given Vector with any available data.
     pub fn read_to_end(&mut self, buf: &mut Vec<u8>) -> usize {
         let orig_len = buf.len();
@@ -564,7 +613,7 @@ impl RxStreamOrderer {
 /// QUIC receiving states, based on -transport 3.2.
 #[derive(Debug, Display)]
 // Because a dead_code warning is easier than clippy::unused_self, see https://github.com/rust-lang/rust/issues/68408
-enum RecvStreamState {
+pub(crate) enum RecvStreamState {
     Recv {
         fc: ReceiverFlowControl<StreamId>,
         session_fc: Rc<RefCell<ReceiverFlowControl<()>>>,
@@ -728,6 +777,7 @@ pub struct RecvStream {
     state: RecvStreamState,
     conn_events: ConnectionEvents,
     keep_alive: Option<Rc<()>>,
+    qlog: Qlog,
 }
 
 impl RecvStream {
@@ -742,9 +792,15 @@ impl RecvStream {
             state: RecvStreamState::new(max_stream_data, stream_id, session_fc),
             conn_events,
             keep_alive: None,
+            qlog: Qlog::disabled(),
         }
     }
 
+    /// Set the qlog that state changes and data movement are logged to.
+    pub fn set_qlog(&mut self, qlog: Qlog) {
+        self.qlog = qlog;
+    }
+
     fn set_state(&mut self, new_state: RecvStreamState) {
         debug_assert_ne!(
             mem::discriminant(&self.state),
@@ -755,6 +811,7 @@ impl RecvStream {
             self.stream_id.as_u64(),
             self.state
         );
+        qlog::recv_stream_state_updated(&mut self.qlog, self.stream_id, &self.state, &new_state);
 
         match new_state {
             // Receiving all data, or receiving or requesting RESET_STREAM
@@ -1129,6 +1186,37 @@ impl RecvStream {
     /// # Errors
     /// `NoMoreData` if data and fin bit were previously read by the application.
     pub fn read(&mut self, buf: &mut [u8]) -> Res<(usize, bool)> {
+        self.retire(|recv_buf| Ok(recv_buf.read(buf)))
+    }
+
+    /// The next contiguous chunk of received data, which is empty if none is available.
+    /// Use [`Self::consume`] to mark some or all of it as read.
+    ///
+    /// # Errors
+    /// `NoMoreData` if data and fin bit were previously read by the application.
+    pub fn read_chunk(&self) -> Res<&[u8]> {
+        self.state
+            .recv_buf()
+            .map(|recv_buf| recv_buf.peek().unwrap_or_default())
+            .ok_or(Error::NoMoreData)
+    }
+
+    /// Mark the first `amount` bytes of the chunk returned by [`Self::read_chunk`] as read.
+    /// Returns `true` if this reaches the end of the stream.
+    ///
+    /// # Errors
+    /// `NoMoreData` if data and fin bit were previously read by the application.
+    /// `InvalidInput` if `amount` is more than the length of the chunk.
+    pub fn consume(&mut self, amount: usize) -> Res<bool> {
+        self.retire(|recv_buf| recv_buf.consume(amount).map(|()| amount))
+            .map(|(_, fin)| fin)
+    }
+
+    /// Retire data using `f`, which returns the number of bytes it took from `recv_buf`.
+    fn retire<F>(&mut self, f: F) -> Res<(usize, bool)>
+    where
+        F: FnOnce(&mut RxStreamOrderer) -> Res<usize>,
+    {
         let data_recvd_state = matches!(self.state, RecvStreamState::DataRecvd { .. });
         match &mut self.state {
             RecvStreamState::Recv {
@@ -1147,7 +1235,9 @@ impl RecvStream {
                 fc,
                 session_fc,
             } => {
-                let bytes_read = recv_buf.read(buf);
+                let offset = recv_buf.retired();
+                let bytes_read = f(recv_buf)?;
+                qlog::data_moved_up(&mut self.qlog, self.stream_id, offset, bytes_read);
                 Self::flow_control_retire_data(u64::try_from(bytes_read)?, fc, session_fc);
                 let fin_read = if data_recvd_state {
                     if recv_buf.buffered() == 0 {
@@ -1172,7 +1262,9 @@ impl RecvStream {
                 session_fc,
                 ..
             } => {
-                let bytes_read = recv_buf.read(buf);
+                let offset = recv_buf.retired();
+                let bytes_read = f(recv_buf)?;
+                qlog::data_moved_up(&mut self.qlog, self.stream_id, offset, bytes_read);
                 Self::flow_control_retire_data(u64::try_from(bytes_read)?, fc, session_fc);
                 // Once the whole reliable prefix has been read, surface the reset. A reliable
                 // reset never delivers a FIN, so `fin_read` is always `false`.
@@ -1900,6 +1992,69 @@ mod tests {
         assert!(!s.has_frames_to_write());
     }
 
+    #[test]
+    fn orderer_peek_consume() {
+        let mut rx_ord = RxStreamOrderer::new();
+        assert_eq!(rx_ord.peek(), None);
+        assert_eq!(rx_ord.consume(1), Err(Error::InvalidInput));
+
+        rx_ord.inbound_frame(0, &[1; 10]).unwrap();
+        rx_ord.inbound_frame(20, &[2; 10]).unwrap();
+        assert_eq!(rx_ord.peek(), Some(&[1; 10][..]));
+        assert_eq!(rx_ord.consume(11), Err(Error::InvalidInput));
+
+        rx_ord.consume(4).unwrap();
+        assert_eq!(rx_ord.retired(), 4);
+        assert_eq!(rx_ord.peek(), Some(&[1; 6][..]));
+
+        // Consuming the rest of a chunk doesn't reach past a gap.
+        rx_ord.consume(6).unwrap();
+        assert_eq!(rx_ord.peek(), None);
+        assert!(!rx_ord.data_ready());
+
+        rx_ord.inbound_frame(10, &[3; 10]).unwrap();
+        assert_eq!(rx_ord.peek(), Some(&[3; 10][..]));
+        rx_ord.consume(10).unwrap();
+        assert_eq!(rx_ord.peek(), Some(&[2; 10][..]));
+        rx_ord.consume(10).unwrap();
+        assert_eq!(rx_ord.peek(), None);
+        assert_eq!(rx_ord.retired(), 30);
+        assert_eq!(rx_ord.buffered(), 0);
+    }
+
+    #[test]
+    fn stream_read_chunk() {
+        let mut s = create_stream(1024 * to_u64(INITIAL_LOCAL_MAX_STREAM_DATA));
+        assert_eq!(s.read_chunk().unwrap(), &[]);
+
+        let big_buf = vec![0; INITIAL_LOCAL_MAX_STREAM_DATA];
+        s.inbound_stream_frame(false, 0, &big_buf).unwrap();
+        assert_eq!(s.read_chunk().unwrap().len(), INITIAL_LOCAL_MAX_STREAM_DATA);
+        assert_eq!(
+            s.consume(INITIAL_LOCAL_MAX_STREAM_DATA + 1),
+            Err(Error::InvalidInput)
+        );
+
+        // Flow control credit is only released once data is consumed.
+        assert!(!s.has_frames_to_write());
+        assert!(!s.consume(INITIAL_LOCAL_MAX_STREAM_DATA - 1).unwrap());
+        assert!(s.has_frames_to_write());
+        assert_eq!(s.read_chunk().unwrap(), &[0]);
+        assert_eq!(
+            s.stats().bytes_read(),
+            to_u64(INITIAL_LOCAL_MAX_STREAM_DATA - 1)
+        );
+        assert!(!s.consume(1).unwrap());
+        assert_eq!(s.read_chunk().unwrap(), &[]);
+
+        // Consuming nothing picks up the end of the stream.
+        s.inbound_stream_frame(true, to_u64(INITIAL_LOCAL_MAX_STREAM_DATA), &[])
+            .unwrap();
+        assert!(s.consume(0).unwrap());
+        assert_eq!(s.read_chunk(), Err(Error::NoMoreData));
+        assert_eq!(s.consume(0), Err(Error::NoMoreData));
+    }
+
     fn create_stream(session_fc: u64) -> RecvStream {
         let conn_events = ConnectionEvents::default();
         RecvStream::new(
@@ -36,6 +36,8 @@ pub enum RttSource {
     AckConfirmed,
 }
 
+/// An estimate of the round-trip time of a path, as described in
+/// <https://www.rfc-editor.org/rfc/rfc9002#section-5>.
 #[derive(Debug)]
 pub struct RttEstimate {
     first_sample_time: Option<Instant>,
@@ -48,6 +50,8 @@ pub struct RttEstimate {
 }
 
 impl RttEstimate {
+    /// Create an estimate that starts from `initial_rtt`, before any samples are taken.
+    #[must_use]
     pub fn new(initial_rtt: Duration) -> Self {
         Self {
             first_sample_time: None,
@@ -69,7 +73,7 @@ impl RttEstimate {
         self.rttvar = rtt / 2;
     }
 
-    pub fn set_initial(&mut self, rtt: Duration) {
+    pub(crate) fn set_initial(&mut self, rtt: Duration) {
         qtrace!("initial RTT={rtt:?}");
         if rtt >= GRANULARITY {
             // Ignore if the value is too small.
@@ -78,24 +82,26 @@ impl RttEstimate {
     }
 
     /// For a new path, prime the RTT based on the state of another path.
-    pub fn prime_rtt(&mut self, other: &Self) {
+    pub(crate) fn prime_rtt(&mut self, other: &Self) {
         self.set_initial(other.smoothed_rtt + other.rttvar);
         self.ack_delay = other.ack_delay.clone();
     }
 
-    pub const fn set_ack_delay(&mut self, ack_delay: PeerAckDelay) {
+    pub(crate) const fn set_ack_delay(&mut self, ack_delay: PeerAckDelay) {
         self.ack_delay = ack_delay;
     }
 
-    pub fn update_ack_delay(&mut self, cwnd: usize, mtu: usize) {
+    pub(crate) fn update_ack_delay(&mut self, cwnd: usize, mtu: usize) {
         self.ack_delay.update(cwnd, mtu, self.smoothed_rtt);
     }
 
+    /// Whether the estimate is only a guess, rather than based on an RTT sample.
+    #[must_use]
     pub fn is_guesstimate(&self) -> bool {
         self.best_source == RttSource::Guesstimate
     }
 
-    pub fn update(
+    pub(crate) fn update(
         &mut self,
         qlog: &mut Qlog,
         mut rtt_sample: Duration,
@@ -151,10 +157,14 @@ impl RttEstimate {
     }
 
     /// Get the estimated value.
+    #[must_use]
     pub const fn estimate(&self) -> Duration {
         self.smoothed_rtt
     }
 
+    /// The probe timeout period.  Once the handshake is confirmed,
+    /// this includes the peer's maximum ACK delay.
+    #[must_use]
     pub fn pto(&self, confirmed: bool) -> Duration {
         let mut t = self.estimate() + max(4 * self.rttvar, GRANULARITY);
         if confirmed {
@@ -163,28 +173,26 @@ impl RttEstimate {
         t
     }
 
-    /// Calculate the loss delay based on the current estimate and the last
-    /// RTT measurement received.
-    pub fn loss_delay(&self) -> Duration {
-        // kTimeThreshold = 9/8
-        // loss_delay = kTimeThreshold * max(latest_rtt, smoothed_rtt)
-        // loss_delay = max(loss_delay, kGranularity)
-        let rtt = max(self.latest_rtt, self.smoothed_rtt);
-        max(rtt * 9 / 8, GRANULARITY)
-    }
-
+    /// When the first RTT sample was taken, if any.
+    #[must_use]
     pub const fn first_sample_time(&self) -> Option<Instant> {
         self.first_sample_time
     }
 
+    /// The most recent RTT sample.
+    #[must_use]
     pub const fn latest_rtt(&self) -> Duration {
         self.latest_rtt
     }
 
+    /// The estimated variation in RTT.
+    #[must_use]
     pub const fn rttvar(&self) -> Duration {
         self.rttvar
     }
 
+    /// The minimum RTT seen.
+    #[must_use]
     pub const fn minimum(&self) -> Duration {
         self.min_rtt
     }
@@ -198,11 +206,11 @@ impl RttEstimate {
         self.ack_delay.write_frames(builder, tokens, stats);
     }
 
-    pub const fn frame_lost(&mut self, lost: &AckRate) {
+    pub(crate) const fn frame_lost(&mut self, lost: &AckRate) {
         self.ack_delay.frame_lost(lost);
     }
 
-    pub fn frame_acked(&mut self, acked: &AckRate) {
+    pub(crate) fn frame_acked(&mut self, acked: &AckRate) {
         self.ack_delay.frame_acked(acked);
     }
 }
@@ -9,16 +9,19 @@
 use std::{
     cell::RefCell,
     cmp::{Ordering, max, min},
-    collections::{BTreeMap, VecDeque, btree_map::Entry},
+    collections::{BTreeMap, BTreeSet, VecDeque, btree_map::Entry},
     fmt::{self, Display, Formatter},
     mem,
     num::NonZeroUsize,
     ops::Add,
     rc::Rc,
+    time::Instant,
 };
 
 use indexmap::IndexMap;
-use neqo_common::{Buffer, Encoder, Role, expect_usize, qdebug, qerror, qtrace, to_u64};
+use neqo_common::{
+    Buffer, Bytes, Encoder, Role, expect_usize, qdebug, qerror, qlog::Qlog, qtrace, to_u64,
+};
 use rustc_hash::FxBuildHasher;
 use smallvec::SmallVec;
 use static_assertions::const_assert;
@@ -28,7 +31,7 @@ use crate::{
     events::ConnectionEvents,
     fc::SenderFlowControl,
     frame::{Frame, FrameEncoder as _, FrameType},
-    packet,
+    packet, qlog,
     recovery::{self, StreamRecoveryToken},
     stats::FrameStats,
     stream_id::StreamId,
@@ -103,6 +106,30 @@ pub enum RetransmissionPriority {
     MuchHigher,
 }
 
+/// How send capacity is divided between streams that have data to send at the
+/// same [`TransmissionPriority`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StreamScheduler {
+    /// Streams that are not fair are served first, in order of creation.
+    /// Fair streams are then served in turn, by send group and [`SendOrder`].
+    #[default]
+    SendOrder,
+    /// Weighted fair queuing, using deficit round robin.  Each stream gets a share
+    /// of the send capacity that is proportional to its weight.
+    /// Fairness, send groups, and [`SendOrder`] are ignored.
+    WeightedFair,
+    /// Streams with a deadline are served first, in order of their deadline.
+    /// Other streams are then served as with [`Self::WeightedFair`].
+    EarliestDeadline,
+}
+
+/// The weight of a stream if none is set.
+pub const DEFAULT_STREAM_WEIGHT: u16 = 16;
+
+/// The number of bytes that a stream can send for each unit of weight
+/// in each round of [`StreamScheduler::WeightedFair`].
+const WEIGHT_QUANTUM: i64 = 100;
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 enum RangeState {
     Sent,
@@ -461,11 +488,57 @@ impl RangeTracker {
     }
 }
 
+/// A contiguous run of bytes held by a [`TxBuffer`].
+#[derive(Debug, PartialEq, Eq)]
+enum TxChunk {
+    /// Bytes copied in by [`TxBuffer::send`].  Later copies are appended.
+    Copied(VecDeque<u8>),
+    /// A buffer handed over by [`TxBuffer::send_bytes`], which is kept without copying.
+    Owned(Bytes),
+}
+
+impl TxChunk {
+    fn len(&self) -> usize {
+        match self {
+            Self::Copied(buf) => buf.len(),
+            Self::Owned(buf) => buf.len(),
+        }
+    }
+
+    /// The longest contiguous slice of the chunk that starts at `offset`.
+    fn slice_from(&self, offset: usize) -> &[u8] {
+        match self {
+            Self::Copied(buf) => {
+                // Deque returns two slices. Create a subslice from whichever
+                // one contains `offset`.
+                let (first, second) = buf.as_slices();
+                if offset < first.len() {
+                    &first[offset..]
+                } else {
+                    &second[offset - first.len()..]
+                }
+            }
+            Self::Owned(buf) => &buf.as_ref()[offset..],
+        }
+    }
+
+    /// Drop the first `n` bytes of the chunk.
+    fn drain(&mut self, n: usize) {
+        match self {
+            Self::Copied(buf) => {
+                buf.drain(..n);
+            }
+            Self::Owned(buf) => *buf = mem::take(buf).skip(n),
+        }
+    }
+}
+
 /// Buffer to contain queued bytes and track their state.
 #[derive(Debug, Default, PartialEq, Eq)]
 pub struct TxBuffer {
-    send_buf: VecDeque<u8>, // buffer of not-acked bytes
-    ranges: RangeTracker,   // ranges in buffer that have been sent or acked
+    chunks: VecDeque<TxChunk>, // not-acked bytes, in stream order
+    buffered: usize,           // total length of `chunks`
+    ranges: RangeTracker,      // ranges in buffer that have been sent or acked
 }
 
 const_assert!(MAX_LOCAL_MAX_STREAM_DATA <= to_u64(usize::MAX));
@@ -488,14 +561,33 @@ impl TxBuffer {
 
     /// Attempt to add some or all of the passed-in buffer to the `TxBuffer`.
     pub fn send(&mut self, buf: &[u8]) -> usize {
-        let can_buffer = min(Self::MAX_SIZE - self.buffered(), buf.len());
+        let can_buffer = min(self.avail(), buf.len());
         if can_buffer > 0 {
-            self.send_buf.extend(&buf[..can_buffer]);
-            debug_assert!(self.send_buf.len() <= Self::MAX_SIZE);
+            let buf = &buf[..can_buffer];
+            if let Some(TxChunk::Copied(last)) = self.chunks.back_mut() {
+                last.extend(buf);
+            } else {
+                self.chunks
+                    .push_back(TxChunk::Copied(VecDeque::from(buf.to_vec())));
+            }
+            self.buffered += can_buffer;
+            debug_assert!(self.buffered <= Self::MAX_SIZE);
         }
         can_buffer
     }
 
+    /// Add all of `data` to the `TxBuffer` without copying it, if there is space for it.
+    /// Returns the number of bytes added, which is either zero or the length of `data`.
+    pub fn send_bytes(&mut self, data: Bytes) -> usize {
+        let len = data.len();
+        if len == 0 || len > self.avail() {
+            return 0;
+        }
+        self.chunks.push_back(TxChunk::Owned(data));
+        self.buffered += len;
+        len
+    }
+
     fn first_unmarked_range(&mut self) -> Option<(u64, Option<u64>)> {
         let (start, maybe_len) = self.ranges.first_unmarked_range();
         let buffered = to_u64(self.buffered());
@@ -522,15 +614,17 @@ impl TxBuffer {
         // Convert from ranges-relative-to-zero to
         // ranges-relative-to-buffer-start
         // Conversion is safe because the delta is bounded by send_buf size.
-        let buff_off = expect_usize(start - self.retired());
+        let mut buff_off = expect_usize(start - self.retired());
 
-        // Deque returns two slices. Create a subslice from whichever
-        // one contains the first unmarked data.
-        let slc = if buff_off < self.send_buf.as_slices().0.len() {
-            &self.send_buf.as_slices().0[buff_off..]
-        } else {
-            &self.send_buf.as_slices().1[buff_off - self.send_buf.as_slices().0.len()..]
-        };
+        // Create a subslice from whichever chunk contains the first unmarked data.
+        let slc = self.chunks.iter().find_map(|chunk| {
+            if buff_off < chunk.len() {
+                Some(chunk.slice_from(buff_off))
+            } else {
+                buff_off -= chunk.len();
+                None
+            }
+        })?;
 
         let len = maybe_len.map_or(slc.len(), |range_len| {
             // Safe conversion because of a min over a usize value.
@@ -558,9 +652,19 @@ impl TxBuffer {
 
         // Any newly-retired bytes can be dropped from the buffer.
         // No way this can fail because we have to hold this range in our buffer.
-        let new_retirable = expect_usize(self.retired() - prev_retired);
+        let mut new_retirable = expect_usize(self.retired() - prev_retired);
         debug_assert!(new_retirable <= self.buffered());
-        self.send_buf.drain(..new_retirable);
+        self.buffered -= new_retirable;
+        while new_retirable > 0
+            && let Some(front) = self.chunks.front_mut()
+        {
+            if new_retirable < front.len() {
+                front.drain(new_retirable);
+                break;
+            }
+            new_retirable -= front.len();
+            self.chunks.pop_front();
+        }
     }
 
     pub fn mark_as_lost(&mut self, offset: u64, len: usize) {
@@ -577,11 +681,11 @@ impl TxBuffer {
         self.ranges.acked_from_zero()
     }
 
-    fn buffered(&self) -> usize {
-        self.send_buf.len()
+    const fn buffered(&self) -> usize {
+        self.buffered
     }
 
-    fn avail(&self) -> usize {
+    const fn avail(&self) -> usize {
         Self::MAX_SIZE - self.buffered()
     }
 
@@ -671,11 +775,6 @@ impl State {
             | Self::ResetRecvd { .. } => 0,
         }
     }
-
-    fn transition(&mut self, new_state: Self) {
-        qtrace!("SendStream state {self:?} -> {new_state:?}");
-        *self = new_state;
-    }
 }
 
 // See https://www.w3.org/TR/webtransport/#send-stream-stats.
@@ -737,7 +836,19 @@ pub struct SendStream {
     bytes_sent: u64,
     fair: bool,
     send_group: Option<SendGroupId>,
+    /// The weight of the stream for [`StreamScheduler::WeightedFair`].
+    weight: u16,
+    /// How many bytes the stream can send before it has to wait for the next round
+    /// of [`StreamScheduler::WeightedFair`].  This is negative if the stream sent
+    /// more than its share in the last round.
+    deficit: i64,
+    /// The deadline for [`StreamScheduler::EarliestDeadline`].
+    deadline: Option<Instant>,
+    /// When to give up on sending unacknowledged data, and the error code and
+    /// reliable size of the reset that is sent then.
+    expiry: Option<(Instant, AppError, u64)>,
     writable_event_low_watermark: NonZeroUsize,
+    qlog: Qlog,
 }
 
 impl SendStream {
@@ -761,7 +872,12 @@ impl SendStream {
             bytes_sent: 0,
             fair: false,
             send_group: None,
+            weight: DEFAULT_STREAM_WEIGHT,
+            deficit: 0,
+            deadline: None,
+            expiry: None,
             writable_event_low_watermark: NonZeroUsize::MIN,
+            qlog: Qlog::disabled(),
         };
         if ss.avail() > 0 {
             ss.conn_events.send_stream_writable(stream_id);
@@ -769,6 +885,23 @@ impl SendStream {
         ss
     }
 
+    /// Set the qlog that state changes and data movement are logged to.
+    pub fn set_qlog(&mut self, qlog: Qlog) {
+        self.qlog = qlog;
+    }
+
+    fn set_state(&mut self, new_state: State) {
+        qtrace!("SendStream state {:?} -> {new_state:?}", self.state);
+        qlog::send_stream_state_updated(&mut self.qlog, self.stream_id, &self.state, &new_state);
+        self.state = new_state;
+    }
+
+    /// The number of bytes this stream can send in each round of
+    /// [`StreamScheduler::WeightedFair`].
+    fn quantum(&self) -> i64 {
+        WEIGHT_QUANTUM * i64::from(self.weight)
+    }
+
     /// Returns `true` if [`Self::write_frames`] at this priority has a frame queued:
     /// a pending `RESET_STREAM`, `STREAM_DATA_BLOCKED`, or `STREAM` frame.
     ///
@@ -1153,7 +1286,7 @@ impl SendStream {
             } => {
                 // Reaching `ResetRecvd` does not signal stream completion, even for a reliable
                 // reset that delivered committed data.
-                self.state.transition(State::ResetRecvd {
+                self.set_state(State::ResetRecvd {
                     final_retired,
                     final_written,
                 });
@@ -1168,7 +1301,7 @@ impl SendStream {
                 if send_buf.retired() >= reliable_size {
                     let final_retired = send_buf.retired();
                     let final_written = to_u64(send_buf.buffered());
-                    self.state.transition(State::ResetRecvd {
+                    self.set_state(State::ResetRecvd {
                         final_retired,
                         final_written,
                     });
@@ -1329,7 +1462,7 @@ impl SendStream {
                     self.conn_events.send_stream_complete(self.stream_id);
                     let retired = send_buf.retired();
                     let buffered = to_u64(send_buf.buffered());
-                    self.state.transition(State::DataRecvd {
+                    self.set_state(State::DataRecvd {
                         retired,
                         written: buffered,
                     });
@@ -1350,7 +1483,7 @@ impl SendStream {
                     let final_written = to_u64(send_buf.buffered());
                     if reset_acked {
                         // Both the frame and the committed data are acked: reach `ResetRecvd`.
-                        self.state.transition(State::ResetRecvd {
+                        self.set_state(State::ResetRecvd {
                             final_retired,
                             final_written,
                         });
@@ -1358,7 +1491,7 @@ impl SendStream {
                         // Committed data is acked but the frame is not: drop the buffer and
                         // await the frame ack in `ResetSent` (which keeps `reliable_size` so
                         // that the frame is still retransmitted as RESET_STREAM_AT).
-                        self.state.transition(State::ResetSent {
+                        self.set_state(State::ResetSent {
                             err,
                             final_size,
                             reliable_size,
@@ -1451,20 +1584,48 @@ impl SendStream {
         self.send_internal(buf, true)
     }
 
+    /// Send as much of `data` as flow control and the send buffer allow.
+    /// If all of `data` fits, it is kept without copying.  Otherwise, the part that
+    /// fits is copied and the rest of `data` is returned so that it can be sent later.
+    ///
+    /// # Errors
+    /// When `data` is empty or when the stream is already closed.
+    pub fn send_bytes(&mut self, data: Bytes) -> Res<Option<Bytes>> {
+        let len = self.prepare_send(data.len(), false)?;
+        if len == data.len() {
+            self.buffer(|send_buf| send_buf.send_bytes(data))?;
+            return Ok(None);
+        }
+        if len > 0 {
+            self.buffer(|send_buf| send_buf.send(&data.as_ref()[..len]))?;
+        }
+        Ok(Some(data.skip(len)))
+    }
+
     fn send_blocked_if_space_needed(&mut self, needed_space: usize) {
         if let State::Ready { fc, conn_fc } | State::Send { fc, conn_fc, .. } = &mut self.state {
-            if fc.available() <= needed_space {
-                fc.blocked();
+            if fc.available() <= needed_space && fc.blocked() {
+                qlog::flow_control_blocked(&mut self.qlog, Some(self.stream_id), fc.limit());
             }
 
-            if conn_fc.borrow().available() <= needed_space {
-                conn_fc.borrow_mut().blocked();
+            let mut conn_fc = conn_fc.borrow_mut();
+            if conn_fc.available() <= needed_space && conn_fc.blocked() {
+                qlog::flow_control_blocked(&mut self.qlog, None, conn_fc.limit());
             }
         }
     }
 
     fn send_internal(&mut self, buf: &[u8], atomic: bool) -> Res<usize> {
-        if buf.is_empty() {
+        let len = self.prepare_send(buf.len(), atomic)?;
+        if len == 0 {
+            return Ok(0);
+        }
+        self.buffer(|send_buf| send_buf.send(&buf[..len]))
+    }
+
+    /// Get ready to send `len` bytes, returning how many of them can be buffered.
+    fn prepare_send(&mut self, len: usize, atomic: bool) -> Res<usize> {
+        if len == 0 {
             qerror!("[{self}] zero-length send on stream");
             return Err(Error::InvalidInput);
         }
@@ -1472,7 +1633,7 @@ impl SendStream {
         if let State::Ready { fc, conn_fc } = &mut self.state {
             let owned_fc = mem::replace(fc, SenderFlowControl::new(self.stream_id, 0));
             let owned_conn_fc = Rc::clone(conn_fc);
-            self.state.transition(State::Send {
+            self.set_state(State::Send {
                 fc: owned_fc,
                 conn_fc: owned_conn_fc,
                 send_buf: TxBuffer::new(),
@@ -1484,40 +1645,42 @@ impl SendStream {
             return Err(Error::FinalSize);
         }
 
-        let buf = if self.avail() == 0 {
+        let avail = self.avail();
+        if avail == 0 {
             return Ok(0);
-        } else if self.avail() < buf.len() {
-            if atomic {
-                self.send_blocked_if_space_needed(buf.len());
-                return Ok(0);
-            }
+        } else if avail < len && atomic {
+            self.send_blocked_if_space_needed(len);
+            return Ok(0);
+        }
+        Ok(min(avail, len))
+    }
 
-            &buf[..self.avail()]
-        } else {
-            buf
+    /// Add data to the send buffer using `f`, which returns the number of bytes it added.
+    fn buffer<F>(&mut self, f: F) -> Res<usize>
+    where
+        F: FnOnce(&mut TxBuffer) -> usize,
+    {
+        let State::Send {
+            fc,
+            conn_fc,
+            send_buf,
+            ..
+        } = &mut self.state
+        else {
+            return Err(Error::FinalSize);
         };
-
-        match &mut self.state {
-            State::Ready { .. } => unreachable!(),
-            State::Send {
-                fc,
-                conn_fc,
-                send_buf,
-                ..
-            } => {
-                let sent = send_buf.send(buf);
-                fc.consume(sent);
-                conn_fc.borrow_mut().consume(sent);
-                Ok(sent)
-            }
-            _ => Err(Error::FinalSize),
-        }
+        let offset = send_buf.used();
+        let sent = f(send_buf);
+        fc.consume(sent);
+        conn_fc.borrow_mut().consume(sent);
+        qlog::data_moved_down(&mut self.qlog, self.stream_id, offset, sent);
+        Ok(sent)
     }
 
     pub fn close(&mut self) {
         match &mut self.state {
             State::Ready { .. } => {
-                self.state.transition(State::DataSent {
+                self.set_state(State::DataSent {
                     send_buf: TxBuffer::new(),
                     fin_sent: false,
                     fin_acked: false,
@@ -1531,7 +1694,7 @@ impl SendStream {
             } => {
                 let owned_buf = mem::replace(send_buf, TxBuffer::new());
                 let committed = *committed;
-                self.state.transition(State::DataSent {
+                self.set_state(State::DataSent {
                     send_buf: owned_buf,
                     fin_sent: false,
                     fin_acked: false,
@@ -1660,7 +1823,36 @@ impl SendStream {
                 return;
             }
         };
-        self.state.transition(new_state);
+        self.set_state(new_state);
+    }
+
+    /// Give up on the stream because its expiry time has passed.
+    /// Unless the peer has acknowledged everything or the stream was already reset,
+    /// it is reset with `err`, data from `reliable_size` onwards is no longer
+    /// (re)transmitted, and a [`crate::ConnectionEvent::SendStreamExpired`] event is raised.
+    fn expire(&mut self, err: AppError, reliable_size: u64) {
+        match &mut self.state {
+            State::Ready { .. } => (),
+            State::Send {
+                send_buf,
+                committed,
+                ..
+            }
+            | State::DataSent {
+                send_buf,
+                committed,
+                ..
+            } => {
+                *committed = min(reliable_size, send_buf.used());
+            }
+            State::DataRecvd { .. }
+            | State::ResetSent { .. }
+            | State::ResetSentReliable { .. }
+            | State::ResetRecvd { .. } => return,
+        }
+        qdebug!("[{self}] expired, reliable size {reliable_size}");
+        self.reset(err);
+        self.conn_events.send_stream_expired(self.stream_id);
     }
 
     /// Drop any commitment made via [`Self::commit`] in response to a `STOP_SENDING`: the peer has
@@ -1706,7 +1898,7 @@ impl SendStream {
                         final_written,
                     }
                 };
-                self.state.transition(new_state);
+                self.set_state(new_state);
             }
             _ => {}
         }
@@ -1916,6 +2108,16 @@ pub struct SendStreams {
     // hash lookup) while still resuming after the last-served stream when the packet
     // builder fills mid-pass, preserving fairness.
     fair_rr_next: usize,
+
+    /// How send capacity is divided between streams.
+    scheduler: StreamScheduler,
+    /// Streams with a deadline, in order of their deadline.
+    deadlines: BTreeSet<(Instant, StreamId)>,
+    /// Streams that are reset if they are not complete by a certain time,
+    /// in order of that time.
+    expiries: BTreeSet<(Instant, StreamId)>,
+    /// Round-robin cursor (index into `map`) for [`StreamScheduler::WeightedFair`].
+    weighted_next: usize,
 }
 
 /// Key used in `per_group` to represent the null sendGroup (ungrouped fair streams).
@@ -1924,6 +2126,14 @@ pub struct SendStreams {
 const NULL_GROUP_ID: SendGroupId = SendGroupId::new(0);
 
 impl SendStreams {
+    #[must_use]
+    pub fn new(scheduler: StreamScheduler) -> Self {
+        Self {
+            scheduler,
+            ..Self::default()
+        }
+    }
+
     #[allow(
         clippy::allow_attributes,
         clippy::missing_errors_doc,
@@ -2116,6 +2326,74 @@ impl SendStreams {
         Ok(())
     }
 
+    /// Set the weight of a stream for [`StreamScheduler::WeightedFair`].
+    ///
+    /// # Errors
+    /// Returns [`Error::InvalidStreamId`] if the stream does not exist, or
+    /// [`Error::InvalidInput`] if `weight` is zero.
+    pub fn set_weight(&mut self, stream_id: StreamId, weight: u16) -> Res<()> {
+        if weight == 0 {
+            return Err(Error::InvalidInput);
+        }
+        self.get_mut(stream_id)?.weight = weight;
+        Ok(())
+    }
+
+    /// Set the deadline of a stream for [`StreamScheduler::EarliestDeadline`].
+    ///
+    /// # Errors
+    /// Returns [`Error::InvalidStreamId`] if the stream does not exist.
+    pub fn set_deadline(&mut self, stream_id: StreamId, deadline: Option<Instant>) -> Res<()> {
+        let stream = self.map.get_mut(&stream_id).ok_or(Error::InvalidStreamId)?;
+        if let Some(old) = mem::replace(&mut stream.deadline, deadline) {
+            self.deadlines.remove(&(old, stream_id));
+        }
+        if let Some(deadline) = deadline {
+            self.deadlines.insert((deadline, stream_id));
+        }
+        Ok(())
+    }
+
+    /// Reset a stream with `err` if the peer has not acknowledged all of its data
+    /// by `expiry`, while still delivering the first `reliable_size` bytes.
+    ///
+    /// # Errors
+    /// Returns [`Error::InvalidStreamId`] if the stream does not exist.
+    pub fn set_expiry(
+        &mut self,
+        stream_id: StreamId,
+        expiry: Instant,
+        err: AppError,
+        reliable_size: u64,
+    ) -> Res<()> {
+        let stream = self.map.get_mut(&stream_id).ok_or(Error::InvalidStreamId)?;
+        if let Some((old, ..)) = stream.expiry.replace((expiry, err, reliable_size)) {
+            self.expiries.remove(&(old, stream_id));
+        }
+        self.expiries.insert((expiry, stream_id));
+        Ok(())
+    }
+
+    /// The time at which the next stream expires.
+    #[must_use]
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.expiries.first().map(|&(expiry, _)| expiry)
+    }
+
+    /// Reset any streams that have expired.
+    pub fn expire(&mut self, now: Instant) {
+        while let Some(&(expiry, stream_id)) = self.expiries.first()
+            && expiry <= now
+        {
+            self.expiries.pop_first();
+            if let Some(stream) = self.map.get_mut(&stream_id)
+                && let Some((_, err, reliable_size)) = stream.expiry.take()
+            {
+                stream.expire(err, reliable_size);
+            }
+        }
+    }
+
     pub fn acked(&mut self, token: &RecoveryToken) {
         if let Some(ss) = self.map.get_mut(&token.id) {
             ss.mark_as_acked(token.offset, token.length, token.fin);
@@ -2154,6 +2432,9 @@ impl SendStreams {
         self.per_group.clear();
         self.per_group_next = 0;
         self.fair_rr_next = 0;
+        self.deadlines.clear();
+        self.expiries.clear();
+        self.weighted_next = 0;
     }
 
     /// Remove ended streams. Returns `true` if any were removed.
@@ -2166,6 +2447,12 @@ impl SendStreams {
         let mut removed = false;
         for (stream_id, stream) in self.map.extract_if(.., |_, s| s.is_ended()) {
             removed = true;
+            if let Some(deadline) = stream.deadline {
+                self.deadlines.remove(&(deadline, stream_id));
+            }
+            if let Some((expiry, ..)) = stream.expiry {
+                self.expiries.remove(&(expiry, stream_id));
+            }
             if stream.is_fair() {
                 let group_id = stream.send_group().unwrap_or(NULL_GROUP_ID);
                 if let Some(grp_queues) = self.per_group.get_mut(&group_id) {
@@ -2184,6 +2471,9 @@ impl SendStreams {
         if self.fair_rr_next >= self.map.len() {
             self.fair_rr_next = 0;
         }
+        if self.weighted_next >= self.map.len() {
+            self.weighted_next = 0;
+        }
         removed
     }
 
@@ -2194,6 +2484,20 @@ impl SendStreams {
         tokens: &mut recovery::Tokens,
         stats: &mut FrameStats,
     ) {
+        match self.scheduler {
+            StreamScheduler::SendOrder => (),
+            StreamScheduler::WeightedFair => {
+                self.write_frames_weighted(priority, builder, tokens, stats);
+                return;
+            }
+            StreamScheduler::EarliestDeadline => {
+                if self.write_frames_deadline(priority, builder, tokens, stats) {
+                    self.write_frames_weighted(priority, builder, tokens, stats);
+                }
+                return;
+            }
+        }
+
         // WebTransport data (which is Normal) may have a SendOrder
         // priority attached.  The spec states (6.3 write-chunk 6.1):
 
@@ -2380,6 +2684,115 @@ impl SendStreams {
         }
     }
 
+    /// Serve streams that have a deadline, earliest first.
+    /// Returns `false` if the builder is full.
+    fn write_frames_deadline<B: Buffer>(
+        &mut self,
+        priority: TransmissionPriority,
+        builder: &mut packet::Builder<B>,
+        tokens: &mut recovery::Tokens,
+        stats: &mut FrameStats,
+    ) -> bool {
+        for (_, stream_id) in &self.deadlines {
+            if let Some(stream) = self.map.get_mut(stream_id)
+                && stream.has_data_at(priority)
+                && !stream.write_frames(priority, builder, tokens, stats)
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Serve streams using deficit round robin.
+    ///
+    /// Each stream with data can send while its deficit is positive, which is then
+    /// reduced by the size of what it sent.  A stream can overdraw its deficit by at most
+    /// one packet, which it pays back in later rounds.  Once no stream can send,
+    /// every stream with data gets a quantum that is proportional to its weight.
+    /// A stream that can't use its deficit, such as one that is blocked by flow control,
+    /// keeps at most one quantum, so that it doesn't burst once it is unblocked.
+    ///
+    /// The streams with data are found once, so that each round only visits those.
+    fn write_frames_weighted<B: Buffer>(
+        &mut self,
+        priority: TransmissionPriority,
+        builder: &mut packet::Builder<B>,
+        tokens: &mut recovery::Tokens,
+        stats: &mut FrameStats,
+    ) {
+        let n = self.map.len();
+        if self.weighted_next >= n {
+            self.weighted_next = 0;
+        }
+        // Streams with a deadline have already had their turn.
+        let skip_deadlines = self.scheduler == StreamScheduler::EarliestDeadline;
+        let start = self.weighted_next;
+        let mut active = (0..n)
+            .map(|off| (start + off) % n)
+            .filter(|&idx| {
+                self.map.get_index_mut(idx).is_some_and(|(_, stream)| {
+                    !(skip_deadlines && stream.deadline.is_some()) && stream.has_data_at(priority)
+                })
+            })
+            .collect::<SmallVec<[_; 16]>>();
+        loop {
+            let mut wrote = false;
+            for &idx in &active {
+                // `idx < n`, so this always succeeds; `else` is just to avoid a panic.
+                let Some((_, stream)) = self.map.get_index_mut(idx) else {
+                    continue;
+                };
+                if stream.deficit <= 0 || !stream.has_data_at(priority) {
+                    continue;
+                }
+                let before = builder.len();
+                let more = stream.write_frames(priority, builder, tokens, stats);
+                let written = builder.len() - before;
+                stream.deficit -= i64::try_from(written).unwrap_or(i64::MAX);
+                wrote |= written > 0;
+                if !stream.has_data_at(priority) {
+                    // An idle stream doesn't save up credit.
+                    stream.deficit = min(stream.deficit, 0);
+                }
+                if !more {
+                    // Let this stream use what remains of its deficit next time.
+                    self.weighted_next = if stream.deficit > 0 {
+                        idx
+                    } else {
+                        (idx + 1) % n
+                    };
+                    return;
+                }
+            }
+            active.retain(|idx| {
+                self.map
+                    .get_index_mut(*idx)
+                    .is_some_and(|(_, stream)| stream.has_data_at(priority))
+            });
+            if wrote {
+                continue;
+            }
+            // Every stream that can send has used its deficit.  Skip ahead to the first
+            // round in which one of them can send again.
+            let Some(rounds) = active
+                .iter()
+                .filter_map(|&idx| self.map.get_index(idx))
+                .filter(|(_, stream)| stream.deficit <= 0)
+                .map(|(_, stream)| (stream.quantum() - stream.deficit) / stream.quantum())
+                .min()
+            else {
+                return;
+            };
+            for &idx in &active {
+                if let Some((_, stream)) = self.map.get_index_mut(idx) {
+                    let quantum = stream.quantum();
+                    stream.deficit = min(stream.deficit + rounds * quantum, quantum);
+                }
+            }
+        }
+    }
+
     #[allow(
         clippy::allow_attributes,
         clippy::missing_panics_doc,
@@ -2423,10 +2836,11 @@ pub struct RecoveryToken {
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
-    use std::{cell::RefCell, collections::VecDeque, num::NonZeroUsize, rc::Rc};
+    use std::{cell::RefCell, collections::VecDeque, num::NonZeroUsize, rc::Rc, time::Duration};
 
     use neqo_common::{
-        Encoder, MAX_VARINT, event::Provider as _, expect_usize, hex::HexWithLen, qtrace, to_u64,
+        Bytes, Encoder, MAX_VARINT, event::Provider as _, expect_usize, hex::HexWithLen, qtrace,
+        to_u64,
     };
 
     use super::RecoveryToken;
@@ -2438,7 +2852,8 @@ mod tests {
         packet,
         recovery::{self, StreamRecoveryToken},
         send_stream::{
-            NULL_GROUP_ID, RangeState, RangeTracker, SendStream, SendStreams, State, TxBuffer,
+            NULL_GROUP_ID, RangeState, RangeTracker, SendStream, SendStreams, State,
+            StreamScheduler, TxBuffer,
         },
         stats::FrameStats,
         streams::SendGroupId,
@@ -2482,6 +2897,103 @@ mod tests {
         assert!(ss.set_sendgroup(id, Some(NULL_GROUP_ID)).is_err());
     }
 
+    /// Write one packet from `ss`, returning the size of each `STREAM` frame by stream.
+    fn write_packet(ss: &mut SendStreams) -> Vec<(StreamId, usize)> {
+        let mut tokens = recovery::Tokens::new();
+        let mut builder =
+            packet::Builder::short(Encoder::default(), false, None::<&[u8]>, packet::LIMIT);
+        ss.write_frames(
+            TransmissionPriority::default(),
+            &mut builder,
+            &mut tokens,
+            &mut FrameStats::default(),
+        );
+        tokens
+            .iter()
+            .map(|t| {
+                let t = as_stream_token(t);
+                (t.id, t.length)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn weighted_fair_shares() {
+        let conn_fc = connection_fc(u64::MAX);
+        let conn_events = ConnectionEvents::default();
+        let mut ss = SendStreams::new(StreamScheduler::WeightedFair);
+        let weights = [
+            (StreamId::from(0), 1),
+            (StreamId::from(4), 2),
+            (StreamId::from(8), 4),
+        ];
+        for (id, weight) in weights {
+            let mut s = SendStream::new(id, 1 << 20, Rc::clone(&conn_fc), conn_events.clone());
+            s.send(&[0; 1 << 17]).unwrap();
+            ss.insert(id, s);
+            ss.set_weight(id, weight).unwrap();
+        }
+        assert_eq!(
+            ss.set_weight(StreamId::from(0), 0),
+            Err(Error::InvalidInput)
+        );
+
+        let mut sent = [0; 3];
+        for _ in 0..70 {
+            for (id, len) in write_packet(&mut ss) {
+                sent[weights.iter().position(|&(i, _)| i == id).unwrap()] += len;
+            }
+            // No stream saves up more than one quantum.
+            for (id, _) in weights {
+                let s = ss.get_mut(id).unwrap();
+                assert!(s.deficit <= s.quantum());
+            }
+        }
+        let total: usize = sent.iter().sum();
+        for ((_, weight), sent) in weights.iter().zip(sent) {
+            let expected = total * usize::from(*weight) / 7;
+            assert!(
+                sent.abs_diff(expected) <= 2 * packet::LIMIT,
+                "sent {sent}, expected {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn earliest_deadline_first() {
+        let conn_fc = connection_fc(u64::MAX);
+        let conn_events = ConnectionEvents::default();
+        let mut ss = SendStreams::new(StreamScheduler::EarliestDeadline);
+        let ids = [StreamId::from(0), StreamId::from(4), StreamId::from(8)];
+        for id in ids {
+            let mut s = SendStream::new(id, 1 << 20, Rc::clone(&conn_fc), conn_events.clone());
+            s.send(&[0; 100]).unwrap();
+            ss.insert(id, s);
+        }
+        let now = test_fixture::now();
+        ss.set_deadline(ids[1], Some(now + Duration::from_millis(2)))
+            .unwrap();
+        ss.set_deadline(ids[2], Some(now + Duration::from_millis(1)))
+            .unwrap();
+        let order = write_packet(&mut ss)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(order, [ids[2], ids[1], ids[0]]);
+
+        // Moving a deadline changes the order.
+        for id in ids {
+            ss.get_mut(id).unwrap().send(&[0; 100]).unwrap();
+        }
+        ss.set_deadline(ids[1], Some(now)).unwrap();
+        ss.set_deadline(ids[2], None).unwrap();
+        let order = write_packet(&mut ss)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(order, [ids[1], ids[0], ids[2]]);
+    }
+
     /// A group containing both a regular (null-sendOrder) and a sendordered stream,
     /// both with data: the higher-sendOrder stream is served first, and once it has
     /// drained the regular stream gets its turn. The regular stream must not be
@@ -3405,6 +3917,78 @@ mod tests {
         assert!(txb.next_bytes().is_none());
     }
 
+    #[test]
+    fn tx_buffer_send_bytes() {
+        let mut txb = TxBuffer::new();
+
+        // Copies are appended to each other, owned buffers are kept separate.
+        assert_eq!(txb.send(&[1; 10]), 10);
+        assert_eq!(txb.send(&[1; 10]), 10);
+        assert_eq!(txb.send_bytes(Bytes::from(vec![2; 30])), 30);
+        assert_eq!(txb.send(&[3; 10]), 10);
+        assert_eq!(txb.used(), 60);
+
+        assert!(matches!(txb.next_bytes(),
+                         Some((0, x)) if x.len() == 20 && x.iter().all(|ch| *ch == 1)));
+        txb.mark_as_sent(0, 20);
+        assert!(matches!(txb.next_bytes(),
+                         Some((20, x)) if x.len() == 30 && x.iter().all(|ch| *ch == 2)));
+
+        // Acknowledging part of the owned buffer drops what came before it.
+        txb.mark_as_sent(20, 10);
+        txb.mark_as_acked(0, 30);
+        assert_eq!(txb.retired(), 30);
+        assert!(matches!(txb.next_bytes(),
+                         Some((30, x)) if x.len() == 20 && x.iter().all(|ch| *ch == 2)));
+
+        // A lost range within the owned buffer is resent from it.
+        txb.mark_as_sent(30, 30);
+        txb.mark_as_lost(40, 5);
+        assert!(matches!(txb.next_bytes(),
+                         Some((40, x)) if x.len() == 5 && x.iter().all(|ch| *ch == 2)));
+        txb.mark_as_sent(40, 5);
+
+        txb.mark_as_acked(30, 30);
+        assert_eq!(txb.retired(), 60);
+        assert_eq!(txb.buffered(), 0);
+        assert!(txb.next_bytes().is_none());
+
+        // An owned buffer is only accepted if all of it fits.
+        assert_eq!(
+            txb.send_bytes(Bytes::from(vec![0; TxBuffer::MAX_SIZE + 1])),
+            0
+        );
+        assert_eq!(txb.send_bytes(Bytes::default()), 0);
+    }
+
+    #[test]
+    fn stream_send_bytes() {
+        let conn_fc = connection_fc(4096);
+        let conn_events = ConnectionEvents::default();
+        let mut s = SendStream::new(4.into(), 100, conn_fc, conn_events);
+
+        // Too much for the stream flow control limit, so only a prefix is sent.
+        let data = Bytes::from((0..=100).collect::<Vec<u8>>());
+        let data = s.send_bytes(data).unwrap().unwrap();
+        assert_eq!(data, [100]);
+        assert_eq!(s.avail(), 0);
+        assert!(matches!(s.next_bytes(false),
+                         Some((0, x)) if x.iter().copied().eq(0..100)));
+
+        // With no space at all, the buffer is returned unchanged.
+        let data = s.send_bytes(data).unwrap().unwrap();
+        assert_eq!(data, [100]);
+
+        // More credit allows the rest to be sent.
+        s.set_max_stream_data(101);
+        assert!(s.send_bytes(data).unwrap().is_none());
+        assert_eq!(s.avail(), 0);
+
+        assert_eq!(s.send_bytes(Bytes::default()), Err(Error::InvalidInput));
+        s.close();
+        assert_eq!(s.send_bytes(Bytes::from(vec![1])), Err(Error::FinalSize));
+    }
+
     #[test]
     fn stream_tx() {
         let conn_fc = connection_fc(4096);
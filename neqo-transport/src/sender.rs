@@ -13,9 +13,9 @@ use neqo_common::{qdebug, qlog::Qlog};
 use crate::{
     ConnectionParameters, SlowStart, Stats,
     cc::{
-        ClassicCongestionController, ClassicSlowStart, CongestionControl,
+        Bbr, ClassicCongestionController, ClassicSlowStart, CongestionControl,
         CongestionControlImplementation, CongestionController as _, Cubic, HyStart, NewReno,
-        Search,
+        Prague, SavedCongestionState, Search,
     },
     pace::Pacer,
     pmtud::Pmtud,
@@ -39,73 +39,8 @@ impl PacketSender {
     #[must_use]
     pub fn new(conn_params: &ConnectionParameters, pmtud: Pmtud, now: Instant) -> Self {
         let mtu = pmtud.plpmtu();
-        let spurious_recovery = conn_params.spurious_recovery_enabled();
         Self {
-            cc: match (
-                conn_params.get_congestion_control(),
-                conn_params.get_slow_start(),
-            ) {
-                (CongestionControl::NewReno, SlowStart::Classic) => {
-                    CongestionControlImplementation::ClassicNewReno(
-                        ClassicCongestionController::new(
-                            ClassicSlowStart::default(),
-                            NewReno::default(),
-                            pmtud,
-                            spurious_recovery,
-                        ),
-                    )
-                }
-                (CongestionControl::NewReno, SlowStart::HyStart) => {
-                    CongestionControlImplementation::HyStartNewReno(
-                        ClassicCongestionController::new(
-                            HyStart::new(
-                                conn_params.pacing_enabled(),
-                                conn_params.get_hystart_css_baseline(),
-                            ),
-                            NewReno::default(),
-                            pmtud,
-                            spurious_recovery,
-                        ),
-                    )
-                }
-                (CongestionControl::NewReno, SlowStart::Search) => {
-                    CongestionControlImplementation::SearchNewReno(
-                        ClassicCongestionController::new(
-                            Search::new(),
-                            NewReno::default(),
-                            pmtud,
-                            spurious_recovery,
-                        ),
-                    )
-                }
-                (CongestionControl::Cubic, SlowStart::Classic) => {
-                    CongestionControlImplementation::ClassicCubic(ClassicCongestionController::new(
-                        ClassicSlowStart::default(),
-                        Cubic::default(),
-                        pmtud,
-                        spurious_recovery,
-                    ))
-                }
-                (CongestionControl::Cubic, SlowStart::HyStart) => {
-                    CongestionControlImplementation::HyStartCubic(ClassicCongestionController::new(
-                        HyStart::new(
-                            conn_params.pacing_enabled(),
-                            conn_params.get_hystart_css_baseline(),
-                        ),
-                        Cubic::default(),
-                        pmtud,
-                        spurious_recovery,
-                    ))
-                }
-                (CongestionControl::Cubic, SlowStart::Search) => {
-                    CongestionControlImplementation::SearchCubic(ClassicCongestionController::new(
-                        Search::new(),
-                        Cubic::default(),
-                        pmtud,
-                        spurious_recovery,
-                    ))
-                }
-            },
+            cc: Self::congestion_controller(conn_params, pmtud),
             pacer: Pacer::new(
                 conn_params.pacing_enabled(),
                 now,
@@ -116,6 +51,84 @@ impl PacketSender {
         }
     }
 
+    fn congestion_controller(
+        conn_params: &ConnectionParameters,
+        pmtud: Pmtud,
+    ) -> CongestionControlImplementation {
+        if let Some(factory) = conn_params.get_congestion_control_factory() {
+            return CongestionControlImplementation::Custom(factory.create(pmtud));
+        }
+        let spurious_recovery = conn_params.spurious_recovery_enabled();
+        match (
+            conn_params.get_congestion_control(),
+            conn_params.get_slow_start(),
+        ) {
+            (CongestionControl::NewReno, SlowStart::Classic) => {
+                CongestionControlImplementation::ClassicNewReno(ClassicCongestionController::new(
+                    ClassicSlowStart::default(),
+                    NewReno::default(),
+                    pmtud,
+                    spurious_recovery,
+                ))
+            }
+            (CongestionControl::NewReno, SlowStart::HyStart) => {
+                CongestionControlImplementation::HyStartNewReno(ClassicCongestionController::new(
+                    HyStart::new(
+                        conn_params.pacing_enabled(),
+                        conn_params.get_hystart_css_baseline(),
+                    ),
+                    NewReno::default(),
+                    pmtud,
+                    spurious_recovery,
+                ))
+            }
+            (CongestionControl::NewReno, SlowStart::Search) => {
+                CongestionControlImplementation::SearchNewReno(ClassicCongestionController::new(
+                    Search::new(),
+                    NewReno::default(),
+                    pmtud,
+                    spurious_recovery,
+                ))
+            }
+            (CongestionControl::Cubic, SlowStart::Classic) => {
+                CongestionControlImplementation::ClassicCubic(ClassicCongestionController::new(
+                    ClassicSlowStart::default(),
+                    Cubic::default(),
+                    pmtud,
+                    spurious_recovery,
+                ))
+            }
+            (CongestionControl::Cubic, SlowStart::HyStart) => {
+                CongestionControlImplementation::HyStartCubic(ClassicCongestionController::new(
+                    HyStart::new(
+                        conn_params.pacing_enabled(),
+                        conn_params.get_hystart_css_baseline(),
+                    ),
+                    Cubic::default(),
+                    pmtud,
+                    spurious_recovery,
+                ))
+            }
+            (CongestionControl::Cubic, SlowStart::Search) => {
+                CongestionControlImplementation::SearchCubic(ClassicCongestionController::new(
+                    Search::new(),
+                    Cubic::default(),
+                    pmtud,
+                    spurious_recovery,
+                ))
+            }
+            (CongestionControl::Bbr, _) => CongestionControlImplementation::Bbr(Bbr::new(pmtud)),
+            (CongestionControl::Prague, _) => {
+                CongestionControlImplementation::Prague(ClassicCongestionController::new(
+                    ClassicSlowStart::default(),
+                    Prague::default(),
+                    pmtud,
+                    spurious_recovery,
+                ))
+            }
+        }
+    }
+
     pub fn set_qlog(&mut self, qlog: Qlog) {
         self.qlog = qlog.clone();
         self.cc.set_qlog(qlog);
@@ -150,9 +163,21 @@ impl PacketSender {
         self.cc.cwnd_min()
     }
 
+    /// The window that drives the pacer.  This is the congestion window, unless
+    /// the congestion controller sets a pacing rate of its own.
+    fn pacing_cwnd(&self, rtt: Duration) -> usize {
+        self.cc
+            .pacing_rate()
+            .map_or_else(|| self.cc.cwnd(), |rate| Pacer::cwnd_for_rate(rate, rtt))
+    }
+
     /// Emit a `PacingRate` qlog metric.
     fn maybe_qlog_pacing_rate(&mut self, rtt: Duration, now: Instant) {
-        if let Some(rate) = Pacer::rate(self.cc.cwnd(), rtt) {
+        if let Some(rate) = self
+            .cc
+            .pacing_rate()
+            .or_else(|| Pacer::rate(self.cc.cwnd(), rtt))
+        {
             qlog::metrics_updated(&mut self.qlog, [qlog::Metric::PacingRate(rate)], now);
         }
     }
@@ -211,10 +236,12 @@ impl PacketSender {
     pub fn on_ecn_ce_received(
         &mut self,
         largest_acked_pkt: &sent::Packet,
+        ce_marks: u64,
         now: Instant,
         cc_stats: &mut CongestionControlStats,
     ) -> bool {
-        self.cc.on_ecn_ce_received(largest_acked_pkt, now, cc_stats)
+        self.cc
+            .on_ecn_ce_marks_received(largest_acked_pkt, ce_marks, now, cc_stats)
     }
 
     pub fn discard(&mut self, pkt: &sent::Packet, now: Instant) {
@@ -228,21 +255,31 @@ impl PacketSender {
     }
 
     pub fn on_packet_sent(&mut self, pkt: &sent::Packet, rtt: Duration, now: Instant) {
-        self.pacer
-            .spend(pkt.time_sent(), rtt, self.cc.cwnd(), pkt.len());
+        let cwnd = self.pacing_cwnd(rtt);
+        self.pacer.spend(pkt.time_sent(), rtt, cwnd, pkt.len());
         self.cc.on_packet_sent(pkt, now);
     }
 
     #[must_use]
     pub fn next_paced(&self, rtt: Duration) -> Option<Instant> {
         // Only pace if there are bytes in flight.
-        (self.cc.bytes_in_flight() > 0).then(|| self.pacer.next(rtt, self.cc.cwnd()))
+        (self.cc.bytes_in_flight() > 0).then(|| self.pacer.next(rtt, self.pacing_cwnd(rtt)))
     }
 
     #[must_use]
     pub fn recovery_packet(&self) -> bool {
         self.cc.recovery_packet()
     }
+
+    /// Start Careful Resume with congestion state saved from an earlier connection.
+    pub fn careful_resume(
+        &mut self,
+        saved: SavedCongestionState,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) {
+        self.cc.careful_resume(saved, now, cc_stats);
+    }
 }
 
 #[cfg(test)]
@@ -257,8 +294,12 @@ mod tests {
 
     use super::PacketSender;
     use crate::{
-        ConnectionParameters, SlowStart, cc::CongestionControl, pmtud::Pmtud, recovery::sent,
-        rtt::RttEstimate, stats::Stats,
+        ConnectionParameters, SlowStart,
+        cc::{Bbr, CongestionControl, CongestionControlImplementation, CongestionController},
+        pmtud::Pmtud,
+        recovery::sent,
+        rtt::RttEstimate,
+        stats::Stats,
     };
 
     #[test]
@@ -291,6 +332,12 @@ mod tests {
                 "HyStart++/Cubic",
             ),
             (CongestionControl::Cubic, SlowStart::Search, "SEARCH/Cubic"),
+            (CongestionControl::Bbr, SlowStart::Classic, "BBRv3"),
+            (
+                CongestionControl::Prague,
+                SlowStart::HyStart,
+                "ClassicSlowStart/Prague",
+            ),
         ];
         for (cc, ss, expected_prefix) in cases {
             let params = ConnectionParameters::default()
@@ -309,6 +356,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn packet_sender_congestion_control_factory() {
+        let params = ConnectionParameters::default()
+            .congestion_control(CongestionControl::NewReno)
+            .congestion_control_factory(Box::new(|pmtud| {
+                Box::new(Bbr::new(pmtud)) as Box<dyn CongestionController>
+            }));
+        let sender = PacketSender::new(
+            &params,
+            Pmtud::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), Some(1500)),
+            now(),
+        );
+        assert!(matches!(
+            sender.cc,
+            CongestionControlImplementation::Custom(_)
+        ));
+        assert!(sender.cc.to_string().starts_with("BBRv3"));
+    }
+
     const RTT: Duration = Duration::from_millis(100);
 
     fn make_sender(pacing: bool) -> PacketSender {
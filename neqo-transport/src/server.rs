@@ -7,35 +7,45 @@
 // This file implements a server that can handle multiple connections.
 
 use std::{
-    cell::RefCell,
-    cmp::min,
-    collections::VecDeque,
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::{BinaryHeap, VecDeque, hash_map::Entry},
     fmt::{self, Display, Formatter},
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     num::NonZeroUsize,
     ops::{Deref, DerefMut},
     path::PathBuf,
-    rc::Rc,
-    time::Instant,
+    rc::{Rc, Weak},
+    time::{Duration, Instant},
 };
 
 use neqo_common::{
-    Datagram, Role, Tos, event::Provider as _, hex::Hex, qdebug, qerror, qinfo, qlog::Qlog, qtrace,
-    qwarn,
+    Datagram, Encoder, Role, Tos,
+    event::Provider as _,
+    hex::Hex,
+    qdebug, qerror, qinfo,
+    qlog::{Qlog, SinkConfig},
+    qtrace, qwarn,
 };
 use nss::{
     AntiReplay, Cipher, PrivateKey, PublicKey, ZeroRttCheckResult, ZeroRttChecker,
-    encode_ech_config,
+    encode_ech_config, random,
 };
-use rustc_hash::FxHashSet as HashSet;
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
 
 pub use crate::addr_valid::ValidateAddress;
 use crate::{
-    ConnectionParameters, OutputBatch, Res, Version,
+    ConnectionParameters, Error, OutputBatch, Res, Version,
     addr_valid::{AddressValidation, AddressValidationResult},
-    cid::{ConnectionId, ConnectionIdGenerator, ConnectionIdRef},
+    cid::{ConnectionId, ConnectionIdGenerator, ConnectionIdRef, LocalConnectionIdChange},
     connection::{Connection, Output, State},
+    crypto::{CryptoDxDirection, CryptoDxState, SERVER_INITIAL_LABEL},
+    frame::{FrameEncoder as _, FrameType},
     packet::{self, MIN_INITIAL_PACKET_SIZE, Public},
     saved::SavedDatagram,
+    stateless_reset::{self, Key as StatelessResetKey},
+    stats::ServerStats,
 };
 
 /// A `ServerZeroRttChecker` is a simple wrapper around a single checker.
@@ -100,6 +110,229 @@ impl EchConfig {
     }
 }
 
+/// What a server does with new connections while too many handshakes are in progress.
+/// See [`AdmissionLimits::handshake_threshold`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverloadAction {
+    /// Require clients to validate their address with a Retry, unless they have
+    /// a valid token.  This only changes anything if the server doesn't already
+    /// require address validation, see [`Server::set_validation`].
+    #[default]
+    Retry,
+    /// Refuse new connections with a `CONNECTION_REFUSED` error.
+    Refuse,
+}
+
+/// Limits on the connections that a server accepts, which protect it against
+/// floods of handshakes.  By default, there are no limits.
+///
+/// Connections are refused by sending an Initial packet with a `CONNECTION_CLOSE`
+/// frame, without creating any state for the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdmissionLimits {
+    max_connections: Option<usize>,
+    max_handshakes_per_prefix: Option<usize>,
+    ipv4_prefix_len: u8,
+    ipv6_prefix_len: u8,
+    handshake_threshold: Option<usize>,
+    overload_action: OverloadAction,
+}
+
+impl Default for AdmissionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_handshakes_per_prefix: None,
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 48,
+            handshake_threshold: None,
+            overload_action: OverloadAction::default(),
+        }
+    }
+}
+
+impl AdmissionLimits {
+    /// Refuse new connections while the server has `max` connections.
+    #[must_use]
+    pub const fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Refuse new connections from a source prefix while `max` handshakes from
+    /// that prefix are in progress.  See [`Self::prefix_len`].
+    #[must_use]
+    pub const fn max_handshakes_per_prefix(mut self, max: usize) -> Self {
+        self.max_handshakes_per_prefix = Some(max);
+        self
+    }
+
+    /// Set the length, in bits, of the prefix of IPv4 and IPv6 source addresses
+    /// that handshakes are counted by.  The defaults are 24 and 48.
+    #[must_use]
+    pub const fn prefix_len(mut self, ipv4: u8, ipv6: u8) -> Self {
+        self.ipv4_prefix_len = if ipv4 > 32 { 32 } else { ipv4 };
+        self.ipv6_prefix_len = if ipv6 > 128 { 128 } else { ipv6 };
+        self
+    }
+
+    /// Take `action` for new connections while `threshold` or more handshakes are
+    /// in progress.
+    #[must_use]
+    pub const fn handshake_threshold(mut self, threshold: usize, action: OverloadAction) -> Self {
+        self.handshake_threshold = Some(threshold);
+        self.overload_action = action;
+        self
+    }
+
+    fn prefix(&self, addr: IpAddr) -> IpAddr {
+        match addr {
+            IpAddr::V4(a) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.ipv4_prefix_len))
+                    .unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from_bits(a.to_bits() & mask))
+            }
+            IpAddr::V6(a) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.ipv6_prefix_len))
+                    .unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from_bits(a.to_bits() & mask))
+            }
+        }
+    }
+}
+
+/// The state that the server uses to enforce [`AdmissionLimits`].
+#[derive(Debug, Default)]
+struct Admission {
+    limits: AdmissionLimits,
+    /// The number of handshakes in progress.
+    handshakes: usize,
+    /// The number of handshakes in progress, by source prefix.
+    prefixes: HashMap<IpAddr, usize>,
+    stats: ServerStats,
+}
+
+impl Admission {
+    /// Whether enough handshakes are in progress to take `action`.
+    fn overloaded(&self, action: OverloadAction) -> bool {
+        self.limits.overload_action == action
+            && self
+                .limits
+                .handshake_threshold
+                .is_some_and(|t| self.handshakes >= t)
+    }
+
+    /// Whether to accept a new connection from `peer` when the server has `connections`.
+    fn admit(&mut self, connections: usize, peer: IpAddr) -> bool {
+        if self
+            .limits
+            .max_connections
+            .is_some_and(|max| connections >= max)
+        {
+            qdebug!("Connection limit of {connections} reached");
+            self.stats.refused_connection_limit += 1;
+            false
+        } else if self.overloaded(OverloadAction::Refuse) {
+            qdebug!("{} handshakes in progress", self.handshakes);
+            self.stats.refused_overload += 1;
+            false
+        } else if self.limits.max_handshakes_per_prefix.is_some_and(|max| {
+            self.prefixes
+                .get(&self.limits.prefix(peer))
+                .is_some_and(|&n| n >= max)
+        }) {
+            qdebug!("Handshake limit reached for {peer}");
+            self.stats.refused_prefix_limit += 1;
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Count a handshake from `peer`.  Returns the prefix to pass to [`Self::end_handshake`].
+    fn start_handshake(&mut self, peer: IpAddr) -> IpAddr {
+        let prefix = self.limits.prefix(peer);
+        self.handshakes += 1;
+        *self.prefixes.entry(prefix).or_default() += 1;
+        prefix
+    }
+
+    fn end_handshake(&mut self, prefix: IpAddr) {
+        self.handshakes -= 1;
+        if let Entry::Occupied(mut e) = self.prefixes.entry(prefix) {
+            *e.get_mut() -= 1;
+            if *e.get() == 0 {
+                e.remove();
+            }
+        }
+    }
+}
+
+/// A connection that the server manages, with the state that is used to decide
+/// when to ask it for output.
+#[derive(Debug)]
+struct ServerConnection {
+    /// An identifier that the server assigns.
+    id: u64,
+    c: Rc<RefCell<Connection>>,
+    /// Whether the connection is in the ready queue.
+    ready: Cell<bool>,
+    /// When the connection next needs to be asked for output, if it is waiting for a timer.
+    deadline: Cell<Option<Instant>>,
+    /// The prefix of the client address, while the handshake is in progress.
+    handshake: Cell<Option<IpAddr>>,
+    /// The ready queue of the server, so that a connection that is changed through
+    /// a [`ConnectionRef`] can be woken.
+    ready_queue: Weak<RefCell<VecDeque<Rc<Self>>>>,
+}
+
+impl ServerConnection {
+    /// Put the connection at the back of the ready queue, unless it is already there.
+    fn wake(self: &Rc<Self>) {
+        if !self.ready.replace(true)
+            && let Some(queue) = self.ready_queue.upgrade()
+        {
+            queue.borrow_mut().push_back(Rc::clone(self));
+        }
+    }
+}
+
+/// An entry in the timer heap.  Entries are left in place when a connection's
+/// deadline changes, so an entry only counts if it matches the connection's deadline.
+struct Timer {
+    deadline: Instant,
+    c: Rc<ServerConnection>,
+}
+
+impl Timer {
+    fn is_current(&self) -> bool {
+        self.c.deadline.get() == Some(self.deadline)
+    }
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Reversed, so that [`BinaryHeap`] yields the earliest deadline first.
+impl Ord for Timer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.deadline.cmp(&self.deadline)
+    }
+}
+
 pub struct Server {
     /// The names of certificates.
     certs: Vec<String>,
@@ -115,14 +348,28 @@ pub struct Server {
     cid_generator: Rc<RefCell<dyn ConnectionIdGenerator>>,
     /// Connection parameters.
     conn_params: ConnectionParameters,
-    /// All connections.
-    connections: Vec<Rc<RefCell<Connection>>>,
+    /// All connections, by the identifier that the server assigned.
+    connections: HashMap<u64, Rc<ServerConnection>>,
+    /// The identifier for the next connection.
+    next_connection_id: u64,
+    /// Connections, indexed by their local connection IDs.
+    connection_ids: HashMap<ConnectionId, Rc<ServerConnection>>,
+    /// Connections that might have output, in the order they are asked for it.
+    ready: Rc<RefCell<VecDeque<Rc<ServerConnection>>>>,
+    /// Connections that are waiting for a timer, earliest first.
+    timers: BinaryHeap<Timer>,
     /// Address validation logic, which determines whether we send a Retry.
     address_validation: Rc<RefCell<AddressValidation>>,
     /// Directory to create qlog traces in
     qlog_dir: Option<PathBuf>,
+    /// Sinks for qlog traces, which are used instead of `qlog_dir` if set.
+    qlog_sinks: Option<SinkConfig>,
     /// Encrypted client hello (ECH) configuration.
     ech_config: Option<EchConfig>,
+    /// Limits how many stateless resets are sent.
+    stateless_reset_limit: stateless_reset::RateLimit,
+    /// Limits on new connections.
+    admission: Admission,
     /// Remaining datagrams of a batch of datagrams provided via
     /// [`Server::process_multiple`]. An earlier datagram in the batch required
     /// an immediate return without further processing of the remaining
@@ -154,6 +401,13 @@ impl Server {
         conn_params: ConnectionParameters,
     ) -> Res<Self> {
         let validation = AddressValidation::new(now, ValidateAddress::Never)?;
+        // Always derive stateless reset tokens, so that resets can be sent
+        // for connections that are gone.
+        let conn_params = if conn_params.get_stateless_reset_key().is_some() {
+            conn_params
+        } else {
+            conn_params.stateless_reset_key(StatelessResetKey::random())
+        };
         Ok(Self {
             certs: certs.iter().map(|x| String::from(x.as_ref())).collect(),
             protocols: protocols.iter().map(|x| String::from(x.as_ref())).collect(),
@@ -162,24 +416,91 @@ impl Server {
             zero_rtt_checker: ServerZeroRttChecker::new(zero_rtt_checker),
             cid_generator,
             conn_params,
-            connections: Vec::new(),
+            connections: HashMap::default(),
+            next_connection_id: 0,
+            connection_ids: HashMap::default(),
+            ready: Rc::default(),
+            timers: BinaryHeap::new(),
             address_validation: Rc::new(RefCell::new(validation)),
             qlog_dir: None,
+            qlog_sinks: None,
             ech_config: None,
+            stateless_reset_limit: stateless_reset::RateLimit::default(),
+            admission: Admission::default(),
             saved_datagrams: VecDeque::new(),
         })
     }
 
+    /// Set the key that is used to derive stateless reset tokens.
+    /// This overrides any key set with [`ConnectionParameters::stateless_reset_key`].
+    ///
+    /// By default, a random key is used, which means that stateless resets only work
+    /// for connections that this instance created.  Servers that share a key, or that
+    /// are restarted with the same key, can send stateless resets for connections that
+    /// they have no state for.  This only affects connections that are created after
+    /// the key is set.
+    pub fn set_stateless_reset_key(&mut self, key: StatelessResetKey) {
+        self.conn_params = mem::take(&mut self.conn_params).stateless_reset_key(key);
+    }
+
     /// Set or clear directory to create logs of connection events in QLOG format.
     pub fn set_qlog_dir(&mut self, dir: Option<PathBuf>) {
         self.qlog_dir = dir;
     }
 
+    /// Set or clear the sinks that logs of connection events are sent to in QLOG format.
+    /// This takes precedence over [`Self::set_qlog_dir`].
+    pub fn set_qlog_sinks(&mut self, sinks: Option<SinkConfig>) {
+        self.qlog_sinks = sinks;
+    }
+
     /// Set the policy for address validation.
     pub fn set_validation(&self, v: ValidateAddress) {
         self.address_validation.borrow_mut().set_validation(v);
     }
 
+    /// Set limits on the connections that the server accepts.
+    pub fn set_admission_limits(&mut self, limits: AdmissionLimits) {
+        self.admission.limits = limits;
+    }
+
+    /// Statistics about connections that the server turned away.
+    #[must_use]
+    pub const fn stats(&self) -> &ServerStats {
+        &self.admission.stats
+    }
+
+    /// Set how often the keys that protect Retry and `NEW_TOKEN` tokens are rotated,
+    /// or `None` to disable automatic rotation.
+    /// See [`AddressValidation::set_key_rotation`].
+    pub fn set_token_key_rotation(&self, interval: Option<Duration>) {
+        self.address_validation
+            .borrow_mut()
+            .set_key_rotation(interval);
+    }
+
+    /// Rotate the keys that protect Retry and `NEW_TOKEN` tokens now.
+    pub fn rotate_token_keys(&self, now: Instant) {
+        self.address_validation.borrow_mut().rotate_keys(now);
+    }
+
+    /// Export the keys that protect Retry and `NEW_TOKEN` tokens,
+    /// so that other servers can accept the tokens that this server creates.
+    /// See [`AddressValidation::export_keys`].
+    #[must_use]
+    pub fn export_token_keys(&self) -> Vec<u8> {
+        self.address_validation.borrow().export_keys()
+    }
+
+    /// Import keys that another server exported with [`Self::export_token_keys`].
+    /// See [`AddressValidation::import_keys`].
+    ///
+    /// # Errors
+    /// When `keys` is not valid.
+    pub fn import_token_keys(&self, keys: &[u8], now: Instant) -> Res<()> {
+        self.address_validation.borrow_mut().import_keys(keys, now)
+    }
+
     /// Set the cipher suites that should be used.  Set an empty value to use
     /// default values.
     pub fn set_ciphers<A: AsRef<[Cipher]>>(&mut self, ciphers: A) {
@@ -234,79 +555,162 @@ impl Server {
         qdebug!("[{self}] Handle initial");
         #[cfg(feature = "build-fuzzing-corpus")]
         Self::write_addr_valid_corpus(dgram.source(), &initial.token);
-        let res = self
-            .address_validation
-            .borrow()
-            .validate(&initial.token, dgram.source(), now);
-        match res {
-            AddressValidationResult::Invalid => Output::None,
-            AddressValidationResult::Pass => self.accept_connection(initial, dgram, None, now),
-            AddressValidationResult::ValidRetry(orig_dcid) => {
-                self.accept_connection(initial, dgram, Some(orig_dcid), now)
-            }
+        self.address_validation.borrow_mut().maybe_rotate_keys(now);
+        let mut validation = self.address_validation.borrow().validation();
+        let retry_overload = validation == ValidateAddress::Never
+            && self.admission.overloaded(OverloadAction::Retry);
+        if retry_overload {
+            validation = ValidateAddress::NoToken;
+        }
+        let res = self.address_validation.borrow().validate_with(
+            &initial.token,
+            dgram.source(),
+            now,
+            validation,
+        );
+        let orig_dcid = match res {
+            AddressValidationResult::Invalid => return Output::None,
+            AddressValidationResult::Pass => None,
+            AddressValidationResult::ValidRetry(orig_dcid) => Some(orig_dcid),
             AddressValidationResult::Validate => {
-                qinfo!("[{self}] Send retry for {:?}", initial.dst_cid);
-
-                // > This Destination Connection ID MUST be at least 8 bytes in length.
-                //
-                // <https://www.rfc-editor.org/rfc/rfc9000.html#section-7.2>
-                if initial.dst_cid.len() < 8 {
-                    qerror!(
-                        "[{self}] DCID too short ({} bytes), dropping packet",
-                        initial.dst_cid.len()
-                    );
-                    return Output::None;
+                if retry_overload {
+                    self.admission.stats.retry_overload += 1;
                 }
+                return self.send_retry(&initial, &dgram, now);
+            }
+        };
+        if !self
+            .admission
+            .admit(self.connections.len(), dgram.source().ip())
+        {
+            qinfo!("[{self}] Refuse connection {:?}", initial.dst_cid);
+            return self.refuse_connection(&initial, &dgram);
+        }
+        self.accept_connection(initial, dgram, orig_dcid, now)
+    }
 
-                let res = self.address_validation.borrow().generate_retry_token(
-                    &initial.dst_cid,
-                    dgram.source(),
-                    now,
-                );
-                let Ok(token) = res else {
-                    qerror!("[{self}] unable to generate token, dropping packet");
-                    return Output::None;
-                };
-                if let Some(new_dcid) = self.cid_generator.borrow_mut().generate_cid() {
-                    let packet = packet::Builder::retry(
-                        initial.version,
-                        &initial.src_cid,
-                        &new_dcid,
-                        &token,
-                        &initial.dst_cid,
-                    );
-                    packet.map_or_else(
-                        |_| {
-                            qerror!("[{self}] unable to encode retry, dropping packet");
-                            Output::None
-                        },
-                        |p| {
-                            qdebug!(
-                                "[{self}] type={:?} path:{} {}->{} {:?} len {}",
-                                packet::Type::Retry,
-                                initial.dst_cid,
-                                dgram.destination(),
-                                dgram.source(),
-                                Tos::default(),
-                                p.len(),
-                            );
-                            Output::Datagram(Datagram::new(
-                                dgram.destination(),
-                                dgram.source(),
-                                Tos::default(),
-                                p,
-                            ))
-                        },
-                    )
-                } else {
-                    qerror!("[{self}] no connection ID for retry, dropping packet");
+    fn send_retry<A: AsRef<[u8]>>(
+        &self,
+        initial: &InitialDetails,
+        dgram: &Datagram<A>,
+        now: Instant,
+    ) -> Output {
+        qinfo!("[{self}] Send retry for {:?}", initial.dst_cid);
+
+        // > This Destination Connection ID MUST be at least 8 bytes in length.
+        //
+        // <https://www.rfc-editor.org/rfc/rfc9000.html#section-7.2>
+        if initial.dst_cid.len() < 8 {
+            qerror!(
+                "[{self}] DCID too short ({} bytes), dropping packet",
+                initial.dst_cid.len()
+            );
+            return Output::None;
+        }
+
+        let res = self.address_validation.borrow().generate_retry_token(
+            &initial.dst_cid,
+            dgram.source(),
+            now,
+        );
+        let Ok(token) = res else {
+            qerror!("[{self}] unable to generate token, dropping packet");
+            return Output::None;
+        };
+        if let Some(new_dcid) = self.cid_generator.borrow_mut().generate_cid() {
+            let packet = packet::Builder::retry(
+                initial.version,
+                &initial.src_cid,
+                &new_dcid,
+                &token,
+                &initial.dst_cid,
+            );
+            packet.map_or_else(
+                |_| {
+                    qerror!("[{self}] unable to encode retry, dropping packet");
                     Output::None
-                }
+                },
+                |p| {
+                    qdebug!(
+                        "[{self}] type={:?} path:{} {}->{} {:?} len {}",
+                        packet::Type::Retry,
+                        initial.dst_cid,
+                        dgram.destination(),
+                        dgram.source(),
+                        Tos::default(),
+                        p.len(),
+                    );
+                    Output::Datagram(Datagram::new(
+                        dgram.destination(),
+                        dgram.source(),
+                        Tos::default(),
+                        p,
+                    ))
+                },
+            )
+        } else {
+            qerror!("[{self}] no connection ID for retry, dropping packet");
+            Output::None
+        }
+    }
+
+    /// Close a connection with `CONNECTION_REFUSED` in an Initial packet,
+    /// without creating any state for it.
+    fn refuse_connection<A: AsRef<[u8]>>(
+        &self,
+        initial: &InitialDetails,
+        dgram: &Datagram<A>,
+    ) -> Output {
+        let packet = CryptoDxState::new_initial(
+            initial.version,
+            CryptoDxDirection::Write,
+            SERVER_INITIAL_LABEL,
+            &initial.dst_cid,
+            0,
+        )
+        .and_then(|mut crypto| {
+            let mut builder = packet::Builder::long(
+                Encoder::default(),
+                packet::Type::Initial,
+                initial.version,
+                Some(&initial.src_cid),
+                Some(&initial.dst_cid),
+                MIN_INITIAL_PACKET_SIZE,
+            );
+            builder.initial_token(&[]);
+            builder.pn(0, 1);
+            builder.encode_frame(FrameType::ConnectionCloseTransport, |b| {
+                b.encode_varint(Error::ConnectionRefused.code());
+                b.encode_varint(FrameType::Padding);
+                b.encode_vvec(&[]);
+            });
+            builder.build(&mut crypto)
+        });
+        match packet {
+            Ok(p) => Output::Datagram(Datagram::new(
+                dgram.destination(),
+                dgram.source(),
+                Tos::default(),
+                p.into(),
+            )),
+            Err(e) => {
+                qerror!("[{self}] Unable to encode CONNECTION_CLOSE: {e}");
+                Output::None
             }
         }
     }
 
     fn create_qlog_trace(&self, odcid: ConnectionIdRef<'_>, now: Instant) -> Qlog {
+        if let Some(sinks) = &self.qlog_sinks {
+            return sinks.create(
+                u16::from_ne_bytes(random::<2>()),
+                &format!("server-{odcid}"),
+                Role::Server,
+                Some("Neqo server qlog".to_string()),
+                Some("Neqo server qlog".to_string()),
+                now,
+            );
+        }
         self.qlog_dir
             .as_ref()
             .map_or_else(Qlog::disabled, |qlog_dir| {
@@ -361,9 +765,6 @@ impl Server {
             "[{self}] Accept connection {:?}",
             orig_dcid.as_ref().unwrap_or(&initial.dst_cid)
         );
-        // The internal connection ID manager that we use is not used directly.
-        // Instead, wrap it so that we can save connection IDs.
-
         let mut params = self.conn_params.clone();
         params.get_versions_mut().set_initial(initial.version);
         let sconn = Connection::new_server(
@@ -376,13 +777,18 @@ impl Server {
         match sconn {
             Ok(mut c) => {
                 self.setup_connection(&mut c, initial, orig_dcid, now);
+                c.track_local_cids();
+                let peer = dgram.source().ip();
                 let out = c.process(Some(dgram), now);
-                self.connections.push(Rc::new(RefCell::new(c)));
+                let sc = self.add_connection(c);
+                sc.handshake.set(Some(self.admission.start_handshake(peer)));
+                // The connection might have more to send.
+                sc.wake();
                 out
             }
             Err(e) => {
                 qwarn!("[{self}] Unable to create connection");
-                if e == crate::Error::VersionNegotiation {
+                if e == Error::VersionNegotiation {
                     crate::qlog::server_version_information_failed(
                         &mut self.create_qlog_trace(
                             orig_dcid.unwrap_or(initial.dst_cid).as_cid_ref(),
@@ -398,6 +804,105 @@ impl Server {
         }
     }
 
+    fn add_connection(&mut self, c: Connection) -> Rc<ServerConnection> {
+        let id = self.next_connection_id;
+        self.next_connection_id += 1;
+        let sc = Rc::new(ServerConnection {
+            id,
+            c: Rc::new(RefCell::new(c)),
+            ready: Cell::new(false),
+            deadline: Cell::new(None),
+            handshake: Cell::new(None),
+            ready_queue: Rc::downgrade(&self.ready),
+        });
+        Self::update_connection_ids(&mut self.connection_ids, &sc);
+        self.connections.insert(id, Rc::clone(&sc));
+        sc
+    }
+
+    fn remove_connection(&mut self, sc: &Rc<ServerConnection>) {
+        qdebug!("[{self}] Remove connection {}", sc.id);
+        if let Some(prefix) = sc.handshake.take() {
+            self.admission.end_handshake(prefix);
+        }
+        Self::update_connection_ids(&mut self.connection_ids, sc);
+        for cid in sc.c.borrow().local_cids() {
+            Self::remove_connection_id(&mut self.connection_ids, cid, sc);
+        }
+        sc.deadline.set(None);
+        self.connections.remove(&sc.id);
+    }
+
+    /// Stop counting the handshake of `sc` once it is no longer in progress.
+    fn update_handshake(&mut self, sc: &ServerConnection) {
+        if let Some(prefix) = sc.handshake.get()
+            && !matches!(
+                sc.c.borrow().state(),
+                State::Init | State::WaitInitial | State::WaitVersion | State::Handshaking
+            )
+        {
+            sc.handshake.set(None);
+            self.admission.end_handshake(prefix);
+        }
+    }
+
+    /// Apply changes to the local connection IDs of `c` to the index of connections.
+    /// If connection IDs collide, the connection that used it first keeps it.
+    fn update_connection_ids(
+        connection_ids: &mut HashMap<ConnectionId, Rc<ServerConnection>>,
+        c: &Rc<ServerConnection>,
+    ) {
+        for change in c.c.borrow_mut().take_local_cid_changes() {
+            match change {
+                LocalConnectionIdChange::Added(cid) => {
+                    connection_ids.entry(cid).or_insert_with(|| Rc::clone(c));
+                }
+                LocalConnectionIdChange::Removed(cid) => {
+                    Self::remove_connection_id(connection_ids, &cid, c);
+                }
+            }
+        }
+    }
+
+    /// Remove `cid` from the index of connections, if it refers to `c`.
+    fn remove_connection_id(
+        connection_ids: &mut HashMap<ConnectionId, Rc<ServerConnection>>,
+        cid: &ConnectionId,
+        c: &Rc<ServerConnection>,
+    ) {
+        if connection_ids.get(cid).is_some_and(|x| Rc::ptr_eq(x, c)) {
+            connection_ids.remove(cid);
+        }
+    }
+
+    /// Produce a stateless reset for a packet of `len` bytes with a connection ID of `dcid`
+    /// that doesn't belong to any connection.
+    fn stateless_reset(
+        &mut self,
+        dcid: ConnectionIdRef<'_>,
+        len: usize,
+        now: Instant,
+    ) -> Option<Vec<u8>> {
+        let key = self.conn_params.get_stateless_reset_key()?;
+        if dcid.is_empty() {
+            return None;
+        }
+        if stateless_reset::packet_len(len).is_none() {
+            qtrace!("[{self}] Packet too small for a stateless reset");
+            return None;
+        }
+        // Check the rate limit before doing any crypto.
+        if !self.stateless_reset_limit.allow(now) {
+            qdebug!("[{self}] Stateless reset rate limit reached");
+            return None;
+        }
+        let token = key
+            .token(&dcid)
+            .inspect_err(|e| qwarn!("[{self}] Unable to derive stateless reset token: {e}"))
+            .ok()?;
+        stateless_reset::packet(&token, len)
+    }
+
     /// Process new input datagrams on the connection.
     pub fn process_multiple_input<
         A: AsRef<[u8]> + AsMut<[u8]>,
@@ -450,19 +955,34 @@ impl Server {
             };
 
             // Finding an existing connection. Should be the most common case.
-            if let Some(c) = self
-                .connections
-                .iter_mut()
-                .find(|c| c.borrow().is_valid_local_cid(packet.dcid()))
-            {
-                c.borrow_mut().process_input(dgram, now);
+            if let Some(c) = self.connection_ids.get(&packet.dcid()[..]).cloned() {
+                c.c.borrow_mut().process_input(dgram, now);
+                Self::update_connection_ids(&mut self.connection_ids, &c);
+                self.update_handshake(&c);
+                c.wake();
                 continue;
             }
 
             if packet.packet_type() == packet::Type::Short {
-                // TODO send a stateless reset here.
                 qtrace!("[{self}] Short header packet for an unknown connection");
-                continue;
+                let dcid = ConnectionId::from(packet.dcid());
+                let Some(reset) = self.stateless_reset(dcid.as_cid_ref(), len, now) else {
+                    continue;
+                };
+                qdebug!(
+                    "[{self}] type=StatelessReset path:{dcid} {destination}->{source} {:?} len {}",
+                    Tos::default(),
+                    reset.len(),
+                );
+
+                self.saved_datagrams.extend(dgrams.map(|d| SavedDatagram {
+                    d: d.to_owned(),
+                    t: now,
+                }));
+
+                return OutputBatch::DatagramBatch(
+                    Datagram::new(destination, source, Tos::default(), reset).into(),
+                );
             }
 
             if packet.packet_type() == packet::Type::OtherVersion
@@ -543,30 +1063,83 @@ impl Server {
         OutputBatch::None
     }
 
-    /// Iterate through the pending connections looking for any that might want
-    /// to send a datagram.  Stop at the first one that does.
+    /// Set the deadline for asking `sc` for output again.
+    fn set_timer(&mut self, sc: &Rc<ServerConnection>, deadline: Instant) {
+        if sc.deadline.replace(Some(deadline)) == Some(deadline) {
+            return;
+        }
+        // Drop stale entries if they start to outnumber the connections.
+        if self.timers.len() > 2 * self.connections.len() {
+            self.timers.retain(Timer::is_current);
+        }
+        self.timers.push(Timer {
+            deadline,
+            c: Rc::clone(sc),
+        });
+    }
+
+    /// Move connections with expired timers to the ready queue.
+    fn wake_expired(&mut self, now: Instant) {
+        while let Some(t) = self.timers.peek()
+            && t.deadline <= now
+        {
+            let t = self.timers.pop().expect("peeked");
+            if t.is_current() {
+                t.c.deadline.set(None);
+                t.c.wake();
+            }
+        }
+    }
+
+    /// The time until the earliest timer expires.
+    fn next_timer(&mut self, now: Instant) -> OutputBatch {
+        while self.timers.peek().is_some_and(|t| !t.is_current()) {
+            self.timers.pop();
+        }
+        self.timers.peek().map_or(OutputBatch::None, |t| {
+            OutputBatch::Callback(t.deadline.saturating_duration_since(now))
+        })
+    }
+
+    fn next_ready(&self) -> Option<Rc<ServerConnection>> {
+        let sc = self.ready.borrow_mut().pop_front()?;
+        sc.ready.set(false);
+        Some(sc)
+    }
+
+    /// Ask connections in the ready queue for output, in turn, stopping at the
+    /// first one that has something to send.  That connection goes to the back of
+    /// the queue, so that connections take turns.  Connections that have nothing to
+    /// send wait for their timer, or to be woken by input or the application.
     fn process_next_output(&mut self, now: Instant, max_datagrams: NonZeroUsize) -> OutputBatch {
         assert!(
             self.saved_datagrams.is_empty(),
             "Always process all inbound datagrams first."
         );
-        let mut callback = None;
+        self.wake_expired(now);
 
-        for connection in &mut self.connections {
-            match connection
-                .borrow_mut()
-                .process_multiple_output(now, max_datagrams)
-            {
-                OutputBatch::None => {}
-                d @ OutputBatch::DatagramBatch(_) => return d,
-                OutputBatch::Callback(next) => match callback {
-                    Some(previous) => callback = Some(min(previous, next)),
-                    None => callback = Some(next),
-                },
+        while let Some(sc) = self.next_ready() {
+            let out =
+                sc.c.borrow_mut()
+                    .process_multiple_output(now, max_datagrams);
+            Self::update_connection_ids(&mut self.connection_ids, &sc);
+            self.update_handshake(&sc);
+            match out {
+                d @ OutputBatch::DatagramBatch(_) => {
+                    sc.wake();
+                    return d;
+                }
+                OutputBatch::Callback(delay) => self.set_timer(&sc, now + delay),
+                OutputBatch::None => {
+                    sc.deadline.set(None);
+                    if matches!(sc.c.borrow().state(), State::Closed(_)) {
+                        self.remove_connection(&sc);
+                    }
+                }
             }
         }
 
-        callback.map_or(OutputBatch::None, OutputBatch::Callback)
+        self.next_timer(now)
     }
 
     /// Short-hand for [`Server::process`] without an input datagram.
@@ -601,17 +1174,7 @@ impl Server {
         }
 
         // Process output datagrams.
-        let maybe_callback = match self.process_next_output(now, max_datagrams) {
-            // Return immediately. Do any maintenance on next call.
-            o @ OutputBatch::DatagramBatch(_) => return o,
-            o @ (OutputBatch::Callback(_) | OutputBatch::None) => o,
-        };
-
-        // Clean-up closed connections.
-        self.connections
-            .retain(|c| !matches!(c.borrow().state(), State::Closed(_)));
-
-        maybe_callback
+        self.process_next_output(now, max_datagrams)
     }
 
     /// This lists the connections that have received new events
@@ -623,8 +1186,8 @@ impl Server {
     #[must_use]
     pub fn active_connections(&self) -> HashSet<ConnectionRef> {
         self.connections
-            .iter()
-            .filter(|c| c.borrow().has_events())
+            .values()
+            .filter(|c| c.c.borrow().has_events())
             .map(|c| ConnectionRef { c: Rc::clone(c) })
             .collect()
     }
@@ -633,29 +1196,37 @@ impl Server {
     /// `process()`.
     #[must_use]
     pub fn has_active_connections(&self) -> bool {
-        self.connections.iter().any(|c| c.borrow().has_events())
+        self.connections.values().any(|c| c.c.borrow().has_events())
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct ConnectionRef {
-    c: Rc<RefCell<Connection>>,
+    c: Rc<ServerConnection>,
 }
 
 impl ConnectionRef {
     #[must_use]
     pub fn borrow(&self) -> impl Deref<Target = Connection> + '_ {
-        self.c.borrow()
+        self.c.c.borrow()
     }
 
+    /// Mutable access to the connection.
+    /// The server asks the connection for output the next time it is processed.
     #[must_use]
     pub fn borrow_mut(&self) -> impl DerefMut<Target = Connection> + '_ {
-        self.c.borrow_mut()
+        self.c.wake();
+        self.c.c.borrow_mut()
     }
 
+    /// The connection itself.
+    /// Like [`Self::borrow_mut`], this causes the server to ask the connection for output
+    /// the next time it is processed, but changes made after that are only noticed
+    /// when the connection receives a packet or a timer expires.
     #[must_use]
     pub fn connection(&self) -> Rc<RefCell<Connection>> {
-        Rc::clone(&self.c)
+        self.c.wake();
+        Rc::clone(&self.c.c)
     }
 }
 
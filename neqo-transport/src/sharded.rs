@@ -0,0 +1,486 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A server front-end that spreads connections over several threads.
+//!
+//! [`server::Server`] is built on `Rc<RefCell<..>>`, so it can only be used from
+//! one thread.  [`Server`] runs a [`server::Server`] on each of N threads, which
+//! are called shards, and routes each datagram to a shard.
+//!
+//! Shards encode their index in the connection IDs they choose, using a plaintext
+//! [`quic_lb`] configuration, so packets for an established connection are routed
+//! by their destination connection ID.  The destination connection ID of Initial
+//! and 0-RTT packets from a client is chosen by the client, so these are routed by a
+//! keyed hash of the connection ID.  A Retry uses a connection ID from the shard that
+//! sent it, so the Initial that follows goes to that shard.
+//!
+//! Some state is shared across shards:
+//!
+//! * The keys that protect Retry and `NEW_TOKEN` tokens are held by the front-end,
+//!   imported into each shard, and rotated for all shards at once, so a token from
+//!   one shard is accepted by every shard.
+//! * NSS objects, such as the anti-replay context and ECH keys, can't be moved between
+//!   threads.  Each shard creates its own from the same configuration.  Anti-replay
+//!   still works across shards: packet protection for 0-RTT covers the destination
+//!   connection ID, so a replayed `ClientHello` can only have its 0-RTT data accepted
+//!   with the original connection ID, which routes to the shard that saw it first.
+//!   For ECH, each shard has to be configured with the same keys; the front-end
+//!   checks that all shards advertise the same configuration.
+//!
+//! [`Router`] can be cloned and sent to other threads, so that datagrams can be
+//! read from one UDP socket or from several `SO_REUSEPORT` sockets.  Datagrams that
+//! shards send are collected in one queue, see [`Server::recv_output`].
+
+use std::{
+    cell::RefCell,
+    hash::{BuildHasher as _, RandomState},
+    mem,
+    num::NonZeroUsize,
+    rc::Rc,
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use neqo_common::{Datagram, Decoder, qdebug, qerror, qinfo, to_u64};
+
+use crate::{
+    Error, Output, Res,
+    addr_valid::{AddressValidation, ValidateAddress},
+    quic_lb, server,
+};
+
+/// The config rotation codepoint for connection IDs that carry a shard index.
+const CONFIG_ID: u8 = 0;
+/// The length of the random part of connection IDs.
+const NONCE_LEN: usize = 8;
+
+/// The connection ID configuration that encodes the shard index in one byte.
+fn cid_config() -> quic_lb::Config {
+    quic_lb::Config::new(CONFIG_ID, 1, NONCE_LEN, None).expect("valid QUIC-LB configuration")
+}
+
+/// The server and application state that lives on the thread of one shard.
+pub trait Shard {
+    /// The server for this shard.
+    fn server(&mut self) -> &mut server::Server;
+
+    /// Handle connection events.  This is called each time the server has
+    /// processed input or a timer has expired.
+    fn process_events(&mut self, _now: Instant) {}
+}
+
+impl Shard for server::Server {
+    fn server(&mut self) -> &mut server::Server {
+        self
+    }
+}
+
+/// What a shard needs to create its [`server::Server`].  This is passed to the factory
+/// that is given to [`Server::new`], on the thread of the shard.
+#[derive(Debug)]
+pub struct Context {
+    index: usize,
+    config: quic_lb::Config,
+}
+
+impl Context {
+    /// The index of this shard.
+    #[must_use]
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+
+    /// A connection ID generator that encodes the index of this shard.
+    /// The server has to use this for packets to be routed to it.
+    ///
+    /// # Errors
+    /// When the generator can't be created.
+    pub fn cid_generator(&self) -> Res<Rc<RefCell<quic_lb::Generator>>> {
+        let server_id = [u8::try_from(self.index).map_err(|_| Error::InvalidInput)?];
+        Ok(Rc::new(RefCell::new(quic_lb::Generator::new(
+            self.config.clone(),
+            &server_id,
+        )?)))
+    }
+}
+
+enum Command {
+    Datagram(Datagram),
+    TokenKeys(Vec<u8>),
+    Stop,
+}
+
+/// Routes datagrams to shards.  This can be cloned and sent to other threads.
+#[derive(Clone, Debug)]
+pub struct Router {
+    shards: Vec<Sender<Command>>,
+    /// The first byte of connection IDs that carry a shard index.
+    first_octet: u8,
+    cid_len: usize,
+    /// The key for hashing connection IDs that clients choose.
+    hasher: RandomState,
+}
+
+impl Router {
+    fn new(shards: Vec<Sender<Command>>, config: &quic_lb::Config) -> Self {
+        let cid_len = config.cid_len();
+        Self {
+            shards,
+            first_octet: (config.id() << 5) | u8::try_from(cid_len - 1).expect("fits"),
+            cid_len,
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// The number of shards.
+    #[must_use]
+    pub const fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn dcid<'a>(&self, packet: &'a [u8]) -> Option<&'a [u8]> {
+        let mut dec = Decoder::from(packet);
+        if dec.decode_uint::<u8>()? & 0x80 == 0 {
+            dec.decode(self.cid_len)
+        } else {
+            dec.decode(4)?; // Version
+            dec.decode_vec(1)
+        }
+    }
+
+    /// The index of the shard that handles `packet`.
+    #[must_use]
+    pub fn shard(&self, packet: &[u8]) -> usize {
+        let dcid = self.dcid(packet).unwrap_or_default();
+        if dcid.len() == self.cid_len
+            && let [first, shard, ..] = dcid
+            && *first == self.first_octet
+            && usize::from(*shard) < self.shards.len()
+        {
+            return usize::from(*shard);
+        }
+        let hash = self.hasher.hash_one(dcid);
+        usize::try_from(hash % to_u64(self.shards.len())).expect("less than the shard count")
+    }
+
+    /// Pass a datagram to the shard that handles it.
+    ///
+    /// # Errors
+    /// When the shard has stopped.
+    pub fn route(&self, d: Datagram) -> Res<()> {
+        let shard = self.shard(&d);
+        self.shards[shard]
+            .send(Command::Datagram(d))
+            .map_err(|_| Error::Internal)
+    }
+
+    fn broadcast(&self, cmd: impl Fn() -> Command) {
+        for shard in &self.shards {
+            // A shard that has stopped has nothing to update.
+            _ = shard.send(cmd());
+        }
+    }
+}
+
+/// The loop that runs on the thread of each shard.
+struct Worker<S> {
+    shard: S,
+    commands: Receiver<Command>,
+    output: Sender<Datagram>,
+    clock: fn() -> Instant,
+    input: Vec<Datagram>,
+}
+
+impl<S: Shard> Worker<S> {
+    /// Handle a command.  Returns `false` if the shard should stop.
+    fn command(&mut self, cmd: Command, now: Instant) -> bool {
+        match cmd {
+            Command::Datagram(d) => self.input.push(d),
+            Command::TokenKeys(keys) => {
+                if let Err(e) = self.shard.server().import_token_keys(&keys, now) {
+                    qerror!("Shard failed to import token keys: {e}");
+                }
+            }
+            Command::Stop => return false,
+        }
+        true
+    }
+
+    /// Send datagrams until the server has nothing more to send.
+    /// Returns how long to wait for a timer, or `None` if the output
+    /// queue has gone away.
+    fn send(&mut self, mut out: Output, now: Instant) -> Option<Option<Duration>> {
+        loop {
+            match out {
+                Output::Datagram(d) => {
+                    self.output.send(d).ok()?;
+                    out = self.shard.server().process_output(now);
+                }
+                Output::Callback(t) => return Some(Some(t)),
+                Output::None => return Some(None),
+            }
+        }
+    }
+
+    fn run(mut self) {
+        let mut timeout = None;
+        loop {
+            let cmd = match timeout {
+                Some(t) => self.commands.recv_timeout(t),
+                None => self
+                    .commands
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let now = (self.clock)();
+            match cmd {
+                Ok(cmd) => {
+                    if !self.command(cmd, now) {
+                        return;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            // Process everything that is queued in one batch.
+            loop {
+                match self.commands.try_recv() {
+                    Ok(cmd) => {
+                        if !self.command(cmd, now) {
+                            return;
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+
+            let input = mem::take(&mut self.input);
+            let out = self.shard.server().process(input, now);
+            if self.send(out, now).is_none() {
+                return;
+            }
+            self.shard.process_events(now);
+            let out = self.shard.server().process_output(now);
+            let Some(t) = self.send(out, now) else {
+                return;
+            };
+            timeout = t;
+        }
+    }
+}
+
+/// A server that runs a [`server::Server`] on each of several threads.
+/// See the [module documentation](self) for details.
+pub struct Server {
+    router: Router,
+    output: Receiver<Datagram>,
+    threads: Vec<JoinHandle<()>>,
+    /// The keys that protect tokens, which all shards use.
+    token_keys: AddressValidation,
+    ech_config: Vec<u8>,
+}
+
+impl Server {
+    /// The most shards there can be, as the shard index is encoded in one byte.
+    pub const MAX_SHARDS: usize = 256;
+
+    /// Start `shards` threads, each of which calls `factory` to create a [`Shard`].
+    /// * `now` is the current time.
+    /// * `clock` provides the current time on shard threads.
+    /// * `factory` creates a shard.  It has to create the [`server::Server`] with the
+    ///   connection ID generator from [`Context::cid_generator`].  Every shard should be
+    ///   configured the same way, with an anti-replay context that uses the same parameters
+    ///   and, if ECH is enabled, with the same ECH keys.
+    ///
+    /// # Errors
+    /// When there are more than [`Self::MAX_SHARDS`] shards, when a thread can't be
+    /// started, when `factory` fails, or when shards have different ECH configurations.
+    pub fn new<F, S>(
+        shards: NonZeroUsize,
+        now: Instant,
+        clock: fn() -> Instant,
+        factory: F,
+    ) -> Res<Self>
+    where
+        F: Fn(&Context) -> Res<S> + Send + Sync + 'static,
+        S: Shard + 'static,
+    {
+        if shards.get() > Self::MAX_SHARDS {
+            return Err(Error::InvalidInput);
+        }
+        let token_keys = AddressValidation::new(now, ValidateAddress::Never)?;
+        let keys = token_keys.export_keys();
+        let factory = Arc::new(factory);
+        let (output_tx, output) = mpsc::channel();
+        let (ready_tx, ready) = mpsc::channel();
+        let mut senders = Vec::with_capacity(shards.get());
+        let mut threads = Vec::with_capacity(shards.get());
+        let mut result = Ok(());
+        for index in 0..shards.get() {
+            let (tx, commands) = mpsc::channel();
+            let factory = Arc::clone(&factory);
+            let keys = keys.clone();
+            let output = output_tx.clone();
+            let ready = ready_tx.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("neqo-shard-{index}"))
+                .spawn(move || {
+                    let ctx = Context {
+                        index,
+                        config: cid_config(),
+                    };
+                    let shard = factory(&ctx).and_then(|mut shard| {
+                        let server = shard.server();
+                        server.set_token_key_rotation(None);
+                        server.import_token_keys(&keys, clock())?;
+                        Ok(shard)
+                    });
+                    match shard {
+                        Ok(mut shard) => {
+                            _ = ready.send(Ok(shard.server().ech_config().to_vec()));
+                            drop(ready);
+                            Worker {
+                                shard,
+                                commands,
+                                output,
+                                clock,
+                                input: Vec::new(),
+                            }
+                            .run();
+                        }
+                        Err(e) => {
+                            _ = ready.send(Err(e));
+                        }
+                    }
+                });
+            match spawned {
+                Ok(t) => {
+                    senders.push(tx);
+                    threads.push(t);
+                }
+                Err(e) => {
+                    qerror!("Unable to start shard {index}: {e}");
+                    result = Err(Error::Internal);
+                    break;
+                }
+            }
+        }
+        drop(ready_tx);
+
+        let mut server = Self {
+            router: Router::new(senders, &cid_config()),
+            output,
+            threads,
+            token_keys,
+            ech_config: Vec::new(),
+        };
+        result?;
+        let mut ech_config = None;
+        for _ in 0..server.threads.len() {
+            let config = ready.recv().map_err(|_| Error::Internal)??;
+            if ech_config.get_or_insert_with(|| config.clone()) != &config {
+                qerror!("Shards have different ECH configurations");
+                return Err(Error::InvalidInput);
+            }
+        }
+        server.ech_config = ech_config.unwrap_or_default();
+        qinfo!("Started {} shards", server.threads.len());
+        Ok(server)
+    }
+
+    /// A router that can be used on other threads.
+    #[must_use]
+    pub fn router(&self) -> Router {
+        self.router.clone()
+    }
+
+    /// Pass a datagram to the shard that handles it.
+    ///
+    /// # Errors
+    /// When the shard has stopped.
+    pub fn route(&self, d: Datagram) -> Res<()> {
+        self.router.route(d)
+    }
+
+    /// Take a datagram that a shard wants to send, waiting up to `timeout` for one.
+    /// With no `timeout`, this does not wait.
+    #[must_use]
+    pub fn recv_output(&self, timeout: Option<Duration>) -> Option<Datagram> {
+        timeout.map_or_else(
+            || self.output.try_recv().ok(),
+            |t| self.output.recv_timeout(t).ok(),
+        )
+    }
+
+    /// The ECH configuration that all shards use, which is empty if ECH is not enabled.
+    #[must_use]
+    pub fn ech_config(&self) -> &[u8] {
+        &self.ech_config
+    }
+
+    fn update_token_keys(&self) {
+        let keys = self.token_keys.export_keys();
+        self.router.broadcast(|| Command::TokenKeys(keys.clone()));
+    }
+
+    /// Set how often the keys that protect Retry and `NEW_TOKEN` tokens are rotated,
+    /// or `None` to disable automatic rotation.
+    /// Rotation only happens in [`Self::maybe_rotate_token_keys`].
+    pub fn set_token_key_rotation(&mut self, interval: Option<Duration>) {
+        self.token_keys.set_key_rotation(interval);
+    }
+
+    /// Rotate the keys that protect tokens, if the rotation interval has passed.
+    /// This should be called periodically.
+    pub fn maybe_rotate_token_keys(&mut self, now: Instant) {
+        let before = self.token_keys.export_keys();
+        self.token_keys.maybe_rotate_keys(now);
+        if self.token_keys.export_keys() != before {
+            self.update_token_keys();
+        }
+    }
+
+    /// Rotate the keys that protect tokens on all shards now.
+    pub fn rotate_token_keys(&mut self, now: Instant) {
+        self.token_keys.rotate_keys(now);
+        self.update_token_keys();
+    }
+
+    /// Export the keys that protect tokens.
+    /// See [`server::Server::export_token_keys`].
+    #[must_use]
+    pub fn export_token_keys(&self) -> Vec<u8> {
+        self.token_keys.export_keys()
+    }
+
+    /// Import keys that another server exported, for use on all shards.
+    /// See [`server::Server::import_token_keys`].
+    ///
+    /// # Errors
+    /// When `keys` is not valid.
+    pub fn import_token_keys(&mut self, keys: &[u8], now: Instant) -> Res<()> {
+        self.token_keys.import_keys(keys, now)?;
+        self.update_token_keys();
+        Ok(())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.router.broadcast(|| Command::Stop);
+        for t in self.threads.drain(..) {
+            if t.join().is_err() {
+                qerror!("Shard thread panicked");
+            }
+        }
+        qdebug!("Stopped shards");
+    }
+}
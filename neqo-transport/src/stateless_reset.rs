@@ -6,10 +6,15 @@
 
 //! Stateless Reset Token implementation.
 
+use std::{
+    fmt::{self, Debug, Formatter},
+    time::{Duration, Instant},
+};
+
 use neqo_common::Decoder;
-use nss::random;
+use nss::{TLS_AES_128_GCM_SHA256, TLS_VERSION_1_3, hkdf, random, randomize};
 
-use crate::Error;
+use crate::{Error, Res};
 
 /// A stateless reset token is a 16-byte value that is used to identify
 /// a stateless reset packet.
@@ -75,6 +80,144 @@ impl AsRef<[u8]> for Token {
     }
 }
 
+/// A static key that is used to derive stateless reset tokens from connection IDs.
+///
+/// Deriving tokens means that a server can recompute the token for a connection ID
+/// after it has lost all state for the connection, see
+/// <https://www.rfc-editor.org/rfc/rfc9000.html#section-10.3.2>.
+/// All server instances that share a key produce the same tokens.
+#[derive(Clone)]
+pub struct Key([u8; Self::LEN]);
+
+impl Key {
+    pub const LEN: usize = 32;
+
+    /// Create a key from existing key material.
+    #[must_use]
+    pub const fn new(key: [u8; Self::LEN]) -> Self {
+        Self(key)
+    }
+
+    /// Generate a random key.
+    #[must_use]
+    pub fn random() -> Self {
+        Self(random::<{ Self::LEN }>())
+    }
+
+    /// Derive the stateless reset token for `cid`.
+    ///
+    /// The token is HMAC-SHA256(key, cid), truncated to [`Token::LEN`] bytes.
+    /// This is computed as HKDF-Extract with this key as the salt, which is the same thing.
+    ///
+    /// # Errors
+    /// When the connection ID is empty or NSS fails.
+    pub fn token(&self, cid: &[u8]) -> Res<Token> {
+        if cid.is_empty() {
+            return Err(Error::InvalidInput);
+        }
+        let mac = hkdf::extract(
+            TLS_VERSION_1_3,
+            TLS_AES_128_GCM_SHA256,
+            Some(&hkdf::import_key(TLS_VERSION_1_3, &self.0)?),
+            &hkdf::import_key(TLS_VERSION_1_3, cid)?,
+        )?;
+        Ok(Token(mac.as_bytes()?[..Token::LEN].try_into()?))
+    }
+}
+
+/// Don't leak key material into logs.
+impl Debug for Key {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("stateless_reset::Key")
+    }
+}
+
+/// The smallest stateless reset that can be sent: one byte for the first byte
+/// of a short header, four bytes of unpredictable bits and the token.
+/// See <https://www.rfc-editor.org/rfc/rfc9000.html#section-10.3>.
+pub const MIN_PACKET_LEN: usize = 5 + Token::LEN;
+
+/// The largest stateless reset that is sent.
+/// This is enough to look like a short header packet with a long connection ID.
+pub const MAX_PACKET_LEN: usize = 43;
+
+/// The length of a stateless reset sent in response to a packet of `received` bytes.
+///
+/// To prevent looping, the reset is always smaller than the packet that triggered it,
+/// which also means that it cannot be used for amplification.
+/// Returns `None` if the triggering packet is too small for that.
+/// See <https://www.rfc-editor.org/rfc/rfc9000.html#section-10.3.3>.
+#[must_use]
+pub fn packet_len(received: usize) -> Option<usize> {
+    let len = received.checked_sub(1)?.min(MAX_PACKET_LEN);
+    (len >= MIN_PACKET_LEN).then_some(len)
+}
+
+/// Build a stateless reset in response to a packet of `received` bytes.
+/// See [`packet_len`] for how long the reset is.
+#[must_use]
+pub fn packet(token: &Token, received: usize) -> Option<Vec<u8>> {
+    let len = packet_len(received)?;
+    let mut buf = vec![0; len];
+    randomize(&mut buf[..len - Token::LEN]);
+    // Look like a short header packet: clear the header form bit, set the fixed bit.
+    buf[0] = (buf[0] & 0x3f) | 0x40;
+    buf[len - Token::LEN..].copy_from_slice(token.as_ref());
+    Some(buf)
+}
+
+/// Limits the number of stateless resets that are sent in each interval,
+/// so that resets can't be used to flood a third party.
+#[derive(Debug)]
+pub struct RateLimit {
+    /// The number of resets that can be sent in each interval.
+    limit: usize,
+    /// The length of the interval.
+    interval: Duration,
+    /// The start of the current interval.
+    start: Option<Instant>,
+    /// The number of resets sent in the current interval.
+    sent: usize,
+}
+
+impl RateLimit {
+    pub const DEFAULT_LIMIT: usize = 100;
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+    #[must_use]
+    pub const fn new(limit: usize, interval: Duration) -> Self {
+        Self {
+            limit,
+            interval,
+            start: None,
+            sent: 0,
+        }
+    }
+
+    /// Record an attempt to send a stateless reset at `now`.
+    /// Returns `false` if the limit for the current interval has been reached.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        if self
+            .start
+            .is_none_or(|start| now.saturating_duration_since(start) >= self.interval)
+        {
+            self.start = Some(now);
+            self.sent = 0;
+        }
+        if self.sent >= self.limit {
+            return false;
+        }
+        self.sent += 1;
+        true
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_LIMIT, Self::DEFAULT_INTERVAL)
+    }
+}
+
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
@@ -124,4 +267,57 @@ mod tests {
         let result = Token::try_from(&bytes[..]);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn key_derives_stable_tokens() {
+        nss::init().unwrap();
+        let key = Key::new([7; Key::LEN]);
+        let cid = [1, 2, 3, 4, 5, 6, 7, 8];
+        let token = key.token(&cid).unwrap();
+        assert_eq!(token, Key::new([7; Key::LEN]).token(&cid).unwrap());
+        assert_ne!(token, key.token(&cid[1..]).unwrap());
+        assert_ne!(token, Key::new([8; Key::LEN]).token(&cid).unwrap());
+        assert!(key.token(&[]).is_err());
+    }
+
+    /// The token is a truncated HMAC-SHA256 of the connection ID.
+    #[test]
+    fn key_token_is_hmac() {
+        nss::init().unwrap();
+        let key = Key::new([7; Key::LEN]);
+        let token = key.token(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        assert_eq!(
+            token.as_bytes(),
+            &[
+                0x6d, 0x51, 0x0a, 0x8d, 0x01, 0x69, 0x2e, 0xfe, 0x4d, 0xe0, 0x4b, 0x28, 0x62, 0xe2,
+                0x6b, 0xaf
+            ]
+        );
+    }
+
+    #[test]
+    fn packet_smaller_than_trigger() {
+        nss::init().unwrap();
+        let token = Token::new([9; Token::LEN]);
+        assert!(packet(&token, MIN_PACKET_LEN).is_none());
+        assert_eq!(packet_len(MIN_PACKET_LEN + 1), Some(MIN_PACKET_LEN));
+
+        let p = packet(&token, MIN_PACKET_LEN + 1).unwrap();
+        assert_eq!(p.len(), MIN_PACKET_LEN);
+        assert_eq!(p[0] & 0xc0, 0x40);
+        assert_eq!(&p[p.len() - Token::LEN..], token.as_ref());
+
+        let p = packet(&token, 1200).unwrap();
+        assert_eq!(p.len(), MAX_PACKET_LEN);
+    }
+
+    #[test]
+    fn rate_limit() {
+        let now = Instant::now();
+        let mut limit = RateLimit::new(2, Duration::from_secs(1));
+        assert!(limit.allow(now));
+        assert!(limit.allow(now));
+        assert!(!limit.allow(now + Duration::from_millis(999)));
+        assert!(limit.allow(now + Duration::from_secs(1)));
+    }
 }
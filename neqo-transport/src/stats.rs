@@ -18,12 +18,18 @@ use enum_map::EnumMap;
 use neqo_common::{Dscp, Ecn, qdebug};
 use strum::IntoEnumIterator as _;
 
-use crate::{cc::CongestionTrigger, ecn, packet, version::Version};
+use crate::{
+    cc::{CarefulResumePhase, CongestionTrigger},
+    ecn, packet,
+    version::Version,
+};
 
 #[derive(Default, Clone, PartialEq, Eq)]
 pub struct FrameStats {
     pub ack: usize,
     pub largest_acknowledged: packet::Number,
+    /// The number of packet receive timestamps in ACK frames.
+    pub receive_timestamps: usize,
 
     pub crypto: usize,
     pub stream: usize,
@@ -52,6 +58,7 @@ pub struct FrameStats {
     pub new_token: usize,
 
     pub ack_frequency: usize,
+    pub immediate_ack: usize,
     pub datagram: usize,
 }
 
@@ -64,8 +71,8 @@ impl Debug for FrameStats {
         )?;
         writeln!(
             f,
-            "    ack {} (max {}) ping {} padding {}",
-            self.ack, self.largest_acknowledged, self.ping, self.padding
+            "    ack {} (max {}) timestamps {} ping {} padding {}",
+            self.ack, self.largest_acknowledged, self.receive_timestamps, self.ping, self.padding
         )?;
         writeln!(
             f,
@@ -91,7 +98,11 @@ impl Debug for FrameStats {
             self.path_challenge,
             self.path_response,
         )?;
-        writeln!(f, "    ack_frequency {}", self.ack_frequency)
+        writeln!(
+            f,
+            "    ack_frequency {} immediate_ack {}",
+            self.ack_frequency, self.immediate_ack
+        )
     }
 }
 
@@ -120,6 +131,7 @@ impl FrameStats {
             + self.handshake_done
             + self.new_token
             + self.ack_frequency
+            + self.immediate_ack
             + self.datagram
     }
 }
@@ -134,6 +146,9 @@ pub struct DatagramStats {
     /// The number of datagrams dropped due to reaching the limit of the
     /// outgoing queue.
     pub dropped_queue_full: usize,
+    /// The number of datagrams dropped because their deadline passed
+    /// before they could be sent.
+    pub expired: usize,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -184,6 +199,16 @@ pub struct SearchResetStats {
     pub max_passed_bins: Option<usize>,
 }
 
+/// Careful Resume statistics.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CarefulResumeStats {
+    /// The number of times each phase was entered.
+    pub phases: EnumMap<CarefulResumePhase, usize>,
+    /// The congestion window that was jumped to on entering the unvalidated phase.
+    /// `None` if the saved congestion window was never used.
+    pub jump_cwnd: Option<usize>,
+}
+
 /// Congestion Control stats
 #[derive(Default, Clone, PartialEq)]
 pub struct CongestionControlStats {
@@ -236,6 +261,12 @@ pub struct CongestionControlStats {
     /// occurred or Cubic is not in use. Recorded as a stat to approximate a connection's ideal
     /// congestion window in metrics.
     pub w_max: Option<f64>,
+    /// The fraction of bytes that were ECN CE marked in the most recent round trip, as
+    /// estimated by Prague. `None` if no round has completed or Prague is not in use.
+    pub ecn_ce_fraction: Option<f64>,
+    /// Careful Resume phase changes. Only meaningful when resuming with
+    /// [`crate::ConnectionParameters::careful_resume`] enabled.
+    pub careful_resume: CarefulResumeStats,
 }
 
 impl Debug for CongestionControlStats {
@@ -383,6 +414,14 @@ pub struct Stats {
     pub lost: usize,
     /// Late acknowledgments, for packets that were declared lost already.
     pub late_ack: usize,
+    /// The current packet reordering threshold used for loss detection.
+    /// This grows when late acknowledgments show that packets were reordered,
+    /// not lost, and decays back to `recovery::PACKET_THRESHOLD` over time.
+    pub loss_packet_threshold: u64,
+    /// The current time threshold used for loss detection, as a multiple of
+    /// the round-trip time.  This adapts in the same way as
+    /// [`Self::loss_packet_threshold`], starting from 9/8.
+    pub loss_time_threshold: f64,
     /// Acknowledgments for packets that contained data that was marked
     /// for retransmission when the PTO timer popped.
     pub pto_ack: usize,
@@ -510,6 +549,11 @@ impl Debug for Stats {
             "  tx: {} lost {} lateack {} ptoack {} unackdrop {}",
             self.packets_tx, self.lost, self.late_ack, self.pto_ack, self.unacked_range_dropped
         )?;
+        writeln!(
+            f,
+            "  loss thresholds: packet {} time {}",
+            self.loss_packet_threshold, self.loss_time_threshold
+        )?;
         writeln!(f, "  cc:")?;
         self.cc.fmt(f)?;
         writeln!(
@@ -569,6 +613,21 @@ impl Debug for StatsCell {
     }
 }
 
+/// Server statistics, which count the new connections that a server turned away.
+/// See [`crate::server::AdmissionLimits`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ServerStats {
+    /// Connections refused because the server had too many connections.
+    pub refused_connection_limit: usize,
+    /// Connections refused because too many handshakes from the same source prefix
+    /// were in progress.
+    pub refused_prefix_limit: usize,
+    /// Connections refused because too many handshakes were in progress.
+    pub refused_overload: usize,
+    /// Retry packets sent only because too many handshakes were in progress.
+    pub retry_overload: usize,
+}
+
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
@@ -624,6 +683,7 @@ fn debug() {
   version: Version1
   rx: 0 drop 0 dup 0 saved 0
   tx: 0 lost 0 lateack 0 ptoack 0 unackdrop 0
+  loss thresholds: packet 0 time 0
   cc:
     cwnd 0 in_flight 0
     ce_loss 0 ce_ecn 0 ce_spurious 0
@@ -632,27 +692,27 @@ fn debug() {
   resumed: false
   frames rx:
     crypto 0 done 0 token 0 close 0
-    ack 0 (max 0) ping 0 padding 0
+    ack 0 (max 0) timestamps 0 ping 0 padding 0
     stream 0 reset 0 reset_at 0 stop 0
     max: stream 0 data 0 stream_data 0
     blocked: stream 0 data 0 stream_data 0
     datagram 0
     ncid 0 rcid 0 pchallenge 0 presponse 0
-    ack_frequency 0
+    ack_frequency 0 immediate_ack 0
   frames tx:
     crypto 0 done 0 token 0 close 0
-    ack 0 (max 0) ping 0 padding 0
+    ack 0 (max 0) timestamps 0 ping 0 padding 0
     stream 0 reset 0 reset_at 0 stop 0
     max: stream 0 data 0 stream_data 0
     blocked: stream 0 data 0 stream_data 0
     datagram 0
     ncid 0 rcid 0 pchallenge 0 presponse 0
-    ack_frequency 0
+    ack_frequency 0 immediate_ack 0
   ecn:
     tx:
     acked:
     rx:
-    path validation outcomes: ValidationCount({Capable: 0, NotCapable(BlackHole): 0, NotCapable(Bleaching): 0, NotCapable(ReceivedUnsentECT1): 0})
+    path validation outcomes: ValidationCount({Capable: 0, NotCapable(BlackHole): 0, NotCapable(Bleaching): 0, NotCapable(ReceivedUnsentECT0): 0, NotCapable(ReceivedUnsentECT1): 0})
     mark transitions:
   dscp:\x20
   bytes: rx 0 lost 0 acked 0
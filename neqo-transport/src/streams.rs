@@ -12,7 +12,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use neqo_common::{Buffer, Role, qtrace, qwarn};
+use neqo_common::{Buffer, Role, qlog::Qlog, qtrace, qwarn};
 
 use crate::{
     AppError, ConnectionEvents, Error, Res,
@@ -21,7 +21,7 @@ use crate::{
     packet,
     recovery::{self, StreamRecoveryToken},
     recv_stream::{RecvStream, RecvStreams},
-    send_stream::{SendStream, SendStreams, TransmissionPriority},
+    send_stream::{SendStream, SendStreams, StreamScheduler, TransmissionPriority},
     stats::FrameStats,
     stream_id::{StreamId, StreamType},
     tparams::{
@@ -91,6 +91,7 @@ pub struct Streams {
     local_stream_limits: LocalStreamLimits,
     send: SendStreams,
     recv: RecvStreams,
+    qlog: Qlog,
 }
 
 impl Streams {
@@ -98,6 +99,7 @@ impl Streams {
         tps: Rc<RefCell<TransportParametersHandler>>,
         role: Role,
         events: ConnectionEvents,
+        scheduler: StreamScheduler,
     ) -> Self {
         let limit_bidi = tps.borrow().local().get_integer(InitialMaxStreamsBidi);
         let limit_uni = tps.borrow().local().get_integer(InitialMaxStreamsUni);
@@ -110,11 +112,39 @@ impl Streams {
             receiver_fc: Rc::new(RefCell::new(ReceiverFlowControl::new((), max_data))),
             remote_stream_limits: RemoteStreamLimits::new(limit_bidi, limit_uni, role),
             local_stream_limits: LocalStreamLimits::new(role),
-            send: SendStreams::default(),
+            send: SendStreams::new(scheduler),
             recv: RecvStreams::default(),
+            qlog: Qlog::disabled(),
         }
     }
 
+    /// Set the qlog that is given to new streams.
+    pub fn set_qlog(&mut self, qlog: Qlog) {
+        self.qlog = qlog;
+    }
+
+    fn new_send_stream(&self, stream_id: StreamId, max_stream_data: u64) -> SendStream {
+        let mut stream = SendStream::new(
+            stream_id,
+            max_stream_data,
+            Rc::clone(&self.sender_fc),
+            self.events.clone(),
+        );
+        stream.set_qlog(self.qlog.clone());
+        stream
+    }
+
+    fn new_recv_stream(&self, stream_id: StreamId, max_stream_data: u64) -> RecvStream {
+        let mut stream = RecvStream::new(
+            stream_id,
+            max_stream_data,
+            Rc::clone(&self.receiver_fc),
+            self.events.clone(),
+        );
+        stream.set_qlog(self.qlog.clone());
+        stream
+    }
+
     #[must_use]
     pub fn is_stream_id_allowed(&self, stream_id: StreamId) -> bool {
         self.remote_stream_limits[stream_id.stream_type()].is_allowed(stream_id)
@@ -395,6 +425,21 @@ impl Streams {
         self.recv.read(stream_id, data)
     }
 
+    /// # Errors
+    /// When the stream does not exist or has no more data.
+    pub fn recv_chunk(&self, stream_id: StreamId) -> Res<&[u8]> {
+        self.recv.get(stream_id)?.read_chunk()
+    }
+
+    /// # Errors
+    /// When the stream does not exist, has no more data, or `amount` is too large.
+    ///
+    /// # Returns
+    /// `true` when the stream has ended.
+    pub fn recv_consume(&mut self, stream_id: StreamId, amount: usize) -> Res<bool> {
+        self.recv.consume(stream_id, amount)
+    }
+
     /// # Errors
     /// When the stream does not exist.
     pub fn stop_sending(&mut self, stream_id: StreamId, err: AppError) -> Res<()> {
@@ -440,12 +485,7 @@ impl Streams {
 
             self.recv.insert(
                 next_stream_id,
-                RecvStream::new(
-                    next_stream_id,
-                    recv_initial_max_stream_data,
-                    Rc::clone(&self.receiver_fc),
-                    self.events.clone(),
-                ),
+                self.new_recv_stream(next_stream_id, recv_initial_max_stream_data),
             );
 
             if next_stream_id.is_bidi() {
@@ -461,12 +501,7 @@ impl Streams {
                     .get_integer(InitialMaxStreamDataBidiLocal);
                 self.send.insert(
                     next_stream_id,
-                    SendStream::new(
-                        next_stream_id,
-                        send_initial_max_stream_data,
-                        Rc::clone(&self.sender_fc),
-                        self.events.clone(),
-                    ),
+                    self.new_send_stream(next_stream_id, send_initial_max_stream_data),
                 );
             }
         }
@@ -518,6 +553,39 @@ impl Streams {
         self.send.set_sendgroup(stream_id, group_id)
     }
 
+    /// # Errors
+    /// When the stream does not exist, or the weight is zero.
+    pub fn set_weight(&mut self, stream_id: StreamId, weight: u16) -> Res<()> {
+        self.send.set_weight(stream_id, weight)
+    }
+
+    /// # Errors
+    /// When the stream does not exist.
+    pub fn set_deadline(&mut self, stream_id: StreamId, deadline: Option<Instant>) -> Res<()> {
+        self.send.set_deadline(stream_id, deadline)
+    }
+
+    /// # Errors
+    /// When the stream does not exist.
+    pub fn set_expiry(
+        &mut self,
+        stream_id: StreamId,
+        expiry: Instant,
+        err: AppError,
+        reliable_size: u64,
+    ) -> Res<()> {
+        self.send.set_expiry(stream_id, expiry, err, reliable_size)
+    }
+
+    #[must_use]
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.send.next_expiry()
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        self.send.expire(now);
+    }
+
     /// # Errors
     /// When a stream cannot be created, which might be temporary.
     pub fn stream_create(&mut self, st: StreamType) -> Res<StreamId> {
@@ -529,12 +597,7 @@ impl Streams {
                     StreamType::BiDi => InitialMaxStreamDataBidiRemote,
                 };
                 let send_limit = self.tps.borrow().remote().get_integer(send_limit_tp);
-                let stream = SendStream::new(
-                    new_id,
-                    send_limit,
-                    Rc::clone(&self.sender_fc),
-                    self.events.clone(),
-                );
+                let stream = self.new_send_stream(new_id, send_limit);
                 self.send.insert(new_id, stream);
 
                 if st == StreamType::BiDi {
@@ -551,12 +614,7 @@ impl Streams {
 
                     self.recv.insert(
                         new_id,
-                        RecvStream::new(
-                            new_id,
-                            recv_initial_max_stream_data,
-                            Rc::clone(&self.receiver_fc),
-                            self.events.clone(),
-                        ),
+                        self.new_recv_stream(new_id, recv_initial_max_stream_data),
                     );
                 }
                 Ok(new_id)
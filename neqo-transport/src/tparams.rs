@@ -9,6 +9,7 @@
 use std::{
     cell::RefCell,
     cmp::min,
+    collections::BTreeMap,
     fmt::{self, Debug, Display, Formatter},
     net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6},
     rc::Rc,
@@ -59,7 +60,11 @@ pub enum TransportParameterId {
     // draft-ietf-scone-protocol
     Scone = 0x219e,
     GreaseQuicBit = 0x2ab2,
-    MinAckDelay = 0xff02_de1a,
+    // draft-ietf-quic-ack-frequency
+    MinAckDelay = 0xff04_de1b,
+    // draft-smith-quic-receive-ts
+    MaxReceiveTimestampsPerAck = 0x0ff0_a002,
+    ReceiveTimestampsExponent = 0x0ff0_a003,
     MaxDatagramFrameSize = 0x0020,
     #[cfg(test)]
     TestTransportParameter = 0xce16,
@@ -144,6 +149,9 @@ impl PreferredAddress {
     }
 }
 
+/// A transport parameter that this crate doesn't define: its identifier and value.
+type Extension = (u64, Vec<u8>);
+
 #[derive(Clone, PartialEq, Eq)]
 pub enum TransportParameter {
     Bytes(Vec<u8>),
@@ -303,14 +311,17 @@ impl TransportParameter {
         Ok(Self::Versions { current, other })
     }
 
-    fn decode(role: Role, dec: &mut Decoder) -> Res<Option<(TransportParameterId, Self)>> {
+    /// Decode a single transport parameter.
+    /// Parameters that this crate doesn't define are returned as an [`Extension`].
+    fn decode(
+        role: Role,
+        dec: &mut Decoder,
+    ) -> Res<Result<(TransportParameterId, Self), Extension>> {
         let tp = dec.decode_varint().ok_or(Error::NoMoreData)?;
         let content = dec.decode_vvec().ok_or(Error::NoMoreData)?;
         qtrace!("TP {tp:x} length {:x}", content.len());
-        let tp = match tp.try_into() {
-            Ok(tp) => tp,
-            Err(Error::UnknownTransportParameter) => return Ok(None), // Skip
-            Err(e) => return Err(e),
+        let Ok(tp) = TransportParameterId::try_from(tp) else {
+            return Ok(Err((tp, content.to_vec())));
         };
         let mut d = Decoder::from(content);
         let value = match tp {
@@ -335,6 +346,7 @@ impl TransportParameter {
             | TransportParameterId::InitialMaxStreamDataBidiLocal
             | TransportParameterId::InitialMaxStreamDataBidiRemote
             | TransportParameterId::InitialMaxStreamDataUni
+            | TransportParameterId::MaxReceiveTimestampsPerAck
             | TransportParameterId::MaxDatagramFrameSize => match d.decode_varint() {
                 Some(v) => Self::Integer(v),
                 None => return Err(Error::TransportParameter),
@@ -356,7 +368,8 @@ impl TransportParameter {
                 }
                 _ => return Err(Error::TransportParameter),
             },
-            TransportParameterId::AckDelayExponent => match d.decode_varint() {
+            TransportParameterId::AckDelayExponent
+            | TransportParameterId::ReceiveTimestampsExponent => match d.decode_varint() {
                 Some(v) if v <= 20 => Self::Integer(v),
                 _ => return Err(Error::TransportParameter),
             },
@@ -388,13 +401,15 @@ impl TransportParameter {
             return Err(Error::TooMuchData);
         }
         qtrace!("TP decoded; type {tp} val {value:?}");
-        Ok(Some((tp, value)))
+        Ok(Ok((tp, value)))
     }
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct TransportParameters {
     params: EnumMap<TransportParameterId, Option<TransportParameter>>,
+    /// Parameters that this crate doesn't define, which are left to the application.
+    extensions: BTreeMap<u64, Vec<u8>>,
 }
 
 impl TransportParameters {
@@ -426,7 +441,7 @@ impl TransportParameters {
 
         while d.remaining() > 0 {
             match TransportParameter::decode(role, d) {
-                Ok(Some((tipe, tp))) => {
+                Ok(Ok((tipe, tp))) => {
                     // RFC 9000, Section 7.4:
                     //
                     // > An endpoint MUST NOT send a parameter more than once in a given transport
@@ -443,7 +458,12 @@ impl TransportParameters {
                     }
                     tps.set(tipe, tp);
                 }
-                Ok(None) => {}
+                Ok(Err((id, value))) => {
+                    if tps.extensions.insert(id, value).is_some() {
+                        qinfo!("Duplicate transport parameter 0x{id:x}");
+                        return Err(Error::TransportParameter);
+                    }
+                }
                 Err(e) => return Err(e),
             }
         }
@@ -457,12 +477,13 @@ impl TransportParameters {
     pub(crate) fn encode<B: Buffer>(&self, enc: &mut Encoder<B>) {
         #[cfg(feature = "build-fuzzing-corpus")]
         let start = enc.len();
-        self.encode_filtered(Self::retain_all, enc);
+        self.encode_filtered(Self::retain_all, true, enc);
         #[cfg(feature = "build-fuzzing-corpus")]
         neqo_common::write_item_to_fuzzing_corpus("tparams", &enc.as_ref()[start..]);
     }
 
-    fn encode_filtered<F, B: Buffer>(&self, f: F, enc: &mut Encoder<B>)
+    /// Encode the parameters that `f` selects, plus extensions if `extensions` is set.
+    fn encode_filtered<F, B: Buffer>(&self, f: F, extensions: bool, enc: &mut Encoder<B>)
     where
         F: Fn(TransportParameterId, Option<&TransportParameter>) -> bool,
     {
@@ -471,6 +492,44 @@ impl TransportParameters {
                 tp.encode(enc, i);
             }
         }
+        if extensions {
+            for (id, value) in &self.extensions {
+                qtrace!("TP encoded; extension 0x{id:x} val {}", Hex::new(value));
+                enc.encode_varint(*id);
+                enc.encode_vvec(value);
+            }
+        }
+    }
+
+    /// Set the value of a transport parameter that this crate doesn't define,
+    /// such as one for an extension that the application implements.
+    ///
+    /// # Errors
+    ///
+    /// `InvalidInput` if `id` is a parameter that this crate defines or is too
+    /// large to encode.
+    pub fn set_extension(&mut self, id: u64, value: Vec<u8>) -> Res<()> {
+        if id >= (1 << 62) || TransportParameterId::try_from(id).is_ok() {
+            return Err(Error::InvalidInput);
+        }
+        self.extensions.insert(id, value);
+        Ok(())
+    }
+
+    /// Clear a parameter that was set with [`Self::set_extension`].
+    pub fn remove_extension(&mut self, id: u64) {
+        self.extensions.remove(&id);
+    }
+
+    /// Get the value of a transport parameter that this crate doesn't define.
+    #[must_use]
+    pub fn get_extension(&self, id: u64) -> Option<&[u8]> {
+        self.extensions.get(&id).map(Vec::as_slice)
+    }
+
+    /// All of the transport parameters that this crate doesn't define, in order of identifier.
+    pub fn extensions(&self) -> impl Iterator<Item = (u64, &[u8])> {
+        self.extensions.iter().map(|(id, v)| (*id, v.as_slice()))
     }
 
     // Get an integer type or a default.
@@ -502,6 +561,8 @@ impl TransportParameters {
             | TransportParameterId::InitialMaxStreamsBidi
             | TransportParameterId::InitialMaxStreamsUni
             | TransportParameterId::MinAckDelay
+            | TransportParameterId::MaxReceiveTimestampsPerAck
+            | TransportParameterId::ReceiveTimestampsExponent
             | TransportParameterId::MaxDatagramFrameSize => 0,
             TransportParameterId::MaxUdpPayloadSize => 65527,
             TransportParameterId::AckDelayExponent => 3,
@@ -632,6 +693,8 @@ impl TransportParameters {
                         | TransportParameterId::ActiveConnectionIdLimit
                         | TransportParameterId::PreferredAddress
                         | TransportParameterId::Scone
+                        | TransportParameterId::MaxReceiveTimestampsPerAck
+                        | TransportParameterId::ReceiveTimestampsExponent
                 )
             {
                 continue;
@@ -676,7 +739,11 @@ impl TransportParameters {
                 return false;
             }
         }
-        true
+        // The meaning of extensions is unknown, so their values can't change.
+        remembered
+            .extensions
+            .iter()
+            .all(|(id, v_rem)| self.extensions.get(id) == Some(v_rem))
     }
 
     /// Get the preferred address in a usable form.
@@ -900,7 +967,8 @@ impl ExtensionHandler for TransportParametersHandler {
         } else {
             TransportParameters::retain_all
         };
-        self.local.encode_filtered(f, &mut enc);
+        // Extensions are left out of the outer `ClientHello`, like most parameters.
+        self.local.encode_filtered(f, !ch_outer, &mut enc);
         ExtensionWriterResult::Write(enc.len())
     }
 
@@ -1362,6 +1430,66 @@ mod tests {
         assert_eq!(tps.get_integer(InitialMaxData), 20);
     }
 
+    const EXTENSION: u64 = 0x7a3b;
+
+    #[test]
+    fn extension_round_trip() {
+        let mut tps = TransportParameters::default();
+        assert_eq!(
+            tps.set_extension(u64::from(IdleTimeout), vec![1]),
+            Err(Error::InvalidInput)
+        );
+        assert_eq!(
+            tps.set_extension(1 << 62, vec![1]),
+            Err(Error::InvalidInput)
+        );
+        tps.set_extension(EXTENSION, vec![1, 2]).unwrap();
+        tps.set_extension(0x1b, Vec::new()).unwrap();
+        tps.set_integer(IdleTimeout, 10);
+
+        let mut enc = Encoder::default();
+        tps.encode(&mut enc);
+        let decoded = TransportParameters::decode(Client, &mut enc.as_decoder()).unwrap();
+        assert_eq!(decoded, tps);
+        assert_eq!(decoded.get_extension(EXTENSION), Some(&[1, 2][..]));
+        assert_eq!(
+            decoded.extensions().collect::<Vec<_>>(),
+            [(0x1b, &[][..]), (EXTENSION, &[1, 2][..])]
+        );
+
+        tps.remove_extension(0x1b);
+        assert_eq!(tps.get_extension(0x1b), None);
+    }
+
+    #[test]
+    fn extension_duplicate_rejected() {
+        let mut enc = Encoder::default();
+        for v in [[1], [2]] {
+            enc.encode_varint(EXTENSION);
+            enc.encode_vvec(&v);
+        }
+        assert_eq!(
+            TransportParameters::decode(Client, &mut enc.as_decoder()).unwrap_err(),
+            Error::TransportParameter
+        );
+    }
+
+    /// Remembered extensions need to be unchanged for 0-RTT.
+    #[test]
+    fn extension_ok_for_0rtt() {
+        let mut remembered = TransportParameters::default();
+        remembered.set_extension(EXTENSION, vec![1]).unwrap();
+        let mut tps = remembered.clone();
+        assert!(tps.ok_for_0rtt(&remembered));
+        tps.set_extension(EXTENSION, vec![2]).unwrap();
+        assert!(!tps.ok_for_0rtt(&remembered));
+        tps.remove_extension(EXTENSION);
+        assert!(!tps.ok_for_0rtt(&remembered));
+
+        // New extensions don't matter.
+        assert!(remembered.ok_for_0rtt(&TransportParameters::default()));
+    }
+
     /// The `reset_stream_at` transport parameter is an empty parameter that round-trips.
     #[test]
     fn reset_stream_at_empty_round_trip() {
@@ -1558,6 +1686,12 @@ mod tests {
         assert!(decode_tp_integer(AckDelayExponent, 21).is_err());
     }
 
+    #[test]
+    fn receive_timestamps_exponent_boundary() {
+        assert!(decode_tp_integer(ReceiveTimestampsExponent, 20).is_ok());
+        assert!(decode_tp_integer(ReceiveTimestampsExponent, 21).is_err());
+    }
+
     #[test]
     fn max_ack_delay_boundary() {
         // Just below the limit is valid.
@@ -7,7 +7,7 @@
 // Tracking of received packets and generating ACKs thereof.
 
 use std::{
-    cmp::min,
+    cmp::{max, min},
     collections::VecDeque,
     fmt::{self, Display, Formatter},
     time::{Duration, Instant},
@@ -16,7 +16,7 @@ use std::{
 use enum_map::{Enum, EnumMap};
 use enumset::{EnumSet, EnumSetType};
 use log::{Level, log_enabled};
-use neqo_common::{Buffer, Ecn, MAX_VARINT, qdebug, qtrace, qwarn, to_u64};
+use neqo_common::{Buffer, Ecn, Encoder, MAX_VARINT, qdebug, qtrace, qwarn, to_u64};
 use nss::Epoch;
 use smallvec::SmallVec;
 use strum::{Display, EnumIter};
@@ -170,6 +170,11 @@ pub const DEFAULT_REMOTE_ACK_DELAY: Duration = Duration::from_millis(25);
 /// The default number of in-order packets we will receive after
 /// largest acknowledged without sending an immediate acknowledgment.
 pub const DEFAULT_ACK_PACKET_TOLERANCE: packet::Number = 1;
+/// The default reordering threshold, which acknowledges any packet
+/// that arrives out of order immediately, as RFC 9000 recommends.
+pub const DEFAULT_REORDERING_THRESHOLD: packet::Number = 1;
+/// The most receive timestamps we send in each ACK, whatever the peer asks for.
+pub const MAX_RECEIVE_TIMESTAMPS_PER_ACK: usize = 64;
 const MAX_TRACKED_RANGES: usize = 32;
 const MAX_ACKS_PER_FRAME: usize = 32;
 
@@ -187,6 +192,72 @@ impl AckToken {
     }
 }
 
+/// Receive times for packets that have not been reported to the peer yet,
+/// for draft-smith-quic-receive-ts.
+#[derive(Debug)]
+struct ReceiveTimestamps {
+    /// The most timestamps to send in each ACK.
+    max: usize,
+    /// The time that timestamps are measured from.
+    basis: Instant,
+    /// Packet numbers and receive times, in the order that packets arrived.
+    times: VecDeque<(packet::Number, Instant)>,
+}
+
+impl ReceiveTimestamps {
+    fn record(&mut self, pn: packet::Number, now: Instant) {
+        self.times.push_back((pn, now));
+        if self.times.len() > self.max {
+            self.times.pop_front();
+        }
+    }
+
+    /// Take the receive times for packets between `smallest` and `largest`
+    /// and arrange them into timestamp ranges that fit into `space` bytes.
+    /// Each range holds its largest packet number and the timestamp deltas,
+    /// in microseconds.
+    fn take_ranges(
+        &mut self,
+        largest: packet::Number,
+        smallest: packet::Number,
+        mut space: usize,
+    ) -> Vec<(packet::Number, Vec<u64>)> {
+        let mut times = self
+            .times
+            .drain(..)
+            .filter(|(pn, _)| (smallest..=largest).contains(pn))
+            .collect::<Vec<_>>();
+        times.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+
+        let mut ranges: Vec<(packet::Number, Vec<u64>)> = Vec::new();
+        let mut prev: Option<(packet::Number, u64)> = None;
+        for (pn, t) in times {
+            let offset = u64::try_from(t.saturating_duration_since(self.basis).as_micros())
+                .map_or(MAX_VARINT, |v| min(v, MAX_VARINT));
+            let delta = match prev {
+                None => offset,
+                // Deltas can't be negative, so skip any packet that arrived
+                // after a packet with a larger packet number.
+                Some((_, prev_offset)) if offset > prev_offset => continue,
+                Some((_, prev_offset)) => prev_offset - offset,
+            };
+            let adjacent = prev.is_some_and(|(prev_pn, _)| prev_pn == pn + 1);
+            // Starting a new range needs space for the gap and count as well.
+            let len = Encoder::varint_len(delta) + if adjacent { 0 } else { 16 };
+            if len > space {
+                break;
+            }
+            space -= len;
+            match ranges.last_mut() {
+                Some((_, deltas)) if adjacent => deltas.push(delta),
+                _ => ranges.push((pn, vec![delta])),
+            }
+            prev = Some((pn, offset));
+        }
+        ranges
+    }
+}
+
 /// A structure that tracks what packets have been received,
 /// and what needs acknowledgement for a packet number space.
 #[derive(Debug)]
@@ -212,11 +283,17 @@ pub struct RecvdPackets {
     /// The number of contiguous packets that can be received without
     /// acknowledging immediately.
     unacknowledged_tolerance: packet::Number,
-    /// Whether we are ignoring packets that arrive out of order
-    /// for the purposes of generating immediate acknowledgment.
-    ignore_order: bool,
+    /// How far out of order packets can arrive before they are acknowledged
+    /// immediately.  0 means never, and 1 means any reordering.
+    reordering_threshold: packet::Number,
+    /// Missing packets below this packet number have already been reported
+    /// as missing in an ACK, which lets the peer declare them lost.  They don't
+    /// count toward the reordering threshold.
+    unreported: packet::Number,
     // The counts of different ECN marks that have been received.
     ecn_count: ecn::Count,
+    /// Receive times to report, if the peer asked for them.
+    receive_timestamps: Option<ReceiveTimestamps>,
 }
 
 impl RecvdPackets {
@@ -238,11 +315,23 @@ impl RecvdPackets {
                 // ACK more aggressively
                 0
             },
-            ignore_order: false,
+            reordering_threshold: DEFAULT_REORDERING_THRESHOLD,
+            unreported: 0,
             ecn_count: ecn::Count::default(),
+            receive_timestamps: None,
         }
     }
 
+    /// Start recording when packets are received so that ACK frames can report
+    /// up to `max` receive times, measured from `basis`.
+    fn enable_receive_timestamps(&mut self, max: usize, basis: Instant) {
+        self.receive_timestamps = Some(ReceiveTimestamps {
+            max,
+            basis,
+            times: VecDeque::with_capacity(max),
+        });
+    }
+
     /// Get the ECN counts.
     pub const fn ecn_marks(&mut self) -> &mut ecn::Count {
         &mut self.ecn_count
@@ -259,7 +348,7 @@ impl RecvdPackets {
         seqno: u64,
         tolerance: packet::Number,
         delay: Duration,
-        ignore_order: bool,
+        reordering_threshold: packet::Number,
     ) {
         // Yes, this means that we will overwrite values if a sequence number is
         // reused, but that is better than using an `Option<packet::Number>`
@@ -268,7 +357,7 @@ impl RecvdPackets {
             self.ack_frequency_seqno = seqno;
             self.unacknowledged_tolerance = tolerance;
             self.ack_delay = delay;
-            self.ignore_order = ignore_order;
+            self.reordering_threshold = reordering_threshold;
         }
     }
 
@@ -339,6 +428,9 @@ impl RecvdPackets {
 
         self.add(pn)?;
         self.trim_ranges(stats)?;
+        if let Some(receive_timestamps) = &mut self.receive_timestamps {
+            receive_timestamps.record(pn, now);
+        }
 
         // The new addition was the largest, so update the time we use for calculating ACK delay.
         let largest = if pn >= next_in_order_pn {
@@ -352,7 +444,7 @@ impl RecvdPackets {
             self.unacknowledged_count += 1;
 
             let immediate_ack = self.space != PacketNumberSpace::ApplicationData
-                || (pn != next_in_order_pn && !self.ignore_order)
+                || self.reordered(pn, next_in_order_pn)
                 || self.unacknowledged_count > self.unacknowledged_tolerance;
 
             let ack_time = if immediate_ack {
@@ -372,6 +464,43 @@ impl RecvdPackets {
         Ok(largest)
     }
 
+    /// Whether packets are far enough out of order to acknowledge immediately,
+    /// after receiving `pn` when `next_in_order_pn` was expected.
+    ///
+    /// With a threshold above 1, this follows draft-ietf-quic-ack-frequency: acknowledge
+    /// when the smallest missing packet that has not been reported is at least the
+    /// threshold below the largest received packet.
+    fn reordered(&self, pn: packet::Number, next_in_order_pn: packet::Number) -> bool {
+        match self.reordering_threshold {
+            0 => false,
+            1 => pn != next_in_order_pn,
+            threshold => {
+                let Some(largest) = self.ranges.front().map(|r| r.largest) else {
+                    return false;
+                };
+                self.smallest_unreported_missing()
+                    .is_some_and(|missing| largest - missing >= threshold)
+            }
+        }
+    }
+
+    /// The smallest packet number that has not been received and has not been
+    /// reported as missing, if any is below the largest received packet.
+    fn smallest_unreported_missing(&self) -> Option<packet::Number> {
+        let mut next = max(self.unreported, self.min_tracked);
+        // Walk the ranges from the smallest up, looking for a gap.
+        for r in self.ranges.iter().rev() {
+            if r.largest < next {
+                continue;
+            }
+            if r.smallest > next {
+                return Some(next);
+            }
+            next = r.largest + 1;
+        }
+        None
+    }
+
     /// If we just received a PING frame, we should immediately acknowledge.
     pub fn immediate_ack(&mut self, now: Instant) {
         self.ack_time = Some(now);
@@ -470,11 +599,26 @@ impl RecvdPackets {
         let ack_delay = min(MAX_VARINT, ack_delay);
         let extra_ranges = to_u64(ranges.len() - 1);
 
+        // Add receive timestamps in whatever space the ACK ranges leave,
+        // after allowing for a longer frame type and the timestamp range count.
+        let timestamps = self
+            .receive_timestamps
+            .as_mut()
+            .map_or_else(Vec::new, |rt| {
+                let smallest = ranges.last().map_or(first.smallest, |r| r.smallest);
+                let space = builder
+                    .remaining()
+                    .saturating_sub(Self::USEFUL_ACK_LEN + 16 * ranges.len() + 3 + 8);
+                rt.take_ranges(first.largest, smallest, space)
+            });
+        stats.receive_timestamps += timestamps.iter().map(|(_, d)| d.len()).sum::<usize>();
+
         builder.encode_frame(
-            if self.ecn_count.is_some() {
-                FrameType::AckEcn
-            } else {
-                FrameType::Ack
+            match (self.ecn_count.is_some(), timestamps.is_empty()) {
+                (false, true) => FrameType::Ack,
+                (true, true) => FrameType::AckEcn,
+                (false, false) => FrameType::AckReceiveTimestamps,
+                (true, false) => FrameType::AckEcnReceiveTimestamps,
             },
             |b| {
                 b.encode_varint(first.largest);
@@ -496,6 +640,21 @@ impl RecvdPackets {
                     b.encode_varint(self.ecn_count[Ecn::Ect1]);
                     b.encode_varint(self.ecn_count[Ecn::Ce]);
                 }
+
+                if !timestamps.is_empty() {
+                    b.encode_varint(to_u64(timestamps.len()));
+                    // The first gap is measured from the largest acknowledged,
+                    // the rest from two below the end of the previous range.
+                    let mut next = first.largest + 2;
+                    for (largest, deltas) in &timestamps {
+                        b.encode_varint(next - largest - 2); // Gap
+                        b.encode_varint(to_u64(deltas.len()));
+                        for delta in deltas {
+                            b.encode_varint(*delta);
+                        }
+                        next = largest + 1 - to_u64(deltas.len());
+                    }
+                }
             },
         );
 
@@ -503,6 +662,12 @@ impl RecvdPackets {
         self.ack_time = None;
         self.last_ack_time = Some(now);
         self.unacknowledged_count = 0;
+        // Once this is acknowledged, the peer can declare packets lost that
+        // are at least the reordering threshold below the largest acknowledged.
+        self.unreported = max(
+            self.unreported,
+            (first.largest + 1).saturating_sub(self.reordering_threshold),
+        );
 
         tokens.push(recovery::Token::Ack(AckToken {
             space: self.space,
@@ -543,11 +708,22 @@ impl AckTracker {
         seqno: u64,
         tolerance: packet::Number,
         delay: Duration,
-        ignore_order: bool,
+        reordering_threshold: packet::Number,
     ) {
         // Only ApplicationData ever delays ACK.
         if let Some(space) = self.get_mut(PacketNumberSpace::ApplicationData) {
-            space.ack_freq(seqno, tolerance, delay, ignore_order);
+            space.ack_freq(seqno, tolerance, delay, reordering_threshold);
+        }
+    }
+
+    /// Report receive times for up to `max` packets in each ACK, as the peer asked.
+    /// Only packets that are received from now on are included.
+    pub fn receive_timestamps(&mut self, max: u64, now: Instant) {
+        let max = usize::try_from(max).map_or(MAX_RECEIVE_TIMESTAMPS_PER_ACK, |max| {
+            min(max, MAX_RECEIVE_TIMESTAMPS_PER_ACK)
+        });
+        if let Some(space) = self.get_mut(PacketNumberSpace::ApplicationData) {
+            space.enable_receive_timestamps(max, now);
         }
     }
 
@@ -727,7 +903,7 @@ mod tests {
         assert!(rp.ack_time().is_none());
         assert!(!rp.ack_now(now(), RTT));
 
-        rp.ack_freq(0, COUNT, DELAY, false);
+        rp.ack_freq(0, COUNT, DELAY, 1);
 
         // Some packets won't cause an ACK to be needed.
         for i in 0..COUNT {
@@ -823,7 +999,7 @@ mod tests {
         let mut rp = RecvdPackets::new(PacketNumberSpace::ApplicationData);
 
         // Set tolerance to 2 and then it takes three packets.
-        rp.ack_freq(0, 2, Duration::from_millis(10), true);
+        rp.ack_freq(0, 2, Duration::from_millis(10), 0);
 
         rp.set_received(now(), 1, true, &mut stats).unwrap();
         assert_ne!(Some(now()), rp.ack_time());
@@ -841,7 +1017,7 @@ mod tests {
         write_frame(&mut rp);
 
         // Set tolerance to 2 and then it takes three packets.
-        rp.ack_freq(0, 2, Duration::from_millis(10), true);
+        rp.ack_freq(0, 2, Duration::from_millis(10), 0);
 
         rp.set_received(now(), 3, true, &mut stats).unwrap();
         assert_ne!(Some(now()), rp.ack_time());
@@ -851,13 +1027,73 @@ mod tests {
         assert_eq!(Some(now()), rp.ack_time());
     }
 
+    /// With a reordering threshold of 3, a gap only causes an immediate ACK
+    /// once the largest packet is 3 beyond it.
+    #[test]
+    fn reordering_threshold() {
+        let mut stats = Stats::default();
+        let mut rp = RecvdPackets::new(PacketNumberSpace::ApplicationData);
+        rp.ack_freq(0, 10, Duration::from_millis(10), 3);
+
+        rp.set_received(now(), 0, true, &mut stats).unwrap();
+        // Skip 1.
+        for pn in 2..4 {
+            rp.set_received(now(), pn, true, &mut stats).unwrap();
+            assert_ne!(Some(now()), rp.ack_time());
+        }
+        rp.set_received(now(), 4, true, &mut stats).unwrap();
+        assert_eq!(Some(now()), rp.ack_time());
+    }
+
+    /// A missing packet that has been reported in an ACK doesn't count
+    /// toward the reordering threshold again.
+    #[test]
+    fn reordering_threshold_reported() {
+        let mut stats = Stats::default();
+        let mut rp = RecvdPackets::new(PacketNumberSpace::ApplicationData);
+        rp.ack_freq(0, 10, Duration::from_millis(10), 3);
+
+        rp.set_received(now(), 0, true, &mut stats).unwrap();
+        // Skip 1.
+        for pn in 2..5 {
+            rp.set_received(now(), pn, true, &mut stats).unwrap();
+        }
+        write_frame(&mut rp);
+
+        // Packet 1 is still missing, but the peer knows that.
+        for pn in 5..8 {
+            rp.set_received(now(), pn, true, &mut stats).unwrap();
+            assert_ne!(Some(now()), rp.ack_time());
+        }
+        // Skip 8.
+        for pn in 9..11 {
+            rp.set_received(now(), pn, true, &mut stats).unwrap();
+            assert_ne!(Some(now()), rp.ack_time());
+        }
+        rp.set_received(now(), 11, true, &mut stats).unwrap();
+        assert_eq!(Some(now()), rp.ack_time());
+
+        // A gap exactly at the largest acknowledged plus one, less the threshold,
+        // is not yet reported: the peer can't declare it lost.
+        let mut rp = RecvdPackets::new(PacketNumberSpace::ApplicationData);
+        rp.ack_freq(0, 10, Duration::from_millis(10), 3);
+        for pn in [0, 1, 3, 4] {
+            rp.set_received(now(), pn, true, &mut stats).unwrap();
+            assert_ne!(Some(now()), rp.ack_time());
+        }
+        let later = now() + Duration::from_millis(10);
+        write_frame_at(&mut rp, later);
+        rp.set_received(later, 5, true, &mut stats).unwrap();
+        assert_eq!(Some(later), rp.ack_time());
+    }
+
     /// Test that an in-order packet that is not ack-eliciting doesn't
     /// increase the number of packets needed to cause an ACK.
     #[test]
     fn non_ack_eliciting_skip() {
         let mut stats = Stats::default();
         let mut rp = RecvdPackets::new(PacketNumberSpace::ApplicationData);
-        rp.ack_freq(0, 1, Duration::from_millis(10), true);
+        rp.ack_freq(0, 1, Duration::from_millis(10), 0);
 
         // This should be ignored.
         rp.set_received(now(), 0, false, &mut stats).unwrap();
@@ -874,7 +1110,7 @@ mod tests {
     fn non_ack_eliciting_reorder() {
         let mut stats = Stats::default();
         let mut rp = RecvdPackets::new(PacketNumberSpace::ApplicationData);
-        rp.ack_freq(0, 1, Duration::from_millis(10), false);
+        rp.ack_freq(0, 1, Duration::from_millis(10), 1);
 
         // These are out of order, but they are not ack-eliciting.
         rp.set_received(now(), 1, false, &mut stats).unwrap();
@@ -894,7 +1130,7 @@ mod tests {
         const DELAY: Duration = Duration::from_millis(17);
         let mut stats = Stats::default();
         let mut tracker = AckTracker::default();
-        tracker.ack_freq(0, 1, DELAY, false);
+        tracker.ack_freq(0, 1, DELAY, 1);
         // This packet won't trigger an ACK.
         tracker
             .get_mut(PacketNumberSpace::Handshake)
@@ -1034,6 +1270,55 @@ mod tests {
         assert_eq!(ack_delay, 2, "ack_delay must be 16\u{b5}s / 8 = 2");
     }
 
+    #[test]
+    fn receive_timestamps() {
+        let t = now();
+        let ms = Duration::from_millis;
+        let mut tracker = AckTracker::default();
+        tracker.receive_timestamps(16, t);
+        let rp = tracker.get_mut(PacketNumberSpace::ApplicationData).unwrap();
+        // Packet 3 is missing and packet 5 arrives before packet 4.
+        for (pn, at) in [(0, 1), (1, 2), (2, 3), (5, 4), (4, 5)] {
+            rp.set_received(t + ms(at), pn, true, &mut Stats::default())
+                .unwrap();
+        }
+
+        let mut builder =
+            packet::Builder::short(Encoder::default(), false, None::<&[u8]>, packet::LIMIT);
+        let mut stats = FrameStats::default();
+        tracker.write_frame(
+            PacketNumberSpace::ApplicationData,
+            t + ms(5),
+            RTT,
+            &mut builder,
+            &mut recovery::Tokens::new(),
+            &mut stats,
+        );
+        assert_eq!(stats.ack, 1);
+        // Packet 4 arrived after packet 5, so it can't be included.
+        assert_eq!(stats.receive_timestamps, 4);
+
+        let enc: Encoder = builder.into();
+        let bytes = Vec::from(enc);
+        let frame = Frame::decode(&mut Decoder::from(&bytes[1..])).unwrap();
+        let Frame::AckReceiveTimestamps {
+            largest_acknowledged,
+            timestamp_ranges,
+            ..
+        } = frame
+        else {
+            panic!("expected ACK_RECEIVE_TIMESTAMPS frame, got {frame:?}");
+        };
+        assert_eq!(
+            Frame::decode_receive_timestamps(largest_acknowledged, &timestamp_ranges, 0).unwrap(),
+            [(5, ms(4)), (2, ms(3)), (1, ms(2)), (0, ms(1))]
+        );
+
+        // Receive times are only reported once.
+        let rp = tracker.get_mut(PacketNumberSpace::ApplicationData).unwrap();
+        assert!(rp.receive_timestamps.as_ref().unwrap().times.is_empty());
+    }
+
     #[test]
     fn no_room_for_ack() {
         let mut tracker = AckTracker::default();
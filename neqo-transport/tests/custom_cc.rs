@@ -0,0 +1,280 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! An application-supplied congestion controller, built only from the public API.
+
+use std::{
+    cell::Cell,
+    cmp::max,
+    fmt::{self, Display, Formatter},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use neqo_common::qlog::Qlog;
+use neqo_transport::{
+    CongestionControlStats, CongestionController, ConnectionParameters, Pmtud, RttEstimate,
+    SentPacket, State,
+};
+use test_fixture::{
+    boxed,
+    sim::{
+        Simulator,
+        connection::{Node, ReachState, ReceiveData, SendData},
+        network::{Drop, RandomDelay, TailDrop},
+    },
+};
+
+const TRANSFER_AMOUNT: usize = 1 << 20;
+const DELAY: Duration = Duration::from_millis(50);
+
+/// What a controller saw, shared with the test.
+#[derive(Debug, Default)]
+struct Seen {
+    /// The number of bytes acknowledged.
+    acked: Cell<usize>,
+    /// The number of acknowledged packets that the peer reported receive times for.
+    received: Cell<usize>,
+}
+
+/// Additive increase, multiplicative decrease, with at most one decrease per round trip.
+#[derive(Debug)]
+struct Aimd {
+    pmtud: Pmtud,
+    cwnd: usize,
+    bytes_in_flight: usize,
+    /// Losses of packets sent before this time don't reduce the window again.
+    recovery_start: Option<Instant>,
+    seen: Rc<Seen>,
+}
+
+impl Aimd {
+    fn new(pmtud: Pmtud, seen: Rc<Seen>) -> Self {
+        let cwnd = 10 * pmtud.plpmtu();
+        Self {
+            pmtud,
+            cwnd,
+            bytes_in_flight: 0,
+            recovery_start: None,
+            seen,
+        }
+    }
+
+    fn in_recovery(&self, pkt: &SentPacket) -> bool {
+        self.recovery_start.is_some_and(|t| pkt.time_sent() <= t)
+    }
+
+    fn reduce(&mut self, now: Instant) {
+        self.cwnd = max(self.cwnd / 2, self.cwnd_min());
+        self.recovery_start = Some(now);
+    }
+}
+
+impl Display for Aimd {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "AIMD [cwnd {}, bif {}]", self.cwnd, self.bytes_in_flight)
+    }
+}
+
+impl CongestionController for Aimd {
+    fn set_qlog(&mut self, _qlog: Qlog) {}
+
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    fn bytes_in_flight(&self) -> usize {
+        self.bytes_in_flight
+    }
+
+    fn cwnd_avail(&self) -> usize {
+        self.cwnd.saturating_sub(self.bytes_in_flight)
+    }
+
+    fn cwnd_min(&self) -> usize {
+        2 * self.pmtud.plpmtu()
+    }
+
+    fn pmtud(&self) -> &Pmtud {
+        &self.pmtud
+    }
+
+    fn pmtud_mut(&mut self) -> &mut Pmtud {
+        &mut self.pmtud
+    }
+
+    fn on_packets_acked(
+        &mut self,
+        acked_pkts: &[SentPacket],
+        _rtt_est: &RttEstimate,
+        _now: Instant,
+        _cc_stats: &mut CongestionControlStats,
+    ) {
+        let received = acked_pkts.iter().filter_map(SentPacket::time_received);
+        self.seen
+            .received
+            .set(self.seen.received.get() + received.count());
+        for pkt in acked_pkts.iter().filter(|p| p.cc_outstanding()) {
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+            self.seen.acked.set(self.seen.acked.get() + pkt.len());
+            if !self.in_recovery(pkt) {
+                self.cwnd += self.pmtud.plpmtu() * pkt.len() / self.cwnd;
+            }
+        }
+    }
+
+    fn on_packets_lost(
+        &mut self,
+        _first_rtt_sample_time: Option<Instant>,
+        _prev_largest_acked_sent: Option<Instant>,
+        _pto: Duration,
+        lost_packets: &[SentPacket],
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        let mut congestion = false;
+        for pkt in lost_packets.iter().filter(|p| p.cc_in_flight()) {
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+            congestion |= !pkt.is_pmtud_probe() && !self.in_recovery(pkt);
+        }
+        if congestion {
+            cc_stats.congestion_events.loss += 1;
+            self.reduce(now);
+        }
+        congestion
+    }
+
+    fn on_ecn_ce_received(
+        &mut self,
+        largest_acked_pkt: &SentPacket,
+        now: Instant,
+        cc_stats: &mut CongestionControlStats,
+    ) -> bool {
+        if self.in_recovery(largest_acked_pkt) {
+            return false;
+        }
+        cc_stats.congestion_events.ecn += 1;
+        self.reduce(now);
+        true
+    }
+
+    fn recovery_packet(&self) -> bool {
+        false
+    }
+
+    fn discard(&mut self, pkt: &SentPacket, _now: Instant) {
+        if pkt.cc_outstanding() {
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.len());
+        }
+    }
+
+    fn on_packet_sent(&mut self, pkt: &SentPacket, _now: Instant) {
+        if pkt.cc_in_flight() {
+            self.bytes_in_flight += pkt.len();
+        }
+    }
+
+    fn discard_in_flight(&mut self, _now: Instant) {
+        self.bytes_in_flight = 0;
+    }
+}
+
+fn aimd(seen: &Rc<Seen>) -> ConnectionParameters {
+    let seen = Rc::clone(seen);
+    ConnectionParameters::default()
+        .congestion_control_factory(Box::new(move |pmtud: Pmtud| {
+            Box::new(Aimd::new(pmtud, Rc::clone(&seen))) as Box<dyn CongestionController>
+        }))
+        .pmtud(true)
+        .mlkem(false)
+}
+
+#[test]
+fn custom_cc_transfer_taildrop() {
+    let client = Rc::<Seen>::default();
+    let server = Rc::<Seen>::default();
+    Simulator::new(
+        "custom_cc_transfer_taildrop",
+        boxed![
+            Node::new_client(
+                aimd(&client),
+                boxed![ReachState::new(State::Confirmed)],
+                boxed![SendData::new(TRANSFER_AMOUNT)]
+            ),
+            TailDrop::dsl_downlink(),
+            Node::new_server(
+                aimd(&server),
+                boxed![ReachState::new(State::Confirmed)],
+                boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+            ),
+            TailDrop::dsl_uplink(),
+        ],
+    )
+    .run();
+
+    // The client's controller saw the whole transfer acknowledged,
+    // the server's only its handshake packets and other ACK-eliciting packets.
+    assert!(client.acked.get() >= TRANSFER_AMOUNT);
+    assert!(server.acked.get() > 0);
+    assert!(server.acked.get() < TRANSFER_AMOUNT);
+    // Neither asked for receive timestamps.
+    assert_eq!(client.received.get(), 0);
+    assert_eq!(server.received.get(), 0);
+}
+
+#[test]
+fn custom_cc_transfer_delay_drop() {
+    let client = Rc::<Seen>::default();
+    Simulator::new(
+        "custom_cc_transfer_delay_drop",
+        boxed![
+            Node::new_client(
+                aimd(&client),
+                boxed![ReachState::new(State::Confirmed)],
+                boxed![SendData::new(TRANSFER_AMOUNT)]
+            ),
+            RandomDelay::new(DELAY..DELAY),
+            Drop::percentage(1),
+            Node::new_server(
+                aimd(&Rc::default()),
+                boxed![ReachState::new(State::Confirmed)],
+                boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+            ),
+            RandomDelay::new(DELAY..DELAY),
+            Drop::percentage(1),
+        ],
+    )
+    .run();
+    assert!(client.acked.get() >= TRANSFER_AMOUNT);
+}
+
+/// A client that asks for receive timestamps sees them with acknowledged packets.
+#[test]
+fn custom_cc_receive_timestamps() {
+    let client = Rc::<Seen>::default();
+    let server = Rc::<Seen>::default();
+    Simulator::new(
+        "custom_cc_receive_timestamps",
+        boxed![
+            Node::new_client(
+                aimd(&client).receive_timestamps(16),
+                boxed![ReachState::new(State::Confirmed)],
+                boxed![SendData::new(TRANSFER_AMOUNT)]
+            ),
+            TailDrop::dsl_downlink(),
+            Node::new_server(
+                aimd(&server),
+                boxed![ReachState::new(State::Confirmed)],
+                boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+            ),
+            TailDrop::dsl_uplink(),
+        ],
+    )
+    .run();
+    assert!(client.acked.get() >= TRANSFER_AMOUNT);
+    assert!(client.received.get() > 0);
+    assert_eq!(server.received.get(), 0);
+}
@@ -6,7 +6,7 @@
 
 use std::{ops::Range, time::Duration};
 
-use neqo_transport::{CloseReason, ConnectionParameters, Error, State};
+use neqo_transport::{CloseReason, CongestionControl, ConnectionParameters, Error, State};
 use test_fixture::{
     boxed,
     sim::{
@@ -28,6 +28,19 @@ const fn weeks(m: u32) -> Duration {
     Duration::from_secs(m as u64 * 60 * 60 * 24 * 7)
 }
 
+fn bbr() -> ConnectionParameters {
+    ConnectionParameters::default()
+        .congestion_control(CongestionControl::Bbr)
+        .pmtud(true)
+        .mlkem(false)
+}
+
+fn prague() -> ConnectionParameters {
+    ConnectionParameters::default()
+        .congestion_control(CongestionControl::Prague)
+        .mlkem(false)
+}
+
 simulate!(
     connect_direct,
     [
@@ -160,6 +173,18 @@ simulate!(
     ],
 );
 
+simulate!(
+    transfer_delay_drop_zero_copy,
+    [
+        Node::default_client(boxed![SendData::new(TRANSFER_AMOUNT).zero_copy(true)]),
+        RandomDelay::new(DELAY_RANGE),
+        Drop::percentage(1),
+        Node::default_server(boxed![ReceiveData::new(TRANSFER_AMOUNT)]),
+        RandomDelay::new(DELAY_RANGE),
+        Drop::percentage(1),
+    ],
+);
+
 simulate!(
     transfer_taildrop,
     [
@@ -192,6 +217,62 @@ simulate!(
     ],
 );
 
+simulate!(
+    transfer_bbr_taildrop,
+    [
+        Node::new_client(
+            bbr(),
+            boxed![ReachState::new(State::Confirmed)],
+            boxed![SendData::new(TRANSFER_AMOUNT)]
+        ),
+        TailDrop::dsl_downlink(),
+        Node::new_server(
+            bbr(),
+            boxed![ReachState::new(State::Confirmed)],
+            boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+        ),
+        TailDrop::dsl_uplink(),
+    ],
+);
+
+simulate!(
+    transfer_bbr_delay_drop,
+    [
+        Node::new_client(
+            bbr(),
+            boxed![ReachState::new(State::Confirmed)],
+            boxed![SendData::new(TRANSFER_AMOUNT)]
+        ),
+        RandomDelay::new(DELAY_RANGE),
+        Drop::percentage(1),
+        Node::new_server(
+            bbr(),
+            boxed![ReachState::new(State::Confirmed)],
+            boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+        ),
+        RandomDelay::new(DELAY_RANGE),
+        Drop::percentage(1),
+    ],
+);
+
+simulate!(
+    transfer_prague_l4s,
+    [
+        Node::new_client(
+            prague(),
+            boxed![ReachState::new(State::Confirmed)],
+            boxed![SendData::new(TRANSFER_AMOUNT)]
+        ),
+        TailDrop::new(1_000_000, 65_536, Aqm::L4s, Duration::from_millis(50)),
+        Node::new_server(
+            prague(),
+            boxed![ReachState::new(State::Confirmed)],
+            boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+        ),
+        TailDrop::new(200_000, 16_384, Aqm::L4s, Duration::from_millis(50))
+    ],
+);
+
 /// This test is a nasty piece of work.  Delays are anything from 0 to 50ms and 1% of
 /// packets get dropped.
 #[test]
@@ -0,0 +1,134 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Check that loss detection adapts to a link that reorders packets.
+
+use std::{
+    cell::Cell,
+    ops::Range,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use neqo_transport::{Connection, ConnectionEvent, ConnectionParameters, State};
+use test_fixture::{
+    boxed,
+    sim::{
+        GoalStatus, Simulator,
+        connection::{Goal, Node, ReachState, ReceiveData, SendData},
+        network::{RandomDelay, TailDrop},
+    },
+};
+
+const TRANSFER_AMOUNT: usize = 1 << 19;
+const ZERO: Duration = Duration::from_millis(0);
+const DELAY: Duration = Duration::from_millis(50);
+/// Enough variation in delay to reorder packets by several places at the
+/// rate of `TailDrop::dsl_uplink`, and by more than the 1/8 of an RTT that
+/// the initial time threshold allows.
+const REORDER: Range<Duration> = ZERO..Duration::from_millis(30);
+
+/// The largest loss detection thresholds that a sender reported.
+#[derive(Debug, Default)]
+struct Seen {
+    initial_packet_threshold: Cell<u64>,
+    packet_threshold: Cell<u64>,
+    time_threshold: Cell<f64>,
+    late_ack: Cell<usize>,
+}
+
+/// Send data, recording the loss detection thresholds along the way.
+#[derive(Debug)]
+struct SendObserved {
+    send: SendData,
+    seen: Rc<Seen>,
+}
+
+impl SendObserved {
+    fn observe(&self, c: &Connection, status: GoalStatus) -> GoalStatus {
+        let stats = c.stats();
+        let seen = &self.seen;
+        seen.packet_threshold
+            .set(seen.packet_threshold.get().max(stats.loss_packet_threshold));
+        seen.time_threshold
+            .set(seen.time_threshold.get().max(stats.loss_time_threshold));
+        seen.late_ack.set(stats.late_ack);
+        status
+    }
+}
+
+impl Goal for SendObserved {
+    fn init(&mut self, c: &mut Connection, now: Instant) {
+        self.seen
+            .initial_packet_threshold
+            .set(c.stats().loss_packet_threshold);
+        self.send.init(c, now);
+    }
+
+    fn process(&mut self, c: &mut Connection, now: Instant) -> GoalStatus {
+        let status = self.send.process(c, now);
+        self.observe(c, status)
+    }
+
+    fn handle_event(
+        &mut self,
+        c: &mut Connection,
+        e: &ConnectionEvent,
+        now: Instant,
+    ) -> GoalStatus {
+        let status = self.send.handle_event(c, e, now);
+        self.observe(c, status)
+    }
+}
+
+/// Transfer data over a link where the delay varies over `delay`.
+fn transfer(name: &str, delay: Range<Duration>) -> Rc<Seen> {
+    let seen = Rc::new(Seen::default());
+    Simulator::new(
+        name,
+        boxed![
+            Node::new_client(
+                ConnectionParameters::default().mlkem(false),
+                boxed![ReachState::new(State::Confirmed)],
+                boxed![SendObserved {
+                    send: SendData::new(TRANSFER_AMOUNT),
+                    seen: Rc::clone(&seen),
+                }]
+            ),
+            TailDrop::dsl_uplink(),
+            RandomDelay::new(delay),
+            Node::new_server(
+                ConnectionParameters::default().mlkem(false),
+                boxed![ReachState::new(State::Confirmed)],
+                boxed![ReceiveData::new(TRANSFER_AMOUNT)]
+            ),
+            RandomDelay::new(DELAY..DELAY),
+        ],
+    )
+    .run();
+    seen
+}
+
+/// Reordering causes spurious losses, which raise both thresholds.
+#[test]
+fn reordering_raises_thresholds() {
+    let seen = transfer("reordering_raises_thresholds", REORDER);
+    assert!(seen.late_ack.get() > 0);
+    assert!(seen.packet_threshold.get() > seen.initial_packet_threshold.get());
+    assert!(seen.time_threshold.get() > 9.0 / 8.0);
+}
+
+/// Without reordering, the thresholds stay at their initial values.
+#[test]
+fn no_reordering_keeps_thresholds() {
+    let seen = transfer("no_reordering_keeps_thresholds", DELAY..DELAY);
+    assert_eq!(seen.late_ack.get(), 0);
+    assert_eq!(
+        seen.packet_threshold.get(),
+        seen.initial_packet_threshold.get()
+    );
+    assert!((seen.time_threshold.get() - 9.0 / 8.0).abs() < f64::EPSILON);
+}
@@ -105,6 +105,59 @@ fn retry_ech_fallback() {
     assert_eq!(fallback_config, server.ech_config());
 }
 
+/// A Retry token from one server is accepted by another server that shares its keys.
+#[test]
+fn retry_shared_keys() {
+    let mut server = default_server();
+    server.set_validation(ValidateAddress::Always);
+    let mut other = default_server();
+    other.set_validation(ValidateAddress::Always);
+    other
+        .import_token_keys(&server.export_token_keys(), now())
+        .unwrap();
+    let mut client = default_client();
+
+    let dgram = client.process_output(now()).dgram(); // Initial
+    let dgram2 = client.process_output(now()).dgram(); // Initial
+    _ = server.process(dgram, now()).dgram().unwrap(); // Retry
+    let dgram = server.process(dgram2, now()).dgram().unwrap(); // Retry
+    assertions::assert_retry(&dgram);
+
+    let dgram = client.process(Some(dgram), now()).dgram(); // Initial w/token
+    let dgram2 = client.process_output(now()).dgram(); // Initial
+    _ = other.process(dgram, now()).dgram().unwrap();
+    let dgram = other.process(dgram2, now()).dgram();
+    let dgram = client.process(dgram, now()).dgram();
+    let dgram = other.process(dgram, now()).dgram(); // Initial, HS
+    assert!(dgram.is_some());
+    drop(client.process(dgram, now()).dgram()); // Ingest, drop any ACK.
+    client.authenticated(AuthenticationStatus::Ok, now());
+    let dgram = client.process_output(now()).dgram(); // Send Finished
+    assert_eq!(*client.state(), State::Connected);
+    drop(other.process(dgram, now()).dgram());
+    connected_server(&other);
+}
+
+/// Once the key that protected a Retry token has been rotated out,
+/// the token is rejected.
+#[test]
+fn retry_rotated_key() {
+    let mut server = default_server();
+    server.set_validation(ValidateAddress::Always);
+    let mut client = default_client();
+
+    let dgram = client.process_output(now()).dgram(); // Initial
+    let dgram2 = client.process_output(now()).dgram(); // Initial
+    _ = server.process(dgram, now()).dgram().unwrap(); // Retry
+    let dgram = server.process(dgram2, now()).dgram().unwrap(); // Retry
+    assertions::assert_retry(&dgram);
+
+    server.rotate_token_keys(now());
+    server.rotate_token_keys(now());
+    let dgram = client.process(Some(dgram), now()).dgram(); // Initial w/token
+    assert!(server.process(dgram, now()).dgram().is_none());
+}
+
 /// Receiving a Retry is enough to infer something about the RTT.
 /// Probably.
 #[test]
@@ -0,0 +1,247 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Measure how stream schedulers divide a connection between streams.
+
+use std::{
+    cell::RefCell,
+    cmp::min,
+    collections::BTreeMap,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use neqo_transport::{
+    Connection, ConnectionEvent, ConnectionParameters, State, StreamId, StreamScheduler, StreamType,
+};
+use test_fixture::{
+    boxed,
+    sim::{
+        GoalStatus, Simulator,
+        connection::{Goal, Node, ReachState},
+    },
+};
+
+/// The amount of data to send on each stream.
+const STREAM_AMOUNT: usize = 1 << 17;
+
+/// Send `STREAM_AMOUNT` bytes on a new stream for each entry in `streams`,
+/// which holds the weight and deadline of the stream.
+#[derive(Debug)]
+struct SendStreams {
+    streams: Vec<(u16, Option<Duration>)>,
+    remaining: Vec<(StreamId, usize)>,
+}
+
+impl SendStreams {
+    fn new<I: IntoIterator<Item = (u16, Option<Duration>)>>(streams: I) -> Self {
+        Self {
+            streams: streams.into_iter().collect(),
+            remaining: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, c: &mut Connection) -> GoalStatus {
+        const DATA: &[u8] = &[0; 4096];
+        let mut status = GoalStatus::Waiting;
+        for (stream_id, remaining) in &mut self.remaining {
+            while *remaining > 0 {
+                let end = min(*remaining, DATA.len());
+                let sent = c.stream_send(*stream_id, &DATA[..end]).unwrap();
+                if sent == 0 {
+                    break;
+                }
+                *remaining -= sent;
+                if *remaining == 0 {
+                    c.stream_close_send(*stream_id).unwrap();
+                }
+                status = GoalStatus::Active;
+            }
+        }
+        self.remaining.retain(|&(_, remaining)| remaining > 0);
+        if self.remaining.is_empty() {
+            GoalStatus::Done
+        } else {
+            status
+        }
+    }
+}
+
+impl Goal for SendStreams {
+    fn init(&mut self, c: &mut Connection, now: Instant) {
+        for &(weight, deadline) in &self.streams {
+            let stream_id = c.stream_create(StreamType::UniDi).unwrap();
+            c.stream_weight(stream_id, weight).unwrap();
+            c.stream_send_deadline(stream_id, deadline.map(|d| now + d))
+                .unwrap();
+            self.remaining.push((stream_id, STREAM_AMOUNT));
+        }
+    }
+
+    fn process(&mut self, c: &mut Connection, _now: Instant) -> GoalStatus {
+        self.send(c)
+    }
+
+    fn handle_event(
+        &mut self,
+        c: &mut Connection,
+        e: &ConnectionEvent,
+        _now: Instant,
+    ) -> GoalStatus {
+        if matches!(e, ConnectionEvent::SendStreamWritable { .. }) {
+            self.send(c)
+        } else {
+            GoalStatus::Waiting
+        }
+    }
+}
+
+/// What a receiver saw.
+#[derive(Debug, Default)]
+struct Received {
+    /// The bytes received on each stream.
+    bytes: BTreeMap<StreamId, usize>,
+    /// The bytes received on each stream when `STREAM_AMOUNT` bytes had been received.
+    sample: Option<BTreeMap<StreamId, usize>>,
+    /// Streams in the order that they ended.
+    ended: Vec<StreamId>,
+}
+
+/// Receive all data on `streams` streams.
+#[derive(Debug)]
+struct ReceiveStreams {
+    streams: usize,
+    received: Rc<RefCell<Received>>,
+}
+
+impl Goal for ReceiveStreams {
+    fn handle_event(
+        &mut self,
+        c: &mut Connection,
+        e: &ConnectionEvent,
+        _now: Instant,
+    ) -> GoalStatus {
+        let ConnectionEvent::RecvStreamReadable { stream_id } = e else {
+            return GoalStatus::Waiting;
+        };
+        let mut received = self.received.borrow_mut();
+        if received.ended.contains(stream_id) {
+            return GoalStatus::Waiting;
+        }
+        let mut buf = vec![0; 4096];
+        loop {
+            let (recvd, fin) = c.stream_recv(*stream_id, &mut buf).unwrap();
+            *received.bytes.entry(*stream_id).or_default() += recvd;
+            if received.sample.is_none() && received.bytes.values().sum::<usize>() >= STREAM_AMOUNT
+            {
+                received.sample = Some(received.bytes.clone());
+            }
+            if fin {
+                received.ended.push(*stream_id);
+            }
+            if recvd == 0 || fin {
+                break;
+            }
+        }
+        if received.ended.len() == self.streams {
+            GoalStatus::Done
+        } else {
+            GoalStatus::Active
+        }
+    }
+}
+
+/// Send on streams with the given weights and deadlines and return what was received.
+fn run(name: &str, scheduler: StreamScheduler, streams: &[(u16, Option<Duration>)]) -> Received {
+    let received = Rc::new(RefCell::new(Received::default()));
+    Simulator::new(
+        name,
+        boxed![
+            Node::new_client(
+                ConnectionParameters::default()
+                    .stream_scheduler(scheduler)
+                    .mlkem(false),
+                boxed![ReachState::new(State::Confirmed)],
+                boxed![SendStreams::new(streams.iter().copied())]
+            ),
+            Node::new_server(
+                ConnectionParameters::default().mlkem(false),
+                boxed![ReachState::new(State::Confirmed)],
+                boxed![ReceiveStreams {
+                    streams: streams.len(),
+                    received: Rc::clone(&received),
+                }]
+            ),
+        ],
+    )
+    .run();
+    Rc::into_inner(received).unwrap().into_inner()
+}
+
+/// Check that the shares of the first `STREAM_AMOUNT` bytes are in proportion to `weights`.
+fn assert_shares(received: &Received, weights: &[u16]) {
+    let sample = received.sample.as_ref().unwrap();
+    let total = sample.values().sum::<usize>();
+    let weight_sum = weights.iter().map(|&w| usize::from(w)).sum::<usize>();
+    assert_eq!(received.bytes.len(), weights.len());
+    for (&weight, stream_id) in weights.iter().zip(received.bytes.keys()) {
+        let bytes = sample.get(stream_id).copied().unwrap_or_default();
+        let expected = total * usize::from(weight) / weight_sum;
+        assert!(
+            bytes.abs_diff(expected) <= total / 16,
+            "stream {stream_id} received {bytes} of {total}, expected {expected}"
+        );
+    }
+}
+
+#[test]
+fn weighted_fair_shares() {
+    let received = run(
+        "weighted_fair_shares",
+        StreamScheduler::WeightedFair,
+        &[(1, None), (2, None), (4, None)],
+    );
+    assert_shares(&received, &[1, 2, 4]);
+}
+
+#[test]
+fn weighted_fair_equal_shares() {
+    let received = run(
+        "weighted_fair_equal_shares",
+        StreamScheduler::WeightedFair,
+        &[(16, None), (16, None), (16, None)],
+    );
+    assert_shares(&received, &[1, 1, 1]);
+}
+
+/// The default scheduler sends streams in the order that they were created.
+#[test]
+fn send_order_no_sharing() {
+    let received = run(
+        "send_order_no_sharing",
+        StreamScheduler::SendOrder,
+        &[(1, None), (2, None), (4, None)],
+    );
+    assert_shares(&received, &[1, 0, 0]);
+}
+
+#[test]
+fn earliest_deadline_first() {
+    const MS: Duration = Duration::from_millis(1);
+    let received = run(
+        "earliest_deadline_first",
+        StreamScheduler::EarliestDeadline,
+        &[
+            (16, None),
+            (16, Some(MS * 30)),
+            (16, Some(MS * 10)),
+            (16, Some(MS * 20)),
+        ],
+    );
+    assert_shares(&received, &[0, 0, 1, 0]);
+    let ids = received.bytes.keys().copied().collect::<Vec<_>>();
+    assert_eq!(received.ended, [ids[2], ids[3], ids[1], ids[0]]);
+}
@@ -6,14 +6,25 @@
 
 mod common;
 
-use std::{cell::RefCell, net::SocketAddr, rc::Rc, time::Duration};
+use std::{
+    cell::RefCell,
+    net::{Ipv6Addr, SocketAddr},
+    rc::Rc,
+    sync::mpsc,
+    time::Duration,
+};
 
 use common::{connect, connected_server, default_server, find_ticket, generate_ticket, new_server};
-use neqo_common::{Datagram, Decoder, Encoder, Role, qtrace};
+use neqo_common::{
+    Datagram, Decoder, Encoder, Role,
+    event::Provider as _,
+    qlog::{Sink, SinkConfig},
+    qtrace,
+};
 use neqo_transport::{
-    CloseReason, Connection, ConnectionParameters, Error, MIN_INITIAL_PACKET_SIZE, Output, State,
-    StreamType, Version,
-    server::{ConnectionRef, Server, ValidateAddress},
+    CloseReason, Connection, ConnectionParameters, Error, MIN_INITIAL_PACKET_SIZE, Output,
+    ServerStats, State, StatelessResetKey, StreamType, Version,
+    server::{AdmissionLimits, ConnectionRef, OverloadAction, Server, ValidateAddress},
     version,
 };
 use nss::{
@@ -259,21 +270,102 @@ fn drop_short_initial() {
     assert!(server.process(Some(bogus), now()).dgram().is_none());
 }
 
-#[test]
-fn drop_short_header_packet_for_unknown_connection() {
+fn short_header_packet(len: usize) -> Datagram {
     const CID: &[u8] = &[55; 8]; // not a real connection ID
-    let mut server = default_server();
-
-    let mut header = Encoder::with_capacity(MIN_INITIAL_PACKET_SIZE);
+    let mut header = Encoder::with_capacity(len);
     header
         .encode_byte(0x40) // short header
         .encode_vec(1, CID)
         .encode_byte(1);
     let mut bogus_data: Vec<u8> = header.into();
-    bogus_data.resize(MIN_INITIAL_PACKET_SIZE, 66);
+    bogus_data.resize(len, 66);
+    datagram(bogus_data)
+}
 
-    let bogus = datagram(bogus_data);
+#[test]
+fn stateless_reset_for_unknown_connection() {
+    let mut server = default_server();
+
+    let bogus = short_header_packet(MIN_INITIAL_PACKET_SIZE);
+    let reset = server.process(Some(bogus), now()).dgram().unwrap();
+    // The reset looks like a short header packet and is smaller than the trigger.
+    assert_eq!(reset[0] & 0xc0, 0x40);
+    assert!(reset.len() < MIN_INITIAL_PACKET_SIZE);
+}
+
+#[test]
+fn stateless_reset_smaller_than_trigger() {
+    let mut server = default_server();
+
+    // A reset has to be at least 21 bytes, so there is no reset for a packet that small.
+    let bogus = short_header_packet(21);
     assert!(server.process(Some(bogus), now()).dgram().is_none());
+
+    let bogus = short_header_packet(30);
+    let reset = server.process(Some(bogus), now()).dgram().unwrap();
+    assert_eq!(reset.len(), 29);
+}
+
+#[test]
+fn stateless_reset_rate_limited() {
+    let mut server = default_server();
+    let mut resets = 0;
+    for _ in 0..1000 {
+        let bogus = short_header_packet(MIN_INITIAL_PACKET_SIZE);
+        if server.process(Some(bogus), now()).dgram().is_some() {
+            resets += 1;
+        }
+    }
+    assert!(resets > 0);
+    assert!(resets < 1000);
+}
+
+/// A server that has lost the state for a connection can still reset it,
+/// as long as it uses the same key as the server that created the connection.
+#[test]
+fn stateless_reset_after_restart() {
+    let key = StatelessResetKey::new([0x5a; StatelessResetKey::LEN]);
+    let mut server = default_server();
+    server.set_stateless_reset_key(key.clone());
+    let mut client = default_client();
+    connect(&mut client, &mut server);
+
+    let mut server = default_server();
+    server.set_stateless_reset_key(key);
+
+    let stream = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream, &[0; 100]).unwrap();
+    let dgram = client.process_output(now()).dgram();
+    let reset = server.process(dgram, now()).dgram();
+    assert!(reset.is_some());
+
+    client.process_input(reset.unwrap(), now());
+    assert!(matches!(
+        client.state(),
+        State::Draining {
+            error: CloseReason::Transport(Error::StatelessReset),
+            ..
+        }
+    ));
+}
+
+/// Without a shared key, a restarted server can't produce a valid reset.
+#[test]
+fn stateless_reset_different_key() {
+    let mut server = default_server();
+    let mut client = default_client();
+    connect(&mut client, &mut server);
+
+    let mut server = default_server();
+
+    let stream = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream, &[0; 100]).unwrap();
+    let dgram = client.process_output(now()).dgram();
+    let reset = server.process(dgram, now()).dgram();
+    assert!(reset.is_some());
+
+    client.process_input(reset.unwrap(), now());
+    assert_eq!(*client.state(), State::Confirmed);
 }
 
 /// Verify that the server can read 0-RTT properly.  A more robust server would buffer
@@ -778,6 +870,35 @@ fn closed() {
     assert_eq!(res, Output::None);
 }
 
+/// Once a connection is removed, its connection IDs no longer route packets to it,
+/// so the server sends a stateless reset instead.
+#[test]
+fn stateless_reset_after_close() {
+    let mut server = default_server();
+    let mut client = default_client();
+    connect(&mut client, &mut server);
+
+    let stream = client.stream_create(StreamType::UniDi).unwrap();
+    client.stream_send(stream, &[0; 100]).unwrap();
+    let dgram = client.process_output(now()).dgram();
+    assert!(dgram.is_some());
+
+    // Let the server connection idle out and be removed.
+    let later = now() + Duration::from_secs(60);
+    assert_eq!(server.process_output(later), Output::None);
+
+    let reset = server.process(dgram, later).dgram();
+    assert!(reset.is_some());
+    client.process_input(reset.unwrap(), now());
+    assert!(matches!(
+        client.state(),
+        State::Draining {
+            error: CloseReason::Transport(Error::StatelessReset),
+            ..
+        }
+    ));
+}
+
 #[cfg(test)]
 fn can_create_streams(c: &mut Connection, t: StreamType, n: u64) {
     for _ in 0..n {
@@ -900,6 +1021,32 @@ fn ech() {
     );
 }
 
+/// Connections that have data to send take turns.
+#[test]
+fn output_round_robin() {
+    let mut server = default_server();
+    let mut client1 = default_client();
+    let conn1 = connect(&mut client1, &mut server);
+    while conn1.borrow_mut().next_event().is_some() {}
+    let mut client2 = default_client();
+    let conn2 = connect(&mut client2, &mut server);
+    assert_ne!(conn1, conn2);
+    while server.process_output(now()).dgram().is_some() {}
+
+    for conn in [&conn1, &conn2] {
+        let stream = conn.borrow_mut().stream_create(StreamType::UniDi).unwrap();
+        conn.borrow_mut().stream_send(stream, &[0; 5_000]).unwrap();
+    }
+    let packets_tx = |c: &ConnectionRef| c.borrow().stats().packets_tx;
+    let mut senders = Vec::new();
+    for _ in 0..4 {
+        let before = packets_tx(&conn1);
+        assert!(server.process_output(now()).dgram().is_some());
+        senders.push(if packets_tx(&conn1) > before { 1 } else { 2 });
+    }
+    assert_eq!(senders, [1, 2, 1, 2]);
+}
+
 #[test]
 fn has_active_connections() {
     let mut server = default_server();
@@ -964,3 +1111,139 @@ fn saved_datagrams() {
         .dgram()
         .expect("fourth packet triggers third vn");
 }
+
+/// Check that `dgram` closes the connection of `client` with `CONNECTION_REFUSED`.
+fn assert_refused(client: &mut Connection, dgram: Option<Datagram>) {
+    client.process_input(dgram.expect("a CONNECTION_CLOSE"), now());
+    assert!(matches!(
+        client.state(),
+        State::Draining { error: CloseReason::Transport(Error::Peer(code)), .. } if *code == Error::ConnectionRefused.code()
+    ));
+}
+
+/// Start a handshake with the server, returning the client and the server response.
+fn start_handshake(server: &mut Server) -> (Connection, Option<Datagram>) {
+    let mut client = default_client();
+    let dgram = client.process_output(now()).dgram();
+    let out = server.process(dgram, now()).dgram();
+    (client, out)
+}
+
+#[test]
+fn admission_connection_limit() {
+    let mut server = default_server();
+    server.set_admission_limits(AdmissionLimits::default().max_connections(1));
+    let mut client = default_client();
+    connect(&mut client, &mut server);
+
+    let (mut client, out) = start_handshake(&mut server);
+    assert_refused(&mut client, out);
+    assert_eq!(server.stats().refused_connection_limit, 1);
+    assert_eq!(server.active_connections().len(), 1);
+}
+
+#[test]
+fn admission_prefix_limit() {
+    let mut server = default_server();
+    server.set_admission_limits(AdmissionLimits::default().max_handshakes_per_prefix(1));
+    let (mut client, out) = start_handshake(&mut server);
+
+    // Another client from the same prefix is refused until the handshake completes.
+    let (mut refused, refusal) = start_handshake(&mut server);
+    assert_refused(&mut refused, refusal);
+    assert_eq!(server.stats().refused_prefix_limit, 1);
+    complete_connection(&mut client, &mut server, out);
+
+    _ = start_handshake(&mut server);
+    assert_eq!(server.active_connections().len(), 2);
+
+    // Handshakes from other prefixes are counted separately.
+    let mut other = default_client();
+    let dgram = other.process_output(now()).dgram().unwrap();
+    let dgram = Datagram::new(
+        SocketAddr::new(Ipv6Addr::new(0xfe80, 1, 0, 0, 0, 0, 0, 1).into(), 443),
+        dgram.destination(),
+        dgram.tos(),
+        dgram.to_vec(),
+    );
+    assert!(server.process(Some(dgram), now()).dgram().is_some());
+    assert_eq!(server.active_connections().len(), 3);
+    assert_eq!(server.stats().refused_prefix_limit, 1);
+}
+
+#[test]
+fn admission_overload_retry() {
+    let mut server = default_server();
+    server.set_admission_limits(
+        AdmissionLimits::default().handshake_threshold(1, OverloadAction::Retry),
+    );
+    _ = start_handshake(&mut server);
+
+    let (mut client, retry) = start_handshake(&mut server);
+    let retry = retry.unwrap();
+    assertions::assert_retry(&retry);
+    assert_eq!(server.stats().retry_overload, 1);
+
+    // With the token from the Retry, the client is accepted.
+    let dgram = client.process(Some(retry), now()).dgram();
+    _ = server.process(dgram, now());
+    assert_eq!(server.active_connections().len(), 2);
+    assert_eq!(
+        server.stats(),
+        &ServerStats {
+            retry_overload: 1,
+            ..ServerStats::default()
+        }
+    );
+}
+
+#[test]
+fn admission_overload_refuse() {
+    let mut server = default_server();
+    server.set_admission_limits(
+        AdmissionLimits::default().handshake_threshold(1, OverloadAction::Refuse),
+    );
+    let (mut client, out) = start_handshake(&mut server);
+
+    let (mut refused, refusal) = start_handshake(&mut server);
+    assert_refused(&mut refused, refusal);
+    assert_eq!(server.stats().refused_overload, 1);
+
+    // Once the handshake completes, new connections are accepted again.
+    complete_connection(&mut client, &mut server, out);
+    let mut client = default_client();
+    connect(&mut client, &mut server);
+    assert_eq!(server.stats().refused_overload, 1);
+}
+
+#[test]
+fn qlog_sinks() {
+    let (tx, rx) = mpsc::channel();
+    let mut server = default_server();
+    server.set_qlog_sinks(Some(SinkConfig::new(move |label: &str| -> Box<dyn Sink> {
+        assert!(label.starts_with("server-"));
+        Box::new(tx.clone())
+    })));
+    let mut client = default_client();
+    connect(&mut client, &mut server);
+
+    let records = rx.try_iter().collect::<Vec<_>>();
+    assert!(records.len() > 1);
+    assert!(records[0].starts_with(b"\x1e{\"qlog_version\""));
+    assert!(
+        records
+            .iter()
+            .all(|r| r.starts_with(b"\x1e") && r.ends_with(b"\n"))
+    );
+}
+
+#[test]
+fn qlog_sinks_sampled_out() {
+    let mut server = default_server();
+    server.set_qlog_sinks(Some(
+        SinkConfig::new(|_: &str| -> Box<dyn Sink> { panic!("connection is not sampled") })
+            .sample_percent(0),
+    ));
+    let mut client = default_client();
+    connect(&mut client, &mut server);
+}
@@ -0,0 +1,147 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::{num::NonZeroUsize, time::Duration};
+
+use neqo_transport::{
+    Connection, ConnectionParameters, Error, Res, State, StreamType,
+    server::{Server, ValidateAddress},
+    sharded::{self, Context},
+};
+use nss::{AllowZeroRtt, generate_ech_keys};
+use test_fixture::{
+    DEFAULT_ALPN, DEFAULT_KEYS, anti_replay, default_client, fixture_init, maybe_authenticate, now,
+};
+
+/// How long to wait for shards to respond.
+const WAIT: Duration = Duration::from_millis(100);
+
+fn shard(ctx: &Context) -> Res<Server> {
+    Server::new(
+        now(),
+        DEFAULT_KEYS,
+        DEFAULT_ALPN,
+        anti_replay(),
+        Box::new(AllowZeroRtt {}),
+        ctx.cid_generator()?,
+        ConnectionParameters::default(),
+    )
+}
+
+fn sharded_server<F>(shards: usize, factory: F) -> Res<sharded::Server>
+where
+    F: Fn(&Context) -> Res<Server> + Send + Sync + 'static,
+{
+    fixture_init();
+    sharded::Server::new(NonZeroUsize::new(shards).unwrap(), now(), now, factory)
+}
+
+/// Exchange packets between `client` and the shards until the handshake is confirmed.
+fn handshake(server: &sharded::Server, client: &mut Connection) {
+    let mut input = Vec::new();
+    for _ in 0..20 {
+        for d in input.drain(..) {
+            client.process_input(d, now());
+        }
+        _ = maybe_authenticate(client);
+        while let Some(d) = client.process_output(now()).dgram() {
+            server.route(d).unwrap();
+        }
+        if *client.state() == State::Confirmed {
+            return;
+        }
+        while let Some(d) = server.recv_output(Some(WAIT)) {
+            input.push(d);
+        }
+    }
+    panic!("handshake did not complete: {:?}", client.state());
+}
+
+#[test]
+fn sharded_handshake() {
+    let server = sharded_server(4, shard).unwrap();
+    assert_eq!(server.router().shards(), 4);
+    for _ in 0..8 {
+        let mut client = default_client();
+        handshake(&server, &mut client);
+
+        // Once connected, the client uses a connection ID that the shard chose,
+        // which routes its packets to the same shard every time.
+        let stream = client.stream_create(StreamType::BiDi).unwrap();
+        client.stream_send(stream, b"hello").unwrap();
+        let d1 = client.process_output(now()).dgram().unwrap();
+        client.close(now(), 0, "done");
+        let d2 = client.process_output(now()).dgram().unwrap();
+        assert_eq!(server.router().shard(&d1), server.router().shard(&d2));
+    }
+}
+
+/// The Initial that follows a Retry goes to the shard that sent the Retry.
+#[test]
+fn sharded_retry() {
+    let server = sharded_server(2, |ctx| {
+        let server = shard(ctx)?;
+        server.set_validation(ValidateAddress::Always);
+        Ok(server)
+    })
+    .unwrap();
+    let mut client = default_client();
+    handshake(&server, &mut client);
+}
+
+#[test]
+fn sharded_token_keys() {
+    let mut server = sharded_server(2, shard).unwrap();
+    let keys = server.export_token_keys();
+    server.rotate_token_keys(now());
+    assert_ne!(server.export_token_keys(), keys);
+
+    let mut other = sharded_server(2, shard).unwrap();
+    other.import_token_keys(&keys, now()).unwrap();
+    assert_eq!(other.export_token_keys(), keys);
+    assert_eq!(
+        other.import_token_keys(&[], now()),
+        Err(Error::InvalidInput)
+    );
+
+    // Shards still work after the keys change.
+    let mut client = default_client();
+    handshake(&other, &mut client);
+}
+
+#[test]
+fn sharded_factory_error() {
+    let res = sharded_server(3, |ctx| {
+        if ctx.index() == 1 {
+            Err(Error::InvalidInput)
+        } else {
+            shard(ctx)
+        }
+    });
+    assert_eq!(res.err(), Some(Error::InvalidInput));
+}
+
+#[test]
+fn sharded_too_many_shards() {
+    let res = sharded_server(sharded::Server::MAX_SHARDS + 1, shard);
+    assert_eq!(res.err(), Some(Error::InvalidInput));
+}
+
+/// Each shard has to be configured with the same ECH keys.
+#[test]
+fn sharded_ech_mismatch() {
+    let ech_shard = |ctx: &Context| {
+        let mut server = shard(ctx)?;
+        let (sk, pk) = generate_ech_keys().unwrap();
+        server.enable_ech(0x4a, "public.example", &sk, &pk)?;
+        Ok(server)
+    };
+    let server = sharded_server(1, ech_shard).unwrap();
+    assert!(!server.ech_config().is_empty());
+
+    let res = sharded_server(2, ech_shard);
+    assert_eq!(res.err(), Some(Error::InvalidInput));
+}
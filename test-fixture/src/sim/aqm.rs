@@ -18,6 +18,8 @@ use super::Rng;
 const CODEL_TARGET: Duration = Duration::from_millis(5);
 const CODEL_INTERVAL: Duration = Duration::from_millis(100);
 const CODEL_FAST_RESTART_WINDOW: Duration = CODEL_INTERVAL.saturating_mul(16);
+/// The sojourn time above which L4S packets are marked, as for the L queue in RFC 9332.
+const L4S_THRESHOLD: Duration = Duration::from_millis(1);
 
 /// `CoDel` (RFC 8289) algorithm state.
 #[derive(Clone, Default)]
@@ -132,7 +134,7 @@ pub(super) enum MarkResult {
     Dropped,
 }
 
-/// CE-mark an ECT(0) datagram in place; forward CE unchanged; drop if not ECT-capable.
+/// CE-mark an ECT datagram in place; forward CE unchanged; drop if not ECT-capable.
 fn mark_ce(mut dgram: Datagram) -> Option<Datagram> {
     let tos = dgram.tos();
     let ecn = Ecn::from(tos);
@@ -140,7 +142,6 @@ fn mark_ce(mut dgram: Datagram) -> Option<Datagram> {
         // Already marked; forwarding again is a no-op (RFC 3168 §5).
         Some(dgram)
     } else if ecn.is_ect() {
-        assert_ne!(ecn, Ecn::Ect1, "ECT(1)/L4S is not implemented");
         qtrace!("taildrop marking {} bytes CE", dgram.len());
         dgram.set_tos(Tos::from((Dscp::from(tos), Ecn::Ce)));
         Some(dgram)
@@ -162,6 +163,9 @@ pub enum Aqm {
     CoDel(CodelState),
     /// RED (Random Early Detection) ECN marking; requires RNG initialisation via `Node::init`.
     Red(RedState),
+    /// L4S step marking: ECT(1) packets are CE marked when their sojourn time exceeds 1ms.
+    /// Other packets are only dropped on buffer overflow.
+    L4s,
 }
 
 impl Aqm {
@@ -198,6 +202,7 @@ impl Aqm {
         let should_signal = match self {
             Self::CoDel(state) => state.update(sojourn, used == 0, now),
             Self::Red(state) => Ecn::from(pkt.tos()).is_ect() && state.should_mark(used, capacity),
+            Self::L4s => Ecn::from(pkt.tos()) == Ecn::Ect1 && sojourn > L4S_THRESHOLD,
             Self::None => false,
         };
         if should_signal {
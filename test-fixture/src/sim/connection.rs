@@ -8,11 +8,12 @@
 
 use std::{
     cmp::min,
+    collections::VecDeque,
     fmt::{self, Debug},
     time::Instant,
 };
 
-use neqo_common::{Datagram, event::Provider as _, qdebug, qinfo, qtrace};
+use neqo_common::{Bytes, Datagram, event::Provider as _, qdebug, qinfo, qtrace};
 use neqo_transport::{
     Connection, ConnectionEvent, ConnectionParameters, EmptyConnectionIdGenerator, Output, State,
     StreamId, StreamType,
@@ -220,17 +221,40 @@ impl Goal for ReachState {
 pub struct SendData {
     remaining: usize,
     stream_id: Option<StreamId>,
+    /// The buffers to send without copying, if `zero_copy` is set.
+    buffers: Option<VecDeque<Bytes>>,
 }
 
 impl SendData {
+    /// The size of each buffer that is sent without copying.
+    const ZERO_COPY_CHUNK: usize = 1 << 16;
+
     #[must_use]
     pub const fn new(amount: usize) -> Self {
         Self {
             remaining: amount,
             stream_id: None,
+            buffers: None,
         }
     }
 
+    /// Hand owned buffers to the connection using `Connection::stream_send_bytes`,
+    /// rather than having it copy the data.
+    /// The buffers are allocated here, so that this isn't counted when sending.
+    #[must_use]
+    pub fn zero_copy(mut self, zero_copy: bool) -> Self {
+        self.buffers = zero_copy.then(|| {
+            (0..self.remaining)
+                .step_by(Self::ZERO_COPY_CHUNK)
+                .map(|start| {
+                    let len = min(Self::ZERO_COPY_CHUNK, self.remaining - start);
+                    Bytes::from(vec![0; len])
+                })
+                .collect()
+        });
+        self
+    }
+
     fn make_stream(&mut self, c: &mut Connection) {
         if self.stream_id.is_none()
             && let Ok(stream_id) = c.stream_create(StreamType::UniDi)
@@ -244,8 +268,21 @@ impl SendData {
         const DATA: &[u8] = &[0; 4096];
         let mut status = GoalStatus::Waiting;
         loop {
-            let end = min(self.remaining, DATA.len());
-            let sent = c.stream_send(stream_id, &DATA[..end]).unwrap();
+            let sent = if let Some(buffers) = &mut self.buffers {
+                let data = buffers.pop_front().unwrap();
+                let len = data.len();
+                if let Some(rest) = c.stream_send_bytes(stream_id, data).unwrap() {
+                    // Not enough space for all of it, so send the rest later.
+                    let sent = len - rest.len();
+                    buffers.push_front(rest);
+                    sent
+                } else {
+                    len
+                }
+            } else {
+                let end = min(self.remaining, DATA.len());
+                c.stream_send(stream_id, &DATA[..end]).unwrap()
+            };
             if sent == 0 {
                 return status;
             }
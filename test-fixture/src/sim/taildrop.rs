@@ -435,6 +435,29 @@ mod test {
         assert!(gaps.windows(2).all(|w| w[1] < w[0]));
     }
 
+    /// L4S marking applies only to ECT(1) packets that queue for longer than the threshold.
+    #[test]
+    fn l4s_marks_ect1_only() {
+        // 1 Mbps link: each 1200 byte packet takes about 10ms to send.
+        let mut td = TailDrop::new(1_000_000, 1_000_000, Aqm::L4s, Duration::from_millis(1));
+        let t0 = now();
+        td.prepare(t0);
+
+        for _ in 0..10 {
+            td.process(Some(make_datagram(Ecn::Ect0)), t0);
+        }
+        drain(&mut td, t0);
+        assert_eq!(td.stats.marked, 0);
+
+        for _ in 0..10 {
+            td.process(Some(make_datagram(Ecn::Ect1)), t0);
+        }
+        drain(&mut td, t0);
+        // All but the first packet queue behind another one.
+        assert_eq!(td.stats.marked, 9);
+        assert_eq!(td.stats.dropped, 0);
+    }
+
     fn mark_rate(used: usize, capacity: usize, trials: usize, salt: u64) -> usize {
         let mut enc = Encoder::default();
         enc.encode_uint(8, u64::try_from(used).unwrap());